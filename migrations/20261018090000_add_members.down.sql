@@ -0,0 +1,4 @@
+DROP TABLE membership_fee_collections;
+DROP TABLE member_family_members;
+DROP TABLE members;
+DROP TYPE membership_type;
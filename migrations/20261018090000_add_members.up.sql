@@ -0,0 +1,45 @@
+CREATE TYPE membership_type AS ENUM (
+    'Fitness',
+    'Family',
+    'AdultActive',
+    'AdultSuporting',
+    'AdultPremium',
+    'Youth',
+    'Free'
+);
+
+CREATE TABLE members (
+    id SERIAL PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    salutation TEXT NOT NULL,
+    first_name TEXT NOT NULL,
+    last_name TEXT NOT NULL,
+    street TEXT NOT NULL,
+    zipcode TEXT NOT NULL,
+    city TEXT NOT NULL,
+    email TEXT NOT NULL,
+    phone TEXT NOT NULL,
+    birthday TEXT NOT NULL,
+    membership_type MEMBERSHIP_TYPE NOT NULL,
+    start_date DATE NOT NULL,
+    end_date DATE,
+    account_owner TEXT NOT NULL,
+    iban TEXT NOT NULL
+);
+
+CREATE TABLE member_family_members (
+    id SERIAL PRIMARY KEY,
+    member_id INTEGER NOT NULL REFERENCES members (id) ON DELETE CASCADE,
+    first_name TEXT NOT NULL,
+    last_name TEXT NOT NULL,
+    birthday TEXT NOT NULL
+);
+
+CREATE TABLE membership_fee_collections (
+    id SERIAL PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    member_id INTEGER NOT NULL REFERENCES members (id),
+    year SMALLINT NOT NULL,
+    amount DECIMAL(12, 2) NOT NULL,
+    UNIQUE (member_id, year)
+);
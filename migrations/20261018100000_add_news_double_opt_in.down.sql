@@ -0,0 +1,5 @@
+ALTER TABLE news_subscribers
+DROP COLUMN consent_ip,
+DROP COLUMN consent_at;
+
+DROP TABLE news_subscription_requests;
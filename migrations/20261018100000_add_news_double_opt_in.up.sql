@@ -0,0 +1,17 @@
+-- Pending subscriptions waiting for the confirmation of the subscriber
+CREATE TABLE news_subscription_requests (
+    id SERIAL PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    email TEXT NOT NULL,
+    general BOOLEAN NOT NULL,
+    events BOOLEAN NOT NULL,
+    fitness BOOLEAN NOT NULL,
+    request_ip TEXT
+);
+
+-- Proof of consent (double opt-in)
+ALTER TABLE news_subscribers
+ADD COLUMN consent_at TIMESTAMP WITH TIME ZONE,
+ADD COLUMN consent_ip TEXT;
+
+COMMENT ON COLUMN news_subscribers.consent_at IS 'Timestamp of the latest confirmed consent. NULL for subscriptions created before the double opt-in was introduced.';
@@ -0,0 +1,4 @@
+DROP TABLE news_campaign_recipients;
+DROP TABLE news_campaigns;
+DROP TYPE news_campaign_recipient_status;
+DROP TYPE news_campaign_status;
@@ -0,0 +1,24 @@
+CREATE TYPE news_campaign_status AS ENUM ('Draft', 'Scheduled', 'Sending', 'Sent');
+
+CREATE TYPE news_campaign_recipient_status AS ENUM ('Pending', 'Sent', 'Failed', 'Bounced');
+
+CREATE TABLE news_campaigns (
+    id SERIAL PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    topic TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    body TEXT NOT NULL,
+    status NEWS_CAMPAIGN_STATUS NOT NULL,
+    scheduled_at TIMESTAMP WITH TIME ZONE,
+    sent_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE TABLE news_campaign_recipients (
+    id SERIAL PRIMARY KEY,
+    campaign_id INTEGER NOT NULL REFERENCES news_campaigns (id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    status NEWS_CAMPAIGN_RECIPIENT_STATUS NOT NULL DEFAULT 'Pending',
+    sent_at TIMESTAMP WITH TIME ZONE,
+    error TEXT,
+    UNIQUE (campaign_id, email)
+);
@@ -0,0 +1,39 @@
+ALTER TABLE news_campaigns
+DROP CONSTRAINT news_campaigns_topic_fkey;
+
+ALTER TABLE news_subscription_requests
+ADD COLUMN general BOOLEAN NOT NULL DEFAULT FALSE,
+ADD COLUMN events BOOLEAN NOT NULL DEFAULT FALSE,
+ADD COLUMN fitness BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE news_subscription_requests
+SET general = 'General' = ANY(topics),
+    events = 'Events' = ANY(topics),
+    fitness = 'Fitness' = ANY(topics);
+
+ALTER TABLE news_subscription_requests
+DROP COLUMN topics;
+
+ALTER TABLE news_subscribers
+ADD COLUMN general BOOLEAN NOT NULL DEFAULT FALSE,
+ADD COLUMN events BOOLEAN NOT NULL DEFAULT FALSE,
+ADD COLUMN fitness BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE news_subscribers s
+SET general = EXISTS (SELECT 1 FROM news_subscriptions ns JOIN news_topics t ON t.id = ns.topic_id WHERE ns.subscriber_id = s.id AND t.name = 'General'),
+    events = EXISTS (SELECT 1 FROM news_subscriptions ns JOIN news_topics t ON t.id = ns.topic_id WHERE ns.subscriber_id = s.id AND t.name = 'Events'),
+    fitness = EXISTS (SELECT 1 FROM news_subscriptions ns JOIN news_topics t ON t.id = ns.topic_id WHERE ns.subscriber_id = s.id AND t.name = 'Fitness');
+
+ALTER TABLE news_subscribers
+ALTER COLUMN general DROP DEFAULT,
+ALTER COLUMN events DROP DEFAULT,
+ALTER COLUMN fitness DROP DEFAULT;
+
+ALTER TABLE news_subscription_requests
+ALTER COLUMN general DROP DEFAULT,
+ALTER COLUMN events DROP DEFAULT,
+ALTER COLUMN fitness DROP DEFAULT;
+
+DROP TABLE news_subscriptions;
+DROP TABLE news_topics;
+DROP TYPE email_type;
@@ -0,0 +1,54 @@
+CREATE TYPE email_type AS ENUM ('Fitness', 'Events', 'Info', 'Kunstrasen', 'Jugendturnier', 'Mitglieder');
+
+-- Newsletter topics managed by the admins
+CREATE TABLE news_topics (
+    id SERIAL PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    name TEXT NOT NULL UNIQUE,
+    display_name TEXT NOT NULL,
+    email_type EMAIL_TYPE NOT NULL
+);
+
+COMMENT ON COLUMN news_topics.name IS 'Key of the topic used in the API, e.g. General.';
+COMMENT ON COLUMN news_topics.email_type IS 'Email account used to send mails regarding the topic.';
+
+INSERT INTO news_topics (name, display_name, email_type) VALUES
+('General', 'Allgemein', 'Info'),
+('Events', 'Events', 'Events'),
+('Fitness', 'Fitness', 'Fitness');
+
+CREATE TABLE news_subscriptions (
+    subscriber_id INTEGER NOT NULL REFERENCES news_subscribers (id) ON DELETE CASCADE,
+    topic_id INTEGER NOT NULL REFERENCES news_topics (id) ON DELETE CASCADE,
+    PRIMARY KEY (subscriber_id, topic_id)
+);
+
+INSERT INTO news_subscriptions (subscriber_id, topic_id)
+SELECT s.id, t.id
+FROM news_subscribers s
+JOIN news_topics t ON (t.name = 'General' AND s.general)
+    OR (t.name = 'Events' AND s.events)
+    OR (t.name = 'Fitness' AND s.fitness);
+
+ALTER TABLE news_subscribers
+DROP COLUMN general,
+DROP COLUMN events,
+DROP COLUMN fitness;
+
+ALTER TABLE news_subscription_requests
+ADD COLUMN topics TEXT [] NOT NULL DEFAULT '{}';
+
+UPDATE news_subscription_requests
+SET topics = ARRAY_REMOVE(ARRAY[
+    CASE WHEN general THEN 'General' END,
+    CASE WHEN events THEN 'Events' END,
+    CASE WHEN fitness THEN 'Fitness' END
+], NULL);
+
+ALTER TABLE news_subscription_requests
+DROP COLUMN general,
+DROP COLUMN events,
+DROP COLUMN fitness;
+
+ALTER TABLE news_campaigns
+ADD CONSTRAINT news_campaigns_topic_fkey FOREIGN KEY (topic) REFERENCES news_topics (name) ON UPDATE CASCADE;
@@ -0,0 +1,15 @@
+CREATE TYPE user_role AS ENUM ('Admin', 'Treasurer', 'FitnessCoordinator', 'EventsCoordinator', 'TrainerReadonly');
+
+-- Roles of the users of the admin area, identified by their Google account email
+CREATE TABLE user_roles (
+    email TEXT NOT NULL,
+    role USER_ROLE NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (email, role)
+);
+
+-- keep the access of the accounts which have been allowed before
+INSERT INTO user_roles (email, role) VALUES
+('info@sv-eutingen.de', 'Admin'),
+('fitness@sv-eutingen.de', 'FitnessCoordinator'),
+('events@sv-eutingen.de', 'EventsCoordinator');
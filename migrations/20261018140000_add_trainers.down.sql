@@ -0,0 +1,2 @@
+DROP TABLE trainer_events;
+DROP TABLE trainers;
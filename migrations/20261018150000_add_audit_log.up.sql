@@ -0,0 +1,26 @@
+-- Append-only log of all changes made in the admin area
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL,
+    path TEXT NOT NULL,
+    -- no foreign keys: entries have to outlive the events and bookings they refer to
+    event_id INTEGER,
+    booking_id INTEGER,
+    before JSONB,
+    after JSONB
+);
+
+CREATE INDEX audit_log_event_id_idx ON audit_log (event_id);
+CREATE INDEX audit_log_created_idx ON audit_log (created);
+
+CREATE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+BEFORE UPDATE OR DELETE ON audit_log
+FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
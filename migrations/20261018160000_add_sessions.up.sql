@@ -0,0 +1,16 @@
+-- Refresh tokens of the admin sessions, only the SHA-256 hash of a token is stored
+CREATE TABLE sessions (
+    id SERIAL PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    -- all refresh tokens issued by rotating the token of a login share the family
+    family TEXT NOT NULL,
+    email TEXT NOT NULL,
+    hd TEXT,
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    rotated_at TIMESTAMP WITH TIME ZONE,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX sessions_email_idx ON sessions (email);
+CREATE INDEX sessions_family_idx ON sessions (family);
@@ -0,0 +1,11 @@
+-- API keys of the schedulers calling the task endpoints, only the SHA-256 hash of a key is stored
+CREATE TABLE api_keys (
+    id SERIAL PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    name TEXT NOT NULL UNIQUE,
+    key_hash TEXT NOT NULL UNIQUE,
+    -- names of the tasks the key may call
+    scopes TEXT[] NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE,
+    last_used_at TIMESTAMP WITH TIME ZONE
+);
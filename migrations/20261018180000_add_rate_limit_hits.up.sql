@@ -0,0 +1,12 @@
+-- Requests to the public endpoints, used for the sliding-window rate limits.
+-- The table is shared by all Lambda instances.
+CREATE TABLE rate_limit_hits (
+    id BIGSERIAL PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    -- name of the limited endpoint group, e.g. booking
+    bucket TEXT NOT NULL,
+    -- limited client, e.g. ip:192.0.2.1 or email:info@sv-eutingen.de
+    key TEXT NOT NULL
+);
+
+CREATE INDEX rate_limit_hits_bucket_key_created_idx ON rate_limit_hits (bucket, key, created);
@@ -0,0 +1 @@
+DROP TABLE captcha_solutions;
@@ -0,0 +1,5 @@
+-- Solved proof-of-work challenges, a challenge may only be used once
+CREATE TABLE captcha_solutions (
+    challenge TEXT PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,14 @@
+-- Responses of requests with an Idempotency-Key header, replayed if the request is retried
+CREATE TABLE idempotency_keys (
+    -- method and path of the request
+    scope TEXT NOT NULL,
+    key TEXT NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    -- SHA-256 hash of the request body, a key may not be reused for another payload
+    request_hash TEXT NOT NULL,
+    -- the response is missing as long as the request is in progress
+    status_code INTEGER,
+    headers JSONB,
+    body BYTEA,
+    PRIMARY KEY (scope, key)
+);
@@ -0,0 +1 @@
+ALTER TABLE event_subscribers DROP COLUMN anonymized_at;
@@ -0,0 +1,2 @@
+-- Subscribers whose personal data has been erased, their bookings are kept as financial records
+ALTER TABLE event_subscribers ADD COLUMN anonymized_at TIMESTAMP WITH TIME ZONE;
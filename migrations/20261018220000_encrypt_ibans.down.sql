@@ -0,0 +1 @@
+COMMENT ON COLUMN event_bookings.iban IS 'Dual semantics by events.payment_method: BankTransfer = payer IBAN from CSV verification, SepaDirectDebit = debtor IBAN from booking form. Always join events.payment_method when reading this column.';
@@ -0,0 +1,4 @@
+-- IBANs are encrypted by the application (AES-256-GCM with IBAN_ENCRYPTION_KEY), the key is not
+-- available to the database. Existing plain IBANs are encrypted by the encrypt_ibans task, which
+-- has to be run once after deploying this migration.
+COMMENT ON COLUMN event_bookings.iban IS 'Encrypted IBAN (enc:v1:<first 4>:<last 4>:<ciphertext>), only decrypted for the SEPA export. Dual semantics by events.payment_method: BankTransfer = payer IBAN from CSV verification, SepaDirectDebit = debtor IBAN from booking form. Always join events.payment_method when reading this column.';
@@ -0,0 +1,84 @@
+DROP VIEW v_events;
+DROP VIEW IF EXISTS v_event_bookings CASCADE;
+
+ALTER TABLE events
+ADD COLUMN custom_field_1 INTEGER REFERENCES event_custom_fields (id),
+ADD COLUMN custom_field_2 INTEGER REFERENCES event_custom_fields (id),
+ADD COLUMN custom_field_3 INTEGER REFERENCES event_custom_fields (id),
+ADD COLUMN custom_field_4 INTEGER REFERENCES event_custom_fields (id);
+
+UPDATE events e
+SET
+    custom_field_1 = (SELECT a.custom_field_id FROM event_custom_field_assignments a WHERE a.event_id = e.id ORDER BY a.position OFFSET 0 LIMIT 1),
+    custom_field_2 = (SELECT a.custom_field_id FROM event_custom_field_assignments a WHERE a.event_id = e.id ORDER BY a.position OFFSET 1 LIMIT 1),
+    custom_field_3 = (SELECT a.custom_field_id FROM event_custom_field_assignments a WHERE a.event_id = e.id ORDER BY a.position OFFSET 2 LIMIT 1),
+    custom_field_4 = (SELECT a.custom_field_id FROM event_custom_field_assignments a WHERE a.event_id = e.id ORDER BY a.position OFFSET 3 LIMIT 1);
+
+ALTER TABLE event_bookings
+ADD COLUMN custom_value_1 TEXT,
+ADD COLUMN custom_value_2 TEXT,
+ADD COLUMN custom_value_3 TEXT,
+ADD COLUMN custom_value_4 TEXT;
+
+UPDATE event_bookings
+SET
+    custom_value_1 = custom_values[1],
+    custom_value_2 = custom_values[2],
+    custom_value_3 = custom_values[3],
+    custom_value_4 = custom_values[4];
+
+ALTER TABLE event_bookings
+DROP COLUMN custom_values;
+
+DROP TABLE event_custom_field_assignments;
+
+ALTER TABLE event_custom_fields
+DROP COLUMN options,
+DROP COLUMN required;
+
+-- Values of the enum type can not be dropped, the new types are kept
+
+CREATE VIEW v_events AS
+SELECT
+    e.*,
+    ed.date
+FROM
+    events e,
+    event_dates ed
+WHERE
+    e.id = ed.event_id
+ORDER BY
+    e.created,
+    ed.date;
+
+CREATE VIEW v_event_bookings AS
+SELECT eb.*, es.first_name, es.last_name, es.street, es.city, es.email, es.phone, es.member
+FROM event_bookings eb, event_subscribers es
+WHERE eb.subscriber_id = es.id
+ORDER BY eb.created, eb.enrolled;
+
+CREATE VIEW v_event_counters AS
+SELECT
+	e.id,
+	e.max_subscribers,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		v_event_bookings v
+	WHERE
+		e.id = v.event_id
+		AND v.canceled IS NULL
+		AND v.enrolled IS TRUE) AS subscribers,
+	e.max_waiting_list,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		v_event_bookings v
+	WHERE
+		e.id = v.event_id
+		AND v.canceled IS NULL
+		AND v.enrolled IS FALSE) AS waiting_list
+FROM
+	events e;
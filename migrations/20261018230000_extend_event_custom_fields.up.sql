@@ -0,0 +1,99 @@
+ALTER TYPE event_cf_type ADD VALUE 'Select';
+ALTER TYPE event_cf_type ADD VALUE 'Checkbox';
+ALTER TYPE event_cf_type ADD VALUE 'Date';
+
+ALTER TABLE event_custom_fields
+ADD COLUMN options TEXT [] NOT NULL DEFAULT '{}',
+ADD COLUMN required BOOLEAN NOT NULL DEFAULT FALSE;
+
+COMMENT ON COLUMN event_custom_fields.options IS 'Selectable values of Select fields.';
+COMMENT ON COLUMN event_custom_fields.min_value IS 'Minimum of Number fields, minimum length of Text fields.';
+COMMENT ON COLUMN event_custom_fields.max_value IS 'Maximum of Number fields, maximum length of Text fields.';
+
+-- Replace the four custom field columns of the events with an unbounded, ordered list
+CREATE TABLE event_custom_field_assignments (
+    event_id INTEGER NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+    custom_field_id INTEGER NOT NULL REFERENCES event_custom_fields (id),
+    position SMALLINT NOT NULL,
+    PRIMARY KEY (event_id, custom_field_id)
+);
+
+INSERT INTO event_custom_field_assignments (event_id, custom_field_id, position)
+SELECT e.id, f.custom_field_id, f.position
+FROM events e
+CROSS JOIN LATERAL UNNEST(ARRAY[e.custom_field_1, e.custom_field_2, e.custom_field_3, e.custom_field_4])
+    WITH ORDINALITY AS f (custom_field_id, position)
+WHERE f.custom_field_id IS NOT NULL;
+
+ALTER TABLE event_bookings
+ADD COLUMN custom_values TEXT [] NOT NULL DEFAULT '{}';
+
+-- The values are positional, keep one value per custom field of the event
+UPDATE event_bookings b
+SET custom_values = (ARRAY[
+    COALESCE(b.custom_value_1, ''),
+    COALESCE(b.custom_value_2, ''),
+    COALESCE(b.custom_value_3, ''),
+    COALESCE(b.custom_value_4, '')
+])[1:(SELECT COUNT(*) FROM event_custom_field_assignments a WHERE a.event_id = b.event_id)];
+
+-- Postgres expands e.* and eb.* at CREATE time, the views have to be recreated
+DROP VIEW v_events;
+DROP VIEW IF EXISTS v_event_bookings CASCADE;
+
+ALTER TABLE events
+DROP COLUMN custom_field_1,
+DROP COLUMN custom_field_2,
+DROP COLUMN custom_field_3,
+DROP COLUMN custom_field_4;
+
+ALTER TABLE event_bookings
+DROP COLUMN custom_value_1,
+DROP COLUMN custom_value_2,
+DROP COLUMN custom_value_3,
+DROP COLUMN custom_value_4;
+
+CREATE VIEW v_events AS
+SELECT
+    e.*,
+    ed.date
+FROM
+    events e,
+    event_dates ed
+WHERE
+    e.id = ed.event_id
+ORDER BY
+    e.created,
+    ed.date;
+
+CREATE VIEW v_event_bookings AS
+SELECT eb.*, es.first_name, es.last_name, es.street, es.city, es.email, es.phone, es.member
+FROM event_bookings eb, event_subscribers es
+WHERE eb.subscriber_id = es.id
+ORDER BY eb.created, eb.enrolled;
+
+CREATE VIEW v_event_counters AS
+SELECT
+	e.id,
+	e.max_subscribers,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		v_event_bookings v
+	WHERE
+		e.id = v.event_id
+		AND v.canceled IS NULL
+		AND v.enrolled IS TRUE) AS subscribers,
+	e.max_waiting_list,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		v_event_bookings v
+	WHERE
+		e.id = v.event_id
+		AND v.canceled IS NULL
+		AND v.enrolled IS FALSE) AS waiting_list
+FROM
+	events e;
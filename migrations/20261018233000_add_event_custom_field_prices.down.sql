@@ -0,0 +1,6 @@
+ALTER TABLE event_custom_fields
+DROP CONSTRAINT option_prices_match_options;
+
+ALTER TABLE event_custom_fields
+DROP COLUMN price,
+DROP COLUMN option_prices;
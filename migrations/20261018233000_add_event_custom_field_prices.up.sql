@@ -0,0 +1,10 @@
+ALTER TABLE event_custom_fields
+ADD COLUMN price DECIMAL(12, 2),
+ADD COLUMN option_prices DECIMAL(12, 2) [] NOT NULL DEFAULT '{}';
+
+ALTER TABLE event_custom_fields
+ADD CONSTRAINT option_prices_match_options
+CHECK (CARDINALITY(option_prices) = 0 OR CARDINALITY(option_prices) = CARDINALITY(options));
+
+COMMENT ON COLUMN event_custom_fields.price IS 'Surcharge of checked Checkbox fields, surcharge per unit of Number fields which are not price relevant.';
+COMMENT ON COLUMN event_custom_fields.option_prices IS 'Surcharges of the options of Select fields, one per option.';
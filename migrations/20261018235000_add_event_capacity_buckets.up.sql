@@ -0,0 +1,80 @@
+-- Separate quotas of an event (e.g. adults and kids), each with its own waiting list
+CREATE TABLE event_capacity_buckets (
+    id SERIAL PRIMARY KEY,
+    event_id INTEGER NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    max_subscribers SMALLINT NOT NULL,
+    max_waiting_list SMALLINT NOT NULL,
+    position SMALLINT NOT NULL
+);
+
+CREATE INDEX event_capacity_buckets_event_id_idx ON event_capacity_buckets (event_id);
+
+COMMENT ON TABLE event_capacity_buckets IS 'Capacity buckets of an event. Events without buckets use the capacity of the event.';
+
+ALTER TABLE event_bookings
+ADD COLUMN bucket_id INTEGER REFERENCES event_capacity_buckets (id);
+
+-- Postgres expands eb.* at CREATE time, the views have to be recreated
+DROP VIEW IF EXISTS v_event_bookings CASCADE;
+
+CREATE VIEW v_event_bookings AS
+SELECT eb.*, es.first_name, es.last_name, es.street, es.city, es.email, es.phone, es.member
+FROM event_bookings eb, event_subscribers es
+WHERE eb.subscriber_id = es.id
+ORDER BY eb.created, eb.enrolled;
+
+CREATE VIEW v_event_counters AS
+SELECT
+	e.id,
+	e.max_subscribers,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		v_event_bookings v
+	WHERE
+		e.id = v.event_id
+		AND v.canceled IS NULL
+		AND v.enrolled IS TRUE) AS subscribers,
+	e.max_waiting_list,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		v_event_bookings v
+	WHERE
+		e.id = v.event_id
+		AND v.canceled IS NULL
+		AND v.enrolled IS FALSE) AS waiting_list
+FROM
+	events e;
+
+CREATE VIEW v_event_bucket_counters AS
+SELECT
+	b.id,
+	b.event_id,
+	b.name,
+	b.position,
+	b.max_subscribers,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		event_bookings eb
+	WHERE
+		b.id = eb.bucket_id
+		AND eb.canceled IS NULL
+		AND eb.enrolled IS TRUE) AS subscribers,
+	b.max_waiting_list,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		event_bookings eb
+	WHERE
+		b.id = eb.bucket_id
+		AND eb.canceled IS NULL
+		AND eb.enrolled IS FALSE) AS waiting_list
+FROM
+	event_capacity_buckets b;
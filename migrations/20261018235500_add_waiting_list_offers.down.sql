@@ -0,0 +1,76 @@
+DROP VIEW v_event_bucket_counters;
+DROP VIEW v_event_counters;
+DROP VIEW v_events;
+
+DROP TABLE event_waiting_list_offers;
+
+ALTER TABLE events
+DROP COLUMN waiting_list_offer_hours;
+
+CREATE VIEW v_events AS
+SELECT
+    e.*,
+    ed.date
+FROM
+    events e,
+    event_dates ed
+WHERE
+    e.id = ed.event_id
+ORDER BY
+    e.created,
+    ed.date;
+
+CREATE VIEW v_event_counters AS
+SELECT
+	e.id,
+	e.max_subscribers,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		v_event_bookings v
+	WHERE
+		e.id = v.event_id
+		AND v.canceled IS NULL
+		AND v.enrolled IS TRUE) AS subscribers,
+	e.max_waiting_list,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		v_event_bookings v
+	WHERE
+		e.id = v.event_id
+		AND v.canceled IS NULL
+		AND v.enrolled IS FALSE) AS waiting_list
+FROM
+	events e;
+
+CREATE VIEW v_event_bucket_counters AS
+SELECT
+	b.id,
+	b.event_id,
+	b.name,
+	b.position,
+	b.max_subscribers,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		event_bookings eb
+	WHERE
+		b.id = eb.bucket_id
+		AND eb.canceled IS NULL
+		AND eb.enrolled IS TRUE) AS subscribers,
+	b.max_waiting_list,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		event_bookings eb
+	WHERE
+		b.id = eb.bucket_id
+		AND eb.canceled IS NULL
+		AND eb.enrolled IS FALSE) AS waiting_list
+FROM
+	event_capacity_buckets b;
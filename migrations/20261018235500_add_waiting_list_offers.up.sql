@@ -0,0 +1,125 @@
+ALTER TABLE events
+ADD COLUMN waiting_list_offer_hours SMALLINT NOT NULL DEFAULT 0 CHECK (waiting_list_offer_hours >= 0);
+
+COMMENT ON COLUMN events.waiting_list_offer_hours IS 'Hours to accept a freed spot offered to the waiting list, 0 enrolls the next waiting person immediately.';
+
+CREATE TABLE event_waiting_list_offers (
+    id SERIAL PRIMARY KEY,
+    booking_id INTEGER NOT NULL REFERENCES event_bookings (id) ON DELETE CASCADE,
+    created TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    accepted_at TIMESTAMPTZ,
+    expired_at TIMESTAMPTZ
+);
+
+-- at most one open offer per booking
+CREATE UNIQUE INDEX event_waiting_list_offers_open_idx ON event_waiting_list_offers (booking_id)
+WHERE accepted_at IS NULL AND expired_at IS NULL;
+
+-- Postgres expands e.* at CREATE time, the view has to be recreated
+DROP VIEW v_events;
+
+CREATE VIEW v_events AS
+SELECT
+    e.*,
+    ed.date
+FROM
+    events e,
+    event_dates ed
+WHERE
+    e.id = ed.event_id
+ORDER BY
+    e.created,
+    ed.date;
+
+-- Waiting list bookings with an open offer keep the freed spot until the offer is accepted or expired
+CREATE OR REPLACE VIEW v_event_counters AS
+SELECT
+	e.id,
+	e.max_subscribers,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		v_event_bookings v
+	WHERE
+		e.id = v.event_id
+		AND v.canceled IS NULL
+		AND (v.enrolled IS TRUE
+			OR EXISTS (
+			SELECT
+				1
+			FROM
+				event_waiting_list_offers o
+			WHERE
+				o.booking_id = v.id
+				AND o.accepted_at IS NULL
+				AND o.expired_at IS NULL))) AS subscribers,
+	e.max_waiting_list,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		v_event_bookings v
+	WHERE
+		e.id = v.event_id
+		AND v.canceled IS NULL
+		AND v.enrolled IS FALSE
+		AND NOT EXISTS (
+		SELECT
+			1
+		FROM
+			event_waiting_list_offers o
+		WHERE
+			o.booking_id = v.id
+			AND o.accepted_at IS NULL
+			AND o.expired_at IS NULL)) AS waiting_list
+FROM
+	events e;
+
+CREATE OR REPLACE VIEW v_event_bucket_counters AS
+SELECT
+	b.id,
+	b.event_id,
+	b.name,
+	b.position,
+	b.max_subscribers,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		event_bookings eb
+	WHERE
+		b.id = eb.bucket_id
+		AND eb.canceled IS NULL
+		AND (eb.enrolled IS TRUE
+			OR EXISTS (
+			SELECT
+				1
+			FROM
+				event_waiting_list_offers o
+			WHERE
+				o.booking_id = eb.id
+				AND o.accepted_at IS NULL
+				AND o.expired_at IS NULL))) AS subscribers,
+	b.max_waiting_list,
+	(
+	SELECT
+		COUNT(*)
+	FROM
+		event_bookings eb
+	WHERE
+		b.id = eb.bucket_id
+		AND eb.canceled IS NULL
+		AND eb.enrolled IS FALSE
+		AND NOT EXISTS (
+		SELECT
+			1
+		FROM
+			event_waiting_list_offers o
+		WHERE
+			o.booking_id = eb.id
+			AND o.accepted_at IS NULL
+			AND o.expired_at IS NULL)) AS waiting_list
+FROM
+	event_capacity_buckets b;
@@ -0,0 +1,8 @@
+-- Named templates to prefill new events
+CREATE TABLE event_templates (
+    id SERIAL PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    name TEXT NOT NULL UNIQUE,
+    -- partial event as used by the update endpoint, without id, status and dates
+    event JSONB NOT NULL
+);
@@ -0,0 +1,21 @@
+DROP VIEW v_events;
+
+ALTER TABLE events
+DROP CONSTRAINT events_booking_window_check,
+DROP COLUMN publish_at,
+DROP COLUMN unpublish_at,
+DROP COLUMN booking_start,
+DROP COLUMN booking_end;
+
+CREATE VIEW v_events AS
+SELECT
+    e.*,
+    ed.date
+FROM
+    events e,
+    event_dates ed
+WHERE
+    e.id = ed.event_id
+ORDER BY
+    e.created,
+    ed.date;
@@ -0,0 +1,27 @@
+ALTER TABLE events
+ADD COLUMN publish_at TIMESTAMPTZ,
+ADD COLUMN unpublish_at TIMESTAMPTZ,
+ADD COLUMN booking_start TIMESTAMPTZ,
+ADD COLUMN booking_end TIMESTAMPTZ,
+ADD CONSTRAINT events_booking_window_check CHECK (booking_start < booking_end);
+
+COMMENT ON COLUMN events.publish_at IS 'Draft or review events are published at this time.';
+COMMENT ON COLUMN events.unpublish_at IS 'Review or published events are moved into status running (no longer visible) at this time.';
+COMMENT ON COLUMN events.booking_start IS 'Bookings are possible from this time on, NULL for no restriction.';
+COMMENT ON COLUMN events.booking_end IS 'Bookings are possible until this time, NULL for no restriction.';
+
+-- Postgres expands e.* at CREATE time, the view has to be recreated
+DROP VIEW v_events;
+
+CREATE VIEW v_events AS
+SELECT
+    e.*,
+    ed.date
+FROM
+    events e,
+    event_dates ed
+WHERE
+    e.id = ed.event_id
+ORDER BY
+    e.created,
+    ed.date;
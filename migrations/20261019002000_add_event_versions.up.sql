@@ -0,0 +1,10 @@
+-- Snapshot of an event after every write, existing events get their first version on the next write
+CREATE TABLE event_versions (
+    id SERIAL PRIMARY KEY,
+    event_id INTEGER NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    -- the event as returned by the admin API
+    snapshot JSONB NOT NULL,
+    UNIQUE (event_id, version)
+);
@@ -0,0 +1 @@
+-- Values of the enum type can not be dropped, the canceled status is kept
@@ -0,0 +1 @@
+ALTER TYPE lifecycle_status ADD VALUE 'Canceled' AFTER 'Archived';
@@ -0,0 +1,3 @@
+ALTER TABLE news_campaign_recipients
+DROP COLUMN attempts,
+DROP COLUMN claimed_at;
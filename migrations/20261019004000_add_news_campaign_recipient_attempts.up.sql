@@ -0,0 +1,3 @@
+ALTER TABLE news_campaign_recipients
+ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN claimed_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,3 @@
+DROP INDEX audit_log_subject_idx;
+
+ALTER TABLE audit_log DROP COLUMN subject;
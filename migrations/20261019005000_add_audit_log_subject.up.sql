@@ -0,0 +1,4 @@
+-- Person whose data is concerned by the call, e.g. of a data subject request
+ALTER TABLE audit_log ADD COLUMN subject TEXT;
+
+CREATE INDEX audit_log_subject_idx ON audit_log (subject);
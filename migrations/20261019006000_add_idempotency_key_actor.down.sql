@@ -0,0 +1,6 @@
+DELETE FROM idempotency_keys;
+
+ALTER TABLE idempotency_keys
+DROP CONSTRAINT idempotency_keys_pkey,
+DROP COLUMN actor,
+ADD PRIMARY KEY (scope, key);
@@ -0,0 +1,7 @@
+-- Keys are scoped to the caller; stored responses of the former scopes are dropped
+DELETE FROM idempotency_keys;
+
+ALTER TABLE idempotency_keys
+ADD COLUMN actor TEXT NOT NULL,
+DROP CONSTRAINT idempotency_keys_pkey,
+ADD PRIMARY KEY (actor, scope, key);
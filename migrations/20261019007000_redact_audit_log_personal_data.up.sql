@@ -0,0 +1,20 @@
+-- Booking snapshots of the audit log only reference the subscriber by its id
+ALTER TABLE audit_log DISABLE TRIGGER audit_log_append_only;
+
+UPDATE audit_log
+SET before = before - 'subscriber' - 'comment' - 'custom_values',
+    after = after - 'subscriber' - 'comment' - 'custom_values'
+WHERE booking_id IS NOT NULL;
+
+ALTER TABLE audit_log ENABLE TRIGGER audit_log_append_only;
+
+-- The subject of an entry may be removed by the erasure of the personal data
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND NEW.subject IS NULL
+        AND to_jsonb(NEW) - 'subject' = to_jsonb(OLD) - 'subject' THEN
+        RETURN NEW;
+    END IF;
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1 @@
+DROP TABLE membership_fee_schedule;
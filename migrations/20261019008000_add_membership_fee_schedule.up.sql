@@ -0,0 +1,5 @@
+CREATE TABLE membership_fee_schedule (
+    membership_type MEMBERSHIP_TYPE PRIMARY KEY,
+    annual_fee DECIMAL(12, 2) NOT NULL CHECK (annual_fee >= 0),
+    family_member_fee DECIMAL(12, 2) NOT NULL DEFAULT 0 CHECK (family_member_fee >= 0)
+);
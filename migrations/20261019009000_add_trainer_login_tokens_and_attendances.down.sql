@@ -0,0 +1,2 @@
+DROP TABLE event_attendances;
+DROP TABLE trainer_login_tokens;
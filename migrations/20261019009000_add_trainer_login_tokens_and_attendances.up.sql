@@ -0,0 +1,16 @@
+-- Login links of trainers can only be used once
+CREATE TABLE trainer_login_tokens (
+    id TEXT PRIMARY KEY,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    trainer_id INTEGER NOT NULL REFERENCES trainers(id) ON DELETE CASCADE,
+    used_at TIMESTAMP WITH TIME ZONE
+);
+
+-- Attendance of the participants per event date, marked by the trainers
+CREATE TABLE event_attendances (
+    booking_id INTEGER NOT NULL REFERENCES event_bookings(id) ON DELETE CASCADE,
+    date TIMESTAMP WITH TIME ZONE NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    trainer_id INTEGER REFERENCES trainers(id) ON DELETE SET NULL,
+    PRIMARY KEY (booking_id, date)
+);
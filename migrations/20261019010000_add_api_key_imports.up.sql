@@ -0,0 +1,8 @@
+-- Names of keys imported from secrets, so a revoked key is not imported again
+CREATE TABLE api_key_imports (
+    name TEXT PRIMARY KEY,
+    imported TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO api_key_imports (name, imported)
+SELECT name, created FROM api_keys WHERE name = 'TASK_API_KEY';
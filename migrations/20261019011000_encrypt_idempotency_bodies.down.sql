@@ -0,0 +1,2 @@
+-- Encrypted response bodies can't be replayed without the encryption
+DELETE FROM idempotency_keys WHERE body IS NOT NULL;
@@ -0,0 +1,2 @@
+-- Stored response bodies are encrypted from now on, the plain ones are dropped
+DELETE FROM idempotency_keys WHERE body IS NOT NULL;
@@ -0,0 +1,3 @@
+DROP TRIGGER event_custom_fields_freeze_prices ON event_custom_fields;
+
+DROP FUNCTION event_custom_fields_freeze_prices();
@@ -0,0 +1,21 @@
+-- the prices of bookings are recalculated from the custom fields, so the
+-- price rules of a field are fixed once an event using it has bookings
+CREATE FUNCTION event_custom_fields_freeze_prices() RETURNS TRIGGER AS $$
+BEGIN
+    IF (OLD.type, OLD.options, OLD.price_relevant, OLD.price, OLD.option_prices)
+        IS DISTINCT FROM (NEW.type, NEW.options, NEW.price_relevant, NEW.price, NEW.option_prices)
+        AND EXISTS (
+            SELECT 1 FROM event_custom_field_assignments a
+            JOIN event_bookings b ON b.event_id = a.event_id
+            WHERE a.custom_field_id = OLD.id
+        )
+    THEN
+        RAISE EXCEPTION 'the price rules of custom field % are used by bookings', OLD.id;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER event_custom_fields_freeze_prices
+BEFORE UPDATE ON event_custom_fields
+FOR EACH ROW EXECUTE FUNCTION event_custom_fields_freeze_prices();
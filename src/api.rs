@@ -8,16 +8,16 @@ use std::sync::Arc;
 
 use anyhow::{Result, anyhow};
 use axum::body::Body;
-use axum::extract::{self, FromRequestParts, Path, Query, State};
+use axum::extract::{self, FromRequestParts, MatchedPath, Path, Query, State};
 use axum::http::{
-    Request, StatusCode,
+    Method, Request, StatusCode,
     header::{self, HeaderMap},
     request::Parts,
 };
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
-use axum::routing::{delete, get, patch, post};
-use axum::{Json, Router};
+use axum::routing::{delete, get, patch, post, put};
+use axum::{Extension, Json, Router};
 use chrono::{NaiveDate, Utc};
 use jsonwebtoken::{
     Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header,
@@ -25,19 +25,31 @@ use jsonwebtoken::{
 use lambda_http::request::RequestContext;
 use serde::de;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
 use tokio::sync::RwLock;
-use tracing::{debug, error};
+use tracing::{debug, error, info};
 use urlencoding::encode;
 
 use crate::calendar::CalendarClient;
 use crate::email::RealEmailSender;
 use crate::error::ValidationError;
+use crate::logic::api_keys::{self, TaskAccess};
+use crate::logic::audit::{self, AuditRecord};
+use crate::logic::captcha::{self, CaptchaVerifier};
+use crate::logic::idempotency::{self, IdempotencyState, StoredResponse};
+use crate::logic::rate_limits::{self, RateLimit, RateLimitDecision, RateLimits};
 use crate::logic::secrets::{SecretKey, SecretProvider};
-use crate::logic::{calendar, contact, events, export, membership, news, tasks};
+use crate::logic::{
+    calendar, campaigns, contact, encryption, events, export, gdpr, membership, news, roles,
+    sessions, tasks, trainers,
+};
 use crate::models::{
-    ContactMessage, Email, EventBooking, EventEmail, EventId, EventType, LifecycleStatus,
-    MembershipApplication, NewsSubscription, NewsTopic, PartialEvent,
+    ApiKeyInput, AuditLogFilter, ContactMessage, Email, EventBooking, EventCancellationInput,
+    EventCloneOptions, EventEmail, EventId, EventTemplate, EventType, LifecycleStatus,
+    MemberEndDate, MemberImport, MembershipApplication, MembershipFeeSchedule, NewsCampaignBounce,
+    NewsCampaignInput, NewsPreferences, NewsSubscription, NewsTopic, NewsTopicDefinition,
+    NewsTopicUpdate, PartialEvent, Role, RoleAssignment, TrainerAttendance, TrainerInput,
 };
 
 pub(crate) struct ResponseError {
@@ -151,18 +163,30 @@ pub(crate) async fn router(
 
     let email_sender = RealEmailSender::new(secrets.clone());
     let calendar_client = CalendarClient::new(secrets.clone());
+    let captcha_verifier = captcha::verifier(pg_pool.clone(), secrets.clone()).await?;
+    if api_keys::import_legacy_key(&pg_pool, &secrets.get(SecretKey::TaskApiKey).await?).await? {
+        info!("Imported TASK_API_KEY as API key with all task scopes");
+    }
+    // IBANs stored before the encryption has been introduced are encrypted during the rollout
+    let encrypted_ibans = encryption::encrypt_plain_ibans(&pg_pool, &*secrets).await?;
+    if encrypted_ibans > 0 {
+        info!("Encrypted {} plain IBANs", encrypted_ibans);
+    }
 
     let state = AppState {
         pg_pool,
         jwks,
         http_client: http_client.clone(),
-        allowed_emails: vec![
-            "fitness@sv-eutingen.de".to_string(),
-            "events@sv-eutingen.de".to_string(),
-        ],
-        allowed_domain: "sv-eutingen.de".to_string(),
-        task_api_key: secrets.get(SecretKey::TaskApiKey).await?,
-        session_secret: secrets.get(SecretKey::SessionSecret).await?,
+        session_keys: SessionKeys::new(
+            secrets.get(SecretKey::SessionSecret).await?,
+            // previous secrets are empty except during a key rotation
+            &secrets.get(SecretKey::PreviousSessionSecrets).await?,
+        ),
+        // the rate limits are optional and fall back to the defaults
+        rate_limits: Arc::new(RateLimits::from_config(
+            &secrets.get(SecretKey::RateLimits).await?,
+        )?),
+        captcha_verifier,
         secrets,
         email_sender,
         calendar_client,
@@ -178,17 +202,91 @@ pub(crate) async fn router(
                         .route("/", get(events))
                         .route("/custom_fields", get(custom_fields))
                         .route("/counter", get(counter))
-                        .route("/booking", post(booking))
-                        .route("/prebooking/{hash}", get(prebooking))
-                        .route("/prebooking/{hash}/iban", post(prebooking_iban)),
+                        .route(
+                            "/booking",
+                            post(booking)
+                                .layer(axum::middleware::from_fn_with_state(
+                                    state.rate_limiter("booking"),
+                                    rate_limit_middleware_fn,
+                                ))
+                                .layer(axum::middleware::from_fn_with_state(
+                                    state.clone(),
+                                    idempotency_middleware_fn,
+                                )),
+                        )
+                        .route(
+                            "/booking/{token}/confirmation",
+                            post(resend_confirmation).layer(axum::middleware::from_fn_with_state(
+                                state.rate_limiter("confirmation"),
+                                rate_limit_middleware_fn,
+                            )),
+                        )
+                        .route(
+                            "/offer/{token}",
+                            post(accept_waiting_list_offer).layer(
+                                axum::middleware::from_fn_with_state(
+                                    state.rate_limiter("prebooking"),
+                                    rate_limit_middleware_fn,
+                                ),
+                            ),
+                        )
+                        .route(
+                            "/prebooking/{hash}",
+                            get(prebooking)
+                                .layer(axum::middleware::from_fn_with_state(
+                                    state.rate_limiter("prebooking"),
+                                    rate_limit_middleware_fn,
+                                ))
+                                .layer(axum::middleware::from_fn_with_state(
+                                    state.clone(),
+                                    idempotency_middleware_fn,
+                                )),
+                        )
+                        .route(
+                            "/prebooking/{hash}/iban",
+                            post(prebooking_iban)
+                                .layer(axum::middleware::from_fn_with_state(
+                                    state.rate_limiter("prebooking"),
+                                    rate_limit_middleware_fn,
+                                ))
+                                .layer(axum::middleware::from_fn_with_state(
+                                    state.clone(),
+                                    idempotency_middleware_fn,
+                                )),
+                        ),
                 )
                 .nest(
                     "/news",
                     Router::new()
-                        .route("/subscribe", post(subscribe))
-                        .route("/unsubscribe", post(unsubscribe)),
+                        .route(
+                            "/subscribe",
+                            post(subscribe).layer(axum::middleware::from_fn_with_state(
+                                state.rate_limiter("news"),
+                                rate_limit_middleware_fn,
+                            )),
+                        )
+                        .route("/confirm/{token}", get(confirm_subscription))
+                        .route("/unsubscribe", post(unsubscribe))
+                        .route("/unsubscribe/{token}", post(unsubscribe_by_token))
+                        .route(
+                            "/preferences/{token}",
+                            get(news_preferences).put(update_news_preferences),
+                        ),
+                )
+                .nest(
+                    "/captcha",
+                    Router::new().route("/challenge", get(captcha_challenge)),
+                )
+                .nest(
+                    "/contact",
+                    Router::new().route(
+                        "/message",
+                        post(message).layer(axum::middleware::from_fn_with_state(
+                            state.rate_limiter("contact"),
+                            rate_limit_middleware_fn,
+                        )),
+                    ),
                 )
-                .nest("/contact", Router::new().route("/message", post(message)))
                 .nest(
                     "/calendar",
                     Router::new()
@@ -197,11 +295,47 @@ pub(crate) async fn router(
                 )
                 .nest(
                     "/membership",
-                    Router::new().route("/application", post(membership_application)),
+                    Router::new().route(
+                        "/application",
+                        post(membership_application).layer(axum::middleware::from_fn_with_state(
+                            state.rate_limiter("membership"),
+                            rate_limit_middleware_fn,
+                        )),
+                    ),
                 )
                 .nest(
                     "/auth",
-                    Router::new().route("/session", post(exchange_session)),
+                    Router::new()
+                        .route("/session", post(exchange_session))
+                        .route("/refresh", post(refresh_session))
+                        .route("/logout", post(logout)),
+                )
+                .nest(
+                    "/trainer",
+                    Router::new()
+                        .nest(
+                            "/events",
+                            Router::new()
+                                .route("/", get(trainer_events))
+                                .route("/{id}/participants", get(trainer_event_participants))
+                                .route(
+                                    "/{id}/participants_list",
+                                    get(trainer_event_participants_list),
+                                )
+                                .route("/{id}/attendance", put(trainer_event_attendance))
+                                .layer(axum::middleware::from_fn_with_state(
+                                    state.clone(),
+                                    trainer_middleware_fn,
+                                )),
+                        )
+                        .route(
+                            "/login",
+                            post(trainer_login).layer(axum::middleware::from_fn_with_state(
+                                state.rate_limiter("trainer_login"),
+                                rate_limit_middleware_fn,
+                            )),
+                        )
+                        .route("/session", post(trainer_session)),
                 )
                 .nest(
                     "/tasks",
@@ -210,7 +344,20 @@ pub(crate) async fn router(
                         .route("/renew_calendar_watch", get(renew_calendar_watch))
                         .route("/send_event_reminders", get(send_event_reminders))
                         .route("/close_finished_events", get(close_finished_events))
-                        .layer(axum::middleware::from_fn_with_state(
+                        .route("/publish_scheduled_events", get(publish_scheduled_events))
+                        .route(
+                            "/expire_waiting_list_offers",
+                            get(expire_waiting_list_offers),
+                        )
+                        .route("/expire_news_subscriptions", get(expire_news_subscriptions))
+                        .route("/send_news_campaigns", get(send_news_campaigns))
+                        .route("/delete_expired_sessions", get(delete_expired_sessions))
+                        .route(
+                            "/anonymize_archived_participants",
+                            get(anonymize_archived_participants),
+                        )
+                        .route("/encrypt_ibans", get(encrypt_ibans))
+                        .route_layer(axum::middleware::from_fn_with_state(
                             state.clone(),
                             api_key_middleware_fn,
                         )),
@@ -223,8 +370,29 @@ pub(crate) async fn router(
                             Router::new()
                                 .route("/", get(admin_events))
                                 .route("/update", post(update))
+                                .route("/templates", get(event_templates).post(save_event_template))
+                                .route("/templates/{id}", delete(delete_event_template))
                                 .route("/{id}", delete(delete_event))
-                                .route("/{id}/sepa_xml", post(export_sepa_xml))
+                                .route("/{id}/clone", post(clone_event))
+                                .route("/{id}/cancel", post(cancel_event))
+                                .route("/{id}/history", get(event_history))
+                                .route("/{id}/versions", get(event_versions))
+                                .route("/{id}/versions/diff", get(event_version_diff))
+                                .route(
+                                    "/{id}/versions/{version}/restore",
+                                    post(restore_event_version),
+                                )
+                                .route("/{id}/duplicates", get(duplicate_bookings))
+                                .route("/{id}/waiting_list_offers", get(waiting_list_offers))
+                                .route(
+                                    "/{id}/sepa_xml",
+                                    post(export_sepa_xml).layer(
+                                        axum::middleware::from_fn_with_state(
+                                            state.clone(),
+                                            idempotency_middleware_fn,
+                                        ),
+                                    ),
+                                )
                                 .nest(
                                     "/booking",
                                     Router::new()
@@ -242,14 +410,74 @@ pub(crate) async fn router(
                                 .nest(
                                     "/payments",
                                     Router::new()
-                                        .route("/verify", post(verify_payments))
-                                        .route("/unpaid/{event_type}", get(unpaid_bookings)),
+                                        .route(
+                                            "/verify",
+                                            post(verify_payments).layer(
+                                                axum::middleware::from_fn_with_state(
+                                                    state.clone(),
+                                                    idempotency_middleware_fn,
+                                                ),
+                                            ),
+                                        )
+                                        .route("/unpaid/{event_type}", get(unpaid_bookings))
+                                        .route("/refunds/{event_type}", get(event_refunds)),
+                                )
+                                .route_layer(axum::middleware::from_fn_with_state(
+                                    EVENT_ROLES,
+                                    role_middleware_fn,
+                                )),
+                        )
+                        .nest(
+                            "/contact",
+                            Router::new().route("/emails", post(emails)).route_layer(
+                                axum::middleware::from_fn_with_state(
+                                    COORDINATOR_ROLES,
+                                    role_middleware_fn,
                                 ),
+                            ),
+                        )
+                        .nest(
+                            "/membership",
+                            Router::new()
+                                .route(
+                                    "/fee_schedule",
+                                    get(membership_fee_schedule).put(save_membership_fee_schedule),
+                                )
+                                .route("/fees/{year}", get(membership_fees))
+                                .route(
+                                    "/fees/{year}/sepa_xml",
+                                    post(export_membership_fees_sepa_xml),
+                                )
+                                .route("/members/import", post(import_members))
+                                .route("/members/{id}", patch(update_member_end_date))
+                                .route_layer(axum::middleware::from_fn_with_state(
+                                    FINANCE_ROLES,
+                                    role_middleware_fn,
+                                )),
                         )
-                        .nest("/contact", Router::new().route("/emails", post(emails)))
                         .nest(
                             "/news",
-                            Router::new().route("/subscribers", get(subscribers)),
+                            Router::new()
+                                .route("/subscribers", get(subscribers))
+                                .route("/topics", get(news_topics).post(create_news_topic))
+                                .route(
+                                    "/topics/{name}",
+                                    patch(update_news_topic).delete(delete_news_topic),
+                                )
+                                .route("/campaigns", get(news_campaigns).post(create_news_campaign))
+                                .route("/campaigns/preview", post(preview_news_campaign))
+                                .route(
+                                    "/campaigns/{id}",
+                                    get(news_campaign)
+                                        .patch(update_news_campaign)
+                                        .delete(delete_news_campaign),
+                                )
+                                .route("/campaigns/{id}/recipients", get(news_campaign_recipients))
+                                .route("/campaigns/{id}/bounces", post(news_campaign_bounces))
+                                .route_layer(axum::middleware::from_fn_with_state(
+                                    COORDINATOR_ROLES,
+                                    role_middleware_fn,
+                                )),
                         )
                         .nest(
                             "/tasks",
@@ -261,8 +489,74 @@ pub(crate) async fn router(
                                 .route(
                                     "/send_participation_confirmation/{event_id}",
                                     get(send_participation_confirmation),
+                                )
+                                .route_layer(axum::middleware::from_fn_with_state(
+                                    TASK_ROLES,
+                                    role_middleware_fn,
+                                )),
+                        )
+                        .nest(
+                            "/trainers",
+                            Router::new()
+                                .route("/", get(admin_trainers).put(update_trainer))
+                                .route("/{id}", delete(delete_trainer))
+                                .route_layer(axum::middleware::from_fn_with_state(
+                                    COORDINATOR_ROLES,
+                                    role_middleware_fn,
+                                )),
+                        )
+                        .nest(
+                            "/audit_log",
+                            Router::new().route("/", get(audit_log)).route_layer(
+                                axum::middleware::from_fn_with_state(
+                                    ADMIN_ROLES,
+                                    role_middleware_fn,
                                 ),
+                            ),
+                        )
+                        .nest(
+                            "/api_keys",
+                            Router::new()
+                                .route("/", get(admin_api_keys).post(create_api_key))
+                                .route("/scopes", get(api_key_scopes))
+                                .route("/{id}", delete(delete_api_key))
+                                .route_layer(axum::middleware::from_fn_with_state(
+                                    ADMIN_ROLES,
+                                    role_middleware_fn,
+                                )),
+                        )
+                        .nest(
+                            "/sessions",
+                            Router::new()
+                                .route("/{email}", delete(revoke_sessions))
+                                .route_layer(axum::middleware::from_fn_with_state(
+                                    ADMIN_ROLES,
+                                    role_middleware_fn,
+                                )),
+                        )
+                        .nest(
+                            "/roles",
+                            Router::new()
+                                .route("/", get(role_assignments).put(update_role_assignment))
+                                .route_layer(axum::middleware::from_fn_with_state(
+                                    ADMIN_ROLES,
+                                    role_middleware_fn,
+                                )),
+                        )
+                        .nest(
+                            "/gdpr",
+                            Router::new()
+                                .route("/export", get(export_personal_data))
+                                .route("/erase", post(erase_personal_data))
+                                .route_layer(axum::middleware::from_fn_with_state(
+                                    ADMIN_ROLES,
+                                    role_middleware_fn,
+                                )),
                         )
+                        .layer(axum::middleware::from_fn_with_state(
+                            state.clone(),
+                            audit_middleware_fn,
+                        ))
                         .layer(axum::middleware::from_fn_with_state(
                             state.clone(),
                             auth_middleware_fn,
@@ -276,24 +570,160 @@ pub(crate) async fn router(
 struct Claims {
     email: String,
     hd: Option<String>, // Hosted domain (Google Workspace domain)
+    #[serde(default)]
+    roles: Vec<Role>,
+    exp: usize,
+    iat: usize,
+}
+
+impl Claims {
+    fn has_any_role(&self, roles: &[Role]) -> bool {
+        self.roles.iter().any(|role| roles.contains(role))
+    }
+
+    fn can_manage(&self, event_type: EventType) -> bool {
+        self.roles.iter().any(|role| role.can_manage(event_type))
+    }
+
+    fn can_view(&self, event_type: EventType) -> bool {
+        self.roles.iter().any(|role| role.can_view(event_type))
+    }
+}
+
+/// Roles with access to the events and bookings. Access to a single event is
+/// further restricted by its event type.
+const EVENT_ROLES: &[Role] = &[
+    Role::Admin,
+    Role::Treasurer,
+    Role::FitnessCoordinator,
+    Role::EventsCoordinator,
+    Role::TrainerReadonly,
+];
+const COORDINATOR_ROLES: &[Role] = &[
+    Role::Admin,
+    Role::FitnessCoordinator,
+    Role::EventsCoordinator,
+];
+const FINANCE_ROLES: &[Role] = &[Role::Admin, Role::Treasurer];
+const TASK_ROLES: &[Role] = &[
+    Role::Admin,
+    Role::Treasurer,
+    Role::FitnessCoordinator,
+    Role::EventsCoordinator,
+];
+const ADMIN_ROLES: &[Role] = &[Role::Admin];
+
+/// Audience of the session JWTs of external trainers. Trainer sessions are
+/// separate from the admin sessions and only grant access to the trainer area.
+const TRAINER_AUDIENCE: &str = "trainer";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct TrainerClaims {
+    sub: i32,
+    aud: String,
     exp: usize,
     iat: usize,
 }
 
+/// Number of minutes an access token of the admin area is valid. Sessions are
+/// continued with the refresh token, which can be revoked server-side.
+const ACCESS_TOKEN_VALIDITY_MINUTES: usize = 15;
+
+/// Key used to sign session JWTs, identified by the `kid` header.
+#[derive(Clone)]
+struct SessionKey {
+    kid: String,
+    secret: String,
+}
+
+impl SessionKey {
+    fn new(secret: String) -> Self {
+        // derive the kid from the secret so it changes with every rotation
+        let kid = hex::encode(&Sha256::digest(secret.as_bytes())[..8]);
+        Self { kid, secret }
+    }
+}
+
+/// The current signing key plus the previous keys which are still accepted,
+/// so the `SessionSecret` can be rotated without ending all sessions.
+#[derive(Clone)]
+struct SessionKeys {
+    current: SessionKey,
+    previous: Vec<SessionKey>,
+}
+
+impl SessionKeys {
+    fn new(current: String, previous: &str) -> Self {
+        Self {
+            current: SessionKey::new(current),
+            previous: previous
+                .split(',')
+                .map(str::trim)
+                .filter(|secret| !secret.is_empty())
+                .map(|secret| SessionKey::new(secret.to_string()))
+                .collect(),
+        }
+    }
+
+    fn encode<T: Serialize>(&self, claims: &T) -> Result<String> {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(self.current.kid.clone());
+        let token = jsonwebtoken::encode(
+            &header,
+            claims,
+            &EncodingKey::from_secret(self.current.secret.as_bytes()),
+        )?;
+        Ok(token)
+    }
+
+    fn decode<T: de::DeserializeOwned>(&self, token: &str, validation: &Validation) -> Result<T> {
+        let kid = decode_header(token)?
+            .kid
+            .ok_or_else(|| anyhow!("Missing kid in session JWT"))?;
+        let key = std::iter::once(&self.current)
+            .chain(self.previous.iter())
+            .find(|key| key.kid == kid)
+            .ok_or_else(|| anyhow!("Unknown session key {}", kid))?;
+        let token_data = decode::<T>(
+            token,
+            &DecodingKey::from_secret(key.secret.as_bytes()),
+            validation,
+        )?;
+        Ok(token_data.claims)
+    }
+}
+
 #[derive(Clone)]
 struct AppState {
     pg_pool: PgPool,
     jwks: Arc<RwLock<JwksCache>>,
     http_client: reqwest::Client,
-    allowed_emails: Vec<String>,
-    allowed_domain: String,
-    task_api_key: String,
-    session_secret: String,
+    session_keys: SessionKeys,
+    rate_limits: Arc<RateLimits>,
+    captcha_verifier: Arc<dyn CaptchaVerifier>,
     secrets: Arc<dyn SecretProvider>,
     email_sender: RealEmailSender,
     calendar_client: CalendarClient,
 }
 
+impl AppState {
+    /// State of the rate limit middleware of the given endpoint group.
+    fn rate_limiter(&self, bucket: &'static str) -> RateLimiter {
+        RateLimiter {
+            pg_pool: self.pg_pool.clone(),
+            bucket,
+            limit: self.rate_limits.get(bucket),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct RateLimiter {
+    pg_pool: PgPool,
+    bucket: &'static str,
+    limit: Option<RateLimit>,
+}
+
 #[derive(Clone)]
 struct JwksCache {
     keys: HashMap<String, Arc<DecodingKey>>,
@@ -317,24 +747,15 @@ impl JwksCache {
 
 async fn auth_middleware_fn(
     State(state): State<AppState>,
-    req: Request<Body>,
+    mut req: Request<Body>,
     next: Next,
 ) -> Response {
-    let auth_header = match req
-        .headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-    {
-        Some(h) => h,
-        None => return (StatusCode::UNAUTHORIZED, "Missing Authorization header").into_response(),
+    let token = match bearer_token(req.headers()) {
+        Ok(t) => t,
+        Err(reason) => return (StatusCode::UNAUTHORIZED, reason).into_response(),
     };
 
-    let token = match auth_header.strip_prefix("Bearer ") {
-        Some(t) => t,
-        None => return (StatusCode::UNAUTHORIZED, "Invalid Authorization header").into_response(),
-    };
-
-    let claims = match verify_session_jwt(token, &state.session_secret) {
+    let claims = match verify_session_jwt(token, &state.session_keys) {
         Ok(c) => c,
         Err(e) => {
             tracing::warn!("Session JWT verification failed: {:?}", e);
@@ -342,24 +763,320 @@ async fn auth_middleware_fn(
         }
     };
 
-    if !authorize(&claims, &state.allowed_emails, &state.allowed_domain) {
+    if !authorize(&claims) {
         return (StatusCode::FORBIDDEN, "Access denied").into_response();
     }
 
+    req.extensions_mut().insert(claims);
+    next.run(req).await
+}
+
+/// Record all calls of the admin area in the audit log, except plain reads.
+/// Handlers attach an [`AuditRecord`] to their response to add the affected
+/// event or booking and the changes made; a read with a record (e.g. an export)
+/// is recorded as well. Reads whose entry can't be written fail, mutations are
+/// only logged as they have been committed already.
+async fn audit_middleware_fn(
+    State(state): State<AppState>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(claims) = req.extensions().get::<Claims>().cloned() else {
+        return next.run(req).await;
+    };
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let action = format!(
+        "{} {}",
+        method,
+        req.extensions()
+            .get::<MatchedPath>()
+            .map(|matched_path| matched_path.as_str())
+            .unwrap_or(&path)
+    );
+
+    let response = next.run(req).await;
+
+    let record = response.extensions().get::<AuditRecord>();
+    if response.status().is_success()
+        && (method != Method::GET || record.is_some())
+        && let Err(e) = audit::record(
+            &state.pg_pool,
+            &claims.email,
+            &action,
+            &path,
+            record.unwrap_or(&AuditRecord::default()),
+        )
+        .await
+    {
+        error!("Writing audit log entry for {} failed: {:?}", action, e);
+        // Reads which can't be audited must not succeed, e.g. exports are not delivered.
+        // Mutations have been committed already and must not be reported as failed.
+        if method == Method::GET {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Writing the audit log failed",
+            )
+                .into_response();
+        }
+    }
+
+    response
+}
+
+async fn trainer_middleware_fn(
+    State(state): State<AppState>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    let token = match bearer_token(req.headers()) {
+        Ok(t) => t,
+        Err(reason) => return (StatusCode::UNAUTHORIZED, reason).into_response(),
+    };
+
+    let claims = match verify_trainer_session_jwt(token, &state.session_keys) {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::warn!("Trainer session JWT verification failed: {:?}", e);
+            return (StatusCode::UNAUTHORIZED, "Invalid session token").into_response();
+        }
+    };
+
+    req.extensions_mut().insert(claims);
     next.run(req).await
 }
 
+/// Extract the bearer token of the Authorization header. Returns the reason on failure.
+fn bearer_token(headers: &HeaderMap) -> Result<&str, &'static str> {
+    headers
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .ok_or("Missing Authorization header")?
+        .strip_prefix("Bearer ")
+        .ok_or("Invalid Authorization header")
+}
+
+async fn role_middleware_fn(
+    State(roles): State<&'static [Role]>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    match req.extensions().get::<Claims>() {
+        Some(claims) if claims.has_any_role(roles) => next.run(req).await,
+        _ => (StatusCode::FORBIDDEN, "Access denied").into_response(),
+    }
+}
+
 async fn api_key_middleware_fn(
     State(state): State<AppState>,
     req: Request<Body>,
     next: Next,
 ) -> Response {
-    let api_key = req.headers().get("x-api-key").and_then(|h| h.to_str().ok());
+    let Some(api_key) = req.headers().get("x-api-key").and_then(|h| h.to_str().ok()) else {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    };
+    // the name of the task is the last segment of the route
+    let task = req
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|matched_path| matched_path.as_str().rsplit('/').next())
+        .unwrap_or_default();
+
+    match api_keys::authorize(&state.pg_pool, api_key, task).await {
+        Ok(TaskAccess::Granted) => next.run(req).await,
+        Ok(TaskAccess::InvalidKey) => (StatusCode::UNAUTHORIZED, "Unauthorized").into_response(),
+        Ok(TaskAccess::MissingScope) => (StatusCode::FORBIDDEN, "Access denied").into_response(),
+        Err(e) => {
+            error!("Failed to check API key: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Maximum size of a request body which is read to find the email address of the client.
+const RATE_LIMIT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+#[derive(Deserialize)]
+struct RateLimitedPayload {
+    email: Option<String>,
+}
+
+async fn rate_limit_middleware_fn(
+    State(limiter): State<RateLimiter>,
+    ClientIp(ip): ClientIp,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(limit) = limiter.limit else {
+        return next.run(req).await;
+    };
+
+    // the email address is part of the JSON payload, so the body has to be buffered
+    let (req, email) = if limit.per_email.is_some() {
+        let (parts, body) = req.into_parts();
+        let Ok(bytes) = axum::body::to_bytes(body, RATE_LIMIT_MAX_BODY_SIZE).await else {
+            return (StatusCode::PAYLOAD_TOO_LARGE, "Payload too large").into_response();
+        };
+        let email = serde_json::from_slice::<RateLimitedPayload>(&bytes)
+            .ok()
+            .and_then(|payload| payload.email);
+        (Request::from_parts(parts, Body::from(bytes)), email)
+    } else {
+        (req, None)
+    };
+
+    match rate_limits::check(
+        &limiter.pg_pool,
+        limiter.bucket,
+        &limit,
+        ip,
+        email.as_deref(),
+    )
+    .await
+    {
+        Ok(RateLimitDecision::Allowed) => next.run(req).await,
+        Ok(RateLimitDecision::Limited { retry_after }) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            "Zu viele Anfragen. Bitte versuche es später noch einmal.",
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to check rate limit of {}: {:?}", limiter.bucket, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Maximum size of a request or response body of a request with an `Idempotency-Key` header.
+const IDEMPOTENCY_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+/// Replay the stored response if the caller has made a request with the same
+/// `Idempotency-Key` header before. Requests without the header are not affected.
+/// The response bodies are stored encrypted.
+async fn idempotency_middleware_fn(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(key) = req.headers().get("idempotency-key") else {
+        return next.run(req).await;
+    };
+    let Some(key) = key
+        .to_str()
+        .ok()
+        .map(str::to_string)
+        .filter(|key| !key.is_empty() && key.len() <= 255)
+    else {
+        return (StatusCode::BAD_REQUEST, "Invalid Idempotency-Key header").into_response();
+    };
+    let scope = format!("{} {}", req.method(), req.uri().path());
+    // the signed in user, or the client of the public endpoints
+    let actor = match req.extensions().get::<Claims>() {
+        Some(claims) => claims.email.clone(),
+        None => ip.map(|ip| ip.to_string()).unwrap_or_default(),
+    };
+
+    let (parts, body) = req.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, IDEMPOTENCY_MAX_BODY_SIZE).await else {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "Payload too large").into_response();
+    };
+    let request_hash = hex::encode(Sha256::digest(&body));
+
+    match idempotency::begin(
+        &state.pg_pool,
+        &*state.secrets,
+        &actor,
+        &scope,
+        &key,
+        &request_hash,
+    )
+    .await
+    {
+        Ok(IdempotencyState::New) => (),
+        Ok(IdempotencyState::InProgress) => {
+            return (
+                StatusCode::CONFLICT,
+                [(header::RETRY_AFTER, "1")],
+                "A request with this Idempotency-Key is in progress",
+            )
+                .into_response();
+        }
+        Ok(IdempotencyState::Mismatch) => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The Idempotency-Key has been used for another request",
+            )
+                .into_response();
+        }
+        Ok(IdempotencyState::Completed(stored)) => return into_replayed_response(stored),
+        Err(e) => {
+            error!("Failed to check idempotency key: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    // server errors and rate limits are not stored, so the request can be retried
+    let status = response.status();
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        if let Err(e) = idempotency::release(&state.pg_pool, &actor, &scope, &key).await {
+            error!("Failed to release idempotency key: {:?}", e);
+        }
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, IDEMPOTENCY_MAX_BODY_SIZE).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to read response of idempotent request: {:?}", e);
+            let _ = idempotency::release(&state.pg_pool, &actor, &scope, &key).await;
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let stored = StoredResponse {
+        status_code: parts.status.as_u16(),
+        headers: parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (name.to_string(), value.to_string()))
+            })
+            .collect(),
+        body: body.to_vec(),
+    };
+    if let Err(e) = idempotency::complete(
+        &state.pg_pool,
+        &*state.secrets,
+        &actor,
+        &scope,
+        &key,
+        &stored,
+    )
+    .await
+    {
+        error!("Failed to store response of idempotent request: {:?}", e);
+    }
 
-    match api_key {
-        Some(key) if key == state.task_api_key => next.run(req).await,
-        _ => (StatusCode::UNAUTHORIZED, "Unauthorized").into_response(),
+    Response::from_parts(parts, Body::from(body))
+}
+
+fn into_replayed_response(stored: StoredResponse) -> Response {
+    let mut response = Response::builder()
+        .status(stored.status_code)
+        .header("Idempotent-Replayed", "true");
+    for (name, value) in stored.headers {
+        response = response.header(name, value);
     }
+    response
+        .body(Body::from(stored.body))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
 }
 
 async fn fetch_jwks(
@@ -409,7 +1126,7 @@ async fn exchange_session(
     State(state): State<AppState>,
     Json(req): Json<SessionExchangeRequest>,
 ) -> Response {
-    let claims = match verify_google_token(&req.google_token, &state).await {
+    let mut claims = match verify_google_token(&req.google_token, &state).await {
         Ok(c) => c,
         Err(e) => {
             tracing::warn!("Google token verification failed: {:?}", e);
@@ -417,12 +1134,44 @@ async fn exchange_session(
         }
     };
 
-    if !authorize(&claims, &state.allowed_emails, &state.allowed_domain) {
+    claims.roles = match roles::get_roles(&state.pg_pool, &claims.email).await {
+        Ok(roles) => roles,
+        Err(e) => {
+            tracing::error!("Failed to load roles: {:?}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to issue session").into_response();
+        }
+    };
+
+    if !authorize(&claims) {
         return (StatusCode::FORBIDDEN, "Access denied").into_response();
     }
 
-    match mint_session_jwt(&claims.email, claims.hd.as_deref(), &state.session_secret) {
-        Ok(token) => Json(SessionExchangeResponse { token }).into_response(),
+    let refresh_token =
+        match sessions::create_session(&state.pg_pool, &claims.email, claims.hd.as_deref()).await {
+            Ok(refresh_token) => refresh_token,
+            Err(e) => {
+                tracing::error!("Failed to create session: {:?}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to issue session")
+                    .into_response();
+            }
+        };
+
+    session_response(&state, &claims, refresh_token)
+}
+
+fn session_response(state: &AppState, claims: &Claims, refresh_token: String) -> Response {
+    match mint_session_jwt(
+        &claims.email,
+        claims.hd.as_deref(),
+        &claims.roles,
+        &state.session_keys,
+    ) {
+        Ok(token) => Json(SessionResponse {
+            token,
+            refresh_token,
+            expires_in: ACCESS_TOKEN_VALIDITY_MINUTES * 60,
+        })
+        .into_response(),
         Err(e) => {
             tracing::error!("Failed to mint session JWT: {:?}", e);
             (StatusCode::INTERNAL_SERVER_ERROR, "Failed to issue session").into_response()
@@ -430,28 +1179,85 @@ async fn exchange_session(
     }
 }
 
-async fn verify_google_token(token: &str, state: &AppState) -> Result<Claims> {
-    let header = decode_header(token)?;
-    let kid = header.kid.ok_or_else(|| anyhow!("Missing kid in JWT"))?;
+#[derive(Deserialize)]
+struct RefreshSessionRequest {
+    refresh_token: String,
+}
 
-    let decoding_key = {
-        let needs_refresh = {
-            let jwks_cache = state.jwks.read().await;
-            jwks_cache.is_expired() || !jwks_cache.keys.contains_key(&kid)
-        };
-        if needs_refresh {
-            tracing::info!("Refreshing JWKS cache (expired or missing kid: {})", kid);
-            let new_keys = fetch_jwks(
-                &state.http_client,
-                "https://www.googleapis.com/oauth2/v3/certs",
-            )
-            .await?;
-            let mut jwks_cache = state.jwks.write().await;
-            jwks_cache.keys = new_keys;
-            jwks_cache.last_updated = std::time::Instant::now();
-        }
-        let jwks_cache = state.jwks.read().await;
-        jwks_cache
+#[derive(Serialize)]
+struct SessionResponse {
+    token: String,
+    refresh_token: String,
+    /// Validity of the access token in seconds
+    expires_in: usize,
+}
+
+/// Issue a new access token and rotate the refresh token. The roles are
+/// loaded again, so changes take effect with the next refresh.
+async fn refresh_session(
+    State(state): State<AppState>,
+    Json(req): Json<RefreshSessionRequest>,
+) -> Response {
+    let (user, refresh_token) = match sessions::refresh(&state.pg_pool, &req.refresh_token).await {
+        Ok(Some(session)) => session,
+        Ok(None) => return (StatusCode::UNAUTHORIZED, "Invalid refresh token").into_response(),
+        Err(e) => {
+            tracing::error!("Failed to refresh session: {:?}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to issue session").into_response();
+        }
+    };
+
+    let roles = match roles::get_roles(&state.pg_pool, &user.email).await {
+        Ok(roles) => roles,
+        Err(e) => {
+            tracing::error!("Failed to load roles: {:?}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to issue session").into_response();
+        }
+    };
+    let claims = Claims {
+        email: user.email,
+        hd: user.hd,
+        roles,
+        exp: 0,
+        iat: 0,
+    };
+    if !authorize(&claims) {
+        return (StatusCode::FORBIDDEN, "Access denied").into_response();
+    }
+
+    session_response(&state, &claims, refresh_token)
+}
+
+async fn logout(
+    State(state): State<AppState>,
+    Json(req): Json<RefreshSessionRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    sessions::logout(&state.pg_pool, &req.refresh_token).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn verify_google_token(token: &str, state: &AppState) -> Result<Claims> {
+    let header = decode_header(token)?;
+    let kid = header.kid.ok_or_else(|| anyhow!("Missing kid in JWT"))?;
+
+    let decoding_key = {
+        let needs_refresh = {
+            let jwks_cache = state.jwks.read().await;
+            jwks_cache.is_expired() || !jwks_cache.keys.contains_key(&kid)
+        };
+        if needs_refresh {
+            tracing::info!("Refreshing JWKS cache (expired or missing kid: {})", kid);
+            let new_keys = fetch_jwks(
+                &state.http_client,
+                "https://www.googleapis.com/oauth2/v3/certs",
+            )
+            .await?;
+            let mut jwks_cache = state.jwks.write().await;
+            jwks_cache.keys = new_keys;
+            jwks_cache.last_updated = std::time::Instant::now();
+        }
+        let jwks_cache = state.jwks.read().await;
+        jwks_cache
             .keys
             .get(&kid)
             .cloned()
@@ -464,36 +1270,102 @@ async fn verify_google_token(token: &str, state: &AppState) -> Result<Claims> {
     Ok(token_data.claims)
 }
 
-fn mint_session_jwt(email: &str, hd: Option<&str>, secret: &str) -> Result<String> {
+fn mint_session_jwt(
+    email: &str,
+    hd: Option<&str>,
+    roles: &[Role],
+    keys: &SessionKeys,
+) -> Result<String> {
     let now = Utc::now().timestamp() as usize;
     let claims = Claims {
         email: email.to_string(),
         hd: hd.map(|s| s.to_string()),
-        exp: now + 30 * 24 * 60 * 60,
+        roles: roles.to_vec(),
+        exp: now + ACCESS_TOKEN_VALIDITY_MINUTES * 60,
         iat: now,
     };
-    let token = jsonwebtoken::encode(
-        &Header::new(Algorithm::HS256),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )?;
-    Ok(token)
+    keys.encode(&claims)
 }
 
-fn verify_session_jwt(token: &str, secret: &str) -> Result<Claims> {
+fn verify_session_jwt(token: &str, keys: &SessionKeys) -> Result<Claims> {
     let mut validation = Validation::new(Algorithm::HS256);
     validation.validate_aud = false;
     validation.validate_exp = true;
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &validation,
-    )?;
-    Ok(token_data.claims)
+    keys.decode(token, &validation)
+}
+
+fn mint_trainer_session_jwt(trainer_id: i32, keys: &SessionKeys) -> Result<String> {
+    let now = Utc::now().timestamp() as usize;
+    let claims = TrainerClaims {
+        sub: trainer_id,
+        aud: TRAINER_AUDIENCE.to_string(),
+        exp: now + 7 * 24 * 60 * 60,
+        iat: now,
+    };
+    keys.encode(&claims)
+}
+
+fn verify_trainer_session_jwt(token: &str, keys: &SessionKeys) -> Result<TrainerClaims> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_audience(&[TRAINER_AUDIENCE]);
+    validation.validate_exp = true;
+    keys.decode(token, &validation)
+}
+
+/// Only users with at least one role assigned may access the admin area.
+fn authorize(claims: &Claims) -> bool {
+    !claims.roles.is_empty()
+}
+
+fn forbidden() -> ResponseError {
+    ResponseError {
+        err: anyhow!("Access denied"),
+        response: Some((StatusCode::FORBIDDEN, "Access denied".to_string())),
+    }
 }
 
-fn authorize(claims: &Claims, allowed_emails: &[String], allowed_domain: &str) -> bool {
-    allowed_emails.contains(&claims.email) || claims.hd.as_deref() == Some(allowed_domain)
+fn ensure_access(allowed: bool) -> Result<(), ResponseError> {
+    if allowed { Ok(()) } else { Err(forbidden()) }
+}
+
+fn not_found(message: &str) -> ResponseError {
+    ResponseError {
+        err: anyhow!(message.to_string()),
+        response: Some((StatusCode::NOT_FOUND, message.to_string())),
+    }
+}
+
+async fn event_type_of_event(
+    state: &AppState,
+    event_id: &EventId,
+) -> Result<EventType, ResponseError> {
+    events::get_event_type(&state.pg_pool, event_id)
+        .await?
+        .ok_or_else(|| not_found("Event not found"))
+}
+
+async fn event_type_of_booking(
+    state: &AppState,
+    booking_id: i32,
+) -> Result<EventType, ResponseError> {
+    events::get_event_type_of_booking(&state.pg_pool, booking_id)
+        .await?
+        .ok_or_else(|| not_found("Booking not found"))
+}
+
+/// Whether the user may change the template with the given id.
+async fn can_manage_event_template(
+    state: &AppState,
+    claims: &Claims,
+    id: i32,
+) -> Result<bool, ResponseError> {
+    let template = events::get_event_template(&state.pg_pool, id)
+        .await?
+        .ok_or_else(|| not_found("Event template not found"))?;
+    Ok(template
+        .event
+        .event_type
+        .is_some_and(|event_type| claims.can_manage(event_type)))
 }
 
 // events
@@ -550,12 +1422,36 @@ pub(crate) struct SubscribersQueryParams {
     topic: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub(crate) struct PersonalDataQueryParams {
+    email: String,
+    /// `json` (default) or `zip`
+    format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PersonalDataErasureInput {
+    email: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct VerifyPaymentInput {
     csv: String,
     start_date: Option<NaiveDate>,
 }
 
+#[derive(Debug, Deserialize)]
+pub(crate) struct UpdateEventQueryParams {
+    /// Inform participants about material changes (default: true)
+    notify: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct EventVersionDiffQueryParams {
+    from: i32,
+    to: i32,
+}
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct UpdateEventBookingQueryParams {
     update_payment: Option<bool>,
@@ -570,6 +1466,7 @@ async fn events(
         query.beta.take(),
         query.status.take(),
         Some(false), // Public endpoint never returns subscribers
+        false,       // canceled events are only listed for the admins
     )
     .await?;
     Ok(Json(events))
@@ -577,15 +1474,18 @@ async fn events(
 
 async fn admin_events(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     query: Query<EventsQueryParams>,
 ) -> Result<impl IntoResponse, ResponseError> {
-    let events = events::get_events(
+    let mut events = events::get_events(
         &state.pg_pool,
         query.beta,
         query.status.clone(),
         query.subscribers,
+        true,
     )
     .await?;
+    events.retain(|event| claims.can_view(event.event_type));
     Ok(Json(events))
 }
 
@@ -607,8 +1507,38 @@ async fn booking(
     ClientIp(ip): ClientIp,
     extract::Json(booking): extract::Json<EventBooking>,
 ) -> Result<impl IntoResponse, ResponseError> {
-    validate_captcha(&booking.token, ip, &*state.secrets).await?;
-    let response = events::booking(&state.pg_pool, booking, &state.email_sender).await;
+    validate_captcha(&booking.token, ip, &*state.captcha_verifier).await?;
+    let response = events::booking(
+        &state.pg_pool,
+        booking,
+        &*state.secrets,
+        &state.email_sender,
+    )
+    .await;
+    Ok(Json(response))
+}
+
+async fn resend_confirmation(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let response =
+        events::resend_confirmation(&state.pg_pool, &token, &*state.secrets, &state.email_sender)
+            .await;
+    Ok(Json(response))
+}
+
+async fn accept_waiting_list_offer(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let response = events::accept_waiting_list_offer(
+        &state.pg_pool,
+        &token,
+        &*state.secrets,
+        &state.email_sender,
+    )
+    .await;
     Ok(Json(response))
 }
 
@@ -616,7 +1546,8 @@ async fn prebooking(
     State(state): State<AppState>,
     Path(hash): Path<String>,
 ) -> Result<impl IntoResponse, ResponseError> {
-    let response = events::prebooking(&state.pg_pool, hash, &state.email_sender).await;
+    let response =
+        events::prebooking(&state.pg_pool, hash, &*state.secrets, &state.email_sender).await;
     Ok(Json(response))
 }
 
@@ -630,50 +1561,263 @@ async fn prebooking_iban(
     Path(hash): Path<String>,
     Json(payload): Json<IbanPayload>,
 ) -> Result<impl IntoResponse, ResponseError> {
-    let response =
-        events::prebook_with_iban(&state.pg_pool, &hash, payload.iban, &state.email_sender).await?;
+    let response = events::prebook_with_iban(
+        &state.pg_pool,
+        &hash,
+        payload.iban,
+        &*state.secrets,
+        &state.email_sender,
+    )
+    .await?;
     Ok(Json(response))
 }
 
 async fn update(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    query: Query<UpdateEventQueryParams>,
     extract::Json(partial_event): extract::Json<PartialEvent>,
 ) -> Result<impl IntoResponse, ResponseError> {
-    let event = events::update(&state.pg_pool, partial_event, &state.email_sender).await?;
-    Ok(Json(event))
+    if let Some(event_id) = &partial_event.id {
+        ensure_access(claims.can_manage(event_type_of_event(&state, event_id).await?))?;
+    }
+    if let Some(event_type) = partial_event.event_type {
+        ensure_access(claims.can_manage(event_type))?;
+    }
+    let before = match &partial_event.id {
+        Some(event_id) => audit::event_snapshot(&state.pg_pool, event_id).await?,
+        None => None,
+    };
+    let event = events::update(
+        &state.pg_pool,
+        partial_event,
+        query.notify.unwrap_or(true),
+        &state.email_sender,
+    )
+    .await?;
+    let after = audit::event_snapshot(&state.pg_pool, &event.id).await?;
+    Ok((
+        Extension(AuditRecord::event(event.id).with_changes(before, after)),
+        Json(event),
+    ))
 }
 
 async fn delete_event(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(path): Path<EventId>,
 ) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.can_manage(event_type_of_event(&state, &path).await?))?;
+    let before = audit::event_snapshot(&state.pg_pool, &path).await?;
     events::delete(&state.pg_pool, path).await?;
+    Ok((
+        Extension(AuditRecord::event(path).with_changes(before, None)),
+        StatusCode::OK,
+    ))
+}
+
+async fn clone_event(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(event_id): Path<EventId>,
+    extract::Json(options): extract::Json<EventCloneOptions>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.can_manage(event_type_of_event(&state, &event_id).await?))?;
+    let event = events::clone_event(&state.pg_pool, &event_id, options).await?;
+    let after = audit::event_snapshot(&state.pg_pool, &event.id).await?;
+    Ok((
+        Extension(AuditRecord::event(event.id).with_changes(None, after)),
+        Json(event),
+    ))
+}
+
+async fn cancel_event(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(event_id): Path<EventId>,
+    extract::Json(input): extract::Json<EventCancellationInput>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.can_manage(event_type_of_event(&state, &event_id).await?))?;
+    let before = audit::event_snapshot(&state.pg_pool, &event_id).await?;
+    let cancellation = events::cancel_event(
+        &state.pg_pool,
+        &event_id,
+        &input.reason,
+        &state.email_sender,
+    )
+    .await?
+    .ok_or_else(|| not_found("Event not found"))?;
+    let after = audit::event_snapshot(&state.pg_pool, &event_id).await?;
+    Ok((
+        Extension(AuditRecord::event(event_id).with_changes(before, after)),
+        Json(cancellation),
+    ))
+}
+
+async fn event_templates(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let templates: Vec<EventTemplate> = events::get_event_templates(&state.pg_pool)
+        .await?
+        .into_iter()
+        .filter(|template| {
+            template
+                .event
+                .event_type
+                .is_some_and(|event_type| claims.can_view(event_type))
+        })
+        .collect();
+    Ok(Json(templates))
+}
+
+async fn save_event_template(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    extract::Json(template): extract::Json<EventTemplate>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(
+        template
+            .event
+            .event_type
+            .is_some_and(|event_type| claims.can_manage(event_type)),
+    )?;
+    if let Some(id) = template.id {
+        ensure_access(can_manage_event_template(&state, &claims, id).await?)?;
+    }
+    Ok(Json(
+        events::save_event_template(&state.pg_pool, template).await?,
+    ))
+}
+
+async fn delete_event_template(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(can_manage_event_template(&state, &claims, id).await?)?;
+    events::delete_event_template(&state.pg_pool, id).await?;
     Ok(StatusCode::OK)
 }
 
+async fn event_history(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(event_id): Path<EventId>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.can_view(event_type_of_event(&state, &event_id).await?))?;
+    Ok(Json(
+        audit::get_event_history(&state.pg_pool, event_id).await?,
+    ))
+}
+
+async fn event_versions(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(event_id): Path<EventId>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.can_view(event_type_of_event(&state, &event_id).await?))?;
+    Ok(Json(
+        events::get_event_versions(&state.pg_pool, &event_id).await?,
+    ))
+}
+
+async fn event_version_diff(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(event_id): Path<EventId>,
+    query: Query<EventVersionDiffQueryParams>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.can_view(event_type_of_event(&state, &event_id).await?))?;
+    let diff = events::diff_event_versions(&state.pg_pool, &event_id, query.from, query.to)
+        .await?
+        .ok_or_else(|| not_found("Event version not found"))?;
+    Ok(Json(diff))
+}
+
+async fn restore_event_version(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path((event_id, version)): Path<(EventId, i32)>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.can_manage(event_type_of_event(&state, &event_id).await?))?;
+    let before = audit::event_snapshot(&state.pg_pool, &event_id).await?;
+    let event =
+        events::restore_event_version(&state.pg_pool, &event_id, version, &state.email_sender)
+            .await?
+            .ok_or_else(|| not_found("Event version not found"))?;
+    let after = audit::event_snapshot(&state.pg_pool, &event.id).await?;
+    Ok((
+        Extension(AuditRecord::event(event.id).with_changes(before, after)),
+        Json(event),
+    ))
+}
+
+async fn duplicate_bookings(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(event_id): Path<EventId>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.can_view(event_type_of_event(&state, &event_id).await?))?;
+    Ok(Json(
+        events::get_duplicate_bookings(&state.pg_pool, &event_id).await?,
+    ))
+}
+
+async fn waiting_list_offers(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(event_id): Path<EventId>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.can_view(event_type_of_event(&state, &event_id).await?))?;
+    Ok(Json(
+        events::get_waiting_list_offers(&state.pg_pool, &event_id).await?,
+    ))
+}
+
 async fn verify_payments(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     extract::Json(input): extract::Json<VerifyPaymentInput>,
 ) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.has_any_role(FINANCE_ROLES))?;
     Ok(Json(
-        events::verify_payments(&state.pg_pool, input.csv, input.start_date).await?,
+        events::verify_payments(&state.pg_pool, input.csv, input.start_date, &*state.secrets)
+            .await?,
     ))
 }
 
 async fn unpaid_bookings(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(event_type): Path<EventType>,
 ) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.has_any_role(FINANCE_ROLES) || claims.can_manage(event_type))?;
     Ok(Json(
         events::get_unpaid_bookings(&state.pg_pool, event_type).await?,
     ))
 }
 
+async fn event_refunds(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(event_type): Path<EventType>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.has_any_role(FINANCE_ROLES) || claims.can_manage(event_type))?;
+    Ok(Json(
+        events::get_event_refunds(&state.pg_pool, event_type).await?,
+    ))
+}
+
 async fn update_event_booking(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(booking_id): Path<i32>,
     query: Query<UpdateEventBookingQueryParams>,
 ) -> Result<impl IntoResponse, ResponseError> {
+    let event_type = event_type_of_booking(&state, booking_id).await?;
+    ensure_access(claims.has_any_role(FINANCE_ROLES) || claims.can_manage(event_type))?;
+    let before = audit::booking_snapshot(&state.pg_pool, booking_id).await?;
     if let Some(update_payment) = query.update_payment {
         match events::update_payment(&state.pg_pool, booking_id, update_payment).await {
             Ok(()) => {}
@@ -693,68 +1837,106 @@ async fn update_event_booking(
             }
         }
     }
-    Ok(StatusCode::OK)
+    let after = audit::booking_snapshot(&state.pg_pool, booking_id).await?;
+    Ok((
+        Extension(AuditRecord::booking(booking_id).with_changes(before, after)),
+        StatusCode::OK,
+    ))
 }
 
 async fn cancel_event_booking(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(booking_id): Path<i32>,
 ) -> Result<impl IntoResponse, ResponseError> {
-    events::cancel_booking(&state.pg_pool, booking_id, &state.email_sender).await?;
-    Ok(StatusCode::OK)
+    ensure_access(claims.can_manage(event_type_of_booking(&state, booking_id).await?))?;
+    let before = audit::booking_snapshot(&state.pg_pool, booking_id).await?;
+    events::cancel_booking(
+        &state.pg_pool,
+        booking_id,
+        &*state.secrets,
+        &state.email_sender,
+    )
+    .await?;
+    let after = audit::booking_snapshot(&state.pg_pool, booking_id).await?;
+    Ok((
+        Extension(AuditRecord::booking(booking_id).with_changes(before, after)),
+        StatusCode::OK,
+    ))
 }
 
 async fn export_event_bookings(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(event_id): Path<EventId>,
 ) -> Result<impl IntoResponse, ResponseError> {
+    let event_type = event_type_of_event(&state, &event_id).await?;
+    ensure_access(claims.has_any_role(FINANCE_ROLES) || claims.can_manage(event_type))?;
     let (filename, bytes) = export::event_bookings(&state.pg_pool, event_id).await?;
-    Ok(into_file_response(filename, bytes))
+    Ok((
+        Extension(AuditRecord::event(event_id)),
+        into_file_response(filename, bytes),
+    ))
 }
 
 async fn export_event_participants_list(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(event_id): Path<EventId>,
 ) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.can_view(event_type_of_event(&state, &event_id).await?))?;
     let (filename, bytes) = export::event_participants_list(&state.pg_pool, event_id).await?;
-    Ok(into_file_response(filename, bytes))
+    Ok((
+        Extension(AuditRecord::event(event_id)),
+        into_file_response(filename, bytes),
+    ))
 }
 
 async fn export_sepa_xml(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(event_id): Path<EventId>,
 ) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.has_any_role(FINANCE_ROLES))?;
+    let (filename, xml) = events::export_sepa_xml(&state.pg_pool, event_id, &*state.secrets)
+        .await
+        .map_err(into_sepa_export_response_error)?;
+
+    Ok((
+        Extension(AuditRecord::event(event_id)),
+        into_xml_file_response(filename, xml),
+    ))
+}
+
+fn into_sepa_export_response_error(e: anyhow::Error) -> ResponseError {
     use crate::models::SepaExportError;
 
-    let (filename, xml) =
-        match events::export_sepa_xml(&state.pg_pool, event_id, &*state.secrets).await {
-            Ok(result) => result,
-            Err(e) => {
-                if let Some(sepa_err) = e.downcast_ref::<SepaExportError>() {
-                    let status = match sepa_err {
-                        SepaExportError::NotASepaEvent => StatusCode::BAD_REQUEST,
-                        SepaExportError::NoBookingsAvailable => StatusCode::CONFLICT,
-                        SepaExportError::BicLookupFailed(_) => StatusCode::UNPROCESSABLE_ENTITY,
-                        SepaExportError::ConfigIncomplete => StatusCode::INTERNAL_SERVER_ERROR,
-                    };
-                    let message = e.to_string();
-                    return Err(ResponseError {
-                        err: e,
-                        response: Some((status, message)),
-                    });
-                }
-                return Err(e.into());
-            }
+    if let Some(sepa_err) = e.downcast_ref::<SepaExportError>() {
+        let status = match sepa_err {
+            SepaExportError::NotASepaEvent => StatusCode::BAD_REQUEST,
+            SepaExportError::NoBookingsAvailable => StatusCode::CONFLICT,
+            SepaExportError::NoMembershipFeesDue => StatusCode::CONFLICT,
+            SepaExportError::BicLookupFailed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            SepaExportError::ConfigIncomplete => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let message = e.to_string();
+        return ResponseError {
+            err: e,
+            response: Some((status, message)),
         };
+    }
+    e.into()
+}
 
-    Ok(Response::builder()
+fn into_xml_file_response(filename: String, xml: String) -> Response {
+    Response::builder()
         .header("Content-Type", "application/xml")
         .header(
             "Content-Disposition",
             format!("attachment; filename=\"{}\"", filename),
         )
         .body(Body::from(xml))
-        .unwrap())
+        .unwrap()
 }
 
 // news
@@ -764,8 +1946,31 @@ async fn subscribe(
     ClientIp(ip): ClientIp,
     extract::Json(subscription): extract::Json<NewsSubscription>,
 ) -> Result<impl IntoResponse, ResponseError> {
-    validate_captcha(&subscription.token, ip, &*state.secrets).await?;
-    news::subscribe(&state.pg_pool, subscription, &state.email_sender).await?;
+    validate_captcha(&subscription.token, ip, &*state.captcha_verifier).await?;
+    news::subscribe(
+        &state.pg_pool,
+        subscription,
+        ip,
+        &*state.secrets,
+        &state.email_sender,
+    )
+    .await?;
+    Ok(StatusCode::OK)
+}
+
+async fn confirm_subscription(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, ResponseError> {
+    news::confirm(
+        &state.pg_pool,
+        &token,
+        ip,
+        &*state.secrets,
+        &state.email_sender,
+    )
+    .await?;
     Ok(StatusCode::OK)
 }
 
@@ -774,30 +1979,357 @@ async fn unsubscribe(
     ClientIp(ip): ClientIp,
     extract::Json(subscription): extract::Json<NewsSubscription>,
 ) -> Result<impl IntoResponse, ResponseError> {
-    validate_captcha(&subscription.token, ip, &*state.secrets).await?;
+    validate_captcha(&subscription.token, ip, &*state.captcha_verifier).await?;
     news::unsubscribe(&state.pg_pool, subscription).await?;
     Ok(StatusCode::OK)
 }
 
-async fn subscribers(
+async fn unsubscribe_by_token(
     State(state): State<AppState>,
-    query: Query<SubscribersQueryParams>,
+    Path(token): Path<String>,
 ) -> Result<impl IntoResponse, ResponseError> {
-    let topics: HashSet<NewsTopic> = query
-        .topic
-        .split(',')
-        .filter_map(|s| NewsTopic::from_str(s).ok())
-        .collect();
-    let subscriptions = news::get_subscriptions(&state.pg_pool).await?;
-    let emails: HashSet<_> = subscriptions
-        .into_iter()
-        .filter(|(topic, _)| topics.contains(topic))
-        .flat_map(|(_, emails)| emails)
-        .collect();
-    Ok(Json(emails))
+    news::unsubscribe_by_token(&state.pg_pool, &token, &*state.secrets).await?;
+    Ok(StatusCode::OK)
 }
 
-// calendar
+async fn news_preferences(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(
+        news::get_preferences(&state.pg_pool, &token, &*state.secrets).await?,
+    ))
+}
+
+async fn update_news_preferences(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(token): Path<String>,
+    extract::Json(preferences): extract::Json<NewsPreferences>,
+) -> Result<impl IntoResponse, ResponseError> {
+    news::update_preferences(&state.pg_pool, &token, preferences, ip, &*state.secrets).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn subscribers(
+    State(state): State<AppState>,
+    query: Query<SubscribersQueryParams>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let topics: HashSet<NewsTopic> = query
+        .topic
+        .split(',')
+        .filter_map(|s| NewsTopic::from_str(s).ok())
+        .collect();
+    let subscriptions = news::get_subscriptions(&state.pg_pool).await?;
+    let emails: HashSet<_> = subscriptions
+        .into_iter()
+        .filter(|(topic, _)| topics.contains(topic))
+        .flat_map(|(_, emails)| emails)
+        .collect();
+    Ok(Json(emails))
+}
+
+async fn news_topics(State(state): State<AppState>) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(news::get_topics(&state.pg_pool).await?))
+}
+
+async fn create_news_topic(
+    State(state): State<AppState>,
+    extract::Json(topic): extract::Json<NewsTopicDefinition>,
+) -> Result<impl IntoResponse, ResponseError> {
+    news::create_topic(&state.pg_pool, topic).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn update_news_topic(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    extract::Json(update): extract::Json<NewsTopicUpdate>,
+) -> Result<impl IntoResponse, ResponseError> {
+    news::update_topic(&state.pg_pool, NewsTopic::new(name), update).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn delete_news_topic(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, ResponseError> {
+    news::delete_topic(&state.pg_pool, NewsTopic::new(name)).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn news_campaigns(State(state): State<AppState>) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(campaigns::get_campaigns(&state.pg_pool).await?))
+}
+
+async fn news_campaign(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ResponseError> {
+    match campaigns::get_campaign(&state.pg_pool, id).await? {
+        Some(campaign) => Ok(Json(campaign).into_response()),
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+async fn create_news_campaign(
+    State(state): State<AppState>,
+    extract::Json(input): extract::Json<NewsCampaignInput>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(
+        campaigns::create_campaign(&state.pg_pool, input).await?,
+    ))
+}
+
+async fn preview_news_campaign(
+    State(state): State<AppState>,
+    extract::Json(input): extract::Json<NewsCampaignInput>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(campaigns::preview(&state.pg_pool, &input).await?))
+}
+
+async fn update_news_campaign(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    extract::Json(input): extract::Json<NewsCampaignInput>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(
+        campaigns::update_campaign(&state.pg_pool, id, input).await?,
+    ))
+}
+
+async fn delete_news_campaign(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ResponseError> {
+    campaigns::delete_campaign(&state.pg_pool, id).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn news_campaign_recipients(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(campaigns::get_recipients(&state.pg_pool, id).await?))
+}
+
+async fn news_campaign_bounces(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    extract::Json(bounces): extract::Json<Vec<NewsCampaignBounce>>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(
+        campaigns::record_bounces(&state.pg_pool, id, bounces).await?,
+    ))
+}
+
+// roles
+
+// audit log
+
+async fn audit_log(
+    State(state): State<AppState>,
+    Query(filter): Query<AuditLogFilter>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(audit::get_entries(&state.pg_pool, &filter).await?))
+}
+
+async fn admin_api_keys(State(state): State<AppState>) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(api_keys::get_api_keys(&state.pg_pool).await?))
+}
+
+async fn api_key_scopes() -> impl IntoResponse {
+    Json(api_keys::TASK_SCOPES)
+}
+
+async fn create_api_key(
+    State(state): State<AppState>,
+    Json(input): Json<ApiKeyInput>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(api_keys::create_api_key(&state.pg_pool, input).await?))
+}
+
+async fn delete_api_key(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ResponseError> {
+    if !api_keys::delete_api_key(&state.pg_pool, id).await? {
+        return Err(not_found("API key not found"));
+    }
+    Ok(StatusCode::OK)
+}
+
+async fn revoke_sessions(
+    State(state): State<AppState>,
+    Path(email): Path<String>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let result = sessions::revoke_all(&state.pg_pool, &email).await?;
+    Ok((Extension(AuditRecord::subject(email)), Json(result)))
+}
+
+// gdpr
+
+async fn export_personal_data(
+    State(state): State<AppState>,
+    Query(query): Query<PersonalDataQueryParams>,
+) -> Result<Response, ResponseError> {
+    let data = gdpr::export(&state.pg_pool, &query.email).await?;
+    // exports of personal data are audited like any change
+    let audit = Extension(AuditRecord::subject(data.email.clone()));
+    match query.format.as_deref() {
+        None | Some("json") => Ok((audit, Json(data)).into_response()),
+        Some("zip") => {
+            let (filename, bytes) = gdpr::export_as_zip(&data)?;
+            Ok((
+                audit,
+                [(header::CONTENT_TYPE, "application/zip")],
+                into_file_response(filename, bytes),
+            )
+                .into_response())
+        }
+        Some(format) => Err(anyhow::Error::from(ValidationError::new(format!(
+            "Unknown export format: {}",
+            format
+        )))
+        .into()),
+    }
+}
+
+async fn erase_personal_data(
+    State(state): State<AppState>,
+    Json(input): Json<PersonalDataErasureInput>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let result = gdpr::erase(&state.pg_pool, &input.email).await?;
+    // the erased email address must not be recorded again
+    Ok((
+        Extension(AuditRecord::subject(format!(
+            "erasure:{}",
+            result.erasure_id
+        ))),
+        Json(result),
+    ))
+}
+
+async fn role_assignments(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(roles::get_role_assignments(&state.pg_pool).await?))
+}
+
+async fn update_role_assignment(
+    State(state): State<AppState>,
+    extract::Json(assignment): extract::Json<RoleAssignment>,
+) -> Result<impl IntoResponse, ResponseError> {
+    roles::set_roles(&state.pg_pool, assignment).await?;
+    Ok(StatusCode::OK)
+}
+
+// calendar
+
+// trainers
+
+#[derive(Deserialize)]
+struct TrainerLoginRequest {
+    email: String,
+}
+
+#[derive(Deserialize)]
+struct TrainerSessionRequest {
+    token: String,
+}
+
+async fn trainer_login(
+    State(state): State<AppState>,
+    Json(req): Json<TrainerLoginRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    trainers::request_login(
+        &state.pg_pool,
+        &req.email,
+        &*state.secrets,
+        &state.email_sender,
+    )
+    .await?;
+    Ok(StatusCode::OK)
+}
+
+async fn trainer_session(
+    State(state): State<AppState>,
+    Json(req): Json<TrainerSessionRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let trainer = trainers::login(&state.pg_pool, &req.token, &*state.secrets).await?;
+    let token = mint_trainer_session_jwt(trainer.id, &state.session_keys)?;
+    Ok(Json(SessionExchangeResponse { token }))
+}
+
+async fn ensure_trainer_of_event(
+    state: &AppState,
+    claims: &TrainerClaims,
+    event_id: &EventId,
+) -> Result<(), ResponseError> {
+    ensure_access(trainers::is_trainer_of_event(&state.pg_pool, claims.sub, event_id).await?)
+}
+
+async fn trainer_events(
+    State(state): State<AppState>,
+    Extension(claims): Extension<TrainerClaims>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(
+        trainers::get_events(&state.pg_pool, claims.sub).await?,
+    ))
+}
+
+async fn trainer_event_participants(
+    State(state): State<AppState>,
+    Extension(claims): Extension<TrainerClaims>,
+    Path(event_id): Path<EventId>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_trainer_of_event(&state, &claims, &event_id).await?;
+    Ok(Json(
+        trainers::get_participants(&state.pg_pool, &event_id).await?,
+    ))
+}
+
+async fn trainer_event_attendance(
+    State(state): State<AppState>,
+    Extension(claims): Extension<TrainerClaims>,
+    Path(event_id): Path<EventId>,
+    Json(attendance): Json<TrainerAttendance>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_trainer_of_event(&state, &claims, &event_id).await?;
+    trainers::set_attendance(&state.pg_pool, &event_id, claims.sub, &attendance).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn trainer_event_participants_list(
+    State(state): State<AppState>,
+    Extension(claims): Extension<TrainerClaims>,
+    Path(event_id): Path<EventId>,
+) -> Result<impl IntoResponse, ResponseError> {
+    ensure_trainer_of_event(&state, &claims, &event_id).await?;
+    let (filename, bytes) = export::event_participants_list(&state.pg_pool, event_id).await?;
+    Ok(into_file_response(filename, bytes))
+}
+
+async fn admin_trainers(State(state): State<AppState>) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(trainers::get_trainers(&state.pg_pool).await?))
+}
+
+async fn update_trainer(
+    State(state): State<AppState>,
+    Json(input): Json<TrainerInput>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(trainers::save_trainer(&state.pg_pool, input).await?))
+}
+
+async fn delete_trainer(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ResponseError> {
+    if !trainers::delete_trainer(&state.pg_pool, id).await? {
+        return Err(not_found("Trainer not found"));
+    }
+    Ok(StatusCode::OK)
+}
 
 async fn appointments(State(state): State<AppState>) -> Result<impl IntoResponse, ResponseError> {
     let result = calendar::appointments(&state.calendar_client).await?;
@@ -834,21 +2366,26 @@ async fn message(
     ClientIp(ip): ClientIp,
     Json(message): Json<ContactMessage>,
 ) -> Result<impl IntoResponse, ResponseError> {
-    validate_captcha(&message.token, ip, &*state.secrets).await?;
+    validate_captcha(&message.token, ip, &*state.captcha_verifier).await?;
     contact::message(message, &state.email_sender).await?;
     Ok(StatusCode::OK)
 }
 
 async fn emails(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     extract::Json(body): extract::Json<EmailsBody>,
 ) -> Result<impl IntoResponse, ResponseError> {
+    let mut record = AuditRecord::default();
     if let Some(emails) = body.emails {
+        ensure_access(claims.has_any_role(ADMIN_ROLES))?;
         contact::emails(emails, &state.email_sender).await?;
     } else if let Some(event) = body.event {
+        ensure_access(claims.can_manage(event_type_of_event(&state, &event.event_id).await?))?;
+        record = AuditRecord::event(event.event_id);
         events::send_event_email(&state.pg_pool, event, &state.email_sender).await?;
     }
-    Ok(StatusCode::OK)
+    Ok((Extension(record), StatusCode::OK))
 }
 
 // membership
@@ -858,11 +2395,71 @@ async fn membership_application(
     ClientIp(ip): ClientIp,
     extract::Json(application): extract::Json<MembershipApplication>,
 ) -> Result<impl IntoResponse, ResponseError> {
-    validate_captcha(&application.token, ip, &*state.secrets).await?;
-    membership::application(&state.pg_pool, application, &state.email_sender).await?;
+    validate_captcha(&application.token, ip, &*state.captcha_verifier).await?;
+    membership::application(
+        &state.pg_pool,
+        application,
+        &*state.secrets,
+        &state.email_sender,
+    )
+    .await?;
     Ok(StatusCode::OK)
 }
 
+async fn membership_fees(
+    State(state): State<AppState>,
+    Path(year): Path<i32>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(membership::fee_preview(&state.pg_pool, year).await?))
+}
+
+async fn membership_fee_schedule(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ResponseError> {
+    Ok(Json(membership::get_fee_schedule(&state.pg_pool).await?))
+}
+
+async fn save_membership_fee_schedule(
+    State(state): State<AppState>,
+    extract::Json(schedule): extract::Json<Vec<MembershipFeeSchedule>>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let schedule = membership::save_fee_schedule(&state.pg_pool, schedule).await?;
+    Ok((Extension(AuditRecord::default()), Json(schedule)))
+}
+
+async fn import_members(
+    State(state): State<AppState>,
+    extract::Json(members): extract::Json<Vec<MemberImport>>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let count = membership::import_members(&state.pg_pool, members, &*state.secrets).await?;
+    Ok((Extension(AuditRecord::default()), Json(count)))
+}
+
+async fn update_member_end_date(
+    State(state): State<AppState>,
+    Path(member_id): Path<i32>,
+    extract::Json(input): extract::Json<MemberEndDate>,
+) -> Result<impl IntoResponse, ResponseError> {
+    if !membership::update_end_date(&state.pg_pool, member_id, input.end_date).await? {
+        return Err(not_found("Member not found"));
+    }
+    Ok((Extension(AuditRecord::default()), StatusCode::OK))
+}
+
+async fn export_membership_fees_sepa_xml(
+    State(state): State<AppState>,
+    Path(year): Path<i32>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let (filename, xml) = membership::export_fee_sepa_xml(&state.pg_pool, year, &*state.secrets)
+        .await
+        .map_err(into_sepa_export_response_error)?;
+
+    Ok((
+        Extension(AuditRecord::default()),
+        into_xml_file_response(filename, xml),
+    ))
+}
+
 // tasks
 
 async fn check_email_connectivity(
@@ -893,20 +2490,71 @@ async fn close_finished_events(
     Ok(StatusCode::OK)
 }
 
+async fn publish_scheduled_events(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ResponseError> {
+    tasks::publish_scheduled_events(&state.pg_pool).await;
+    Ok(StatusCode::OK)
+}
+
+async fn expire_waiting_list_offers(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ResponseError> {
+    tasks::expire_waiting_list_offers(&state.pg_pool, &*state.secrets, &state.email_sender).await;
+    Ok(StatusCode::OK)
+}
+
+async fn expire_news_subscriptions(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ResponseError> {
+    tasks::expire_news_subscriptions(&state.pg_pool).await;
+    Ok(StatusCode::OK)
+}
+
+async fn send_news_campaigns(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ResponseError> {
+    tasks::send_news_campaigns(&state.pg_pool, &*state.secrets, &state.email_sender).await;
+    Ok(StatusCode::OK)
+}
+
+async fn delete_expired_sessions(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ResponseError> {
+    tasks::delete_expired_sessions(&state.pg_pool).await;
+    Ok(StatusCode::OK)
+}
+
+async fn anonymize_archived_participants(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ResponseError> {
+    tasks::anonymize_archived_participants(&state.pg_pool, &*state.secrets).await;
+    Ok(StatusCode::OK)
+}
+
+async fn encrypt_ibans(State(state): State<AppState>) -> Result<impl IntoResponse, ResponseError> {
+    tasks::encrypt_ibans(&state.pg_pool, &*state.secrets).await;
+    Ok(StatusCode::OK)
+}
+
 async fn send_payment_reminders(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(event_type): Path<EventType>,
 ) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.has_any_role(FINANCE_ROLES) || claims.can_manage(event_type))?;
     tasks::send_payment_reminders(&state.pg_pool, event_type, &state.email_sender).await?;
-    Ok(StatusCode::OK)
+    Ok((Extension(AuditRecord::default()), StatusCode::OK))
 }
 
 async fn send_participation_confirmation(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(event_id): Path<EventId>,
 ) -> Result<impl IntoResponse, ResponseError> {
+    ensure_access(claims.can_manage(event_type_of_event(&state, &event_id).await?))?;
     tasks::send_participation_confirmation(&state.pg_pool, event_id, &state.email_sender).await?;
-    Ok(StatusCode::OK)
+    Ok((Extension(AuditRecord::event(event_id)), StatusCode::OK))
 }
 
 fn into_file_response(filename: String, bytes: Vec<u8>) -> impl IntoResponse {
@@ -920,61 +2568,48 @@ fn into_file_response(filename: String, bytes: Vec<u8>) -> impl IntoResponse {
     )
 }
 
-/// Validates the provided captcha token using the hCaptcha service.
+/// Issues a challenge of the self-hosted proof of work captcha. Not available
+/// with hCaptcha, which issues its challenges itself.
+async fn captcha_challenge(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ResponseError> {
+    // only the self-hosted proof of work issues challenges
+    let challenge = state
+        .captcha_verifier
+        .challenge()
+        .await?
+        .ok_or_else(|| not_found("No captcha challenge available"))?;
+    Ok(Json(challenge))
+}
+
+/// Validates the provided captcha token with the configured verifier.
 /// Returns Ok(()) if the captcha is valid, or a ResponseError if validation fails.
 async fn validate_captcha(
     token: &Option<String>,
     client_ip: Option<IpAddr>,
-    secrets: &dyn SecretProvider,
+    verifier: &dyn CaptchaVerifier,
 ) -> Result<(), ResponseError> {
     let token = token.as_ref().ok_or_else(|| ResponseError {
         err: anyhow::anyhow!("No captcha token provided"),
         response: Some((StatusCode::BAD_REQUEST, "Captcha token is required.".into())),
     })?;
 
-    let secret = &secrets.get(SecretKey::CaptchaSecret).await?;
-
-    let captcha = hcaptcha::Captcha::new(token.as_str()).map_err(|e| ResponseError {
-        err: anyhow::anyhow!("Failed to create captcha: {:?}", e),
-        response: Some((StatusCode::BAD_REQUEST, "Invalid captcha token.".into())),
-    })?;
-
-    let mut request = hcaptcha::Request::new(secret, captcha).map_err(|e| ResponseError {
-        err: anyhow::anyhow!("Failed to build captcha request: {:?}", e),
-        response: Some((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Captcha validation failed.".into(),
-        )),
-    })?;
-
-    if let Some(ip) = client_ip {
-        request = request
-            .set_remoteip(&ip.to_string())
-            .map_err(|e| ResponseError {
-                err: anyhow::anyhow!("Failed to build captcha request: {:?}", e),
-                response: Some((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Captcha validation failed.".into(),
-                )),
-            })?;
-    }
-
-    let response = hcaptcha::Client::new()
-        .verify(request)
+    let valid = verifier
+        .verify(token, client_ip)
         .await
         .map_err(|e| ResponseError {
-            err: anyhow::anyhow!("Captcha verification failed: {:?}", e),
+            err: e.context("Captcha validation failed"),
             response: Some((
-                StatusCode::BAD_REQUEST,
-                "Captcha verification failed.".into(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Captcha validation failed.".into(),
             )),
         })?;
 
-    if response.success() {
+    if valid {
         Ok(())
     } else {
         Err(ResponseError {
-            err: anyhow::anyhow!("Captcha invalid: {:?}", response.error_codes()),
+            err: anyhow::anyhow!("Captcha invalid"),
             response: Some((StatusCode::BAD_REQUEST, "Invalid captcha token.".into())),
         })
     }
@@ -985,82 +2620,299 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
-    fn test_secret() -> String {
-        "test-secret-key-that-is-at-least-32-bytes!".to_string()
+    #[sqlx::test]
+    async fn test_task_scopes_match_task_routes(pool: PgPool) -> Result<()> {
+        use tower::ServiceExt;
+
+        let secrets = crate::test_utils::mock_secrets(vec![
+            (SecretKey::TaskApiKey, ""),
+            (SecretKey::CaptchaProvider, ""),
+            (
+                SecretKey::SessionSecret,
+                "test-secret-key-that-is-at-least-32-bytes!",
+            ),
+            (SecretKey::PreviousSessionSecrets, ""),
+            (SecretKey::RateLimits, ""),
+        ]);
+        let app = router(pool, reqwest::Client::new(), Arc::new(secrets)).await?;
+
+        // every scope has a task route which requires an API key, other tasks don't exist
+        let tasks = api_keys::TASK_SCOPES
+            .iter()
+            .map(|task| (*task, StatusCode::UNAUTHORIZED))
+            .chain([("unknown_task", StatusCode::NOT_FOUND)]);
+        for (task, status) in tasks {
+            let request = Request::get(format!("/api/tasks/{}", task)).body(Body::empty())?;
+            let response = app.clone().oneshot(request).await?;
+            assert_eq!(response.status(), status, "{}", task);
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_erasure_is_audited_without_the_email(pool: PgPool) -> Result<()> {
+        use tower::ServiceExt;
+
+        let secret = "test-secret-key-that-is-at-least-32-bytes!";
+        let secrets = crate::test_utils::mock_secrets(vec![
+            (SecretKey::TaskApiKey, ""),
+            (SecretKey::CaptchaProvider, ""),
+            (SecretKey::SessionSecret, secret),
+            (SecretKey::PreviousSessionSecrets, ""),
+            (SecretKey::RateLimits, ""),
+        ]);
+        let app = router(pool.clone(), reqwest::Client::new(), Arc::new(secrets)).await?;
+        crate::db::subscribe(
+            &pool,
+            NewsSubscription::new("max@test.com".into(), vec![NewsTopic::GENERAL]),
+            None,
+        )
+        .await?;
+
+        let token = mint_session_jwt(
+            "admin@sv-eutingen.de",
+            None,
+            &[Role::Admin],
+            &SessionKeys::new(secret.to_string(), ""),
+        )?;
+        let request = Request::post("/api/admin/gdpr/erase")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"email": "Max@Test.com"}"#))?;
+        let response = app.oneshot(request).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+        let result: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(result["deleted_news_subscribers"], 1);
+
+        let entries = audit::get_entries(&pool, &AuditLogFilter::default()).await?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, "POST /api/admin/gdpr/erase");
+        assert_eq!(
+            entries[0].subject,
+            Some(format!(
+                "erasure:{}",
+                result["erasure_id"].as_str().unwrap()
+            ))
+        );
+        assert!(
+            !serde_json::to_string(&entries)?
+                .to_lowercase()
+                .contains("max@test.com")
+        );
+
+        Ok(())
+    }
+
+    fn test_keys() -> SessionKeys {
+        SessionKeys::new("test-secret-key-that-is-at-least-32-bytes!".to_string(), "")
     }
 
     #[test]
     fn test_session_jwt_round_trip() {
-        let secret = test_secret();
-        let token =
-            mint_session_jwt("admin@sv-eutingen.de", Some("sv-eutingen.de"), &secret).unwrap();
-        let claims = verify_session_jwt(&token, &secret).unwrap();
+        let keys = test_keys();
+        let token = mint_session_jwt(
+            "admin@sv-eutingen.de",
+            Some("sv-eutingen.de"),
+            &[Role::Admin],
+            &keys,
+        )
+        .unwrap();
+        assert_eq!(
+            decode_header(&token).unwrap().kid.as_deref(),
+            Some(keys.current.kid.as_str())
+        );
+        let claims = verify_session_jwt(&token, &keys).unwrap();
         assert_eq!(claims.email, "admin@sv-eutingen.de");
         assert_eq!(claims.hd.as_deref(), Some("sv-eutingen.de"));
+        assert_eq!(claims.roles, vec![Role::Admin]);
         assert!(claims.exp > claims.iat);
-        assert_eq!(claims.exp - claims.iat, 30 * 24 * 60 * 60);
+        assert_eq!(claims.exp - claims.iat, 15 * 60);
+    }
+
+    #[sqlx::test]
+    async fn test_booking_validates_captcha(pool: PgPool) -> Result<()> {
+        let secrets: Arc<dyn SecretProvider> = Arc::new(crate::test_utils::mock_secrets(vec![]));
+        let state = AppState {
+            pg_pool: pool,
+            jwks: Arc::new(RwLock::new(JwksCache::new())),
+            http_client: reqwest::Client::new(),
+            session_keys: test_keys(),
+            rate_limits: Arc::new(RateLimits::from_config("")?),
+            captcha_verifier: Arc::new(captcha::StubCaptchaVerifier("valid")),
+            secrets: secrets.clone(),
+            email_sender: RealEmailSender::new(secrets.clone()),
+            calendar_client: CalendarClient::new(secrets),
+        };
+        let booking = |token: &str| {
+            let mut booking = EventBooking::new(
+                1,
+                "Max".into(),
+                "Mustermann".into(),
+                "Teststr 1".into(),
+                "Teststadt".into(),
+                "max@test.com".into(),
+                None,
+                Some(true),
+                Some(false),
+                None,
+                vec![],
+                None,
+            );
+            booking.token = Some(token.into());
+            extract::Json(booking)
+        };
+
+        let err = super::booking(State(state.clone()), ClientIp(None), booking("invalid"))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.response.unwrap().0, StatusCode::BAD_REQUEST);
+
+        // a valid captcha is passed on to the booking, which fails for the unknown event
+        let response = super::booking(State(state), ClientIp(None), booking("valid"))
+            .await
+            .ok()
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_captcha() {
+        let verifier = captcha::StubCaptchaVerifier("valid");
+        assert!(
+            validate_captcha(&Some("valid".into()), None, &verifier)
+                .await
+                .is_ok()
+        );
+        for token in [Some("invalid".to_string()), None] {
+            let err = validate_captcha(&token, None, &verifier).await.unwrap_err();
+            assert_eq!(err.response.unwrap().0, StatusCode::BAD_REQUEST);
+        }
     }
 
     #[test]
     fn test_verify_rejects_expired_session_jwt() {
-        let secret = test_secret();
+        let keys = test_keys();
         let claims = Claims {
             email: "admin@sv-eutingen.de".to_string(),
             hd: Some("sv-eutingen.de".to_string()),
+            roles: vec![Role::Admin],
             exp: 1,
             iat: 0,
         };
-        let token = jsonwebtoken::encode(
-            &Header::new(Algorithm::HS256),
-            &claims,
-            &EncodingKey::from_secret(secret.as_bytes()),
+        let token = keys.encode(&claims).unwrap();
+        assert!(verify_session_jwt(&token, &keys).is_err());
+    }
+
+    #[test]
+    fn test_verify_rejects_bad_signature() {
+        let keys = test_keys();
+        let mut wrong_keys = SessionKeys::new("a-completely-different-secret-key!".to_string(), "");
+        // same kid, other secret
+        wrong_keys.current.kid = keys.current.kid.clone();
+        let token = mint_session_jwt(
+            "admin@sv-eutingen.de",
+            Some("sv-eutingen.de"),
+            &[Role::Admin],
+            &keys,
         )
         .unwrap();
-        assert!(verify_session_jwt(&token, &secret).is_err());
+        assert!(verify_session_jwt(&token, &wrong_keys).is_err());
     }
 
     #[test]
-    fn test_verify_rejects_bad_signature() {
-        let secret = test_secret();
-        let wrong_secret = "a-completely-different-secret-key!".to_string();
+    fn test_verify_after_key_rotation() {
+        let old_keys = test_keys();
         let token =
-            mint_session_jwt("admin@sv-eutingen.de", Some("sv-eutingen.de"), &secret).unwrap();
-        assert!(verify_session_jwt(&token, &wrong_secret).is_err());
+            mint_session_jwt("admin@sv-eutingen.de", None, &[Role::Admin], &old_keys).unwrap();
+
+        let rotated = SessionKeys::new(
+            "the-new-secret-key-that-is-at-least-32-bytes!".to_string(),
+            " test-secret-key-that-is-at-least-32-bytes! ,",
+        );
+        assert!(verify_session_jwt(&token, &rotated).is_ok());
+
+        let without_previous = SessionKeys::new(
+            "the-new-secret-key-that-is-at-least-32-bytes!".to_string(),
+            "",
+        );
+        assert!(verify_session_jwt(&token, &without_previous).is_err());
     }
 
     #[test]
-    fn test_authorize_allows_allowlisted_email() {
-        let claims = Claims {
-            email: "fitness@sv-eutingen.de".to_string(),
-            hd: None,
-            exp: 0,
-            iat: 0,
-        };
-        let allowed_emails = vec!["fitness@sv-eutingen.de".to_string()];
-        assert!(authorize(&claims, &allowed_emails, "sv-eutingen.de"));
+    fn test_verify_rejects_session_jwt_without_kid() {
+        // long-lived tokens issued before the key rotation was introduced
+        let secret = "test-secret-key-that-is-at-least-32-bytes!";
+        let token = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims(vec![Role::Admin]),
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap();
+        assert!(verify_session_jwt(&token, &test_keys()).is_err());
     }
 
-    #[test]
-    fn test_authorize_allows_matching_hosted_domain() {
-        let claims = Claims {
+    fn claims(roles: Vec<Role>) -> Claims {
+        Claims {
             email: "someone@sv-eutingen.de".to_string(),
             hd: Some("sv-eutingen.de".to_string()),
+            roles,
             exp: 0,
             iat: 0,
-        };
-        let allowed_emails = vec!["fitness@sv-eutingen.de".to_string()];
-        assert!(authorize(&claims, &allowed_emails, "sv-eutingen.de"));
+        }
     }
 
     #[test]
-    fn test_authorize_rejects_unknown_email_and_mismatched_domain() {
-        let claims = Claims {
-            email: "random@example.com".to_string(),
-            hd: Some("example.com".to_string()),
-            exp: 0,
-            iat: 0,
-        };
-        let allowed_emails = vec!["fitness@sv-eutingen.de".to_string()];
-        assert!(!authorize(&claims, &allowed_emails, "sv-eutingen.de"));
+    fn test_authorize_requires_a_role() {
+        assert!(authorize(&claims(vec![Role::TrainerReadonly])));
+        // membership in the Workspace domain alone no longer grants access
+        assert!(!authorize(&claims(vec![])));
+    }
+
+    #[test]
+    fn test_claims_permissions() {
+        let fitness = claims(vec![Role::FitnessCoordinator]);
+        assert!(fitness.can_manage(EventType::Fitness));
+        assert!(!fitness.can_manage(EventType::Events));
+        assert!(!fitness.has_any_role(FINANCE_ROLES));
+
+        let treasurer = claims(vec![Role::Treasurer, Role::EventsCoordinator]);
+        assert!(treasurer.has_any_role(FINANCE_ROLES));
+        assert!(treasurer.can_manage(EventType::Events));
+        assert!(treasurer.can_view(EventType::Fitness));
+        assert!(!treasurer.can_manage(EventType::Fitness));
+    }
+
+    #[test]
+    fn test_session_jwt_without_roles_has_no_access() {
+        // tokens without roles are valid but grant no access
+        let keys = test_keys();
+        let token = keys
+            .encode(&serde_json::json!({
+                "email": "fitness@sv-eutingen.de",
+                "hd": null,
+                "exp": Utc::now().timestamp() + 60,
+                "iat": Utc::now().timestamp(),
+            }))
+            .unwrap();
+        let claims = verify_session_jwt(&token, &keys).unwrap();
+        assert!(!authorize(&claims));
+    }
+
+    #[test]
+    fn test_trainer_session_jwt_is_separate_from_admin_session() {
+        let keys = test_keys();
+        let token = mint_trainer_session_jwt(42, &keys).unwrap();
+        assert_eq!(verify_trainer_session_jwt(&token, &keys).unwrap().sub, 42);
+        assert!(verify_session_jwt(&token, &keys).is_err());
+
+        let admin_token =
+            mint_session_jwt("info@sv-eutingen.de", None, &[Role::Admin], &keys).unwrap();
+        assert!(verify_trainer_session_jwt(&admin_token, &keys).is_err());
     }
 }
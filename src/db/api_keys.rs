@@ -0,0 +1,105 @@
+use crate::models::{ApiKey, ApiKeyInput};
+use anyhow::Result;
+use sqlx::{PgPool, query, query_as};
+
+pub(crate) async fn get_api_keys(pool: &PgPool) -> Result<Vec<ApiKey>> {
+    let api_keys = query_as!(
+        ApiKey,
+        r#"SELECT id, created, name, scopes, expires_at, last_used_at FROM api_keys ORDER BY name"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(api_keys)
+}
+
+/// Insert a new API key. Returns `None` if a key with the same name exists already.
+pub(crate) async fn insert_api_key(
+    pool: &PgPool,
+    input: &ApiKeyInput,
+    key_hash: &str,
+) -> Result<Option<ApiKey>> {
+    let api_key = query_as!(
+        ApiKey,
+        r#"INSERT INTO api_keys (name, key_hash, scopes, expires_at) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (name) DO NOTHING
+        RETURNING id, created, name, scopes, expires_at, last_used_at"#,
+        input.name,
+        key_hash,
+        &input.scopes,
+        input.expires_at
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(api_key)
+}
+
+/// Insert an API key imported from a secret. Returns `false` without inserting the
+/// key if a key of the same name has been imported before, even if it has been
+/// deleted since.
+pub(crate) async fn import_api_key(
+    pool: &PgPool,
+    input: &ApiKeyInput,
+    key_hash: &str,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    let result = query!(
+        r#"INSERT INTO api_key_imports (name) VALUES ($1) ON CONFLICT (name) DO NOTHING"#,
+        input.name
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    let result = query!(
+        r#"INSERT INTO api_keys (name, key_hash, scopes, expires_at) VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING"#,
+        input.name,
+        key_hash,
+        &input.scopes,
+        input.expires_at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub(crate) async fn delete_api_key(pool: &PgPool, id: i32) -> Result<bool> {
+    let result = query!(r#"DELETE FROM api_keys WHERE id = $1"#, id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Get the API key of the given hash if it has not expired yet.
+pub(crate) async fn get_valid_api_key(pool: &PgPool, key_hash: &str) -> Result<Option<ApiKey>> {
+    let api_key = query_as!(
+        ApiKey,
+        r#"SELECT id, created, name, scopes, expires_at, last_used_at FROM api_keys
+        WHERE key_hash = $1 AND (expires_at IS NULL OR expires_at > NOW())"#,
+        key_hash
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(api_key)
+}
+
+pub(crate) async fn mark_api_key_used(pool: &PgPool, id: i32) -> Result<()> {
+    query!(
+        r#"UPDATE api_keys SET last_used_at = NOW() WHERE id = $1"#,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
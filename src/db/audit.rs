@@ -0,0 +1,111 @@
+use crate::models::{AuditLogEntry, AuditLogFilter, EventId};
+use anyhow::Result;
+use serde_json::Value;
+use sqlx::{PgPool, query, query_scalar};
+
+/// Maximum number of audit log entries returned at once.
+const MAX_ENTRIES: i64 = 1000;
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn insert_audit_entry(
+    pool: &PgPool,
+    actor: &str,
+    action: &str,
+    path: &str,
+    event_id: Option<&EventId>,
+    booking_id: Option<i32>,
+    subject: Option<&str>,
+    before: Option<&Value>,
+    after: Option<&Value>,
+) -> Result<()> {
+    query!(
+        r#"INSERT INTO audit_log (actor, action, path, event_id, booking_id, subject, before, after)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+        actor,
+        action,
+        path,
+        event_id.map(|id| id.into_inner()),
+        booking_id,
+        subject,
+        before,
+        after
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn get_audit_entries(
+    pool: &PgPool,
+    filter: &AuditLogFilter,
+) -> Result<Vec<AuditLogEntry>> {
+    let entries = query!(
+        r#"SELECT id, created, actor, action, path, event_id, booking_id, subject, before, after
+        FROM audit_log
+        WHERE ($1::TEXT IS NULL OR actor = $1)
+          AND ($2::TEXT IS NULL OR action ILIKE '%' || $2 || '%')
+          AND ($3::INTEGER IS NULL OR event_id = $3)
+          AND ($4::INTEGER IS NULL OR booking_id = $4)
+          AND ($5::TEXT IS NULL OR subject = $5)
+          AND ($6::TIMESTAMPTZ IS NULL OR created >= $6)
+          AND ($7::TIMESTAMPTZ IS NULL OR created < $7)
+        ORDER BY created DESC, id DESC
+        LIMIT $8"#,
+        filter.actor,
+        filter.action,
+        filter.event_id.map(|id| id.into_inner()),
+        filter.booking_id,
+        filter.subject,
+        filter.from,
+        filter.to,
+        filter.limit.unwrap_or(MAX_ENTRIES).clamp(1, MAX_ENTRIES)
+    )
+    .map(|row| AuditLogEntry {
+        id: row.id,
+        created: row.created,
+        actor: row.actor,
+        action: row.action,
+        path: row.path,
+        event_id: row.event_id.map(EventId::from),
+        booking_id: row.booking_id,
+        subject: row.subject,
+        before: row.before,
+        after: row.after,
+    })
+    .fetch_all(pool)
+    .await?;
+
+    Ok(entries)
+}
+
+/// Get the row of the event including its dates as json.
+pub(crate) async fn get_event_snapshot(pool: &PgPool, id: &EventId) -> Result<Option<Value>> {
+    let snapshot = query_scalar!(
+        r#"SELECT to_jsonb(e) || jsonb_build_object('dates', COALESCE(
+            (SELECT jsonb_agg(d.date ORDER BY d.date) FROM event_dates d WHERE d.event_id = e.id),
+            '[]'::jsonb
+        )) AS "snapshot!"
+        FROM events e WHERE e.id = $1"#,
+        id.get_ref()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(snapshot)
+}
+
+/// Get the row of the booking as json. Personal data (IBAN, comment and custom
+/// values) is left out, the subscriber is only referenced by its id.
+pub(crate) async fn get_booking_snapshot(pool: &PgPool, id: i32) -> Result<Option<Value>> {
+    let snapshot = query_scalar!(
+        r#"SELECT to_jsonb(b) - 'iban' - 'comment' - 'custom_values' AS "snapshot!"
+        FROM event_bookings b
+        WHERE b.id = $1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(snapshot)
+}
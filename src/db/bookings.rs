@@ -2,38 +2,19 @@ use std::collections::{HashMap, HashSet};
 
 use anyhow::{Result, anyhow, bail};
 use chrono::{DateTime, Utc};
-use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder, Row, query, query_scalar};
+use itertools::Itertools;
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder, Row, query, query_as, query_scalar};
 
-use super::events::fetch_event;
+use super::events::{fetch_bucket_counters, fetch_custom_fields, fetch_event, save_event_version};
+use crate::error::ValidationError;
+use crate::logic::encryption::IbanCipher;
 use crate::models::{
-    BookingCustomFieldValues, Event, EventBooking, EventCounter, EventCustomField,
-    EventCustomFieldType, EventId, EventSubscription, EventType, LifecycleStatus, PaymentMethod,
-    SepaPaymentNotAllowed, UnpaidEventBooking, VerifyPaymentBookingRecord,
+    BookingCustomFieldValues, CanceledEventBooking, DuplicateBooking, Event, EventBooking,
+    EventBucketCounter, EventCounter, EventCustomField, EventCustomFieldType, EventId, EventRefund,
+    EventSubscription, EventType, LifecycleStatus, PaymentMethod, SepaPaymentNotAllowed,
+    UnpaidEventBooking, VerifyPaymentBookingRecord, WaitingListOffer,
 };
 
-async fn fetch_price_relevant_flags(
-    pool: &PgPool,
-    custom_field_ids: &HashSet<i32>,
-) -> Result<HashMap<i32, bool>> {
-    if custom_field_ids.is_empty() {
-        return Ok(HashMap::new());
-    }
-
-    let mut query_builder: QueryBuilder<Postgres> =
-        QueryBuilder::new("SELECT id, price_relevant FROM event_custom_fields WHERE id IN(");
-    let mut separated = query_builder.separated(", ");
-    for id in custom_field_ids {
-        separated.push_bind(id);
-    }
-    separated.push_unseparated(")");
-
-    let rows = query_builder.build().fetch_all(pool).await?;
-    Ok(rows
-        .into_iter()
-        .map(|row| (row.get("id"), row.get("price_relevant")))
-        .collect())
-}
-
 pub(crate) async fn get_bookings_to_verify_payment(
     pool: &PgPool,
     payment_ids: HashSet<&String>,
@@ -42,19 +23,13 @@ pub(crate) async fn get_bookings_to_verify_payment(
         r#"
 SELECT
     b.id,
+    e.id AS event_id,
     e.name AS event_name,
     CONCAT (s.first_name, ' ', s.last_name) AS full_name,
     e.price_member,
     e.price_non_member,
     s.member,
-    b.custom_value_1,
-    b.custom_value_2,
-    b.custom_value_3,
-    b.custom_value_4,
-    e.custom_field_1,
-    e.custom_field_2,
-    e.custom_field_3,
-    e.custom_field_4,
+    b.custom_values,
     b.payment_id,
     b.canceled,
     b.enrolled,
@@ -77,67 +52,32 @@ WHERE
 ORDER BY
     b.created"#,
     );
-    let rows = query_builder.build().fetch_all(pool).await?;
-
-    let mut custom_field_ids = HashSet::new();
-    let custom_field_columns = [
-        "custom_field_1",
-        "custom_field_2",
-        "custom_field_3",
-        "custom_field_4",
-    ];
-    for row in &rows {
-        for column in custom_field_columns {
-            if let Some(id) = row.try_get::<Option<i32>, _>(column)? {
-                custom_field_ids.insert(id);
-            }
-        }
-    }
+    let mut conn = pool.acquire().await?;
+    let rows = query_builder.build().fetch_all(&mut *conn).await?;
 
-    let price_relevant_by_id = fetch_price_relevant_flags(pool, &custom_field_ids).await?;
+    let event_ids = rows
+        .iter()
+        .map(|row| row.get::<i32, _>("event_id"))
+        .unique()
+        .collect::<Vec<_>>();
+    let custom_fields = fetch_custom_fields(&mut conn, &event_ids).await?;
 
     let result = rows
         .into_iter()
         .map(|row| {
-            let custom_values = [
-                row.try_get::<Option<String>, _>("custom_value_1")
-                    .ok()
-                    .flatten(),
-                row.try_get::<Option<String>, _>("custom_value_2")
-                    .ok()
-                    .flatten(),
-                row.try_get::<Option<String>, _>("custom_value_3")
-                    .ok()
-                    .flatten(),
-                row.try_get::<Option<String>, _>("custom_value_4")
-                    .ok()
-                    .flatten(),
-            ];
-            let custom_field_ids = [
-                row.try_get::<Option<i32>, _>("custom_field_1")
-                    .ok()
-                    .flatten(),
-                row.try_get::<Option<i32>, _>("custom_field_2")
-                    .ok()
-                    .flatten(),
-                row.try_get::<Option<i32>, _>("custom_field_3")
-                    .ok()
-                    .flatten(),
-                row.try_get::<Option<i32>, _>("custom_field_4")
-                    .ok()
-                    .flatten(),
-            ];
-            let price_relevant_flags = std::array::from_fn(|i| {
-                custom_field_ids[i]
-                    .and_then(|id| price_relevant_by_id.get(&id).copied())
-                    .unwrap_or(false)
-            });
-            let price = BookingCustomFieldValues::new(custom_values, price_relevant_flags)
-                .total_price(
-                    &row.get("price_member"),
-                    &row.get("price_non_member"),
-                    row.get("member"),
-                );
+            let custom_values: Vec<String> = row.get("custom_values");
+            let price = BookingCustomFieldValues::new(
+                custom_fields
+                    .get(&row.get::<i32, _>("event_id"))
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+                &custom_values,
+            )
+            .total_price(
+                &row.get("price_member"),
+                &row.get("price_non_member"),
+                row.get("member"),
+            );
 
             VerifyPaymentBookingRecord::new(
                 row.get("id"),
@@ -166,6 +106,8 @@ pub(crate) async fn get_event_bookings_without_payment(
         String,
     )>,
 > {
+    let mut conn = pool.acquire().await?;
+
     let rows = query!(
         r#"
 SELECT
@@ -181,14 +123,7 @@ SELECT
     e.price_member,
     e.price_non_member,
     s.member,
-    b.custom_value_1,
-    b.custom_value_2,
-    b.custom_value_3,
-    b.custom_value_4,
-    e.custom_field_1,
-    e.custom_field_2,
-    e.custom_field_3,
-    e.custom_field_4,
+    b.custom_values,
     b.payment_id,
     b.payment_reminder_sent
 FROM
@@ -219,48 +154,27 @@ ORDER BY
     b.created"#,
         event_type as EventType
     )
-    .fetch_all(pool)
+    .fetch_all(&mut *conn)
     .await?;
 
-    let mut custom_field_ids = HashSet::new();
-    for row in &rows {
-        for id in [
-            row.custom_field_1,
-            row.custom_field_2,
-            row.custom_field_3,
-            row.custom_field_4,
-        ]
+    let event_ids = rows
         .iter()
-        .flatten()
-        {
-            custom_field_ids.insert(*id);
-        }
-    }
-
-    let price_relevant_by_id = fetch_price_relevant_flags(pool, &custom_field_ids).await?;
+        .map(|row| row.event_id)
+        .unique()
+        .collect::<Vec<_>>();
+    let custom_fields = fetch_custom_fields(&mut conn, &event_ids).await?;
 
     let result = rows
         .into_iter()
         .map(|row| {
-            let custom_values = [
-                row.custom_value_1.clone(),
-                row.custom_value_2.clone(),
-                row.custom_value_3.clone(),
-                row.custom_value_4.clone(),
-            ];
-            let custom_field_ids = [
-                row.custom_field_1,
-                row.custom_field_2,
-                row.custom_field_3,
-                row.custom_field_4,
-            ];
-            let price_relevant_flags = std::array::from_fn(|i| {
-                custom_field_ids[i]
-                    .and_then(|id| price_relevant_by_id.get(&id).copied())
-                    .unwrap_or(false)
-            });
-            let price = BookingCustomFieldValues::new(custom_values, price_relevant_flags)
-                .total_price(&row.price_member, &row.price_non_member, row.member);
+            let price = BookingCustomFieldValues::new(
+                custom_fields
+                    .get(&row.event_id)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+                &row.custom_values,
+            )
+            .total_price(&row.price_member, &row.price_non_member, row.member);
 
             (
                 UnpaidEventBooking::new(
@@ -388,7 +302,11 @@ SELECT
     type AS "cf_type: EventCustomFieldType",
     min_value,
     max_value,
-    price_relevant
+    price_relevant,
+    options,
+    required,
+    price,
+    option_prices
 FROM
     event_custom_fields
 "#
@@ -401,6 +319,10 @@ FROM
             row.min_value,
             row.max_value,
             row.price_relevant,
+            row.options,
+            row.required,
+            row.price,
+            row.option_prices,
         )
     })
     .fetch_all(pool)
@@ -438,10 +360,7 @@ SELECT
     v.phone,
     v.member,
     v.payment_id,
-    v.custom_value_1,
-    v.custom_value_2,
-    v.custom_value_3,
-    v.custom_value_4,
+    v.custom_values,
     v.iban
 FROM
     v_event_bookings v
@@ -482,15 +401,7 @@ ORDER BY
                 row.try_get("member")?,
                 None,
                 None,
-                vec![
-                    row.try_get::<Option<String>, _>("custom_value_1")?,
-                    row.try_get::<Option<String>, _>("custom_value_2")?,
-                    row.try_get::<Option<String>, _>("custom_value_3")?,
-                    row.try_get::<Option<String>, _>("custom_value_4")?,
-                ]
-                .into_iter()
-                .flatten()
-                .collect(),
+                row.try_get("custom_values")?,
                 row.try_get("iban")?,
             ),
             row.try_get("subscriber_id")?,
@@ -530,20 +441,41 @@ WHERE
             row.waiting_list.unwrap().try_into().unwrap(),
         )
     })
-    .fetch_all(conn)
+    .fetch_all(&mut *conn)
     .await?;
 
+    let event_ids = event_counters
+        .iter()
+        .map(|counter| counter.id.into_inner())
+        .collect::<Vec<_>>();
+    let mut bucket_counters = fetch_bucket_counters(conn, &event_ids).await?;
+    let event_counters = event_counters
+        .into_iter()
+        .map(|mut counter| {
+            counter.buckets = bucket_counters
+                .remove(counter.id.get_ref())
+                .unwrap_or_default();
+            counter
+        })
+        .collect();
+
     Ok(event_counters)
 }
 
 pub(crate) enum BookingResult {
     Booked(Event, Vec<EventCounter>, String),
     WaitingList(Event, Vec<EventCounter>, String),
-    DuplicateBooking,
+    DuplicateBooking(ExistingBooking),
     NotBookable,
     BookedOut,
 }
 
+/// Active booking of the same person which prevents another booking of the event.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ExistingBooking {
+    pub(crate) id: i32,
+}
+
 enum EventSubscriberId {
     New(i32),
     Existing(i32),
@@ -565,7 +497,20 @@ pub(crate) async fn book_event(pool: &PgPool, booking: &EventBooking) -> Result<
         return Ok(BookingResult::NotBookable);
     }
 
-    let result = match calc_enroll_status(&mut tx, &booking.event_id).await? {
+    // serialize concurrent bookings of the same email address (e.g. double clicks or retries),
+    // so the second one sees the booking of the first one
+    query!(
+        r#"SELECT pg_advisory_xact_lock($1, hashtext(LOWER(TRIM($2))))"#,
+        booking.event_id.get_ref(),
+        booking.email
+    )
+    .execute(&mut *tx)
+    .await?;
+    if let Some(existing) = find_duplicate_booking(&mut tx, booking).await? {
+        return Ok(BookingResult::DuplicateBooking(existing));
+    }
+
+    let result = match calc_enroll_status(&mut tx, &booking.event_id, booking.bucket_id).await? {
         Some(enrolled) => process_booking(&mut tx, booking, enrolled, false).await?,
         None => BookingResult::BookedOut,
     };
@@ -580,6 +525,7 @@ pub(crate) async fn pre_book_event(
     event_id: EventId,
     subscriber_id: i32,
     iban: Option<String>,
+    bucket_id: Option<i32>,
 ) -> Result<(BookingResult, Option<EventBooking>)> {
     let mut tx = pool.begin().await?;
 
@@ -587,7 +533,7 @@ pub(crate) async fn pre_book_event(
         return Ok((BookingResult::NotBookable, None));
     }
 
-    let result = match calc_enroll_status(&mut tx, &event_id).await? {
+    let result = match calc_enroll_status(&mut tx, &event_id, bucket_id).await? {
         Some(enrolled) => {
             let result = insert_booking(
                 &mut tx,
@@ -598,6 +544,7 @@ pub(crate) async fn pre_book_event(
                 &None,
                 &[],
                 &iban,
+                bucket_id,
             )
             .await?;
 
@@ -618,7 +565,7 @@ WHERE
                 subscriber_id
             )
             .map(|row| {
-                EventBooking::new(
+                let mut booking = EventBooking::new(
                     event_id.into_inner(),
                     row.first_name,
                     row.last_name,
@@ -631,7 +578,9 @@ WHERE
                     None,
                     Vec::new(),
                     iban.clone(),
-                )
+                );
+                booking.bucket_id = bucket_id;
+                booking
             })
             .fetch_one(&mut *tx)
             .await?;
@@ -646,25 +595,112 @@ WHERE
     Ok(result)
 }
 
+/// Find an active booking of the event with the same email address and name,
+/// independent of upper and lower case and the other subscriber data.
+async fn find_duplicate_booking(
+    conn: &mut PgConnection,
+    booking: &EventBooking,
+) -> Result<Option<ExistingBooking>> {
+    let existing = query_as!(
+        ExistingBooking,
+        r#"
+SELECT
+    b.id
+FROM
+    event_bookings b,
+    event_subscribers s
+WHERE
+    b.subscriber_id = s.id
+    AND b.event_id = $1
+    AND b.canceled IS NULL
+    AND LOWER(TRIM(s.email)) = LOWER(TRIM($2))
+    AND LOWER(TRIM(s.first_name)) = LOWER(TRIM($3))
+    AND LOWER(TRIM(s.last_name)) = LOWER(TRIM($4))
+ORDER BY
+    b.created
+LIMIT 1"#,
+        booking.event_id.get_ref(),
+        booking.email,
+        booking.first_name,
+        booking.last_name
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(existing)
+}
+
+/// An event is bookable in a bookable lifecycle status and within its booking window.
 async fn is_event_bookable(conn: &mut PgConnection, event_id: &EventId) -> Result<bool> {
-    let lifecycle = query!(
+    let event = query!(
         r#"
 SELECT
-    e.lifecycle_status AS "lifecycle_status: LifecycleStatus"
+    e.lifecycle_status AS "lifecycle_status: LifecycleStatus",
+    e.booking_start,
+    e.booking_end
 FROM
     events e
 WHERE
     e.id = $1"#,
         event_id.get_ref()
     )
-    .map(|row| row.lifecycle_status)
     .fetch_one(conn)
     .await?;
 
-    Ok(lifecycle.is_bookable())
+    let now = Utc::now();
+    Ok(event.lifecycle_status.is_bookable()
+        && event.booking_start.is_none_or(|start| start <= now)
+        && event.booking_end.is_none_or(|end| now < end))
 }
 
-async fn calc_enroll_status(conn: &mut PgConnection, event_id: &EventId) -> Result<Option<bool>> {
+/// Calculate the enroll status of a new booking of the event, within the capacity
+/// of the given bucket or, for events without capacity buckets, of the event.
+async fn calc_enroll_status(
+    conn: &mut PgConnection,
+    event_id: &EventId,
+    bucket_id: Option<i32>,
+) -> Result<Option<bool>> {
+    if let Some(bucket_id) = bucket_id {
+        let bucket_counter = query!(
+            r#"
+SELECT
+    v.id,
+    v.name,
+    v.max_subscribers,
+    v.max_waiting_list,
+    v.subscribers,
+    v.waiting_list
+FROM
+    v_event_bucket_counters v
+WHERE
+    v.id = $1
+    AND v.event_id = $2"#,
+            bucket_id,
+            event_id.get_ref(),
+        )
+        .map(|row| {
+            EventBucketCounter::new(
+                row.id.unwrap(),
+                row.name.unwrap(),
+                row.max_subscribers.unwrap(),
+                row.max_waiting_list.unwrap(),
+                row.subscribers.unwrap().try_into().unwrap(),
+                row.waiting_list.unwrap().try_into().unwrap(),
+            )
+        })
+        .fetch_optional(conn)
+        .await?
+        .ok_or_else(|| {
+            anyhow!(
+                "Found no capacity bucket with id '{}' for event with id '{}'",
+                bucket_id,
+                event_id
+            )
+        })?;
+
+        return Ok(bucket_counter.enroll_status());
+    }
+
     let result = query!(
         r#"
 SELECT
@@ -693,17 +729,7 @@ WHERE
 
     let event_counter = result.ok_or_else(|| anyhow!("Found no event with id '{}'", event_id))?;
 
-    let enroll_status = if event_counter.max_subscribers == -1
-        || event_counter.subscribers < event_counter.max_subscribers
-    {
-        Some(true)
-    } else if event_counter.waiting_list < event_counter.max_waiting_list {
-        Some(false)
-    } else {
-        None
-    };
-
-    Ok(enroll_status)
+    Ok(event_counter.enroll_status())
 }
 
 async fn process_booking(
@@ -722,6 +748,7 @@ async fn process_booking(
         &booking.comments,
         &booking.custom_values,
         &booking.iban,
+        booking.bucket_id,
     )
     .await?;
 
@@ -738,31 +765,30 @@ async fn insert_booking(
     comments: &Option<String>,
     custom_values: &[String],
     iban: &Option<String>,
+    bucket_id: Option<i32>,
 ) -> Result<BookingResult> {
     // check for duplicate booking
     if let EventSubscriberId::Existing(id) = subscriber_id {
-        let count = query!(
+        let existing = query_as!(
+            ExistingBooking,
             r#"
 SELECT
-	COUNT(1)
+    e.id
 FROM
-	event_bookings e
+    event_bookings e
 WHERE
-	e.event_id = $1
-	AND e.subscriber_id = $2
+    e.event_id = $1
+    AND e.subscriber_id = $2
     AND e.canceled IS NULL
 "#,
             event_id.get_ref(),
             id
         )
-        .map(|row| row.count)
-        .fetch_one(&mut *conn)
+        .fetch_optional(&mut *conn)
         .await?;
 
-        if let Some(v) = count
-            && v > 0
-        {
-            return Ok(BookingResult::DuplicateBooking);
+        if let Some(existing) = existing {
+            return Ok(BookingResult::DuplicateBooking(existing));
         }
     }
 
@@ -783,8 +809,8 @@ WHERE
     query!(
         r#"
 INSERT INTO public.event_bookings
-(event_id, enrolled, pre_booking, subscriber_id, comment, payment_id, iban, custom_value_1, custom_value_2, custom_value_3, custom_value_4)
-VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"#,
+(event_id, enrolled, pre_booking, subscriber_id, comment, payment_id, iban, custom_values, bucket_id)
+VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
         event_id.get_ref(),
         enrolled,
         pre_booking,
@@ -792,10 +818,8 @@ VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"#,
         comment,
         payment_id,
         iban.as_deref(),
-        custom_values.first(),
-        custom_values.get(1),
-        custom_values.get(2),
-        custom_values.get(3),
+        custom_values,
+        bucket_id,
     )
     .execute(&mut *conn)
     .await?;
@@ -901,7 +925,7 @@ async fn get_event_subscriber_id(
 pub(crate) async fn cancel_event_booking(
     pool: &PgPool,
     booking_id: i32,
-) -> Result<(Event, EventBooking, Option<(EventBooking, String)>)> {
+) -> Result<(Event, EventBooking, Option<WaitingListPromotion>)> {
     let mut tx = pool.begin().await?;
 
     // cancel booking
@@ -914,7 +938,7 @@ pub(crate) async fn cancel_event_booking(
 
     // fetch the canceled booking data (include enrolled flag so we know
     // whether the canceled booking was an enrolled attendee or a waiting-list entry)
-    let (canceled_booking, canceled_enrolled) = query!(
+    let (canceled_booking, canceled_enrolled, bucket_id) = query!(
         r#"
 SELECT
     v.event_id,
@@ -925,7 +949,8 @@ SELECT
     v.email,
     v.phone,
     v.member,
-    v.enrolled
+    v.enrolled,
+    v.bucket_id
 FROM
     v_event_bookings v
 WHERE
@@ -949,6 +974,7 @@ WHERE
                 None,
             ),
             row.enrolled.unwrap_or(false),
+            row.bucket_id,
         )
     })
     .fetch_one(&mut *tx)
@@ -956,14 +982,227 @@ WHERE
 
     let event_id = canceled_booking.event_id;
 
-    // Only promote a waiting-list entry if the canceled booking was an enrolled attendee.
+    // a spot is freed by an enrolled attendee or by a waiting-list entry with an open offer
+    let canceled_offer = query_scalar!(
+        r#"UPDATE event_waiting_list_offers SET expired_at = NOW() WHERE booking_id = $1 AND accepted_at IS NULL AND expired_at IS NULL RETURNING id"#,
+        booking_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let event = fetch_event(&mut tx, &event_id, false)
+        .await?
+        .ok_or_else(|| anyhow!("Error fetching event with id '{}'", event_id))?;
+
+    // Only promote a waiting-list entry if the canceled booking has freed a spot.
     // If the canceled booking itself was from the waiting list (enrolled == false),
-    // we must not create a new attendee.
-    let first_waiting_list_booking;
-    if canceled_enrolled {
-        // fetch the first waiting list entrance
-        let waiting_list_result: Option<(i32, EventBooking, String)> = query!(
-            r#"
+    // we must not create a new attendee. Each capacity bucket has its own waiting list.
+    let promotion = if canceled_enrolled || canceled_offer.is_some() {
+        promote_waiting_list(&mut tx, &event, bucket_id).await?
+    } else {
+        None
+    };
+
+    tx.commit().await?;
+
+    Ok((event, canceled_booking, promotion))
+}
+
+/// Cancel the event together with all of its bookings and open waiting list offers.
+/// Returns `None` if the event does not exist.
+pub(crate) async fn cancel_event(
+    pool: &PgPool,
+    event_id: &EventId,
+) -> Result<Option<(Event, Vec<CanceledEventBooking>)>> {
+    let mut tx = pool.begin().await?;
+
+    let Some(lifecycle_status) = query_scalar!(
+        r#"SELECT lifecycle_status AS "lifecycle_status: LifecycleStatus" FROM events WHERE id = $1 FOR UPDATE"#,
+        event_id.get_ref()
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(None);
+    };
+    if !lifecycle_status.is_bookable() {
+        bail!(ValidationError::new(format!(
+            "Ein Event im Status {:?} kann nicht abgesagt werden.",
+            lifecycle_status
+        )));
+    }
+
+    // the closing date is the start of the retention period of the participants' data
+    query!(
+        r#"UPDATE events SET lifecycle_status = 'Canceled', closed = NOW() WHERE id = $1"#,
+        event_id.get_ref()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    query!(
+        r#"
+UPDATE
+    event_waiting_list_offers o
+SET
+    expired_at = NOW()
+FROM
+    event_bookings b
+WHERE
+    o.booking_id = b.id
+    AND b.event_id = $1
+    AND o.accepted_at IS NULL
+    AND o.expired_at IS NULL"#,
+        event_id.get_ref()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let event = fetch_event(&mut tx, event_id, false)
+        .await?
+        .ok_or_else(|| anyhow!("Error fetching event with id '{}'", event_id))?;
+
+    let mut bookings = query!(
+        r#"
+UPDATE
+    event_bookings b
+SET
+    canceled = NOW()
+FROM
+    event_subscribers s
+WHERE
+    b.subscriber_id = s.id
+    AND b.event_id = $1
+    AND b.canceled IS NULL
+RETURNING
+    b.id,
+    b.enrolled,
+    s.first_name,
+    s.last_name,
+    s.email,
+    s.member,
+    b.custom_values,
+    b.payment_id,
+    b.payment_confirmed_at IS NOT NULL OR b.sepa_exported_at IS NOT NULL AS "paid!""#,
+        event_id.get_ref()
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|row| CanceledEventBooking {
+        booking_id: row.id,
+        first_name: row.first_name,
+        last_name: row.last_name,
+        email: row.email,
+        enrolled: row.enrolled,
+        price: event.total_price(row.member, &row.custom_values).round(2),
+        payment_id: row.payment_id,
+        paid: row.paid,
+    })
+    .collect::<Vec<_>>();
+    bookings.sort_by_key(|booking| booking.booking_id);
+
+    save_event_version(&mut tx, &event).await?;
+
+    tx.commit().await?;
+
+    Ok(Some((event, bookings)))
+}
+
+/// Get the paid bookings which have been canceled together with their event.
+pub(crate) async fn get_event_refunds(
+    pool: &PgPool,
+    event_type: EventType,
+) -> Result<Vec<EventRefund>> {
+    let mut conn = pool.acquire().await?;
+
+    let rows = query!(
+        r#"
+SELECT
+    e.id AS event_id,
+    e.name AS event_name,
+    e.price_member,
+    e.price_non_member,
+    b.id,
+    b.canceled AS "canceled!",
+    b.custom_values,
+    b.payment_id,
+    s.first_name,
+    s.last_name,
+    s.email,
+    s.member
+FROM
+    events e
+    JOIN event_bookings b ON b.event_id = e.id
+    JOIN event_subscribers s ON s.id = b.subscriber_id
+WHERE
+    e.event_type = $1
+    AND e.lifecycle_status = 'Canceled'
+    AND b.canceled >= e.closed
+    AND (b.payment_confirmed_at IS NOT NULL OR b.sepa_exported_at IS NOT NULL)
+ORDER BY
+    e.closed,
+    e.name,
+    b.id"#,
+        event_type as EventType
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let event_ids = rows
+        .iter()
+        .map(|row| row.event_id)
+        .unique()
+        .collect::<Vec<_>>();
+    let custom_fields = fetch_custom_fields(&mut conn, &event_ids).await?;
+
+    let refunds = rows
+        .into_iter()
+        .map(|row| {
+            let price = BookingCustomFieldValues::new(
+                custom_fields
+                    .get(&row.event_id)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+                &row.custom_values,
+            )
+            .total_price(&row.price_member, &row.price_non_member, row.member);
+
+            EventRefund {
+                event_id: row.event_id.into(),
+                event_name: row.event_name,
+                booking_id: row.id,
+                first_name: row.first_name,
+                last_name: row.last_name,
+                email: row.email,
+                price: price.round(2),
+                payment_id: row.payment_id,
+                canceled: row.canceled,
+            }
+        })
+        .collect();
+
+    Ok(refunds)
+}
+
+/// Promotion of the first booking of the waiting list after a spot has been freed.
+pub(crate) enum WaitingListPromotion {
+    /// The booking has been enrolled immediately.
+    Enrolled(EventBooking, String),
+    /// The freed spot has been offered to the booking until the offer expires.
+    Offered(EventBooking, WaitingListOffer),
+}
+
+/// Promote the first booking of the waiting list (of the capacity bucket) without an open offer.
+/// The booking is enrolled immediately, or offered the spot if the event uses waiting list offers.
+async fn promote_waiting_list(
+    conn: &mut PgConnection,
+    event: &Event,
+    bucket_id: Option<i32>,
+) -> Result<Option<WaitingListPromotion>> {
+    // fetch the first waiting list entrance
+    let waiting_list_result: Option<(i32, EventBooking, String)> = query!(
+        r#"
 SELECT
     v.id,
     v.first_name,
@@ -978,60 +1217,231 @@ FROM
     v_event_bookings v
 WHERE
     v.event_id = $1
+    AND v.bucket_id IS NOT DISTINCT FROM $2
     AND v.canceled IS NULL
     AND v.enrolled IS FALSE
+    AND NOT EXISTS (
+        SELECT
+            1
+        FROM
+            event_waiting_list_offers o
+        WHERE
+            o.booking_id = v.id
+            AND o.accepted_at IS NULL
+            AND o.expired_at IS NULL)
 ORDER BY
     v.created"#,
-            event_id.get_ref()
+        event.id.get_ref(),
+        bucket_id
+    )
+    .map(|row| {
+        (
+            row.id.unwrap(),
+            EventBooking::new(
+                event.id.into_inner(),
+                row.first_name.unwrap(),
+                row.last_name.unwrap(),
+                row.street.unwrap(),
+                row.city.unwrap(),
+                row.email.unwrap(),
+                row.phone,
+                row.member,
+                None,
+                None,
+                Vec::new(),
+                None,
+            ),
+            row.payment_id.unwrap(),
         )
-        .map(|row| {
-            (
-                row.id.unwrap(),
-                EventBooking::new(
-                    event_id.into_inner(),
-                    row.first_name.unwrap(),
-                    row.last_name.unwrap(),
-                    row.street.unwrap(),
-                    row.city.unwrap(),
-                    row.email.unwrap(),
-                    row.phone,
-                    row.member,
-                    None,
-                    None,
-                    Vec::new(),
-                    None,
-                ),
-                row.payment_id.unwrap(),
-            )
-        })
-        .fetch_optional(&mut *tx)
+    })
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some((booking_id, booking, payment_id)) = waiting_list_result else {
+        return Ok(None);
+    };
+
+    if event.waiting_list_offer_hours == 0 {
+        query!(
+            r#"UPDATE event_bookings SET enrolled = true WHERE id = $1"#,
+            booking_id,
+        )
+        .execute(&mut *conn)
         .await?;
 
-        // extract and switch enrolled status for waiting list booking - if available
-        if let Some((booking_id, booking, payment_id)) = waiting_list_result {
-            query!(
-                r#"UPDATE event_bookings SET enrolled = true WHERE id = $1"#,
-                booking_id,
-            )
-            .execute(&mut *tx)
-            .await?;
+        return Ok(Some(WaitingListPromotion::Enrolled(booking, payment_id)));
+    }
 
-            first_waiting_list_booking = Some((booking, payment_id));
-        } else {
-            first_waiting_list_booking = None;
-        }
-    } else {
-        // canceled booking was on the waiting list -> do not promote anyone
-        first_waiting_list_booking = None;
+    let offer = query!(
+        r#"INSERT INTO event_waiting_list_offers (booking_id, expires_at) VALUES ($1, NOW() + MAKE_INTERVAL(hours => $2)) RETURNING id, created, expires_at"#,
+        booking_id,
+        i32::from(event.waiting_list_offer_hours)
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let offer = WaitingListOffer {
+        id: offer.id,
+        booking_id,
+        first_name: booking.first_name.clone(),
+        last_name: booking.last_name.clone(),
+        email: booking.email.clone(),
+        created: offer.created,
+        expires_at: offer.expires_at,
+        accepted_at: None,
+        expired_at: None,
+    };
+
+    Ok(Some(WaitingListPromotion::Offered(booking, offer)))
+}
+
+pub(crate) enum OfferAcceptance {
+    /// The booking with the given id has been enrolled.
+    Accepted(i32),
+    AlreadyAccepted,
+    Expired,
+    NotFound,
+}
+
+/// Accept the offer of a freed spot and enroll the booking, if the offer has not expired.
+pub(crate) async fn accept_waiting_list_offer(
+    pool: &PgPool,
+    offer_id: i32,
+) -> Result<OfferAcceptance> {
+    let mut tx = pool.begin().await?;
+
+    let Some(offer) = query!(
+        r#"
+SELECT
+    o.booking_id,
+    o.expires_at,
+    o.accepted_at,
+    o.expired_at
+FROM
+    event_waiting_list_offers o
+WHERE
+    o.id = $1
+FOR UPDATE"#,
+        offer_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(OfferAcceptance::NotFound);
+    };
+
+    if offer.accepted_at.is_some() {
+        return Ok(OfferAcceptance::AlreadyAccepted);
+    }
+    if offer.expired_at.is_some() || offer.expires_at < Utc::now() {
+        return Ok(OfferAcceptance::Expired);
     }
 
-    let event = fetch_event(&mut tx, &event_id, false)
-        .await?
-        .ok_or_else(|| anyhow!("Error fetching event with id '{}'", event_id))?;
+    query!(
+        r#"UPDATE event_waiting_list_offers SET accepted_at = NOW() WHERE id = $1"#,
+        offer_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    query!(
+        r#"UPDATE event_bookings SET enrolled = true WHERE id = $1"#,
+        offer.booking_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(OfferAcceptance::Accepted(offer.booking_id))
+}
+
+/// Expire the offers which have not been accepted in time, cancel their bookings
+/// and promote the next bookings of the waiting lists.
+/// Returns the number of expired offers and the promotions.
+pub(crate) async fn expire_waiting_list_offers(
+    pool: &PgPool,
+) -> Result<(usize, Vec<(Event, WaitingListPromotion)>)> {
+    let mut tx = pool.begin().await?;
+
+    let expired = query!(
+        r#"
+WITH expired AS (
+    UPDATE
+        event_waiting_list_offers
+    SET
+        expired_at = NOW()
+    WHERE
+        accepted_at IS NULL
+        AND expired_at IS NULL
+        AND expires_at < NOW()
+    RETURNING booking_id
+)
+UPDATE
+    event_bookings b
+SET
+    canceled = NOW()
+FROM
+    expired
+WHERE
+    b.id = expired.booking_id
+RETURNING
+    b.event_id,
+    b.bucket_id"#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut promotions = Vec::new();
+    for row in expired.iter() {
+        let event_id = EventId::from(row.event_id);
+        let event = fetch_event(&mut tx, &event_id, false)
+            .await?
+            .ok_or_else(|| anyhow!("Error fetching event with id '{}'", event_id))?;
+        if let Some(promotion) = promote_waiting_list(&mut tx, &event, row.bucket_id).await? {
+            promotions.push((event, promotion));
+        }
+    }
 
     tx.commit().await?;
 
-    Ok((event, canceled_booking, first_waiting_list_booking))
+    Ok((expired.len(), promotions))
+}
+
+/// Get the waiting list offers of the event in the order they have been made.
+pub(crate) async fn get_waiting_list_offers(
+    pool: &PgPool,
+    event_id: &EventId,
+) -> Result<Vec<WaitingListOffer>> {
+    let offers = query_as!(
+        WaitingListOffer,
+        r#"
+SELECT
+    o.id,
+    o.booking_id,
+    s.first_name,
+    s.last_name,
+    s.email,
+    o.created,
+    o.expires_at,
+    o.accepted_at,
+    o.expired_at
+FROM
+    event_waiting_list_offers o
+JOIN event_bookings b ON
+    b.id = o.booking_id
+JOIN event_subscribers s ON
+    s.id = b.subscriber_id
+WHERE
+    b.event_id = $1
+ORDER BY
+    o.created,
+    o.id"#,
+        event_id.get_ref()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(offers)
 }
 
 /// mark the given bookings that the payment reminder email has been sent
@@ -1062,20 +1472,43 @@ pub(crate) async fn mark_as_payment_reminder_sent(
     Ok(())
 }
 
-pub(crate) async fn find_prior_sepa_iban(
+/// Find the IBAN of the last SEPA booking of the subscriber, decrypted.
+pub(crate) async fn find_prior_sepa_iban(
+    pool: &PgPool,
+    subscriber_id: i32,
+    cipher: &IbanCipher,
+) -> Result<Option<String>> {
+    let iban = query_scalar!(
+        r#"SELECT eb.iban FROM event_bookings eb
+        JOIN events e ON eb.event_id = e.id
+        WHERE eb.subscriber_id = $1 AND eb.iban IS NOT NULL AND e.payment_method = 'SepaDirectDebit'
+        ORDER BY eb.created DESC LIMIT 1"#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    iban.flatten().map(|iban| cipher.decrypt(&iban)).transpose()
+}
+
+/// Find the capacity bucket of the event with the same name as the bucket of the
+/// latest booking of the subscriber, so a prebooking keeps the ticket category.
+pub(crate) async fn find_prior_capacity_bucket(
     pool: &PgPool,
+    event_id: &EventId,
     subscriber_id: i32,
-) -> Result<Option<String>> {
-    let iban = query_scalar!(
-        r#"SELECT eb.iban FROM event_bookings eb
-        JOIN events e ON eb.event_id = e.id
-        WHERE eb.subscriber_id = $1 AND eb.iban IS NOT NULL AND e.payment_method = 'SepaDirectDebit'
+) -> Result<Option<i32>> {
+    let bucket_id = query_scalar!(
+        r#"SELECT nb.id FROM event_bookings eb
+        JOIN event_capacity_buckets b ON eb.bucket_id = b.id
+        JOIN event_capacity_buckets nb ON nb.event_id = $1 AND nb.name = b.name
+        WHERE eb.subscriber_id = $2 AND eb.event_id != $1
         ORDER BY eb.created DESC LIMIT 1"#,
+        event_id.get_ref(),
         subscriber_id
     )
     .fetch_optional(pool)
     .await?;
-    Ok(iban.flatten())
+    Ok(bucket_id)
 }
 
 pub(crate) async fn lock_sepa_eligible_bookings(
@@ -1099,9 +1532,11 @@ pub(crate) async fn lock_sepa_eligible_bookings(
     Ok(())
 }
 
+/// Get the bookings of the event which have not been exported yet, with decrypted IBANs.
 pub(crate) async fn get_sepa_eligible_bookings(
     conn: &mut PgConnection,
     event_id: EventId,
+    cipher: &IbanCipher,
 ) -> Result<Vec<EventSubscription>> {
     let bookings = query!(
         r#"
@@ -1131,20 +1566,18 @@ pub(crate) async fn get_sepa_eligible_bookings(
             row.sepa_exported_at,
             row.iban,
             row.comment,
-            vec![
-                row.custom_value_1,
-                row.custom_value_2,
-                row.custom_value_3,
-                row.custom_value_4,
-            ]
-            .into_iter()
-            .flatten()
-            .collect(),
+            row.custom_values.unwrap_or_default(),
         )
     })
     .fetch_all(conn)
     .await?;
-    Ok(bookings)
+    bookings
+        .into_iter()
+        .map(|mut booking| {
+            booking.iban = booking.iban.map(|iban| cipher.decrypt(&iban)).transpose()?;
+            Ok(booking)
+        })
+        .collect()
 }
 
 pub(crate) async fn mark_sepa_exported(conn: &mut PgConnection, booking_ids: &[i32]) -> Result<()> {
@@ -1157,6 +1590,142 @@ pub(crate) async fn mark_sepa_exported(conn: &mut PgConnection, booking_ids: &[i
     Ok(())
 }
 
+/// Get the active bookings of the event which share the email address and name
+/// with another active booking, ordered by person and booking date.
+pub(crate) async fn get_duplicate_bookings(
+    pool: &PgPool,
+    event_id: &EventId,
+) -> Result<Vec<DuplicateBooking>> {
+    let bookings = query_as!(
+        DuplicateBooking,
+        r#"
+SELECT
+    b.id,
+    b.created,
+    s.first_name,
+    s.last_name,
+    s.email,
+    b.enrolled,
+    b.payment_id,
+    b.payment_confirmed_at
+FROM
+    event_bookings b,
+    event_subscribers s
+WHERE
+    b.subscriber_id = s.id
+    AND b.event_id = $1
+    AND b.canceled IS NULL
+    AND (LOWER(TRIM(s.email)), LOWER(TRIM(s.first_name)), LOWER(TRIM(s.last_name))) IN (
+        SELECT
+            LOWER(TRIM(s2.email)),
+            LOWER(TRIM(s2.first_name)),
+            LOWER(TRIM(s2.last_name))
+        FROM
+            event_bookings b2,
+            event_subscribers s2
+        WHERE
+            b2.subscriber_id = s2.id
+            AND b2.event_id = $1
+            AND b2.canceled IS NULL
+        GROUP BY
+            1, 2, 3
+        HAVING
+            COUNT(*) > 1)
+ORDER BY
+    LOWER(TRIM(s.email)),
+    LOWER(TRIM(s.last_name)),
+    LOWER(TRIM(s.first_name)),
+    b.created"#,
+        event_id.get_ref()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(bookings)
+}
+
+/// Get an active booking with its enrollment status and payment id.
+pub(crate) async fn get_active_booking(
+    pool: &PgPool,
+    booking_id: i32,
+) -> Result<Option<(EventBooking, bool, String)>> {
+    let booking = query!(
+        r#"
+SELECT
+    v.event_id AS "event_id!",
+    v.first_name AS "first_name!",
+    v.last_name AS "last_name!",
+    v.street AS "street!",
+    v.city AS "city!",
+    v.email AS "email!",
+    v.phone,
+    v.member,
+    v.comment,
+    v.custom_values AS "custom_values!",
+    v.iban,
+    v.enrolled AS "enrolled!",
+    v.payment_id AS "payment_id!"
+FROM
+    v_event_bookings v
+WHERE
+    v.id = $1
+    AND v.canceled IS NULL"#,
+        booking_id
+    )
+    .map(|row| {
+        (
+            EventBooking::new(
+                row.event_id,
+                row.first_name,
+                row.last_name,
+                row.street,
+                row.city,
+                row.email,
+                row.phone,
+                row.member,
+                None,
+                row.comment,
+                row.custom_values,
+                row.iban,
+            ),
+            row.enrolled,
+            row.payment_id,
+        )
+    })
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(booking)
+}
+
+/// Get the IBANs which have not been encrypted yet, i.e. which do not start with the prefix.
+pub(crate) async fn get_plain_ibans(pool: &PgPool, prefix: &str) -> Result<Vec<(i32, String)>> {
+    let ibans = query!(
+        r#"SELECT id, iban AS "iban!" FROM event_bookings
+        WHERE iban IS NOT NULL AND NOT starts_with(iban, $1)"#,
+        prefix
+    )
+    .map(|row| (row.id, row.iban))
+    .fetch_all(pool)
+    .await?;
+    Ok(ibans)
+}
+
+pub(crate) async fn update_ibans(pool: &PgPool, ibans: &[(i32, String)]) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for (booking_id, iban) in ibans {
+        query!(
+            r#"UPDATE event_bookings SET iban = $2 WHERE id = $1"#,
+            booking_id,
+            iban
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod db_integration_tests {
     use std::collections::HashMap;
@@ -1165,7 +1734,10 @@ mod db_integration_tests {
     use bigdecimal::BigDecimal;
     use chrono::{DateTime, Utc};
 
-    use crate::models::{EventType, LifecycleStatus, PartialEvent, PaymentMethod};
+    use crate::models::{
+        EventCapacityBucket, EventType, LifecycleStatus, PartialEvent, PaymentMethod,
+    };
+    use crate::test_utils::TEST_IBAN_KEY;
 
     use super::*;
 
@@ -1248,6 +1820,253 @@ mod db_integration_tests {
         Ok(())
     }
 
+    #[sqlx::test]
+    async fn test_capacity_buckets(pool: PgPool) -> Result<()> {
+        let bucket = |name: &str| EventCapacityBucket {
+            id: None,
+            name: name.to_string(),
+            max_subscribers: 1,
+            max_waiting_list: 1,
+        };
+        let partial = PartialEvent {
+            event_type: Some(EventType::Fitness),
+            lifecycle_status: Some(LifecycleStatus::Published),
+            name: Some("Familienwanderung".to_string()),
+            sort_index: Some(1),
+            short_description: Some("Wandern".to_string()),
+            description: Some("Full description".to_string()),
+            image: Some("test.png".to_string()),
+            light: Some(false),
+            duration_in_minutes: Some(60),
+            max_subscribers: Some(-1),
+            max_waiting_list: Some(0),
+            price_member: Some("15.00".parse().unwrap()),
+            price_non_member: Some("20.00".parse().unwrap()),
+            location: Some("Wald".to_string()),
+            booking_template: Some("Template".to_string()),
+            payment_account: Some("Account".to_string()),
+            external_operator: Some(false),
+            dates: Some(vec![Utc::now()]),
+            capacity_buckets: Some(vec![bucket("Erwachsene"), bucket("Kinder")]),
+            ..Default::default()
+        };
+        let (event, _) = crate::db::events::write_event(&pool, partial).await?;
+        let adults = event.capacity_buckets[0].id;
+        let kids = event.capacity_buckets[1].id;
+
+        let booking = |email: &str, bucket_id: Option<i32>| {
+            let mut booking = EventBooking::new(
+                event.id.into_inner(),
+                "Max".into(),
+                "Mustermann".into(),
+                "Teststr 1".into(),
+                "Teststadt".into(),
+                email.into(),
+                None,
+                Some(true),
+                None,
+                None,
+                Vec::new(),
+                None,
+            );
+            booking.bucket_id = bucket_id;
+            booking
+        };
+
+        // each bucket has its own subscribers and waiting list
+        for (email, bucket_id, booked) in [
+            ("kid1@example.com", kids, Some(true)),
+            ("kid2@example.com", kids, Some(false)),
+            ("adult1@example.com", adults, Some(true)),
+            ("adult2@example.com", adults, Some(false)),
+            ("kid3@example.com", kids, None),
+        ] {
+            let result = book_event(&pool, &booking(email, bucket_id)).await?;
+            match booked {
+                Some(true) => assert!(matches!(result, BookingResult::Booked(..)), "{email}"),
+                Some(false) => {
+                    assert!(matches!(result, BookingResult::WaitingList(..)), "{email}")
+                }
+                None => assert!(matches!(result, BookingResult::BookedOut), "{email}"),
+            }
+        }
+
+        let counters = get_event_counters(&pool, LifecycleStatus::Published).await?;
+        let counter = counters
+            .iter()
+            .find(|counter| counter.id == event.id)
+            .unwrap();
+        assert_eq!((counter.subscribers, counter.waiting_list), (2, 2));
+        assert_eq!(
+            counter
+                .buckets
+                .iter()
+                .map(|bucket| (
+                    bucket.name.as_str(),
+                    bucket.subscribers,
+                    bucket.waiting_list
+                ))
+                .collect::<Vec<_>>(),
+            vec![("Erwachsene", 1, 1), ("Kinder", 1, 1)]
+        );
+        assert!(counter.is_booked_up());
+
+        // canceling an adult promotes the adult on the waiting list,
+        // even though the kid has been waiting longer
+        let booking_id = query_scalar!(
+            r#"SELECT b.id FROM event_bookings b, event_subscribers s WHERE b.subscriber_id = s.id AND s.email = $1"#,
+            "adult1@example.com"
+        )
+        .fetch_one(&pool)
+        .await?;
+        let (_, canceled_booking, promotion) = cancel_event_booking(&pool, booking_id).await?;
+        assert_eq!(canceled_booking.email, "adult1@example.com");
+        let Some(WaitingListPromotion::Enrolled(promoted, _)) = promotion else {
+            panic!("the adult on the waiting list should have been enrolled");
+        };
+        assert_eq!(promoted.email, "adult2@example.com");
+
+        let enrolled = get_bookings(&pool, &event.id, Some(true)).await?;
+        assert_eq!(
+            enrolled
+                .iter()
+                .map(|(booking, _, _)| booking.email.as_str())
+                .collect::<Vec<_>>(),
+            vec!["kid1@example.com", "adult2@example.com"]
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_waiting_list_offers(pool: PgPool) -> Result<()> {
+        let partial = PartialEvent {
+            event_type: Some(EventType::Fitness),
+            lifecycle_status: Some(LifecycleStatus::Published),
+            name: Some("Yoga".to_string()),
+            sort_index: Some(1),
+            short_description: Some("Yoga".to_string()),
+            description: Some("Full description".to_string()),
+            image: Some("test.png".to_string()),
+            light: Some(false),
+            duration_in_minutes: Some(60),
+            max_subscribers: Some(1),
+            max_waiting_list: Some(2),
+            price_member: Some("15.00".parse().unwrap()),
+            price_non_member: Some("20.00".parse().unwrap()),
+            location: Some("Halle".to_string()),
+            booking_template: Some("Template".to_string()),
+            payment_account: Some("Account".to_string()),
+            external_operator: Some(false),
+            dates: Some(vec![Utc::now()]),
+            waiting_list_offer_hours: Some(24),
+            ..Default::default()
+        };
+        let (event, _) = crate::db::events::write_event(&pool, partial).await?;
+        assert_eq!(event.waiting_list_offer_hours, 24);
+
+        for email in [
+            "first@example.com",
+            "second@example.com",
+            "third@example.com",
+        ] {
+            let booking = EventBooking::new(
+                event.id.into_inner(),
+                "Max".into(),
+                "Mustermann".into(),
+                "Teststr 1".into(),
+                "Teststadt".into(),
+                email.into(),
+                None,
+                Some(true),
+                None,
+                None,
+                Vec::new(),
+                None,
+            );
+            book_event(&pool, &booking).await?;
+        }
+        let booking_id = |email: &'static str| {
+            query_scalar!(
+                r#"SELECT b.id FROM event_bookings b, event_subscribers s WHERE b.subscriber_id = s.id AND s.email = $1"#,
+                email
+            )
+            .fetch_one(&pool)
+        };
+
+        // canceling the attendee offers the spot to the first person on the waiting list
+        let (_, _, promotion) =
+            cancel_event_booking(&pool, booking_id("first@example.com").await?).await?;
+        let Some(WaitingListPromotion::Offered(offered, offer)) = promotion else {
+            panic!("the spot should have been offered");
+        };
+        assert_eq!(offered.email, "second@example.com");
+        assert!(offer.expires_at > Utc::now() + chrono::Duration::hours(23));
+
+        // the open offer reserves the spot
+        let counters = get_event_counters(&pool, LifecycleStatus::Published).await?;
+        let counter = counters
+            .iter()
+            .find(|counter| counter.id == event.id)
+            .unwrap();
+        assert_eq!((counter.subscribers, counter.waiting_list), (1, 1));
+        assert!(get_bookings(&pool, &event.id, Some(true)).await?.is_empty());
+
+        // an offer which is not overdue does not expire
+        let (expired, _) = expire_waiting_list_offers(&pool).await?;
+        assert_eq!(expired, 0);
+
+        // an overdue offer is expired, its booking canceled and the spot offered to the next person
+        query!(
+            r#"UPDATE event_waiting_list_offers SET expires_at = NOW() - INTERVAL '1 hour' WHERE id = $1"#,
+            offer.id
+        )
+        .execute(&pool)
+        .await?;
+        assert!(matches!(
+            accept_waiting_list_offer(&pool, offer.id).await?,
+            OfferAcceptance::Expired
+        ));
+        let (expired, mut promotions) = expire_waiting_list_offers(&pool).await?;
+        assert_eq!(expired, 1);
+        let Some((_, WaitingListPromotion::Offered(offered, next_offer))) = promotions.pop() else {
+            panic!("the spot should have been offered to the next person");
+        };
+        assert_eq!(offered.email, "third@example.com");
+
+        // accepting the offer enrolls the booking
+        match accept_waiting_list_offer(&pool, next_offer.id).await? {
+            OfferAcceptance::Accepted(id) => assert_eq!(id, booking_id("third@example.com").await?),
+            _ => panic!("the offer should have been accepted"),
+        }
+        assert!(matches!(
+            accept_waiting_list_offer(&pool, next_offer.id).await?,
+            OfferAcceptance::AlreadyAccepted
+        ));
+        let enrolled = get_bookings(&pool, &event.id, Some(true)).await?;
+        assert_eq!(enrolled.len(), 1);
+        assert_eq!(enrolled[0].0.email, "third@example.com");
+
+        // the offer chain is visible per event
+        let offers = get_waiting_list_offers(&pool, &event.id).await?;
+        assert_eq!(
+            offers
+                .iter()
+                .map(|offer| (
+                    offer.email.as_str(),
+                    offer.accepted_at.is_some(),
+                    offer.expired_at.is_some()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("second@example.com", false, true),
+                ("third@example.com", true, false)
+            ]
+        );
+
+        Ok(())
+    }
+
     #[sqlx::test]
     async fn test_mark_as_paid_and_update_payment(pool: PgPool) -> Result<()> {
         let partial = PartialEvent {
@@ -1343,6 +2162,7 @@ mod db_integration_tests {
 
     #[sqlx::test]
     async fn test_find_prior_sepa_iban(pool: PgPool) -> Result<()> {
+        let cipher = IbanCipher::new(TEST_IBAN_KEY)?;
         let partial = PartialEvent {
             event_type: Some(EventType::Events),
             lifecycle_status: Some(LifecycleStatus::Published),
@@ -1439,13 +2259,13 @@ mod db_integration_tests {
             false,
             None::<DateTime<Utc>>,
             "pay_002",
-            Some("DE75512108001245126199"),
+            Some(cipher.encrypt("DE75512108001245126199")?),
             None::<DateTime<Utc>>,
         )
         .execute(&pool)
         .await?;
 
-        let prior_iban = find_prior_sepa_iban(&pool, subscriber_id).await?;
+        let prior_iban = find_prior_sepa_iban(&pool, subscriber_id, &cipher).await?;
         assert_eq!(prior_iban.as_deref(), Some("DE75512108001245126199"));
 
         Ok(())
@@ -1453,6 +2273,7 @@ mod db_integration_tests {
 
     #[sqlx::test]
     async fn test_sepa_eligible_bookings(pool: PgPool) -> Result<()> {
+        let cipher = IbanCipher::new(TEST_IBAN_KEY)?;
         let partial = PartialEvent {
             event_type: Some(EventType::Events),
             lifecycle_status: Some(LifecycleStatus::Published),
@@ -1527,7 +2348,7 @@ mod db_integration_tests {
                 false,
                 canceled,
                 format!("pay_{:03}", i),
-                Some("DE89370400440532013000"),
+                Some(cipher.encrypt("DE89370400440532013000")?),
                 None::<DateTime<Utc>>,
                 sepa_exported_at,
             )
@@ -1536,9 +2357,10 @@ mod db_integration_tests {
         }
 
         let mut conn = pool.acquire().await?;
-        let eligible = get_sepa_eligible_bookings(&mut conn, event_id).await?;
+        let eligible = get_sepa_eligible_bookings(&mut conn, event_id, &cipher).await?;
         assert_eq!(eligible.len(), 1);
         assert_eq!(eligible[0].email, "eligible@example.com");
+        assert_eq!(eligible[0].iban.as_deref(), Some("DE89370400440532013000"));
 
         Ok(())
     }
@@ -1913,6 +2735,10 @@ mod db_integration_tests {
                 None,
                 None,
                 true,
+                Vec::new(),
+                false,
+                None,
+                Vec::new(),
             )]),
             ..Default::default()
         };
@@ -1920,7 +2746,7 @@ mod db_integration_tests {
         let (event, _) = crate::db::events::write_event(&pool, partial).await?;
         let event_id = event.id;
 
-        // subscriber with custom_value_1 = "3" → price = 25 × 3 = 75
+        // subscriber with custom_values = ["3"] → price = 25 × 3 = 75
         query!(
             r#"INSERT INTO event_subscribers (first_name, last_name, street, city, email, phone, member)
             VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
@@ -1942,7 +2768,7 @@ mod db_integration_tests {
         let subscriber_id = subscriber_row.id;
 
         query!(
-            r#"INSERT INTO event_bookings (event_id, subscriber_id, enrolled, pre_booking, canceled, payment_id, custom_value_1)
+            r#"INSERT INTO event_bookings (event_id, subscriber_id, enrolled, pre_booking, canceled, payment_id, custom_values)
             VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
             event_id.get_ref(),
             subscriber_id,
@@ -1950,7 +2776,7 @@ mod db_integration_tests {
             false,
             None::<DateTime<Utc>>,
             "pay_pr_001",
-            "3"
+            &["3".to_string()]
         )
         .execute(&pool)
         .await?;
@@ -1965,6 +2791,99 @@ mod db_integration_tests {
         Ok(())
     }
 
+    #[sqlx::test]
+    async fn test_total_price_with_custom_field_surcharges(pool: PgPool) -> Result<()> {
+        // Bus +5 €, Essen +12 € / +10 €, Personen as multiplier
+        query!(
+            r#"INSERT INTO event_custom_fields (name, type, price_relevant, options, price, option_prices)
+               VALUES ('Bus', 'Checkbox', false, '{}', 5, '{}'),
+                      ('Essen', 'Select', false, '{Fleisch,Vegetarisch}', NULL, '{12,10}'),
+                      ('Personen', 'Number', true, '{}', NULL, '{}')"#
+        )
+        .execute(&pool)
+        .await?;
+        let mut custom_fields = get_all_custom_fields(&pool).await?;
+        custom_fields.sort_by_key(|field| field.id);
+
+        let partial = PartialEvent {
+            event_type: Some(EventType::Events),
+            lifecycle_status: Some(LifecycleStatus::Published),
+            name: Some("Weinwanderung".to_string()),
+            sort_index: Some(1),
+            short_description: Some("Short".to_string()),
+            description: Some("Full".to_string()),
+            image: Some("test.png".to_string()),
+            light: Some(false),
+            duration_in_minutes: Some(90),
+            max_subscribers: Some(20),
+            max_waiting_list: Some(5),
+            price_member: Some("25.00".parse().unwrap()),
+            price_non_member: Some("30.00".parse().unwrap()),
+            location: Some("Weingut".to_string()),
+            booking_template: Some("Template".to_string()),
+            payment_account: Some("DE1234".to_string()),
+            external_operator: Some(false),
+            dates: Some(vec![Utc::now()]),
+            custom_fields: Some(custom_fields),
+            ..Default::default()
+        };
+
+        let (event, _) = crate::db::events::write_event(&pool, partial).await?;
+        let event_id = event.id;
+
+        query!(
+            r#"INSERT INTO event_subscribers (first_name, last_name, street, city, email, phone, member)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            "Max",
+            "Mustermann",
+            "Teststr 1",
+            "Vienna",
+            "max@example.com",
+            None::<String>,
+            true
+        )
+        .execute(&pool)
+        .await?;
+
+        let subscriber_row =
+            query!(r#"SELECT id FROM event_subscribers WHERE email = 'max@example.com'"#)
+                .fetch_one(&pool)
+                .await?;
+        let subscriber_id = subscriber_row.id;
+
+        // (25 + 5 + 10) × 2 = 80
+        query!(
+            r#"INSERT INTO event_bookings (event_id, subscriber_id, enrolled, pre_booking, canceled, payment_id, custom_values)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            event_id.get_ref(),
+            subscriber_id,
+            true,
+            false,
+            None::<DateTime<Utc>>,
+            "pay_pr_001",
+            &[
+                "true".to_string(),
+                "Vegetarisch".to_string(),
+                "2".to_string()
+            ]
+        )
+        .execute(&pool)
+        .await?;
+
+        let pay_id = "pay_pr_001".to_string();
+        let mut payment_ids = HashSet::new();
+        payment_ids.insert(&pay_id);
+        let bookings = get_bookings_to_verify_payment(&pool, payment_ids).await?;
+        assert_eq!(bookings.len(), 1);
+        assert_eq!(bookings[0].price, BigDecimal::from(80));
+
+        let unpaid = get_event_bookings_without_payment(&pool, EventType::Events).await?;
+        assert_eq!(unpaid.len(), 1);
+        assert_eq!(unpaid[0].0.price, BigDecimal::from(80));
+
+        Ok(())
+    }
+
     #[sqlx::test]
     async fn test_verify_payment_base_price_without_price_relevant_field(
         pool: PgPool,
@@ -2079,6 +2998,10 @@ mod db_integration_tests {
                 None,
                 None,
                 true,
+                Vec::new(),
+                false,
+                None,
+                Vec::new(),
             )]),
             ..Default::default()
         };
@@ -2106,9 +3029,9 @@ mod db_integration_tests {
                 .await?;
         let subscriber_id = subscriber_row.id;
 
-        // booking with custom_value_1 = "4" → price = 25 × 4 = 100
+        // booking with custom_values = ["4"] → price = 25 × 4 = 100
         query!(
-            r#"INSERT INTO event_bookings (event_id, subscriber_id, enrolled, pre_booking, canceled, payment_id, payment_confirmed_at, custom_value_1)
+            r#"INSERT INTO event_bookings (event_id, subscriber_id, enrolled, pre_booking, canceled, payment_id, payment_confirmed_at, custom_values)
             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
             event_id.get_ref(),
             subscriber_id,
@@ -2117,7 +3040,7 @@ mod db_integration_tests {
             None::<DateTime<Utc>>,
             "pay_unpr_001",
             None::<DateTime<Utc>>,
-            "4"
+            &["4".to_string()]
         )
         .execute(&pool)
         .await?;
@@ -0,0 +1,293 @@
+use crate::models::{
+    NewsCampaign, NewsCampaignInput, NewsCampaignRecipient, NewsCampaignRecipientStatus,
+    NewsCampaignStatus, NewsTopic,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, query, query_as};
+
+struct CampaignRow {
+    id: i32,
+    created: DateTime<Utc>,
+    topic: String,
+    subject: String,
+    body: String,
+    status: NewsCampaignStatus,
+    scheduled_at: Option<DateTime<Utc>>,
+    sent_at: Option<DateTime<Utc>>,
+}
+
+impl From<CampaignRow> for NewsCampaign {
+    fn from(row: CampaignRow) -> Self {
+        NewsCampaign {
+            id: row.id,
+            created: row.created,
+            topic: NewsTopic::new(row.topic),
+            subject: row.subject,
+            body: row.body,
+            status: row.status,
+            scheduled_at: row.scheduled_at,
+            sent_at: row.sent_at,
+        }
+    }
+}
+
+fn status_of(input: &NewsCampaignInput) -> NewsCampaignStatus {
+    if input.scheduled_at.is_some() {
+        NewsCampaignStatus::Scheduled
+    } else {
+        NewsCampaignStatus::Draft
+    }
+}
+
+pub(crate) async fn get_campaigns(pool: &PgPool) -> Result<Vec<NewsCampaign>> {
+    let campaigns = query_as!(
+        CampaignRow,
+        r#"SELECT id, created, topic, subject, body, status AS "status: NewsCampaignStatus", scheduled_at, sent_at
+        FROM news_campaigns ORDER BY created DESC"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(campaigns.into_iter().map(NewsCampaign::from).collect())
+}
+
+pub(crate) async fn get_campaign(pool: &PgPool, id: i32) -> Result<Option<NewsCampaign>> {
+    let campaign = query_as!(
+        CampaignRow,
+        r#"SELECT id, created, topic, subject, body, status AS "status: NewsCampaignStatus", scheduled_at, sent_at
+        FROM news_campaigns WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(campaign.map(NewsCampaign::from))
+}
+
+pub(crate) async fn insert_campaign(
+    pool: &PgPool,
+    input: &NewsCampaignInput,
+) -> Result<NewsCampaign> {
+    let campaign = query_as!(
+        CampaignRow,
+        r#"INSERT INTO news_campaigns (topic, subject, body, status, scheduled_at) VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, created, topic, subject, body, status AS "status: NewsCampaignStatus", scheduled_at, sent_at"#,
+        input.topic.as_str(),
+        input.subject,
+        input.body,
+        status_of(input) as NewsCampaignStatus,
+        input.scheduled_at
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(campaign.into())
+}
+
+/// Update a campaign which has not been started yet.
+/// Returns `None` if the campaign does not exist or is already being sent.
+pub(crate) async fn update_campaign(
+    pool: &PgPool,
+    id: i32,
+    input: &NewsCampaignInput,
+) -> Result<Option<NewsCampaign>> {
+    let campaign = query_as!(
+        CampaignRow,
+        r#"UPDATE news_campaigns SET topic = $2, subject = $3, body = $4, status = $5, scheduled_at = $6
+        WHERE id = $1 AND status IN ('Draft', 'Scheduled')
+        RETURNING id, created, topic, subject, body, status AS "status: NewsCampaignStatus", scheduled_at, sent_at"#,
+        id,
+        input.topic.as_str(),
+        input.subject,
+        input.body,
+        status_of(input) as NewsCampaignStatus,
+        input.scheduled_at
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(campaign.map(NewsCampaign::from))
+}
+
+/// Delete a campaign which has not been started yet.
+pub(crate) async fn delete_campaign(pool: &PgPool, id: i32) -> Result<bool> {
+    let result = query!(
+        r#"DELETE FROM news_campaigns WHERE id = $1 AND status IN ('Draft', 'Scheduled')"#,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Start all scheduled campaigns which are due: determine the recipients
+/// from the current subscribers of the campaign topic and mark the campaign as sending.
+pub(crate) async fn start_due_campaigns(pool: &PgPool, now: DateTime<Utc>) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+
+    let campaigns = query!(
+        r#"SELECT id, topic FROM news_campaigns
+        WHERE status = 'Scheduled' AND scheduled_at <= $1
+        FOR UPDATE SKIP LOCKED"#,
+        now
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for campaign in &campaigns {
+        query!(
+            r#"INSERT INTO news_campaign_recipients (campaign_id, email)
+            SELECT $1, s.email FROM news_subscribers s
+            JOIN news_subscriptions ns ON ns.subscriber_id = s.id
+            JOIN news_topics t ON t.id = ns.topic_id
+            WHERE t.name = $2"#,
+            campaign.id,
+            campaign.topic
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        query!(
+            r#"UPDATE news_campaigns SET status = 'Sending' WHERE id = $1"#,
+            campaign.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(campaigns.len() as u64)
+}
+
+/// Claim the next recipients of all campaigns which are being sent: the pending ones
+/// and the failed ones with less than `max_attempts` attempts. Recipients which have been
+/// claimed after `claimed_before` are skipped, so overlapping runs don't send a mail twice
+/// and failed mails are retried later. Returns tuples of (recipient id, campaign, email).
+pub(crate) async fn claim_pending_recipients(
+    pool: &PgPool,
+    limit: i64,
+    max_attempts: i32,
+    claimed_before: DateTime<Utc>,
+) -> Result<Vec<(i32, NewsCampaign, String)>> {
+    let rows = query!(
+        r#"WITH claimed AS (
+            UPDATE news_campaign_recipients SET claimed_at = NOW(), attempts = attempts + 1
+            WHERE id IN (
+                SELECT r.id FROM news_campaign_recipients r
+                JOIN news_campaigns c ON c.id = r.campaign_id
+                WHERE c.status = 'Sending'
+                  AND (r.status = 'Pending' OR (r.status = 'Failed' AND r.attempts < $2))
+                  AND (r.claimed_at IS NULL OR r.claimed_at < $3)
+                ORDER BY c.id, r.id
+                LIMIT $1
+                FOR UPDATE OF r SKIP LOCKED
+            )
+            RETURNING id, campaign_id, email
+        )
+        SELECT r.id AS "recipient_id!", r.email AS "email!", c.id, c.created, c.topic, c.subject,
+            c.body, c.status AS "status: NewsCampaignStatus", c.scheduled_at, c.sent_at
+        FROM claimed r
+        JOIN news_campaigns c ON c.id = r.campaign_id
+        ORDER BY c.id, r.id"#,
+        limit,
+        max_attempts,
+        claimed_before
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let recipients = rows
+        .into_iter()
+        .map(|row| {
+            let campaign = NewsCampaign::from(CampaignRow {
+                id: row.id,
+                created: row.created,
+                topic: row.topic,
+                subject: row.subject,
+                body: row.body,
+                status: row.status,
+                scheduled_at: row.scheduled_at,
+                sent_at: row.sent_at,
+            });
+            (row.recipient_id, campaign, row.email)
+        })
+        .collect();
+
+    Ok(recipients)
+}
+
+pub(crate) async fn update_recipient_status(
+    pool: &PgPool,
+    recipient_id: i32,
+    status: NewsCampaignRecipientStatus,
+    error: Option<&str>,
+) -> Result<()> {
+    query!(
+        r#"UPDATE news_campaign_recipients SET status = $2, error = $3, sent_at = CASE WHEN $2 = 'Sent'::news_campaign_recipient_status THEN NOW() ELSE sent_at END WHERE id = $1"#,
+        recipient_id,
+        status as NewsCampaignRecipientStatus,
+        error
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Mark all sending campaigns without pending recipients or failed recipients
+/// to retry as sent.
+pub(crate) async fn finish_sent_campaigns(pool: &PgPool, max_attempts: i32) -> Result<u64> {
+    let result = query!(
+        r#"UPDATE news_campaigns c SET status = 'Sent', sent_at = NOW()
+        WHERE c.status = 'Sending'
+          AND NOT EXISTS (
+              SELECT 1 FROM news_campaign_recipients r
+              WHERE r.campaign_id = c.id
+                AND (r.status = 'Pending' OR (r.status = 'Failed' AND r.attempts < $1))
+          )"#,
+        max_attempts
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub(crate) async fn get_recipients(
+    pool: &PgPool,
+    campaign_id: i32,
+) -> Result<Vec<NewsCampaignRecipient>> {
+    let recipients = query_as!(
+        NewsCampaignRecipient,
+        r#"SELECT email, status AS "status: NewsCampaignRecipientStatus", sent_at, error, attempts
+        FROM news_campaign_recipients WHERE campaign_id = $1 ORDER BY email"#,
+        campaign_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(recipients)
+}
+
+/// Record a bounce reported for a recipient of a campaign.
+pub(crate) async fn mark_recipient_bounced(
+    pool: &PgPool,
+    campaign_id: i32,
+    email: &str,
+    reason: &str,
+) -> Result<bool> {
+    let result = query!(
+        r#"UPDATE news_campaign_recipients SET status = 'Bounced', error = $3
+        WHERE campaign_id = $1 AND email = $2"#,
+        campaign_id,
+        email,
+        reason
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
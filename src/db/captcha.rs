@@ -0,0 +1,27 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, query};
+
+/// Mark the challenge as solved. Returns `false` if it has been solved before.
+/// Solutions created before `valid_since` belong to expired challenges and are removed.
+pub(crate) async fn insert_captcha_solution(
+    pool: &PgPool,
+    challenge: &str,
+    valid_since: DateTime<Utc>,
+) -> Result<bool> {
+    query!(
+        r#"DELETE FROM captcha_solutions WHERE created < $1"#,
+        valid_since
+    )
+    .execute(pool)
+    .await?;
+
+    let result = query!(
+        r#"INSERT INTO captcha_solutions (challenge) VALUES ($1) ON CONFLICT (challenge) DO NOTHING"#,
+        challenge
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
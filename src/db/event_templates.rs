@@ -0,0 +1,72 @@
+use crate::models::EventTemplate;
+use anyhow::Result;
+use sqlx::{PgPool, query, query_scalar};
+
+pub(crate) async fn get_event_templates(pool: &PgPool) -> Result<Vec<EventTemplate>> {
+    let rows = query!(r#"SELECT id, name, event FROM event_templates ORDER BY name"#)
+        .fetch_all(pool)
+        .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(EventTemplate {
+                id: Some(row.id),
+                name: row.name,
+                event: serde_json::from_value(row.event)?,
+            })
+        })
+        .collect()
+}
+
+pub(crate) async fn get_event_template(pool: &PgPool, id: i32) -> Result<Option<EventTemplate>> {
+    let row = query!(
+        r#"SELECT id, name, event FROM event_templates WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    row.map(|row| {
+        Ok(EventTemplate {
+            id: Some(row.id),
+            name: row.name,
+            event: serde_json::from_value(row.event)?,
+        })
+    })
+    .transpose()
+}
+
+/// Insert the template or update it if it has an id. Returns the id of the template.
+pub(crate) async fn save_event_template(pool: &PgPool, template: &EventTemplate) -> Result<i32> {
+    let event = serde_json::to_value(&template.event)?;
+    let id =
+        match template.id {
+            Some(id) => query_scalar!(
+                r#"UPDATE event_templates SET name = $2, event = $3 WHERE id = $1 RETURNING id"#,
+                id,
+                template.name,
+                event
+            )
+            .fetch_one(pool)
+            .await?,
+            None => {
+                query_scalar!(
+                    r#"INSERT INTO event_templates (name, event) VALUES ($1, $2) RETURNING id"#,
+                    template.name,
+                    event
+                )
+                .fetch_one(pool)
+                .await?
+            }
+        };
+
+    Ok(id)
+}
+
+pub(crate) async fn delete_event_template(pool: &PgPool, id: i32) -> Result<()> {
+    query!(r#"DELETE FROM event_templates WHERE id = $1"#, id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -1,13 +1,15 @@
+use crate::error::ValidationError;
 use crate::models::{
-    Event, EventCounter, EventCustomField, EventCustomFieldType, EventId, EventSubscription,
-    EventType, LifecycleStatus, PartialEvent, PaymentMethod,
+    Event, EventBucketCounter, EventCapacityBucket, EventCounter, EventCustomField,
+    EventCustomFieldType, EventId, EventSubscription, EventType, EventVersion, LifecycleStatus,
+    PartialEvent, PaymentMethod,
 };
-use anyhow::{Result, anyhow, bail};
+use anyhow::{Context, Result, anyhow, bail};
 use chrono::{DateTime, Utc};
 use itertools::Itertools;
 use sqlx::{
-    PgConnection, PgPool, Postgres, QueryBuilder, Row, postgres::PgRow, query,
-    query_builder::Separated,
+    PgConnection, PgPool, Postgres, QueryBuilder, Row, postgres::PgRow, query, query_as,
+    query_builder::Separated, query_scalar,
 };
 use std::collections::HashMap;
 
@@ -16,6 +18,7 @@ pub(crate) async fn get_events(
     sort: bool,
     lifecycle_status: Option<Vec<LifecycleStatus>>,
     subscribers: bool,
+    canceled: bool,
 ) -> Result<Vec<Event>> {
     let mut conn = pool.acquire().await?;
 
@@ -47,6 +50,11 @@ SELECT
     e.alt_email_address,
     e.external_operator,
     e.payment_method AS payment_method,
+    e.waiting_list_offer_hours,
+    e.publish_at,
+    e.unpublish_at,
+    e.booking_start,
+    e.booking_end,
     vev.subscribers,
     vev.waiting_list
 FROM
@@ -75,6 +83,13 @@ WHERE
         );
         query_builder.push_bind(LifecycleStatus::Archived);
     }
+    if !canceled {
+        query_builder.push(
+            r#"
+ AND e.lifecycle_status != "#,
+        );
+        query_builder.push_bind(LifecycleStatus::Canceled);
+    }
 
     query_builder.push(
         r#"
@@ -101,6 +116,17 @@ ORDER BY
         ));
     }
 
+    let event_ids = result
+        .iter()
+        .map(|(event, _)| event.id.into_inner())
+        .collect::<Vec<_>>();
+    let mut bucket_counters = fetch_bucket_counters(&mut conn, &event_ids).await?;
+    for (event, counter) in result.iter_mut() {
+        counter.buckets = bucket_counters
+            .remove(event.id.get_ref())
+            .unwrap_or_default();
+    }
+
     let mut iter = result.into_iter();
     let mut events: Vec<Event>;
     if sort {
@@ -118,6 +144,7 @@ ORDER BY
     if !events.is_empty() {
         insert_event_dates(&mut conn, &mut events).await?;
         insert_event_custom_fields(&mut conn, &mut events).await?;
+        insert_event_capacity_buckets(&mut conn, &mut events).await?;
         if subscribers {
             insert_event_subscribers(&mut conn, &mut events).await?;
         }
@@ -179,7 +206,12 @@ SELECT
     e.alt_booking_button_text,
     e.alt_email_address,
     e.external_operator,
-    e.payment_method AS payment_method
+    e.payment_method AS payment_method,
+    e.waiting_list_offer_hours,
+    e.publish_at,
+    e.unpublish_at,
+    e.booking_start,
+    e.booking_end
 FROM
     events e
 WHERE
@@ -199,6 +231,7 @@ WHERE
     if !events.is_empty() {
         insert_event_dates(conn, &mut events).await?;
         insert_event_custom_fields(conn, &mut events).await?;
+        insert_event_capacity_buckets(conn, &mut events).await?;
         if subscribers {
             insert_event_subscribers(conn, &mut events).await?;
         }
@@ -246,64 +279,182 @@ ORDER BY
 }
 
 async fn insert_event_custom_fields(conn: &mut PgConnection, events: &mut [Event]) -> Result<()> {
-    let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+    let event_ids = events
+        .iter()
+        .map(|event| event.id.into_inner())
+        .collect::<Vec<_>>();
+    let mut result = fetch_custom_fields(conn, &event_ids).await?;
+
+    for event in events.iter_mut() {
+        if let Some(custom_fields) = result.remove(event.id.get_ref()) {
+            event.custom_fields = custom_fields;
+        } else {
+            event.custom_fields = Default::default();
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the custom fields of the given events, ordered by their position.
+pub(super) async fn fetch_custom_fields(
+    conn: &mut PgConnection,
+    event_ids: &[i32],
+) -> Result<HashMap<i32, Vec<EventCustomField>>> {
+    let rows = query!(
         r#"
 SELECT
-    e.id AS event_id,
+	a.event_id,
 	ecf.id,
 	ecf.name,
-	ecf.type,
+	ecf.type AS "cf_type: EventCustomFieldType",
 	ecf.min_value,
 	ecf.max_value,
-	ecf.price_relevant
+	ecf.price_relevant,
+	ecf.options,
+	ecf.required,
+	ecf.price,
+	ecf.option_prices
 FROM
-	events e
-LEFT JOIN event_custom_fields ecf ON
-	ecf.id = ANY(ARRAY[e.custom_field_1,
-	e.custom_field_2,
-	e.custom_field_3,
-	e.custom_field_4])
+	event_custom_field_assignments a
+JOIN event_custom_fields ecf ON
+	ecf.id = a.custom_field_id
 WHERE
-    e.id IN ("#,
-    );
-    let mut separated = query_builder.separated(", ");
-    for event in events.iter() {
-        separated.push_bind(event.id.get_ref());
-    }
-    separated.push_unseparated(
-        r#")
-	AND ecf.id IS NOT NULL
+	a.event_id = ANY($1)
 ORDER BY
-    e.id"#,
-    );
+	a.event_id,
+	a.position"#,
+        event_ids
+    )
+    .fetch_all(conn)
+    .await?;
 
     let mut result = HashMap::new();
-    for row in query_builder.build().fetch_all(conn).await? {
-        let id: i32 = row.try_get("event_id")?;
+    for row in rows {
         result
-            .entry(id)
+            .entry(row.event_id)
             .or_insert_with(Vec::new)
             .push(EventCustomField::new(
-                row.try_get("id")?,
-                row.try_get("name")?,
-                row.try_get("type")?,
-                row.try_get("min_value")?,
-                row.try_get("max_value")?,
-                row.try_get("price_relevant")?,
+                row.id,
+                row.name,
+                row.cf_type,
+                row.min_value,
+                row.max_value,
+                row.price_relevant,
+                row.options,
+                row.required,
+                row.price,
+                row.option_prices,
             ));
     }
 
+    Ok(result)
+}
+
+async fn insert_event_capacity_buckets(
+    conn: &mut PgConnection,
+    events: &mut [Event],
+) -> Result<()> {
+    let event_ids = events
+        .iter()
+        .map(|event| event.id.into_inner())
+        .collect::<Vec<_>>();
+    let mut result = fetch_capacity_buckets(conn, &event_ids).await?;
+
     for event in events.iter_mut() {
-        if let Some(custom_fields) = result.remove(event.id.get_ref()) {
-            event.custom_fields = custom_fields;
-        } else {
-            event.custom_fields = Default::default();
-        }
+        event.capacity_buckets = result.remove(event.id.get_ref()).unwrap_or_default();
     }
 
     Ok(())
 }
 
+/// Get the capacity buckets of the given events, ordered by their position.
+async fn fetch_capacity_buckets(
+    conn: &mut PgConnection,
+    event_ids: &[i32],
+) -> Result<HashMap<i32, Vec<EventCapacityBucket>>> {
+    let rows = query!(
+        r#"
+SELECT
+    b.event_id,
+    b.id,
+    b.name,
+    b.max_subscribers,
+    b.max_waiting_list
+FROM
+    event_capacity_buckets b
+WHERE
+    b.event_id = ANY($1)
+ORDER BY
+    b.event_id,
+    b.position"#,
+        event_ids
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let mut result = HashMap::new();
+    for row in rows {
+        result
+            .entry(row.event_id)
+            .or_insert_with(Vec::new)
+            .push(EventCapacityBucket::new(
+                row.id,
+                row.name,
+                row.max_subscribers,
+                row.max_waiting_list,
+            ));
+    }
+
+    Ok(result)
+}
+
+/// Get the counters of the capacity buckets of the given events, ordered by their position.
+pub(super) async fn fetch_bucket_counters(
+    conn: &mut PgConnection,
+    event_ids: &[i32],
+) -> Result<HashMap<i32, Vec<EventBucketCounter>>> {
+    let rows = query!(
+        r#"
+SELECT
+    v.event_id,
+    v.id,
+    v.name,
+    v.max_subscribers,
+    v.max_waiting_list,
+    v.subscribers,
+    v.waiting_list
+FROM
+    v_event_bucket_counters v
+WHERE
+    v.event_id = ANY($1)
+ORDER BY
+    v.event_id,
+    v.position"#,
+        event_ids
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let mut result = HashMap::new();
+    for row in rows {
+        result
+            .entry(row.event_id.unwrap())
+            .or_insert_with(Vec::new)
+            .push(EventBucketCounter::new(
+                row.id.unwrap(),
+                row.name.unwrap(),
+                row.max_subscribers.unwrap(),
+                row.max_waiting_list.unwrap(),
+                // try_into is needed to convert the i64 into a i16
+                row.subscribers.unwrap().try_into()?,
+                row.waiting_list.unwrap().try_into()?,
+            ));
+    }
+
+    Ok(result)
+}
+
 async fn insert_event_subscribers(conn: &mut PgConnection, events: &mut [Event]) -> Result<()> {
     let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
         r#"
@@ -324,10 +475,7 @@ SELECT
     v.sepa_exported_at,
     v.iban,
     v.comment,
-    v.custom_value_1,
-    v.custom_value_2,
-    v.custom_value_3,
-    v.custom_value_4
+    v.custom_values
 FROM
     v_event_bookings v
 WHERE
@@ -368,15 +516,7 @@ ORDER BY
                 row.try_get("sepa_exported_at")?,
                 row.try_get("iban")?,
                 row.try_get("comment")?,
-                vec![
-                    row.try_get::<Option<String>, _>("custom_value_1")?,
-                    row.try_get::<Option<String>, _>("custom_value_2")?,
-                    row.try_get::<Option<String>, _>("custom_value_3")?,
-                    row.try_get::<Option<String>, _>("custom_value_4")?,
-                ]
-                .into_iter()
-                .flatten()
-                .collect(),
+                row.try_get("custom_values")?,
             ));
     }
 
@@ -391,10 +531,12 @@ ORDER BY
     Ok(())
 }
 
+/// Create or update the event and store a new version of it.
+/// Returns the written event and, for an update, the event before the update.
 pub(crate) async fn write_event(
     pool: &PgPool,
     partial_event: PartialEvent,
-) -> Result<(Event, Option<Vec<DateTime<Utc>>>)> {
+) -> Result<(Event, Option<Event>)> {
     if let Some(custom_fields) = &partial_event.custom_fields
         && custom_fields.iter().filter(|cf| cf.price_relevant).count() > 1
     {
@@ -411,9 +553,21 @@ async fn update_event(
     pool: &PgPool,
     id: &EventId,
     partial_event: PartialEvent,
-) -> Result<(Event, Option<Vec<DateTime<Utc>>>)> {
+) -> Result<(Event, Option<Event>)> {
     let mut tx = pool.begin().await?;
 
+    // lock the event to serialize concurrent updates and their versions
+    query_scalar!(
+        r#"SELECT id FROM events WHERE id = $1 FOR UPDATE"#,
+        id.get_ref()
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| anyhow!("Error fetching event with id '{}'", id))?;
+    let previous = fetch_event(&mut tx, id, false)
+        .await?
+        .ok_or_else(|| anyhow!("Error fetching event with id '{}'", id))?;
+
     if let Some(new_method) = &partial_event.payment_method {
         let row = query!(
             r#"
@@ -438,6 +592,33 @@ async fn update_event(
         }
     }
 
+    if let Some(new_status) = partial_event.lifecycle_status
+        && !previous.lifecycle_status.can_transition_to(new_status)
+    {
+        bail!(ValidationError::new(format!(
+            "Der Status kann nicht von {:?} auf {:?} geändert werden.",
+            previous.lifecycle_status, new_status
+        )));
+    }
+
+    // the custom values of the bookings are stored by the position of their fields
+    if let Some(custom_fields) = &partial_event.custom_fields
+        && custom_fields
+            .iter()
+            .map(|field| field.id)
+            .ne(previous.custom_fields.iter().map(|field| field.id))
+        && query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM event_bookings WHERE event_id = $1) AS "exists!""#,
+            id.get_ref()
+        )
+        .fetch_one(&mut *tx)
+        .await?
+    {
+        bail!(ValidationError::new(
+            "Die Zusatzfelder können nicht mehr geändert werden, da es bereits Buchungen gibt."
+        ));
+    }
+
     let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new("UPDATE events SET ");
     let mut update_is_needed = false;
     let mut separated = query_builder.separated(", ");
@@ -514,37 +695,13 @@ async fn update_event(
     );
     update_is_needed |= push_bind(
         &mut separated,
-        "CUSTOM_FIELD_1",
-        partial_event
-            .custom_fields
-            .as_ref()
-            .map(|fields| fields.first().map(|value| value.id)),
+        "WAITING_LIST_OFFER_HOURS",
+        partial_event.waiting_list_offer_hours,
     );
-    update_is_needed |= push_bind(
-        &mut separated,
-        "CUSTOM_FIELD_2",
-        partial_event
-            .custom_fields
-            .as_ref()
-            .map(|fields| fields.get(1).map(|value| value.id)),
-    );
-    update_is_needed |= push_bind(
-        &mut separated,
-        "CUSTOM_FIELD_3",
-        partial_event
-            .custom_fields
-            .as_ref()
-            .map(|fields| fields.get(2).map(|value| value.id)),
-    );
-    update_is_needed |= push_bind(
-        &mut separated,
-        "CUSTOM_FIELD_4",
-        partial_event
-            .custom_fields
-            .as_ref()
-            .map(|fields| fields.get(3).map(|value| value.id)),
-    );
-
+    update_is_needed |= push_bind(&mut separated, "PUBLISH_AT", partial_event.publish_at);
+    update_is_needed |= push_bind(&mut separated, "UNPUBLISH_AT", partial_event.unpublish_at);
+    update_is_needed |= push_bind(&mut separated, "BOOKING_START", partial_event.booking_start);
+    update_is_needed |= push_bind(&mut separated, "BOOKING_END", partial_event.booking_end);
     // add closed date if lifecycle status should be updated to closed
     // and no closed date is defined
     let mut event_has_been_closed = false;
@@ -564,20 +721,20 @@ async fn update_event(
         query_builder.build().execute(&mut *tx).await?;
     }
 
-    let mut removed_dates = None;
-
-    if let Some(new_dates) = partial_event.dates {
-        let current_dates = get_event_dates(&mut tx, id).await?;
-        if current_dates != new_dates {
-            removed_dates = Some(
-                current_dates
-                    .into_iter()
-                    .filter(|date| !new_dates.contains(date))
-                    .collect::<Vec<_>>(),
-            );
-            delete_event_dates(&mut tx, id).await?;
-            save_event_dates(&mut tx, id, new_dates).await?;
-        }
+    if let Some(new_dates) = partial_event.dates
+        && previous.dates != new_dates
+    {
+        delete_event_dates(&mut tx, id).await?;
+        save_event_dates(&mut tx, id, new_dates).await?;
+    }
+
+    if let Some(custom_fields) = partial_event.custom_fields {
+        delete_event_custom_fields(&mut tx, id).await?;
+        save_event_custom_fields(&mut tx, id, &custom_fields).await?;
+    }
+
+    if let Some(capacity_buckets) = partial_event.capacity_buckets {
+        save_event_capacity_buckets(&mut tx, id, &capacity_buckets).await?;
     }
 
     // archive events if the event has been closed
@@ -588,10 +745,11 @@ async fn update_event(
     let event = fetch_event(&mut tx, id, false)
         .await?
         .ok_or_else(|| anyhow!("Error fetching event with id '{}'", id))?;
+    save_event_version(&mut tx, &event).await?;
 
     tx.commit().await?;
 
-    Ok((event, removed_dates))
+    Ok((event, Some(previous)))
 }
 
 fn push_bind<'gb, T>(
@@ -622,6 +780,12 @@ async fn save_new_event(pool: &PgPool, partial_event: PartialEvent) -> Result<Ev
     let lifecycle_status = partial_event
         .lifecycle_status
         .ok_or_else(|| anyhow!("Attribute 'lifecycle_status' is missing"))?;
+    if !lifecycle_status.is_initial() {
+        bail!(ValidationError::new(format!(
+            "Eine neue Veranstaltung kann nicht im Status {:?} angelegt werden.",
+            lifecycle_status
+        )));
+    }
     let name = partial_event
         .name
         .ok_or_else(|| anyhow!("Attribute 'name' is missing"))?;
@@ -680,24 +844,21 @@ async fn save_new_event(pool: &PgPool, partial_event: PartialEvent) -> Result<Ev
     let payment_method = partial_event
         .payment_method
         .unwrap_or(PaymentMethod::BankTransfer);
-    let (custom_field_1, custom_field_2, custom_field_3, custom_field_4) =
-        match partial_event.custom_fields {
-            Some(custom_fields) => (
-                custom_fields.first().map(|value| value.id),
-                custom_fields.get(1).map(|value| value.id),
-                custom_fields.get(2).map(|value| value.id),
-                custom_fields.get(3).map(|value| value.id),
-            ),
-            None => (None, None, None, None),
-        };
+    let waiting_list_offer_hours = partial_event.waiting_list_offer_hours.unwrap_or(0);
+    let publish_at = partial_event.publish_at.flatten();
+    let unpublish_at = partial_event.unpublish_at.flatten();
+    let booking_start = partial_event.booking_start.flatten();
+    let booking_end = partial_event.booking_end.flatten();
+    let custom_fields = partial_event.custom_fields.unwrap_or_default();
+    let capacity_buckets = partial_event.capacity_buckets.unwrap_or_default();
 
     let mut tx = pool.begin().await?;
 
-    let mut new_event: Event = query!(
+    let new_event: Event = query!(
         r#"
-INSERT INTO events (closed, event_type, lifecycle_status, name, sort_index, short_description, description, image, light, custom_date, duration_in_minutes, max_subscribers, max_waiting_list, price_member, price_non_member, cost_per_date, location, booking_template, payment_account, alt_booking_button_text, alt_email_address, external_operator, custom_field_1, custom_field_2, custom_field_3, custom_field_4, payment_method)
-VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27)
-RETURNING id, created, closed, event_type AS "event_type: EventType", lifecycle_status AS "lifecycle_status: LifecycleStatus", name, sort_index, short_description, description, image, light, custom_date, duration_in_minutes, max_subscribers, max_waiting_list, price_member, price_non_member, cost_per_date, location, booking_template, payment_account, alt_booking_button_text, alt_email_address, external_operator, payment_method AS "payment_method: PaymentMethod""#,
+INSERT INTO events (closed, event_type, lifecycle_status, name, sort_index, short_description, description, image, light, custom_date, duration_in_minutes, max_subscribers, max_waiting_list, price_member, price_non_member, cost_per_date, location, booking_template, payment_account, alt_booking_button_text, alt_email_address, external_operator, payment_method, waiting_list_offer_hours, publish_at, unpublish_at, booking_start, booking_end)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28)
+RETURNING id, created, closed, event_type AS "event_type: EventType", lifecycle_status AS "lifecycle_status: LifecycleStatus", name, sort_index, short_description, description, image, light, custom_date, duration_in_minutes, max_subscribers, max_waiting_list, price_member, price_non_member, cost_per_date, location, booking_template, payment_account, alt_booking_button_text, alt_email_address, external_operator, payment_method AS "payment_method: PaymentMethod", waiting_list_offer_hours, publish_at, unpublish_at, booking_start, booking_end"#,
         closed,
         event_type as EventType,
         lifecycle_status as LifecycleStatus,
@@ -720,11 +881,12 @@ RETURNING id, created, closed, event_type AS "event_type: EventType", lifecycle_
         alt_booking_button_text,
         alt_email_address,
         external_operator,
-        custom_field_1,
-        custom_field_2,
-        custom_field_3,
-        custom_field_4,
         payment_method as PaymentMethod,
+        waiting_list_offer_hours,
+        publish_at,
+        unpublish_at,
+        booking_start,
+        booking_end,
     )
     .map(|row| {
         Event::new(
@@ -755,38 +917,83 @@ RETURNING id, created, closed, event_type AS "event_type: EventType", lifecycle_
             row.external_operator,
             Vec::new(),
             row.payment_method,
+            row.waiting_list_offer_hours,
+            row.publish_at,
+            row.unpublish_at,
+            row.booking_start,
+            row.booking_end,
         )
     })
     .fetch_one(&mut *tx)
     .await?;
 
-    new_event.dates = save_event_dates(&mut tx, &new_event.id, dates).await?;
-    new_event.custom_fields = get_event_custom_fields(&mut tx, &new_event.id).await?;
+    save_event_dates(&mut tx, &new_event.id, dates).await?;
+    save_event_custom_fields(&mut tx, &new_event.id, &custom_fields).await?;
+    save_event_capacity_buckets(&mut tx, &new_event.id, &capacity_buckets).await?;
+    // re-read the event to snapshot the values as stored in the database
+    let new_event = fetch_event(&mut tx, &new_event.id, false)
+        .await?
+        .ok_or_else(|| anyhow!("Error fetching event with id '{}'", new_event.id))?;
+    save_event_version(&mut tx, &new_event).await?;
 
     tx.commit().await?;
 
     Ok(new_event)
 }
 
-async fn get_event_dates(
-    conn: &mut PgConnection,
-    event_id: &EventId,
-) -> Result<Vec<DateTime<Utc>>> {
-    let result = query!(
+/// Store the event as its next version.
+pub(crate) async fn save_event_version(conn: &mut PgConnection, event: &Event) -> Result<()> {
+    query!(
         r#"
+INSERT INTO event_versions (event_id, version, snapshot)
 SELECT
-    e.date
+    $1,
+    COALESCE(MAX(v.version), 0) + 1,
+    $2
 FROM
-    event_dates e
+    event_versions v
 WHERE
-    e.event_id = $1"#,
+    v.event_id = $1"#,
+        event.id.get_ref(),
+        serde_json::to_value(event)?
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Get all versions of the event, the latest version first.
+pub(crate) async fn get_event_versions(
+    pool: &PgPool,
+    event_id: &EventId,
+) -> Result<Vec<EventVersion>> {
+    let versions = query_as!(
+        EventVersion,
+        r#"SELECT version, created, snapshot FROM event_versions WHERE event_id = $1 ORDER BY version DESC"#,
         event_id.get_ref()
     )
-    .map(|row| row.date)
-    .fetch_all(conn)
+    .fetch_all(pool)
     .await?;
 
-    Ok(result)
+    Ok(versions)
+}
+
+pub(crate) async fn get_event_version(
+    pool: &PgPool,
+    event_id: &EventId,
+    version: i32,
+) -> Result<Option<EventVersion>> {
+    let version = query_as!(
+        EventVersion,
+        r#"SELECT version, created, snapshot FROM event_versions WHERE event_id = $1 AND version = $2"#,
+        event_id.get_ref(),
+        version
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(version)
 }
 
 async fn delete_event_dates(conn: &mut PgConnection, event_id: &EventId) -> Result<()> {
@@ -817,45 +1024,92 @@ async fn save_event_dates(
     Ok(dates)
 }
 
-async fn get_event_custom_fields(
+async fn delete_event_custom_fields(conn: &mut PgConnection, event_id: &EventId) -> Result<()> {
+    query!(
+        r#"DELETE FROM event_custom_field_assignments WHERE event_id = $1"#,
+        event_id.get_ref()
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn save_event_custom_fields(
     conn: &mut PgConnection,
     event_id: &EventId,
-) -> Result<Vec<EventCustomField>> {
-    let custom_fields = query!(
-        r#"
-SELECT
-	ecf.id,
-	ecf.name,
-	ecf.type AS "cf_type: EventCustomFieldType",
-	ecf.min_value,
-	ecf.max_value,
-	ecf.price_relevant
-FROM
-	events e
-LEFT JOIN event_custom_fields ecf ON
-	ecf.id = any(array[e.custom_field_1,
-	e.custom_field_2,
-	e.custom_field_3,
-	e.custom_field_4])
-WHERE
-	e.id = $1
-    AND ecf.id IS NOT NULL"#,
-        event_id.get_ref()
+    custom_fields: &[EventCustomField],
+) -> Result<()> {
+    let ids = vec![event_id.into_inner(); custom_fields.len()];
+    let custom_field_ids: Vec<i32> = custom_fields.iter().map(|field| field.id).collect();
+    let positions: Vec<i16> = (1..=custom_fields.len())
+        .map(i16::try_from)
+        .collect::<Result<_, _>>()?;
+    query!(
+        r#"INSERT INTO event_custom_field_assignments (event_id, custom_field_id, position) SELECT * FROM UNNEST ($1::int4[], $2::int4[], $3::int2[])"#,
+        &ids,
+        &custom_field_ids,
+        &positions
     )
-    .map(|row| {
-        EventCustomField::new(
-            row.id,
-            row.name,
-            row.cf_type,
-            row.min_value,
-            row.max_value,
-            row.price_relevant,
-        )
-    })
-    .fetch_all(conn)
+    .execute(conn)
     .await?;
 
-    Ok(custom_fields)
+    Ok(())
+}
+
+/// Save the capacity buckets of the event in the given order. Buckets with an id
+/// are updated, buckets without an id are added and all other buckets of the
+/// event are removed, which fails for buckets with bookings.
+async fn save_event_capacity_buckets(
+    conn: &mut PgConnection,
+    event_id: &EventId,
+    capacity_buckets: &[EventCapacityBucket],
+) -> Result<()> {
+    let ids: Vec<i32> = capacity_buckets
+        .iter()
+        .filter_map(|bucket| bucket.id)
+        .collect();
+    query!(
+        r#"DELETE FROM event_capacity_buckets WHERE event_id = $1 AND NOT (id = ANY($2))"#,
+        event_id.get_ref(),
+        &ids
+    )
+    .execute(&mut *conn)
+    .await
+    .with_context(|| format!("Error removing the capacity buckets of event {}", event_id))?;
+
+    for (i, bucket) in capacity_buckets.iter().enumerate() {
+        let position = i16::try_from(i + 1)?;
+        match bucket.id {
+            Some(id) => {
+                query!(
+                    r#"UPDATE event_capacity_buckets SET name = $3, max_subscribers = $4, max_waiting_list = $5, position = $6 WHERE id = $1 AND event_id = $2"#,
+                    id,
+                    event_id.get_ref(),
+                    bucket.name,
+                    bucket.max_subscribers,
+                    bucket.max_waiting_list,
+                    position
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            None => {
+                query!(
+                    r#"INSERT INTO event_capacity_buckets (event_id, name, max_subscribers, max_waiting_list, position) VALUES ($1, $2, $3, $4, $5)"#,
+                    event_id.get_ref(),
+                    bucket.name,
+                    bucket.max_subscribers,
+                    bucket.max_waiting_list,
+                    position
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
 }
 
 pub(crate) async fn delete_event(pool: &PgPool, id: EventId) -> Result<()> {
@@ -976,6 +1230,33 @@ WHERE
     Ok(events)
 }
 
+pub(crate) async fn get_event_type(pool: &PgPool, id: &EventId) -> Result<Option<EventType>> {
+    let event_type = query!(
+        r#"SELECT e.event_type AS "event_type: EventType" FROM events e WHERE e.id = $1"#,
+        id.get_ref()
+    )
+    .map(|row| row.event_type)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(event_type)
+}
+
+pub(crate) async fn get_event_type_of_booking(
+    pool: &PgPool,
+    booking_id: i32,
+) -> Result<Option<EventType>> {
+    let event_type = query!(
+        r#"SELECT e.event_type AS "event_type: EventType" FROM event_bookings b JOIN events e ON e.id = b.event_id WHERE b.id = $1"#,
+        booking_id
+    )
+    .map(|row| row.event_type)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(event_type)
+}
+
 /// mark the given event that the reminder email has been sent
 /// (to avoid duplicate sending of reminder emails)
 pub(crate) async fn mark_as_reminder_sent(pool: &PgPool, event_id: &EventId) -> Result<()> {
@@ -1023,6 +1304,60 @@ WHERE
     Ok(event_ids)
 }
 
+/// Publish the draft and review events whose publish time has come and hide the
+/// review, published and running events whose unpublish time has come by moving
+/// them into status finished, which is neither visible nor bookable. Returns the ids of the published and of the unpublished events.
+pub(crate) async fn publish_scheduled_events(
+    pool: &PgPool,
+) -> Result<(Vec<EventId>, Vec<EventId>)> {
+    let mut tx = pool.begin().await?;
+
+    let published = query_scalar!(
+        r#"
+UPDATE
+    events
+SET
+    lifecycle_status = 'Published',
+    publish_at = NULL
+WHERE
+    lifecycle_status IN ('Draft', 'Review')
+    AND publish_at <= NOW()
+RETURNING
+    id"#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let unpublished = query_scalar!(
+        r#"
+UPDATE
+    events
+SET
+    lifecycle_status = 'Finished',
+    unpublish_at = NULL
+WHERE
+    lifecycle_status IN ('Review', 'Published', 'Running')
+    AND unpublish_at <= NOW()
+RETURNING
+    id"#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let published: Vec<EventId> = published.into_iter().map(EventId::from).collect();
+    let unpublished: Vec<EventId> = unpublished.into_iter().map(EventId::from).collect();
+    let changed = [&published[..], &unpublished[..]].concat();
+    if !changed.is_empty() {
+        for event in fetch_events(&mut tx, changed, false).await? {
+            save_event_version(&mut tx, &event).await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok((published, unpublished))
+}
+
 fn map_event(row: &PgRow) -> Result<Event> {
     Ok(Event::new(
         row.try_get("id")?,
@@ -1052,6 +1387,11 @@ fn map_event(row: &PgRow) -> Result<Event> {
         row.try_get("external_operator")?,
         Vec::new(),
         row.try_get("payment_method")?,
+        row.try_get("waiting_list_offer_hours")?,
+        row.try_get("publish_at")?,
+        row.try_get("unpublish_at")?,
+        row.try_get("booking_start")?,
+        row.try_get("booking_end")?,
     ))
 }
 
@@ -1258,6 +1598,10 @@ mod db_integration_tests {
                 None,
                 None,
                 true,
+                Vec::new(),
+                false,
+                None,
+                Vec::new(),
             ),
             EventCustomField::new(
                 cf2.id,
@@ -1266,6 +1610,10 @@ mod db_integration_tests {
                 None,
                 None,
                 true,
+                Vec::new(),
+                false,
+                None,
+                Vec::new(),
             ),
         ];
 
@@ -1305,4 +1653,90 @@ mod db_integration_tests {
 
         Ok(())
     }
+
+    #[sqlx::test]
+    async fn test_custom_fields_are_fixed_after_bookings_exist(pool: PgPool) -> Result<()> {
+        query!(
+            r#"INSERT INTO event_custom_fields (name, type, price_relevant)
+               VALUES ('Allergien', 'Text', false), ('Verein', 'Text', false)"#
+        )
+        .execute(&pool)
+        .await?;
+        let mut custom_fields = crate::db::get_all_custom_fields(&pool).await?;
+        custom_fields.sort_by_key(|field| field.id);
+
+        let partial = PartialEvent {
+            event_type: Some(EventType::Events),
+            lifecycle_status: Some(LifecycleStatus::Published),
+            name: Some("Fixed custom fields".to_string()),
+            sort_index: Some(0),
+            short_description: Some("Short".to_string()),
+            description: Some("Full".to_string()),
+            image: Some("test.png".to_string()),
+            light: Some(false),
+            duration_in_minutes: Some(60),
+            max_subscribers: Some(10),
+            max_waiting_list: Some(5),
+            price_member: Some("10.00".parse().unwrap()),
+            price_non_member: Some("15.00".parse().unwrap()),
+            location: Some("Gym".to_string()),
+            booking_template: Some("Template".to_string()),
+            payment_account: Some("Account".to_string()),
+            external_operator: Some(false),
+            dates: Some(vec![Utc::now()]),
+            custom_fields: Some(custom_fields.clone()),
+            ..Default::default()
+        };
+        let (event, _) = write_event(&pool, partial).await?;
+
+        // the order can be changed as long as there are no bookings
+        let reversed: Vec<_> = custom_fields.iter().rev().cloned().collect();
+        let update = |custom_fields: Vec<EventCustomField>| PartialEvent {
+            id: Some(event.id),
+            custom_fields: Some(custom_fields),
+            ..Default::default()
+        };
+        write_event(&pool, update(reversed.clone())).await?;
+
+        query!(
+            r#"WITH s AS (
+                INSERT INTO event_subscribers (first_name, last_name, street, city, email, member)
+                VALUES ('Eve', 'Green', 'Main St', 'Vienna', 'eve@example.com', true) RETURNING id
+            )
+            INSERT INTO event_bookings (event_id, subscriber_id, enrolled, pre_booking, payment_id, custom_values)
+            SELECT $1, id, true, false, 'pay_001', '{Nüsse,SVE}' FROM s"#,
+            event.id.get_ref()
+        )
+        .execute(&pool)
+        .await?;
+
+        // the same fields can still be saved, but neither reordered nor removed
+        write_event(&pool, update(reversed)).await?;
+        for custom_fields in [custom_fields.clone(), custom_fields[..1].to_vec()] {
+            let err = write_event(&pool, update(custom_fields)).await.unwrap_err();
+            assert!(err.downcast_ref::<ValidationError>().is_some());
+        }
+
+        // the price rules of booked fields are fixed, their labels are not
+        let id = custom_fields[0].id;
+        assert!(
+            query!("UPDATE event_custom_fields SET price = 5 WHERE id = $1", id)
+                .execute(&pool)
+                .await
+                .is_err()
+        );
+        query!(
+            "UPDATE event_custom_fields SET name = 'Unverträglichkeiten' WHERE id = $1",
+            id
+        )
+        .execute(&pool)
+        .await?;
+        query!(
+            "UPDATE event_custom_fields SET price = 5 WHERE id NOT IN (SELECT custom_field_id FROM event_custom_field_assignments)"
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,294 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{PgConnection, PgPool, query, query_scalar};
+
+use crate::models::ErasureResult;
+
+/// First name of anonymized subscribers, all other personal fields are cleared.
+pub(crate) const ANONYMIZED_NAME: &str = "Anonymisiert";
+
+pub(crate) async fn get_event_subscriber_data(pool: &PgPool, email: &str) -> Result<Vec<Value>> {
+    let rows = query_scalar!(
+        r#"SELECT to_jsonb(s) AS "data!" FROM event_subscribers s
+        WHERE LOWER(TRIM(s.email)) = LOWER(TRIM($1))
+        ORDER BY s.id"#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+pub(crate) async fn get_event_booking_data(pool: &PgPool, email: &str) -> Result<Vec<Value>> {
+    let rows = query_scalar!(
+        r#"SELECT to_jsonb(b) || jsonb_build_object('event_name', e.name) AS "data!"
+        FROM event_bookings b, event_subscribers s, events e
+        WHERE b.subscriber_id = s.id AND b.event_id = e.id AND LOWER(TRIM(s.email)) = LOWER(TRIM($1))
+        ORDER BY b.created"#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Entries of the audit log regarding the bookings of the email address or with
+/// the email address as subject.
+pub(crate) async fn get_audit_log_data(pool: &PgPool, email: &str) -> Result<Vec<Value>> {
+    let rows = query_scalar!(
+        r#"SELECT to_jsonb(a) AS "data!" FROM audit_log a
+        WHERE LOWER(a.subject) = LOWER(TRIM($1))
+           OR a.booking_id IN (
+               SELECT b.id FROM event_bookings b, event_subscribers s
+               WHERE b.subscriber_id = s.id AND LOWER(TRIM(s.email)) = LOWER(TRIM($1)))
+        ORDER BY a.created, a.id"#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+pub(crate) async fn get_news_subscriber_data(pool: &PgPool, email: &str) -> Result<Vec<Value>> {
+    let rows = query_scalar!(
+        r#"SELECT to_jsonb(n) || jsonb_build_object('topics', COALESCE((
+            SELECT jsonb_agg(t.name ORDER BY t.name) FROM news_subscriptions ns, news_topics t
+            WHERE ns.topic_id = t.id AND ns.subscriber_id = n.id), '[]'::jsonb)) AS "data!"
+        FROM news_subscribers n
+        WHERE LOWER(TRIM(n.email)) = LOWER(TRIM($1))"#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+pub(crate) async fn get_news_subscription_request_data(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Vec<Value>> {
+    let rows = query_scalar!(
+        r#"SELECT to_jsonb(r) AS "data!" FROM news_subscription_requests r
+        WHERE LOWER(TRIM(r.email)) = LOWER(TRIM($1))
+        ORDER BY r.created"#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+pub(crate) async fn get_news_campaign_recipient_data(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Vec<Value>> {
+    let rows = query_scalar!(
+        r#"SELECT to_jsonb(r) || jsonb_build_object('subject', c.subject) AS "data!"
+        FROM news_campaign_recipients r, news_campaigns c
+        WHERE r.campaign_id = c.id AND LOWER(TRIM(r.email)) = LOWER(TRIM($1))
+        ORDER BY r.id"#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+pub(crate) async fn get_member_data(pool: &PgPool, email: &str) -> Result<Vec<Value>> {
+    let rows = query_scalar!(
+        r#"SELECT to_jsonb(m) || jsonb_build_object(
+            'family_members', COALESCE((
+                SELECT jsonb_agg(to_jsonb(f) - 'member_id') FROM member_family_members f
+                WHERE f.member_id = m.id), '[]'::jsonb),
+            'fee_collections', COALESCE((
+                SELECT jsonb_agg(to_jsonb(c) - 'member_id') FROM membership_fee_collections c
+                WHERE c.member_id = m.id), '[]'::jsonb)) AS "data!"
+        FROM members m
+        WHERE LOWER(TRIM(m.email)) = LOWER(TRIM($1))
+        ORDER BY m.id"#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Count the active bookings of the email address for events which have not been closed yet.
+pub(crate) async fn count_open_bookings(pool: &PgPool, email: &str) -> Result<i64> {
+    let count = query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM event_bookings b, event_subscribers s, events e
+        WHERE b.subscriber_id = s.id AND b.event_id = e.id AND b.canceled IS NULL
+        AND e.lifecycle_status NOT IN ('Closed', 'Archived')
+        AND LOWER(TRIM(s.email)) = LOWER(TRIM($1))"#,
+        email
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+/// Anonymize all personal data of the email address. Booking rows are kept as
+/// financial records, including the IBANs which have been used for a SEPA direct debit.
+pub(crate) async fn erase_personal_data(
+    pool: &PgPool,
+    email: &str,
+    erasure_id: &str,
+) -> Result<ErasureResult> {
+    let mut tx = pool.begin().await?;
+
+    let subscriber_ids = query_scalar!(
+        r#"SELECT id FROM event_subscribers WHERE LOWER(TRIM(email)) = LOWER(TRIM($1))"#,
+        email
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let anonymized_bookings = anonymize_bookings(&mut tx, &subscriber_ids, None).await?;
+    let anonymized_subscribers = anonymize_subscribers(&mut tx, &subscriber_ids).await?;
+
+    let deleted_news_subscribers = query!(
+        r#"DELETE FROM news_subscribers WHERE LOWER(TRIM(email)) = LOWER(TRIM($1))"#,
+        email
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    let deleted_news_subscription_requests = query!(
+        r#"DELETE FROM news_subscription_requests WHERE LOWER(TRIM(email)) = LOWER(TRIM($1))"#,
+        email
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    // the recipients are kept for the statistics of the campaigns
+    let anonymized_campaign_recipients = query!(
+        r#"UPDATE news_campaign_recipients SET email = 'anonymized-' || id, error = NULL
+        WHERE LOWER(TRIM(email)) = LOWER(TRIM($1))"#,
+        email
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    // the audit log is append-only, only the subject of its entries can be removed
+    let redacted_audit_log_entries = query!(
+        r#"UPDATE audit_log SET subject = NULL WHERE LOWER(subject) = LOWER(TRIM($1))"#,
+        email
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    let retained_members = query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM members WHERE LOWER(TRIM(email)) = LOWER(TRIM($1))"#,
+        email
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(ErasureResult {
+        erasure_id: erasure_id.to_string(),
+        anonymized_subscribers,
+        anonymized_bookings,
+        deleted_news_subscribers,
+        deleted_news_subscription_requests,
+        anonymized_campaign_recipients,
+        redacted_audit_log_entries,
+        retained_members: retained_members as u64,
+    })
+}
+
+/// Anonymize the participants of archived and canceled events which have been
+/// closed before the given date. Subscribers who also booked other events are kept until all
+/// their events are due. Returns the number of anonymized subscribers.
+pub(crate) async fn anonymize_archived_participants(
+    pool: &PgPool,
+    closed_before: DateTime<Utc>,
+) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+
+    let event_ids = query_scalar!(
+        r#"SELECT id FROM events WHERE lifecycle_status IN ('Archived', 'Canceled') AND closed < $1"#,
+        closed_before
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let subscriber_ids = query_scalar!(
+        r#"SELECT DISTINCT b.subscriber_id FROM event_bookings b WHERE b.event_id = ANY($1)"#,
+        &event_ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    anonymize_bookings(&mut tx, &subscriber_ids, Some(&event_ids)).await?;
+
+    let due_subscriber_ids = query_scalar!(
+        r#"SELECT s.id FROM event_subscribers s
+        WHERE s.id = ANY($1) AND s.anonymized_at IS NULL
+        AND NOT EXISTS (
+            SELECT 1 FROM event_bookings b WHERE b.subscriber_id = s.id AND NOT b.event_id = ANY($2))"#,
+        &subscriber_ids,
+        &event_ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let anonymized = anonymize_subscribers(&mut tx, &due_subscriber_ids).await?;
+
+    tx.commit().await?;
+
+    Ok(anonymized)
+}
+
+/// Remove the comments and the IBANs which have not been used for a SEPA direct
+/// debit from the bookings of the subscribers, optionally limited to some events.
+async fn anonymize_bookings(
+    conn: &mut PgConnection,
+    subscriber_ids: &[i32],
+    event_ids: Option<&[i32]>,
+) -> Result<u64> {
+    // only the custom values which determine the price are kept, by the position of their fields
+    let result = query!(
+        r#"UPDATE event_bookings b
+        SET comment = NULL, iban = CASE WHEN sepa_exported_at IS NULL THEN NULL ELSE iban END,
+        custom_values = (
+            SELECT COALESCE(ARRAY_AGG(
+                CASE WHEN f.price_relevant OR f.price IS NOT NULL OR CARDINALITY(f.option_prices) > 0
+                THEN v.value ELSE '' END ORDER BY v.position), '{}')
+            FROM UNNEST(b.custom_values) WITH ORDINALITY AS v(value, position)
+            LEFT JOIN event_custom_field_assignments a ON a.event_id = b.event_id AND a.position = v.position
+            LEFT JOIN event_custom_fields f ON f.id = a.custom_field_id
+        )
+        WHERE subscriber_id = ANY($1) AND ($2::INTEGER[] IS NULL OR event_id = ANY($2))"#,
+        subscriber_ids,
+        event_ids as Option<&[i32]>
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+async fn anonymize_subscribers(conn: &mut PgConnection, subscriber_ids: &[i32]) -> Result<u64> {
+    let result = query!(
+        r#"UPDATE event_subscribers
+        SET first_name = $2, last_name = '', street = '', city = '', email = '', phone = NULL, anonymized_at = NOW()
+        WHERE id = ANY($1) AND anonymized_at IS NULL"#,
+        subscriber_ids,
+        ANONYMIZED_NAME
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{PgPool, query, query_as};
+
+pub(crate) struct IdempotencyKeyRow {
+    pub(crate) request_hash: String,
+    pub(crate) status_code: Option<i32>,
+    pub(crate) headers: Option<Value>,
+    pub(crate) body: Option<Vec<u8>>,
+}
+
+/// Claim the key for a new request. Keys created before `expired_before` are
+/// removed, keys whose request is still in progress since `stale_before` are
+/// claimed again. Returns `false` if the key is in use.
+pub(crate) async fn claim_idempotency_key(
+    pool: &PgPool,
+    actor: &str,
+    scope: &str,
+    key: &str,
+    request_hash: &str,
+    expired_before: DateTime<Utc>,
+    stale_before: DateTime<Utc>,
+) -> Result<bool> {
+    query!(
+        r#"DELETE FROM idempotency_keys WHERE created < $1"#,
+        expired_before
+    )
+    .execute(pool)
+    .await?;
+
+    let result = query!(
+        r#"INSERT INTO idempotency_keys (actor, scope, key, request_hash) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (actor, scope, key) DO UPDATE SET created = NOW(), request_hash = EXCLUDED.request_hash
+        WHERE idempotency_keys.status_code IS NULL AND idempotency_keys.created < $5"#,
+        actor,
+        scope,
+        key,
+        request_hash,
+        stale_before
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub(crate) async fn get_idempotency_key(
+    pool: &PgPool,
+    actor: &str,
+    scope: &str,
+    key: &str,
+) -> Result<Option<IdempotencyKeyRow>> {
+    let row = query_as!(
+        IdempotencyKeyRow,
+        r#"SELECT request_hash, status_code, headers, body FROM idempotency_keys WHERE actor = $1 AND scope = $2 AND key = $3"#,
+        actor,
+        scope,
+        key
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+pub(crate) async fn complete_idempotency_key(
+    pool: &PgPool,
+    actor: &str,
+    scope: &str,
+    key: &str,
+    status_code: i32,
+    headers: &Value,
+    body: &[u8],
+) -> Result<()> {
+    query!(
+        r#"UPDATE idempotency_keys SET status_code = $4, headers = $5, body = $6
+        WHERE actor = $1 AND scope = $2 AND key = $3"#,
+        actor,
+        scope,
+        key,
+        status_code,
+        headers,
+        body
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn delete_idempotency_key(
+    pool: &PgPool,
+    actor: &str,
+    scope: &str,
+    key: &str,
+) -> Result<()> {
+    query!(
+        r#"DELETE FROM idempotency_keys WHERE actor = $1 AND scope = $2 AND key = $3"#,
+        actor,
+        scope,
+        key
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
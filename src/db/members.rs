@@ -0,0 +1,251 @@
+use crate::models::{
+    Member, MemberImport, MembershipApplication, MembershipFamilyMember, MembershipFee,
+    MembershipFeeSchedule, MembershipType,
+};
+use anyhow::Result;
+use chrono::NaiveDate;
+use sqlx::{PgConnection, PgPool, query, query_as, query_scalar};
+
+pub(crate) async fn insert_member(
+    pool: &PgPool,
+    application: &MembershipApplication,
+    iban: &str,
+) -> Result<i32> {
+    let mut tx = pool.begin().await?;
+
+    let member_id = query_scalar!(
+        r#"INSERT INTO members (salutation, first_name, last_name, street, zipcode, city, email, phone, birthday, membership_type, start_date, account_owner, iban)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+        RETURNING id"#,
+        application.salutation,
+        application.first_name,
+        application.last_name,
+        application.street,
+        application.zipcode,
+        application.city,
+        application.email,
+        application.phone,
+        application.birthday,
+        application.membership_type as MembershipType,
+        application.start_date,
+        application.account_owner,
+        iban
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    insert_family_members(
+        &mut tx,
+        member_id,
+        application.family_members.as_deref().unwrap_or_default(),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(member_id)
+}
+
+/// Import existing members, the IBANs have to be validated and encrypted already.
+pub(crate) async fn import_members(pool: &PgPool, members: &[MemberImport]) -> Result<usize> {
+    let mut tx = pool.begin().await?;
+
+    for member in members {
+        let member_id = query_scalar!(
+            r#"INSERT INTO members (salutation, first_name, last_name, street, zipcode, city, email, phone, birthday, membership_type, start_date, end_date, account_owner, iban)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            RETURNING id"#,
+            member.salutation,
+            member.first_name,
+            member.last_name,
+            member.street,
+            member.zipcode,
+            member.city,
+            member.email,
+            member.phone,
+            member.birthday,
+            member.membership_type as MembershipType,
+            member.start_date,
+            member.end_date,
+            member.account_owner,
+            member.iban
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        insert_family_members(&mut tx, member_id, &member.family_members).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(members.len())
+}
+
+async fn insert_family_members(
+    conn: &mut PgConnection,
+    member_id: i32,
+    family_members: &[MembershipFamilyMember],
+) -> Result<()> {
+    for family_member in family_members {
+        query!(
+            r#"INSERT INTO member_family_members (member_id, first_name, last_name, birthday) VALUES ($1, $2, $3, $4)"#,
+            member_id,
+            family_member.first_name,
+            family_member.last_name,
+            family_member.birthday
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Set or remove the end of the membership. Returns `false` if the member does not exist.
+pub(crate) async fn update_member_end_date(
+    pool: &PgPool,
+    member_id: i32,
+    end_date: Option<NaiveDate>,
+) -> Result<bool> {
+    let result = query!(
+        r#"UPDATE members SET end_date = $2 WHERE id = $1"#,
+        member_id,
+        end_date
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub(crate) async fn get_membership_fee_schedule(
+    conn: &mut PgConnection,
+) -> Result<Vec<MembershipFeeSchedule>> {
+    let schedule = query_as!(
+        MembershipFeeSchedule,
+        r#"SELECT membership_type AS "membership_type: MembershipType", annual_fee, family_member_fee FROM membership_fee_schedule ORDER BY membership_type"#
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(schedule)
+}
+
+/// Insert or replace the fees of the given membership types.
+pub(crate) async fn save_membership_fee_schedule(
+    pool: &PgPool,
+    schedule: &[MembershipFeeSchedule],
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    for fee in schedule {
+        query!(
+            r#"INSERT INTO membership_fee_schedule (membership_type, annual_fee, family_member_fee) VALUES ($1, $2, $3)
+            ON CONFLICT (membership_type) DO UPDATE SET annual_fee = EXCLUDED.annual_fee, family_member_fee = EXCLUDED.family_member_fee"#,
+            fee.membership_type as MembershipType,
+            fee.annual_fee,
+            fee.family_member_fee
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Get all members which are active in the given year and whose
+/// membership fee has not been collected for this year yet.
+pub(crate) async fn get_members_due_for_fee(
+    conn: &mut PgConnection,
+    year: i32,
+) -> Result<Vec<Member>> {
+    let members = query!(
+        r#"
+        SELECT
+            m.id,
+            m.created,
+            m.first_name,
+            m.last_name,
+            m.membership_type AS "membership_type: MembershipType",
+            m.start_date,
+            m.account_owner,
+            m.iban,
+            (SELECT COUNT(*) FROM member_family_members f WHERE f.member_id = m.id) AS "family_members!"
+        FROM members m
+        WHERE m.start_date <= make_date($1, 12, 31)
+          AND (m.end_date IS NULL OR m.end_date >= make_date($1, 1, 1))
+          AND NOT EXISTS (
+              SELECT 1 FROM membership_fee_collections c
+              WHERE c.member_id = m.id AND c.year = $1
+          )
+        ORDER BY m.last_name, m.first_name, m.id
+        "#,
+        year
+    )
+    .map(|row| Member {
+        id: row.id,
+        created: row.created,
+        first_name: row.first_name,
+        last_name: row.last_name,
+        membership_type: row.membership_type,
+        start_date: row.start_date,
+        account_owner: row.account_owner,
+        iban: row.iban,
+        family_members: row.family_members,
+    })
+    .fetch_all(conn)
+    .await?;
+
+    Ok(members)
+}
+
+pub(crate) async fn insert_membership_fee_collections(
+    conn: &mut PgConnection,
+    year: i32,
+    fees: &[MembershipFee],
+) -> Result<()> {
+    for fee in fees {
+        query!(
+            r#"INSERT INTO membership_fee_collections (member_id, year, amount) VALUES ($1, $2, $3)"#,
+            fee.member_id,
+            year as i16,
+            fee.amount
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Get the IBANs of members which have not been encrypted yet, i.e. which do not
+/// start with the prefix.
+pub(crate) async fn get_plain_member_ibans(
+    pool: &PgPool,
+    prefix: &str,
+) -> Result<Vec<(i32, String)>> {
+    let ibans = query!(
+        r#"SELECT id, iban FROM members WHERE NOT starts_with(iban, $1)"#,
+        prefix
+    )
+    .map(|row| (row.id, row.iban))
+    .fetch_all(pool)
+    .await?;
+    Ok(ibans)
+}
+
+pub(crate) async fn update_member_ibans(pool: &PgPool, ibans: &[(i32, String)]) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for (member_id, iban) in ibans {
+        query!(
+            r#"UPDATE members SET iban = $2 WHERE id = $1"#,
+            member_id,
+            iban
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
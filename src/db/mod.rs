@@ -1,10 +1,34 @@
+pub(crate) mod api_keys;
+pub(crate) mod audit;
 pub(crate) mod bookings;
+pub(crate) mod campaigns;
+pub(crate) mod captcha;
+pub(crate) mod event_templates;
 pub(crate) mod events;
+pub(crate) mod gdpr;
+pub(crate) mod idempotency;
+pub(crate) mod members;
 pub(crate) mod news;
+pub(crate) mod rate_limits;
+pub(crate) mod roles;
+pub(crate) mod sessions;
+pub(crate) mod trainers;
 
+pub(crate) use api_keys::*;
+pub(crate) use audit::*;
 pub(crate) use bookings::*;
+pub(crate) use campaigns::*;
+pub(crate) use captcha::*;
+pub(crate) use event_templates::*;
 pub(crate) use events::*;
+pub(crate) use gdpr::*;
+pub(crate) use idempotency::*;
+pub(crate) use members::*;
 pub(crate) use news::*;
+pub(crate) use rate_limits::*;
+pub(crate) use roles::*;
+pub(crate) use sessions::*;
+pub(crate) use trainers::*;
 
 use anyhow::Result;
 use sqlx::{PgPool, postgres::PgPoolOptions};
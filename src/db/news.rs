@@ -1,25 +1,19 @@
-use crate::models::{NewsSubscription, NewsTopic};
+use crate::models::{EmailType, NewsSubscription, NewsTopic, NewsTopicDefinition};
 use anyhow::Result;
-use sqlx::{FromRow, PgConnection, PgPool, query, query_as};
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool, query, query_scalar};
 
 pub(crate) async fn get_subscriptions(pool: &PgPool) -> Result<Vec<NewsSubscription>> {
-    let subscriptions =
-        query!(r#"SELECT s.email, s.general, s.events, s.fitness FROM news_subscribers s"#)
-            .map(|row| {
-                let mut types = Vec::new();
-                if row.general {
-                    types.push(NewsTopic::General);
-                }
-                if row.events {
-                    types.push(NewsTopic::Events);
-                }
-                if row.fitness {
-                    types.push(NewsTopic::Fitness);
-                }
-                NewsSubscription::new(row.email, types)
-            })
-            .fetch_all(pool)
-            .await?;
+    let subscriptions = query!(
+        r#"SELECT s.email, ARRAY_REMOVE(ARRAY_AGG(t.name ORDER BY t.id), NULL) AS "topics!"
+        FROM news_subscribers s
+        LEFT JOIN news_subscriptions ns ON ns.subscriber_id = s.id
+        LEFT JOIN news_topics t ON t.id = ns.topic_id
+        GROUP BY s.id, s.email"#
+    )
+    .map(|row| NewsSubscription::new(row.email, into_topics(row.topics)))
+    .fetch_all(pool)
+    .await?;
 
     Ok(subscriptions)
 }
@@ -27,61 +21,85 @@ pub(crate) async fn get_subscriptions(pool: &PgPool) -> Result<Vec<NewsSubscript
 pub(crate) async fn subscribe(
     pool: &PgPool,
     subscription: NewsSubscription,
+    consent_ip: Option<&str>,
 ) -> Result<NewsSubscription> {
     let mut tx = pool.begin().await?;
-    let current_subscription = get_current_subscription(&mut tx, &subscription.email).await?;
-
-    let general = subscription.topics.contains(&NewsTopic::General);
-    let events = subscription.topics.contains(&NewsTopic::Events);
-    let fitness = subscription.topics.contains(&NewsTopic::Fitness);
-
-    if let Some(current_subscriptions) = current_subscription {
-        update_subscription(
-            &mut tx,
-            current_subscriptions.id,
-            current_subscriptions.general || general,
-            current_subscriptions.events || events,
-            current_subscriptions.fitness || fitness,
-        )
-        .await?;
-    } else {
-        query!(
-            r#"INSERT INTO news_subscribers (email, general, events, fitness) VALUES($1, $2, $3, $4)"#,
-            &subscription.email,
-            general,
-            events,
-            fitness
-        ).execute(&mut *tx)
-        .await?;
-    }
-
+    let subscription = merge_subscription(&mut tx, subscription, consent_ip).await?;
     tx.commit().await?;
 
     Ok(subscription)
 }
 
-pub(crate) async fn unsubscribe(pool: &PgPool, subscription: &NewsSubscription) -> Result<()> {
+async fn merge_subscription(
+    conn: &mut PgConnection,
+    subscription: NewsSubscription,
+    consent_ip: Option<&str>,
+) -> Result<NewsSubscription> {
+    let subscriber_id = query_scalar!(
+        r#"INSERT INTO news_subscribers (email, consent_at, consent_ip) VALUES ($1, NOW(), $2)
+        ON CONFLICT (email) DO UPDATE SET consent_at = NOW(), consent_ip = $2
+        RETURNING id"#,
+        &subscription.email,
+        consent_ip
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    add_topics(&mut *conn, subscriber_id, &subscription.topics).await?;
+
+    Ok(subscription)
+}
+
+pub(crate) async fn get_subscription(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<NewsSubscription>> {
+    let mut conn = pool.acquire().await?;
+    let subscription = match get_subscriber_id(&mut conn, email).await? {
+        Some(subscriber_id) => Some(NewsSubscription::new(
+            email.to_string(),
+            get_topics_of_subscriber(&mut conn, subscriber_id).await?,
+        )),
+        None => None,
+    };
+
+    Ok(subscription)
+}
+
+/// Replace the topics of a subscription. Deletes the subscription if no topic is left.
+pub(crate) async fn replace_subscription(
+    pool: &PgPool,
+    subscription: &NewsSubscription,
+    consent_ip: Option<&str>,
+) -> Result<()> {
     let mut tx = pool.begin().await?;
 
-    let current_subscription = get_current_subscription(&mut tx, &subscription.email).await?;
-
-    if let Some(current_subscription) = current_subscription {
-        let general =
-            current_subscription.general && !subscription.topics.contains(&NewsTopic::General);
-        let events =
-            current_subscription.events && !subscription.topics.contains(&NewsTopic::Events);
-        let fitness =
-            current_subscription.fitness && !subscription.topics.contains(&NewsTopic::Fitness);
-        if general || events || fitness {
-            update_subscription(&mut tx, current_subscription.id, general, events, fitness).await?;
-        } else {
+    let subscriber_id = get_subscriber_id(&mut tx, &subscription.email).await?;
+
+    match subscriber_id {
+        Some(subscriber_id) if !subscription.topics.is_empty() => {
+            query!(
+                r#"DELETE FROM news_subscriptions ns USING news_topics t
+                WHERE ns.topic_id = t.id AND ns.subscriber_id = $1 AND NOT t.name = ANY($2)"#,
+                subscriber_id,
+                &topic_names(&subscription.topics) as &[&str]
+            )
+            .execute(&mut *tx)
+            .await?;
+            add_topics(&mut tx, subscriber_id, &subscription.topics).await?;
+        }
+        Some(subscriber_id) => {
             query!(
                 r#"DELETE FROM news_subscribers WHERE id = $1"#,
-                current_subscription.id
+                subscriber_id
             )
             .execute(&mut *tx)
             .await?;
         }
+        None if !subscription.topics.is_empty() => {
+            merge_subscription(&mut tx, subscription.clone(), consent_ip).await?;
+        }
+        None => {}
     }
 
     tx.commit().await?;
@@ -89,42 +107,191 @@ pub(crate) async fn unsubscribe(pool: &PgPool, subscription: &NewsSubscription)
     Ok(())
 }
 
-#[derive(FromRow)]
-struct CurrentSubscription {
+/// Store a pending subscription until it gets confirmed by the subscriber.
+pub(crate) async fn insert_subscription_request(
+    pool: &PgPool,
+    subscription: &NewsSubscription,
+    request_ip: Option<&str>,
+) -> Result<i32> {
+    let id = query_scalar!(
+        r#"INSERT INTO news_subscription_requests (email, topics, request_ip) VALUES ($1, $2, $3) RETURNING id"#,
+        &subscription.email,
+        &topic_names(&subscription.topics) as &[&str],
+        request_ip
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// Turn a pending subscription into an active subscription.
+/// Returns `None` if the request does not exist or has been created before `valid_since`.
+pub(crate) async fn confirm_subscription_request(
+    pool: &PgPool,
     id: i32,
-    general: bool,
-    events: bool,
-    fitness: bool,
+    valid_since: DateTime<Utc>,
+    consent_ip: Option<&str>,
+) -> Result<Option<NewsSubscription>> {
+    let mut tx = pool.begin().await?;
+
+    let request = query!(
+        r#"DELETE FROM news_subscription_requests WHERE id = $1 AND created >= $2 RETURNING email, topics"#,
+        id,
+        valid_since
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let subscription = match request {
+        Some(row) => {
+            let subscription = NewsSubscription::new(row.email, into_topics(row.topics));
+            Some(merge_subscription(&mut tx, subscription, consent_ip).await?)
+        }
+        None => None,
+    };
+
+    tx.commit().await?;
+
+    Ok(subscription)
 }
 
-async fn get_current_subscription(
-    conn: &mut PgConnection,
-    email: &str,
-) -> Result<Option<CurrentSubscription>> {
-    let current_subscription: Option<CurrentSubscription> = query_as!(
-        CurrentSubscription,
-        r#"SELECT s.id, s.general, s.events, s.fitness FROM news_subscribers s WHERE s.email = $1"#,
+pub(crate) async fn delete_expired_subscription_requests(
+    pool: &PgPool,
+    created_before: DateTime<Utc>,
+) -> Result<u64> {
+    let result = query!(
+        r#"DELETE FROM news_subscription_requests WHERE created < $1"#,
+        created_before
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub(crate) async fn unsubscribe(pool: &PgPool, subscription: &NewsSubscription) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    if let Some(subscriber_id) = get_subscriber_id(&mut tx, &subscription.email).await? {
+        query!(
+            r#"DELETE FROM news_subscriptions ns USING news_topics t
+            WHERE ns.topic_id = t.id AND ns.subscriber_id = $1 AND t.name = ANY($2)"#,
+            subscriber_id,
+            &topic_names(&subscription.topics) as &[&str]
+        )
+        .execute(&mut *tx)
+        .await?;
+        query!(
+            r#"DELETE FROM news_subscribers s WHERE s.id = $1
+            AND NOT EXISTS (SELECT 1 FROM news_subscriptions ns WHERE ns.subscriber_id = s.id)"#,
+            subscriber_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub(crate) async fn get_topics(pool: &PgPool) -> Result<Vec<NewsTopicDefinition>> {
+    let topics = query!(
+        r#"SELECT name, display_name, email_type AS "email_type: EmailType" FROM news_topics ORDER BY id"#
+    )
+    .map(|row| NewsTopicDefinition {
+        name: NewsTopic::new(row.name),
+        display_name: row.display_name,
+        email_type: row.email_type,
+    })
+    .fetch_all(pool)
+    .await?;
+
+    Ok(topics)
+}
+
+pub(crate) async fn insert_topic(pool: &PgPool, topic: &NewsTopicDefinition) -> Result<bool> {
+    let result = query!(
+        r#"INSERT INTO news_topics (name, display_name, email_type) VALUES ($1, $2, $3) ON CONFLICT (name) DO NOTHING"#,
+        topic.name.as_str(),
+        topic.display_name,
+        topic.email_type.clone() as EmailType
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub(crate) async fn update_topic(pool: &PgPool, topic: &NewsTopicDefinition) -> Result<bool> {
+    let result = query!(
+        r#"UPDATE news_topics SET display_name = $2, email_type = $3 WHERE name = $1"#,
+        topic.name.as_str(),
+        topic.display_name,
+        topic.email_type.clone() as EmailType
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Delete a topic including all subscriptions of the topic.
+/// Fails if the topic is referenced by a campaign.
+pub(crate) async fn delete_topic(pool: &PgPool, name: &NewsTopic) -> Result<bool> {
+    let result = query!(r#"DELETE FROM news_topics WHERE name = $1"#, name.as_str())
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+fn topic_names(topics: &[NewsTopic]) -> Vec<&str> {
+    topics.iter().map(NewsTopic::as_str).collect()
+}
+
+fn into_topics(names: Vec<String>) -> Vec<NewsTopic> {
+    names.into_iter().map(NewsTopic::new).collect()
+}
+
+async fn get_subscriber_id(conn: &mut PgConnection, email: &str) -> Result<Option<i32>> {
+    let id = query_scalar!(
+        r#"SELECT s.id FROM news_subscribers s WHERE s.email = $1"#,
         email
     )
     .fetch_optional(conn)
     .await?;
 
-    Ok(current_subscription)
+    Ok(id)
 }
 
-async fn update_subscription(
+async fn get_topics_of_subscriber(
     conn: &mut PgConnection,
-    id: i32,
-    general: bool,
-    events: bool,
-    fitness: bool,
+    subscriber_id: i32,
+) -> Result<Vec<NewsTopic>> {
+    let topics = query_scalar!(
+        r#"SELECT t.name FROM news_subscriptions ns JOIN news_topics t ON t.id = ns.topic_id
+        WHERE ns.subscriber_id = $1 ORDER BY t.id"#,
+        subscriber_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(into_topics(topics))
+}
+
+async fn add_topics(
+    conn: &mut PgConnection,
+    subscriber_id: i32,
+    topics: &[NewsTopic],
 ) -> Result<()> {
     query!(
-        r#"UPDATE news_subscribers SET general = $2, events = $3, fitness = $4 WHERE id = $1"#,
-        id,
-        general,
-        events,
-        fitness
+        r#"INSERT INTO news_subscriptions (subscriber_id, topic_id)
+        SELECT $1, t.id FROM news_topics t WHERE t.name = ANY($2)
+        ON CONFLICT DO NOTHING"#,
+        subscriber_id,
+        &topic_names(topics) as &[&str]
     )
     .execute(conn)
     .await?;
@@ -143,16 +310,16 @@ mod news_integration_tests {
     async fn test_subscribe_and_unsubscribe(pool: PgPool) -> Result<()> {
         let subscription = crate::models::NewsSubscription::new(
             "test@example.com".to_string(),
-            vec![NewsTopic::General, NewsTopic::Events],
+            vec![NewsTopic::GENERAL, NewsTopic::EVENTS],
         );
 
-        let result = subscribe(&pool, subscription.clone()).await?;
+        let result = subscribe(&pool, subscription.clone(), None).await?;
         assert_eq!(result.email, "test@example.com");
         assert_eq!(result.topics.len(), 2);
 
         let unsubscribe_sub = crate::models::NewsSubscription::new(
             "test@example.com".to_string(),
-            vec![NewsTopic::General],
+            vec![NewsTopic::GENERAL],
         );
         unsubscribe(&pool, &unsubscribe_sub).await?;
 
@@ -161,8 +328,8 @@ mod news_integration_tests {
             .iter()
             .find(|s| s.email == "test@example.com")
             .unwrap();
-        assert!(!sub.topics.contains(&NewsTopic::General));
-        assert!(sub.topics.contains(&NewsTopic::Events));
+        assert!(!sub.topics.contains(&NewsTopic::GENERAL));
+        assert!(sub.topics.contains(&NewsTopic::EVENTS));
 
         let full_unsub =
             crate::models::NewsSubscription::new("test@example.com".to_string(), vec![]);
@@ -173,7 +340,7 @@ mod news_integration_tests {
             .iter()
             .find(|s| s.email == "test@example.com")
             .unwrap();
-        assert!(sub.topics.contains(&NewsTopic::Events));
+        assert!(sub.topics.contains(&NewsTopic::EVENTS));
 
         Ok(())
     }
@@ -183,32 +350,33 @@ mod news_integration_tests {
         let email1 = "alice@example.com";
         let email2 = "bob@example.com";
 
-        query!(
-            r#"INSERT INTO news_subscribers (email, general, events, fitness) VALUES ($1, $2, $3, $4)"#,
-            email1, true, false, true
-        )
-        .execute(&pool)
-        .await?;
-
-        query!(
-            r#"INSERT INTO news_subscribers (email, general, events, fitness) VALUES ($1, $2, $3, $4)"#,
-            email2, false, true, false
-        )
-        .execute(&pool)
-        .await?;
+        for (email, topics) in [
+            (email1, vec!["General", "Fitness"]),
+            (email2, vec!["Events"]),
+        ] {
+            query!(
+                r#"WITH s AS (INSERT INTO news_subscribers (email) VALUES ($1) RETURNING id)
+                INSERT INTO news_subscriptions (subscriber_id, topic_id)
+                SELECT s.id, t.id FROM s, news_topics t WHERE t.name = ANY($2)"#,
+                email,
+                &topics as &[&str]
+            )
+            .execute(&pool)
+            .await?;
+        }
 
         let subscriptions = get_subscriptions(&pool).await?;
         assert_eq!(subscriptions.len(), 2);
 
         let alice = subscriptions.iter().find(|s| s.email == email1).unwrap();
-        assert!(alice.topics.contains(&NewsTopic::General));
-        assert!(!alice.topics.contains(&NewsTopic::Events));
-        assert!(alice.topics.contains(&NewsTopic::Fitness));
+        assert!(alice.topics.contains(&NewsTopic::GENERAL));
+        assert!(!alice.topics.contains(&NewsTopic::EVENTS));
+        assert!(alice.topics.contains(&NewsTopic::FITNESS));
 
         let bob = subscriptions.iter().find(|s| s.email == email2).unwrap();
-        assert!(!bob.topics.contains(&NewsTopic::General));
-        assert!(bob.topics.contains(&NewsTopic::Events));
-        assert!(!bob.topics.contains(&NewsTopic::Fitness));
+        assert!(!bob.topics.contains(&NewsTopic::GENERAL));
+        assert!(bob.topics.contains(&NewsTopic::EVENTS));
+        assert!(!bob.topics.contains(&NewsTopic::FITNESS));
 
         Ok(())
     }
@@ -0,0 +1,58 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, query};
+
+/// Record a hit of the given key if it had less than `max_hits` hits since
+/// `window_start`. Returns the time of the oldest hit in the window if the limit
+/// has been reached.
+pub(crate) async fn register_rate_limit_hit(
+    pool: &PgPool,
+    bucket: &str,
+    key: &str,
+    max_hits: i64,
+    window_start: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>> {
+    let mut tx = pool.begin().await?;
+
+    // serialize concurrent requests of the same key across all instances
+    query!(
+        r#"SELECT pg_advisory_xact_lock(hashtext($1 || ':' || $2))"#,
+        bucket,
+        key
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // drop the expired hits of the whole bucket, so clients which do not come back are cleaned up too
+    query!(
+        r#"DELETE FROM rate_limit_hits WHERE bucket = $1 AND created < $2"#,
+        bucket,
+        window_start
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let hits = query!(
+        r#"SELECT COUNT(*) AS "count!", MIN(created) AS oldest FROM rate_limit_hits
+        WHERE bucket = $1 AND key = $2"#,
+        bucket,
+        key
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if hits.count >= max_hits {
+        return Ok(Some(hits.oldest.unwrap_or(window_start)));
+    }
+
+    query!(
+        r#"INSERT INTO rate_limit_hits (bucket, key) VALUES ($1, $2)"#,
+        bucket,
+        key
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(None)
+}
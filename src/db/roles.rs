@@ -0,0 +1,66 @@
+use crate::models::{Role, RoleAssignment};
+use anyhow::Result;
+use sqlx::{PgPool, query, query_scalar};
+
+pub(crate) async fn get_roles(pool: &PgPool, email: &str) -> Result<Vec<Role>> {
+    let roles = query_scalar!(
+        r#"SELECT role AS "role: Role" FROM user_roles WHERE email = $1 ORDER BY role"#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(roles)
+}
+
+pub(crate) async fn get_role_assignments(pool: &PgPool) -> Result<Vec<RoleAssignment>> {
+    let rows = query!(r#"SELECT email, role AS "role: Role" FROM user_roles ORDER BY email, role"#)
+        .fetch_all(pool)
+        .await?;
+
+    let mut assignments: Vec<RoleAssignment> = Vec::new();
+    for row in rows {
+        match assignments.last_mut() {
+            Some(assignment) if assignment.email == row.email => assignment.roles.push(row.role),
+            _ => assignments.push(RoleAssignment {
+                email: row.email,
+                roles: vec![row.role],
+            }),
+        }
+    }
+
+    Ok(assignments)
+}
+
+/// Replace all roles of the given user. The change is discarded and `false`
+/// is returned if no admin would be left afterwards.
+pub(crate) async fn set_roles(pool: &PgPool, email: &str, roles: &[Role]) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    query!(r#"DELETE FROM user_roles WHERE email = $1"#, email)
+        .execute(&mut *tx)
+        .await?;
+
+    for role in roles {
+        query!(
+            r#"INSERT INTO user_roles (email, role) VALUES ($1, $2) ON CONFLICT DO NOTHING"#,
+            email,
+            *role as Role
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let admins =
+        query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM user_roles WHERE role = 'Admin'"#)
+            .fetch_one(&mut *tx)
+            .await?;
+
+    if admins == 0 {
+        return Ok(false);
+    }
+
+    tx.commit().await?;
+
+    Ok(true)
+}
@@ -0,0 +1,97 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool, query, query_as, query_scalar};
+
+pub(crate) struct SessionRow {
+    pub(crate) id: i32,
+    pub(crate) family: String,
+    pub(crate) email: String,
+    pub(crate) hd: Option<String>,
+    pub(crate) expires_at: DateTime<Utc>,
+    pub(crate) rotated_at: Option<DateTime<Utc>>,
+    pub(crate) revoked_at: Option<DateTime<Utc>>,
+}
+
+pub(crate) async fn insert_session(
+    conn: &mut PgConnection,
+    family: &str,
+    email: &str,
+    hd: Option<&str>,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<()> {
+    query!(
+        r#"INSERT INTO sessions (family, email, hd, token_hash, expires_at) VALUES ($1, $2, $3, $4, $5)"#,
+        family,
+        email,
+        hd,
+        token_hash,
+        expires_at
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Fetch the session of the given refresh token hash and lock it for the rotation.
+pub(crate) async fn lock_session(
+    conn: &mut PgConnection,
+    token_hash: &str,
+) -> Result<Option<SessionRow>> {
+    let session = query_as!(
+        SessionRow,
+        r#"SELECT id, family, email, hd, expires_at, rotated_at, revoked_at
+        FROM sessions WHERE token_hash = $1
+        FOR UPDATE"#,
+        token_hash
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(session)
+}
+
+pub(crate) async fn mark_session_rotated(conn: &mut PgConnection, id: i32) -> Result<()> {
+    query!(
+        r#"UPDATE sessions SET rotated_at = NOW() WHERE id = $1"#,
+        id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn revoke_session_family(conn: &mut PgConnection, family: &str) -> Result<u64> {
+    let result = query!(
+        r#"UPDATE sessions SET revoked_at = NOW() WHERE family = $1 AND revoked_at IS NULL"#,
+        family
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Revoke all sessions of the given user. Returns the number of sessions which
+/// have been active, i.e. whose current refresh token has not expired yet.
+pub(crate) async fn revoke_sessions_of_user(pool: &PgPool, email: &str) -> Result<u64> {
+    let revoked = query_scalar!(
+        r#"UPDATE sessions SET revoked_at = NOW() WHERE email = $1 AND revoked_at IS NULL
+        RETURNING (rotated_at IS NULL AND expires_at > NOW()) AS "active!""#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(revoked.into_iter().filter(|active| *active).count() as u64)
+}
+
+pub(crate) async fn delete_expired_sessions(pool: &PgPool) -> Result<u64> {
+    let result = query!(r#"DELETE FROM sessions WHERE expires_at < NOW()"#)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
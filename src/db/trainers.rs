@@ -0,0 +1,238 @@
+use crate::models::{EventId, Trainer, TrainerAttendance, TrainerInput};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, query, query_scalar};
+use std::collections::HashMap;
+
+pub(crate) async fn get_trainers(pool: &PgPool) -> Result<Vec<Trainer>> {
+    let trainers = query!(
+        r#"SELECT t.id, t.email, t.name,
+            ARRAY_REMOVE(ARRAY_AGG(te.event_id ORDER BY te.event_id), NULL) AS "event_ids!"
+        FROM trainers t
+        LEFT JOIN trainer_events te ON te.trainer_id = t.id
+        GROUP BY t.id
+        ORDER BY t.name, t.email"#
+    )
+    .map(|row| Trainer {
+        id: row.id,
+        email: row.email,
+        name: row.name,
+        event_ids: row.event_ids.into_iter().map(EventId::from).collect(),
+    })
+    .fetch_all(pool)
+    .await?;
+
+    Ok(trainers)
+}
+
+pub(crate) async fn get_trainer(pool: &PgPool, id: i32) -> Result<Option<Trainer>> {
+    let trainer = query!(
+        r#"SELECT t.id, t.email, t.name,
+            ARRAY_REMOVE(ARRAY_AGG(te.event_id ORDER BY te.event_id), NULL) AS "event_ids!"
+        FROM trainers t
+        LEFT JOIN trainer_events te ON te.trainer_id = t.id
+        WHERE t.id = $1
+        GROUP BY t.id"#,
+        id
+    )
+    .map(|row| Trainer {
+        id: row.id,
+        email: row.email,
+        name: row.name,
+        event_ids: row.event_ids.into_iter().map(EventId::from).collect(),
+    })
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(trainer)
+}
+
+pub(crate) async fn get_trainer_id_by_email(pool: &PgPool, email: &str) -> Result<Option<i32>> {
+    let id = query_scalar!(r#"SELECT id FROM trainers WHERE email = $1"#, email)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(id)
+}
+
+/// Insert or update the trainer with the email of the input and replace the assigned events.
+pub(crate) async fn save_trainer(pool: &PgPool, input: &TrainerInput) -> Result<i32> {
+    let mut tx = pool.begin().await?;
+
+    let id = query_scalar!(
+        r#"INSERT INTO trainers (email, name) VALUES ($1, $2)
+        ON CONFLICT (email) DO UPDATE SET name = EXCLUDED.name
+        RETURNING id"#,
+        input.email,
+        input.name
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    query!(r#"DELETE FROM trainer_events WHERE trainer_id = $1"#, id)
+        .execute(&mut *tx)
+        .await?;
+
+    let event_ids = input
+        .event_ids
+        .iter()
+        .map(|id| id.into_inner())
+        .collect::<Vec<i32>>();
+    query!(
+        r#"INSERT INTO trainer_events (trainer_id, event_id)
+        SELECT $1, e.id FROM events e WHERE e.id = ANY($2)"#,
+        id,
+        &event_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(id)
+}
+
+pub(crate) async fn delete_trainer(pool: &PgPool, id: i32) -> Result<bool> {
+    let result = query!(r#"DELETE FROM trainers WHERE id = $1"#, id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub(crate) async fn is_trainer_of_event(
+    pool: &PgPool,
+    trainer_id: i32,
+    event_id: &EventId,
+) -> Result<bool> {
+    let assigned = query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM trainer_events WHERE trainer_id = $1 AND event_id = $2) AS "assigned!""#,
+        trainer_id,
+        event_id.get_ref()
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(assigned)
+}
+
+/// Store the id of a login link sent to the trainer and remove the outdated ones.
+pub(crate) async fn insert_trainer_login_token(
+    pool: &PgPool,
+    id: &str,
+    trainer_id: i32,
+    outdated_before: DateTime<Utc>,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    query!(
+        r#"DELETE FROM trainer_login_tokens WHERE trainer_id = $1 AND created < $2"#,
+        trainer_id,
+        outdated_before
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    query!(
+        r#"INSERT INTO trainer_login_tokens (id, trainer_id) VALUES ($1, $2)"#,
+        id,
+        trainer_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Mark the login link as used. Returns the trainer of the link or `None` if the
+/// link is unknown or has been used already.
+pub(crate) async fn consume_trainer_login_token(pool: &PgPool, id: &str) -> Result<Option<i32>> {
+    let trainer_id = query_scalar!(
+        r#"UPDATE trainer_login_tokens SET used_at = NOW() WHERE id = $1 AND used_at IS NULL RETURNING trainer_id"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(trainer_id)
+}
+
+/// Get the attended dates of the bookings of the event.
+pub(crate) async fn get_attendances(
+    pool: &PgPool,
+    event_id: &EventId,
+) -> Result<HashMap<i32, Vec<DateTime<Utc>>>> {
+    let rows = query!(
+        r#"SELECT a.booking_id, a.date
+        FROM event_attendances a
+        JOIN event_bookings b ON b.id = a.booking_id
+        WHERE b.event_id = $1
+        ORDER BY a.booking_id, a.date"#,
+        event_id.get_ref()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut attendances: HashMap<i32, Vec<DateTime<Utc>>> = HashMap::new();
+    for row in rows {
+        attendances
+            .entry(row.booking_id)
+            .or_default()
+            .push(row.date);
+    }
+
+    Ok(attendances)
+}
+
+/// Mark or unmark the attendance of an enrolled booking of the event at one of
+/// its dates. Returns `false` if the booking or the date does not belong to the event.
+pub(crate) async fn set_attendance(
+    pool: &PgPool,
+    event_id: &EventId,
+    trainer_id: i32,
+    attendance: &TrainerAttendance,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    let valid = query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM event_bookings b
+            JOIN event_dates d ON d.event_id = b.event_id
+            WHERE b.id = $1 AND b.event_id = $2 AND b.enrolled IS TRUE AND b.canceled IS NULL AND d.date = $3
+        ) AS "valid!""#,
+        attendance.booking_id,
+        event_id.get_ref(),
+        attendance.date
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !valid {
+        return Ok(false);
+    }
+
+    if attendance.attended {
+        query!(
+            r#"INSERT INTO event_attendances (booking_id, date, trainer_id) VALUES ($1, $2, $3)
+            ON CONFLICT (booking_id, date) DO NOTHING"#,
+            attendance.booking_id,
+            attendance.date,
+            trainer_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    } else {
+        query!(
+            r#"DELETE FROM event_attendances WHERE booking_id = $1 AND date = $2"#,
+            attendance.booking_id,
+            attendance.date
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(true)
+}
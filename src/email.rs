@@ -5,6 +5,7 @@ use anyhow::{Context, Result, bail};
 use async_trait::async_trait;
 use lettre::message::Mailbox;
 use lettre::message::MessageBuilder;
+use lettre::message::header::{Header, HeaderName, HeaderValue};
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
 #[cfg(test)]
@@ -23,6 +24,47 @@ pub(crate) fn new_message_builder(account: &EmailAccount) -> Result<MessageBuild
     Ok(Message::builder().from(mailbox(account)?).date_now())
 }
 
+/// `List-Unsubscribe` header (RFC 2369) containing the unsubscribe URL.
+#[derive(Clone)]
+pub(crate) struct ListUnsubscribe(pub(crate) String);
+
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe")
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(
+            s.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .into(),
+        ))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), format!("<{}>", self.0))
+    }
+}
+
+/// `List-Unsubscribe-Post` header (RFC 8058) announcing one-click unsubscribe.
+#[derive(Clone)]
+pub(crate) struct ListUnsubscribePost;
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe-Post")
+    }
+
+    fn parse(_: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self)
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), "List-Unsubscribe=One-Click".into())
+    }
+}
+
 pub(crate) fn create_mailer(account: &EmailAccount) -> Result<AsyncSmtpTransport<Tokio1Executor>> {
     let transport = AsyncSmtpTransport::<Tokio1Executor>::relay("smtp.gmail.com")?
         .credentials(Credentials::new(
@@ -0,0 +1,204 @@
+use super::tokens::hash;
+use crate::db;
+use crate::error::ValidationError;
+use crate::models::{ApiKey, ApiKeyInput, CreatedApiKey};
+use anyhow::{Result, bail};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Names of the task endpoints below `/api/tasks` which can be granted to an API key.
+pub(crate) const TASK_SCOPES: &[&str] = &[
+    "check_email_connectivity",
+    "renew_calendar_watch",
+    "send_event_reminders",
+    "close_finished_events",
+    "publish_scheduled_events",
+    "expire_waiting_list_offers",
+    "expire_news_subscriptions",
+    "send_news_campaigns",
+    "delete_expired_sessions",
+    "anonymize_archived_participants",
+    "encrypt_ibans",
+];
+
+/// Name of the API key which is imported from the former `TASK_API_KEY` secret.
+const LEGACY_KEY_NAME: &str = "TASK_API_KEY";
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum TaskAccess {
+    Granted,
+    /// The key is unknown or has expired.
+    InvalidKey,
+    /// The key is valid but may not call the task.
+    MissingScope,
+}
+
+pub(crate) async fn get_api_keys(pool: &PgPool) -> Result<Vec<ApiKey>> {
+    db::get_api_keys(pool).await
+}
+
+/// Create a new API key. The key is part of the result and cannot be retrieved later on.
+pub(crate) async fn create_api_key(pool: &PgPool, mut input: ApiKeyInput) -> Result<CreatedApiKey> {
+    input.name = input.name.trim().to_string();
+    if input.name.is_empty() {
+        bail!(ValidationError::new("Bitte gib einen Namen ein."));
+    }
+    if input.scopes.is_empty() {
+        bail!(ValidationError::new(
+            "Bitte wähle mindestens einen Task aus."
+        ));
+    }
+    if let Some(scope) = input
+        .scopes
+        .iter()
+        .find(|scope| !TASK_SCOPES.contains(&scope.as_str()))
+    {
+        bail!(ValidationError::new(format!("Unbekannter Task: {}", scope)));
+    }
+    input.scopes.sort();
+    input.scopes.dedup();
+
+    let key = format!("sve_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    match db::insert_api_key(pool, &input, &hash(&key)).await? {
+        Some(api_key) => Ok(CreatedApiKey { api_key, key }),
+        None => bail!(ValidationError::new(format!(
+            "Ein API-Key mit dem Namen {} existiert bereits.",
+            input.name
+        ))),
+    }
+}
+
+/// Import the former `TASK_API_KEY` secret once as API key with all scopes, so the
+/// schedulers keep working until they use keys of their own. The key is not imported
+/// again after it has been deleted. Returns whether the key has been created.
+pub(crate) async fn import_legacy_key(pool: &PgPool, key: &str) -> Result<bool> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Ok(false);
+    }
+    let input = ApiKeyInput {
+        name: LEGACY_KEY_NAME.to_string(),
+        scopes: TASK_SCOPES.iter().map(|scope| scope.to_string()).collect(),
+        expires_at: None,
+    };
+    db::import_api_key(pool, &input, &hash(key)).await
+}
+
+pub(crate) async fn delete_api_key(pool: &PgPool, id: i32) -> Result<bool> {
+    db::delete_api_key(pool, id).await
+}
+
+/// Check whether the given key may call the given task and record its usage.
+pub(crate) async fn authorize(pool: &PgPool, key: &str, task: &str) -> Result<TaskAccess> {
+    let Some(api_key) = db::get_valid_api_key(pool, &hash(key)).await? else {
+        return Ok(TaskAccess::InvalidKey);
+    };
+    if !api_key.scopes.iter().any(|scope| scope == task) {
+        return Ok(TaskAccess::MissingScope);
+    }
+    db::mark_api_key_used(pool, api_key.id).await?;
+    Ok(TaskAccess::Granted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use pretty_assertions::assert_eq;
+
+    fn input(name: &str, scopes: &[&str]) -> ApiKeyInput {
+        ApiKeyInput {
+            name: name.into(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            expires_at: None,
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_authorize(pool: PgPool) -> Result<()> {
+        let reminders = create_api_key(
+            &pool,
+            input("Reminder scheduler", &["send_event_reminders"]),
+        )
+        .await?;
+        assert!(reminders.key.starts_with("sve_"));
+        assert_eq!(reminders.api_key.last_used_at, None);
+
+        assert_eq!(
+            authorize(&pool, &reminders.key, "send_event_reminders").await?,
+            TaskAccess::Granted
+        );
+        assert_eq!(
+            authorize(&pool, &reminders.key, "renew_calendar_watch").await?,
+            TaskAccess::MissingScope
+        );
+        assert_eq!(
+            authorize(&pool, "sve_unknown", "send_event_reminders").await?,
+            TaskAccess::InvalidKey
+        );
+        assert!(get_api_keys(&pool).await?[0].last_used_at.is_some());
+
+        let mut expired = input("Expired", &["renew_calendar_watch"]);
+        expired.expires_at = Some(Utc::now() - Duration::minutes(1));
+        let expired = create_api_key(&pool, expired).await?;
+        assert_eq!(
+            authorize(&pool, &expired.key, "renew_calendar_watch").await?,
+            TaskAccess::InvalidKey
+        );
+
+        assert!(delete_api_key(&pool, reminders.api_key.id).await?);
+        assert_eq!(
+            authorize(&pool, &reminders.key, "send_event_reminders").await?,
+            TaskAccess::InvalidKey
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_import_legacy_key(pool: PgPool) -> Result<()> {
+        assert!(!import_legacy_key(&pool, "").await?);
+        assert!(import_legacy_key(&pool, "legacy-secret").await?);
+        assert!(!import_legacy_key(&pool, "legacy-secret").await?);
+
+        for task in TASK_SCOPES {
+            assert_eq!(
+                authorize(&pool, "legacy-secret", task).await?,
+                TaskAccess::Granted
+            );
+        }
+        assert_eq!(get_api_keys(&pool).await?.len(), 1);
+
+        // A revoked key is not imported again on the next start
+        let id = get_api_keys(&pool).await?[0].id;
+        assert!(delete_api_key(&pool, id).await?);
+        assert!(!import_legacy_key(&pool, "legacy-secret").await?);
+        assert_eq!(
+            authorize(&pool, "legacy-secret", TASK_SCOPES[0]).await?,
+            TaskAccess::InvalidKey
+        );
+        assert!(get_api_keys(&pool).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_create_api_key_validation(pool: PgPool) -> Result<()> {
+        for invalid in [
+            input("", &["send_event_reminders"]),
+            input("Scheduler", &[]),
+            input("Scheduler", &["unknown_task"]),
+        ] {
+            let err = create_api_key(&pool, invalid).await.unwrap_err();
+            assert!(err.downcast_ref::<ValidationError>().is_some());
+        }
+
+        create_api_key(&pool, input("Scheduler", &["send_event_reminders"])).await?;
+        let err = create_api_key(&pool, input("Scheduler", &["send_event_reminders"]))
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+use crate::db;
+use crate::models::{AuditLogEntry, AuditLogFilter, EventId};
+use anyhow::Result;
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+
+/// Details of a call in the admin area which are only known to the handler. The
+/// handler attaches the record to its response and the audit middleware writes it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AuditRecord {
+    pub(crate) event_id: Option<EventId>,
+    pub(crate) booking_id: Option<i32>,
+    pub(crate) subject: Option<String>,
+    pub(crate) before: Option<Value>,
+    pub(crate) after: Option<Value>,
+}
+
+impl AuditRecord {
+    pub(crate) fn event(event_id: EventId) -> Self {
+        Self {
+            event_id: Some(event_id),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn booking(booking_id: i32) -> Self {
+        Self {
+            booking_id: Some(booking_id),
+            ..Default::default()
+        }
+    }
+
+    /// Record concerning the data of the person with the given email address.
+    pub(crate) fn subject(email: impl Into<String>) -> Self {
+        Self {
+            subject: Some(email.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Attach the changed fields of the given snapshots. The event of a booking
+    /// is taken from the snapshots if it is not known yet.
+    pub(crate) fn with_changes(mut self, before: Option<Value>, after: Option<Value>) -> Self {
+        if self.event_id.is_none() {
+            self.event_id = before
+                .iter()
+                .chain(after.iter())
+                .find_map(|snapshot| snapshot.get("event_id").and_then(Value::as_i64))
+                .and_then(|id| i32::try_from(id).ok())
+                .map(EventId::from);
+        }
+        (self.before, self.after) = diff(before, after);
+        self
+    }
+}
+
+pub(crate) async fn event_snapshot(pool: &PgPool, event_id: &EventId) -> Result<Option<Value>> {
+    db::get_event_snapshot(pool, event_id).await
+}
+
+pub(crate) async fn booking_snapshot(pool: &PgPool, booking_id: i32) -> Result<Option<Value>> {
+    db::get_booking_snapshot(pool, booking_id).await
+}
+
+pub(crate) async fn record(
+    pool: &PgPool,
+    actor: &str,
+    action: &str,
+    path: &str,
+    record: &AuditRecord,
+) -> Result<()> {
+    db::insert_audit_entry(
+        pool,
+        actor,
+        action,
+        path,
+        record.event_id.as_ref(),
+        record.booking_id,
+        record.subject.as_deref(),
+        record.before.as_ref(),
+        record.after.as_ref(),
+    )
+    .await
+}
+
+pub(crate) async fn get_entries(
+    pool: &PgPool,
+    filter: &AuditLogFilter,
+) -> Result<Vec<AuditLogEntry>> {
+    db::get_audit_entries(pool, filter).await
+}
+
+/// Get all entries regarding the given event and its bookings, newest first.
+pub(crate) async fn get_event_history(
+    pool: &PgPool,
+    event_id: EventId,
+) -> Result<Vec<AuditLogEntry>> {
+    db::get_audit_entries(
+        pool,
+        &AuditLogFilter {
+            event_id: Some(event_id),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Reduce two snapshots of a row to the fields which differ. Snapshots which
+/// are missing on one side (creation or deletion) are kept completely.
+pub(crate) fn diff(before: Option<Value>, after: Option<Value>) -> (Option<Value>, Option<Value>) {
+    match (before, after) {
+        (Some(Value::Object(before)), Some(Value::Object(after))) => {
+            let mut changed_before = Map::new();
+            let mut changed_after = Map::new();
+            for key in before.keys().chain(after.keys()) {
+                let old = before.get(key).unwrap_or(&Value::Null);
+                let new = after.get(key).unwrap_or(&Value::Null);
+                if old != new && !changed_before.contains_key(key) {
+                    changed_before.insert(key.clone(), old.clone());
+                    changed_after.insert(key.clone(), new.clone());
+                }
+            }
+            (
+                Some(Value::Object(changed_before)),
+                Some(Value::Object(changed_after)),
+            )
+        }
+        (before, after) => (before, after),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(
+            diff(
+                Some(json!({"id": 1, "price_member": "20", "name": "Yoga"})),
+                Some(json!({"id": 1, "price_member": "25", "name": "Yoga"}))
+            ),
+            (
+                Some(json!({"price_member": "20"})),
+                Some(json!({"price_member": "25"}))
+            )
+        );
+        assert_eq!(
+            diff(Some(json!({"id": 1})), None),
+            (Some(json!({"id": 1})), None)
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_record_and_filter(pool: PgPool) -> Result<()> {
+        let booking = AuditRecord::booking(7).with_changes(
+            Some(json!({"event_id": 3, "canceled": null})),
+            Some(json!({"event_id": 3, "canceled": "2026-10-18T10:00:00Z"})),
+        );
+        assert_eq!(booking.event_id, Some(EventId::from(3)));
+        record(
+            &pool,
+            "fitness@sv-eutingen.de",
+            "DELETE /api/admin/events/booking/{id}",
+            "/api/admin/events/booking/7",
+            &booking,
+        )
+        .await?;
+        record(
+            &pool,
+            "kassier@sv-eutingen.de",
+            "POST /api/admin/events/payments/verify",
+            "/api/admin/events/payments/verify",
+            &AuditRecord::default(),
+        )
+        .await?;
+        record(
+            &pool,
+            "admin@sv-eutingen.de",
+            "GET /api/admin/gdpr/export",
+            "/api/admin/gdpr/export",
+            &AuditRecord::subject("max.mustermann@example.com"),
+        )
+        .await?;
+
+        let history = get_event_history(&pool, EventId::from(3)).await?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].actor, "fitness@sv-eutingen.de");
+        assert_eq!(history[0].booking_id, Some(7));
+        assert_eq!(history[0].before, Some(json!({"canceled": null})));
+
+        let entries = get_entries(
+            &pool,
+            &AuditLogFilter {
+                action: Some("payments/verify".into()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].actor, "kassier@sv-eutingen.de");
+        let entries = get_entries(
+            &pool,
+            &AuditLogFilter {
+                subject: Some("max.mustermann@example.com".into()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, "GET /api/admin/gdpr/export");
+        assert_eq!(
+            get_entries(&pool, &AuditLogFilter::default()).await?.len(),
+            3
+        );
+
+        // the log is append-only
+        assert!(
+            sqlx::query("DELETE FROM audit_log")
+                .execute(&pool)
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
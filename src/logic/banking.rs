@@ -1,5 +1,6 @@
 use anyhow::{Result, anyhow, bail};
-use chrono::Utc;
+use bigdecimal::BigDecimal;
+use chrono::{NaiveDate, Utc};
 use iban::IbanLike;
 use num_traits::ToPrimitive;
 use quick_xml::Writer;
@@ -8,8 +9,9 @@ use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText};
 use tracing::warn;
 use uuid::Uuid;
 
+use super::secrets::{SecretKey, SecretProvider};
 use crate::error::ValidationError;
-use crate::models::{Event, EventSubscription};
+use crate::models::{Event, EventSubscription, SepaExportError};
 
 pub(crate) fn validate_iban(raw: &str) -> Result<iban::Iban, ValidationError> {
     let normalized: String = raw
@@ -85,6 +87,45 @@ fn write_element_with_attr(
     Ok(())
 }
 
+/// A single direct debit transaction of a pain.008 collection.
+pub(crate) struct SepaDebit {
+    pub(crate) mandate_ref: String,
+    pub(crate) mandate_date: NaiveDate,
+    pub(crate) amount: BigDecimal,
+    pub(crate) debtor_name: String,
+    pub(crate) iban: String,
+    pub(crate) bic: String,
+    pub(crate) remittance_info: String,
+}
+
+pub(crate) struct SepaCreditor {
+    pub(crate) name: String,
+    pub(crate) iban: String,
+    pub(crate) bic: String,
+    pub(crate) id: String,
+}
+
+/// Read the creditor configuration and lookup the BIC of the creditor account.
+pub(crate) async fn load_sepa_creditor(secrets: &dyn SecretProvider) -> Result<SepaCreditor> {
+    let name = secrets.get(SecretKey::SepaCreditorName).await?;
+    let iban = secrets.get(SecretKey::SepaCreditorIban).await?;
+    let id = secrets.get(SecretKey::SepaCreditorId).await?;
+
+    if name.is_empty() || iban.is_empty() || id.is_empty() {
+        return Err(anyhow::Error::from(SepaExportError::ConfigIncomplete));
+    }
+    let bic = lookup_bic(&iban)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to lookup creditor BIC: {}", e))?;
+
+    Ok(SepaCreditor {
+        name,
+        iban,
+        bic,
+        id,
+    })
+}
+
 pub(crate) fn generate_sepa_xml(
     event: &Event,
     bookings: &[(EventSubscription, String)],
@@ -92,6 +133,35 @@ pub(crate) fn generate_sepa_xml(
     creditor_iban: &str,
     creditor_bic: &str,
     creditor_id: &str,
+) -> Result<String> {
+    let debits = bookings
+        .iter()
+        .map(|(sub, bic)| SepaDebit {
+            mandate_ref: format!("SEPA-{}", sub.payment_id),
+            mandate_date: sub.created.date_naive(),
+            amount: sub.total_price(event),
+            debtor_name: format!("{} {}", sub.first_name, sub.last_name),
+            iban: sub.iban.clone().unwrap_or_default(),
+            bic: bic.clone(),
+            remittance_info: format!("Teilnahmegebühr {}", event.name),
+        })
+        .collect::<Vec<_>>();
+
+    generate_direct_debit_xml(
+        &debits,
+        creditor_name,
+        creditor_iban,
+        creditor_bic,
+        creditor_id,
+    )
+}
+
+pub(crate) fn generate_direct_debit_xml(
+    debits: &[SepaDebit],
+    creditor_name: &str,
+    creditor_iban: &str,
+    creditor_bic: &str,
+    creditor_id: &str,
 ) -> Result<String> {
     let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
 
@@ -121,11 +191,11 @@ pub(crate) fn generate_sepa_xml(
         "CreDtTm",
         &Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
     )?;
-    write_element(&mut writer, "NbOfTxs", &bookings.len().to_string())?;
+    write_element(&mut writer, "NbOfTxs", &debits.len().to_string())?;
 
-    let ctrl_sum: f64 = bookings
+    let ctrl_sum: f64 = debits
         .iter()
-        .map(|(sub, _)| sub.total_price(event).to_f64().unwrap_or(0.0))
+        .map(|debit| debit.amount.to_f64().unwrap_or(0.0))
         .sum();
     write_element(&mut writer, "CtrlSum", &format!("{:.2}", ctrl_sum))?;
 
@@ -139,7 +209,7 @@ pub(crate) fn generate_sepa_xml(
     write_element(&mut writer, "PmtInfId", &pmt_inf_id)?;
     write_element(&mut writer, "PmtMtd", "DD")?;
     write_element(&mut writer, "BtchBookg", "true")?;
-    write_element(&mut writer, "NbOfTxs", &bookings.len().to_string())?;
+    write_element(&mut writer, "NbOfTxs", &debits.len().to_string())?;
     write_element(&mut writer, "CtrlSum", &format!("{:.2}", ctrl_sum))?;
 
     writer.write_event(XmlEvent::Start(BytesStart::new("PmtTpInf")))?;
@@ -193,59 +263,50 @@ pub(crate) fn generate_sepa_xml(
     writer.write_event(XmlEvent::End(BytesEnd::new("Id")))?;
     writer.write_event(XmlEvent::End(BytesEnd::new("CdtrSchmeId")))?;
 
-    for (sub, bic) in bookings {
-        let price = sub.total_price(event);
-        let mandate_ref = format!("SEPA-{}", sub.payment_id);
-        let sign_date = sub.created.format("%Y-%m-%d").to_string();
-
+    for debit in debits {
         writer.write_event(XmlEvent::Start(BytesStart::new("DrctDbtTxInf")))?;
 
         writer.write_event(XmlEvent::Start(BytesStart::new("PmtId")))?;
-        write_element(&mut writer, "EndToEndId", &mandate_ref)?;
+        write_element(&mut writer, "EndToEndId", &debit.mandate_ref)?;
         writer.write_event(XmlEvent::End(BytesEnd::new("PmtId")))?;
 
         write_element_with_attr(
             &mut writer,
             "InstdAmt",
             ("Ccy", "EUR"),
-            &format!("{:.2}", price),
+            &format!("{:.2}", debit.amount),
         )?;
 
         writer.write_event(XmlEvent::Start(BytesStart::new("DrctDbtTx")))?;
         writer.write_event(XmlEvent::Start(BytesStart::new("MndtRltdInf")))?;
-        write_element(&mut writer, "MndtId", &mandate_ref)?;
-        write_element(&mut writer, "DtOfSgntr", &sign_date)?;
+        write_element(&mut writer, "MndtId", &debit.mandate_ref)?;
+        write_element(
+            &mut writer,
+            "DtOfSgntr",
+            &debit.mandate_date.format("%Y-%m-%d").to_string(),
+        )?;
         write_element(&mut writer, "AmdmntInd", "false")?;
         writer.write_event(XmlEvent::End(BytesEnd::new("MndtRltdInf")))?;
         writer.write_event(XmlEvent::End(BytesEnd::new("DrctDbtTx")))?;
 
         writer.write_event(XmlEvent::Start(BytesStart::new("DbtrAgt")))?;
         writer.write_event(XmlEvent::Start(BytesStart::new("FinInstnId")))?;
-        write_element(&mut writer, "BIC", bic)?;
+        write_element(&mut writer, "BIC", &debit.bic)?;
         writer.write_event(XmlEvent::End(BytesEnd::new("FinInstnId")))?;
         writer.write_event(XmlEvent::End(BytesEnd::new("DbtrAgt")))?;
 
         writer.write_event(XmlEvent::Start(BytesStart::new("Dbtr")))?;
-        write_element(
-            &mut writer,
-            "Nm",
-            &format!("{} {}", sub.first_name, sub.last_name),
-        )?;
+        write_element(&mut writer, "Nm", &debit.debtor_name)?;
         writer.write_event(XmlEvent::End(BytesEnd::new("Dbtr")))?;
 
-        let iban = sub.iban.as_deref().unwrap_or("");
         writer.write_event(XmlEvent::Start(BytesStart::new("DbtrAcct")))?;
         writer.write_event(XmlEvent::Start(BytesStart::new("Id")))?;
-        write_element(&mut writer, "IBAN", iban)?;
+        write_element(&mut writer, "IBAN", &debit.iban)?;
         writer.write_event(XmlEvent::End(BytesEnd::new("Id")))?;
         writer.write_event(XmlEvent::End(BytesEnd::new("DbtrAcct")))?;
 
         writer.write_event(XmlEvent::Start(BytesStart::new("RmtInf")))?;
-        write_element(
-            &mut writer,
-            "Ustrd",
-            &format!("Teilnahmegebühr {}", event.name),
-        )?;
+        write_element(&mut writer, "Ustrd", &debit.remittance_info)?;
         writer.write_event(XmlEvent::End(BytesEnd::new("RmtInf")))?;
 
         writer.write_event(XmlEvent::End(BytesEnd::new("DrctDbtTxInf")))?;
@@ -338,6 +399,11 @@ mod tests {
             false,
             vec![],
             PaymentMethod::SepaDirectDebit,
+            0,
+            None,
+            None,
+            None,
+            None,
         );
 
         let subscriber = EventSubscription::new(
@@ -451,8 +517,17 @@ mod tests {
                 None,
                 None,
                 true,
+                Vec::new(),
+                false,
+                None,
+                Vec::new(),
             )],
             PaymentMethod::SepaDirectDebit,
+            0,
+            None,
+            None,
+            None,
+            None,
         );
 
         let subscriber = EventSubscription::new(
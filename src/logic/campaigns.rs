@@ -0,0 +1,402 @@
+use super::news;
+use super::secrets::SecretProvider;
+use super::template;
+use crate::db;
+use crate::email::EmailSender;
+use crate::error::ValidationError;
+use crate::models::{
+    NewsCampaign, NewsCampaignBounce, NewsCampaignInput, NewsCampaignPreview,
+    NewsCampaignRecipient, NewsCampaignRecipientStatus, NewsTopicDefinition,
+};
+use anyhow::{Result, anyhow, bail};
+use chrono::{Duration, Utc};
+use lettre::message::SinglePart;
+use lettre::message::header::{self, ContentType};
+use sqlx::PgPool;
+use tracing::warn;
+
+/// Maximum number of mails sent per run of the send task to stay below the
+/// sending limits of the mail provider.
+const BATCH_SIZE: i64 = 50;
+
+/// Maximum number of attempts to send a mail to a recipient.
+const MAX_ATTEMPTS: i32 = 3;
+
+/// Time after which a claimed recipient whose mail has failed (or has not been
+/// sent because the run was aborted) is claimed again.
+const RETRY_DELAY_MINUTES: i64 = 60;
+
+pub(crate) async fn get_campaigns(pool: &PgPool) -> Result<Vec<NewsCampaign>> {
+    db::get_campaigns(pool).await
+}
+
+pub(crate) async fn get_campaign(pool: &PgPool, id: i32) -> Result<Option<NewsCampaign>> {
+    db::get_campaign(pool, id).await
+}
+
+pub(crate) async fn create_campaign(
+    pool: &PgPool,
+    input: NewsCampaignInput,
+) -> Result<NewsCampaign> {
+    preview(pool, &input).await?;
+    db::insert_campaign(pool, &input).await
+}
+
+pub(crate) async fn update_campaign(
+    pool: &PgPool,
+    id: i32,
+    input: NewsCampaignInput,
+) -> Result<NewsCampaign> {
+    preview(pool, &input).await?;
+    match db::update_campaign(pool, id, &input).await? {
+        Some(campaign) => Ok(campaign),
+        None => bail!(ValidationError::new(
+            "Die Kampagne existiert nicht oder wird bereits versendet."
+        )),
+    }
+}
+
+pub(crate) async fn delete_campaign(pool: &PgPool, id: i32) -> Result<()> {
+    if !db::delete_campaign(pool, id).await? {
+        bail!(ValidationError::new(
+            "Die Kampagne existiert nicht oder wird bereits versendet."
+        ));
+    }
+    Ok(())
+}
+
+/// Render the campaign for a sample subscriber. Fails with a [`ValidationError`]
+/// if the topic does not exist or subject or body are no valid templates.
+pub(crate) async fn preview(
+    pool: &PgPool,
+    input: &NewsCampaignInput,
+) -> Result<NewsCampaignPreview> {
+    let topic = news::get_topic_definitions(pool, std::slice::from_ref(&input.topic))
+        .await?
+        .remove(0);
+    let render = |template: &str| {
+        template::render_news_campaign(
+            template,
+            "max.mustermann@example.com",
+            &topic.display_name,
+            "TOKEN",
+        )
+        .map_err(|e| ValidationError::new(format!("Ungültige Vorlage: {}", e)))
+    };
+    Ok(NewsCampaignPreview {
+        subject: render(&input.subject)?,
+        body: render(&input.body)?,
+    })
+}
+
+pub(crate) async fn get_recipients(
+    pool: &PgPool,
+    campaign_id: i32,
+) -> Result<Vec<NewsCampaignRecipient>> {
+    db::get_recipients(pool, campaign_id).await
+}
+
+/// Record bounces reported by the mail provider. Returns the number of matched recipients.
+pub(crate) async fn record_bounces(
+    pool: &PgPool,
+    campaign_id: i32,
+    bounces: Vec<NewsCampaignBounce>,
+) -> Result<usize> {
+    let mut count = 0;
+    for bounce in bounces {
+        if db::mark_recipient_bounced(pool, campaign_id, &bounce.email, &bounce.reason).await? {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Start all due campaigns and send the next batch of pending mails.
+/// Returns the number of mails which have been sent successfully.
+pub(crate) async fn send_due_campaigns(
+    pool: &PgPool,
+    secrets: &dyn SecretProvider,
+    email_sender: &impl EmailSender,
+) -> Result<usize> {
+    db::start_due_campaigns(pool, Utc::now()).await?;
+
+    let topics = news::get_topics(pool).await?;
+    let mut count = 0;
+    let claimed_before = Utc::now() - Duration::minutes(RETRY_DELAY_MINUTES);
+    for (recipient_id, campaign, email) in
+        db::claim_pending_recipients(pool, BATCH_SIZE, MAX_ATTEMPTS, claimed_before).await?
+    {
+        let result = match topics.iter().find(|topic| topic.name == campaign.topic) {
+            Some(topic) => {
+                send_campaign_mail(&campaign, topic, &email, secrets, email_sender).await
+            }
+            None => Err(anyhow!("Unknown topic {}", campaign.topic)),
+        };
+        match result {
+            Ok(_) => {
+                db::update_recipient_status(
+                    pool,
+                    recipient_id,
+                    NewsCampaignRecipientStatus::Sent,
+                    None,
+                )
+                .await?;
+                count += 1;
+            }
+            Err(e) => {
+                warn!(
+                    "Sending campaign {} to {} failed: {}",
+                    campaign.id, email, e
+                );
+                let status = match e.downcast_ref::<lettre::transport::smtp::Error>() {
+                    Some(smtp_error) if smtp_error.is_permanent() => {
+                        NewsCampaignRecipientStatus::Bounced
+                    }
+                    _ => NewsCampaignRecipientStatus::Failed,
+                };
+                db::update_recipient_status(pool, recipient_id, status, Some(&e.to_string()))
+                    .await?;
+            }
+        }
+    }
+
+    db::finish_sent_campaigns(pool, MAX_ATTEMPTS).await?;
+
+    Ok(count)
+}
+
+async fn send_campaign_mail(
+    campaign: &NewsCampaign,
+    topic: &NewsTopicDefinition,
+    email: &str,
+    secrets: &dyn SecretProvider,
+    email_sender: &impl EmailSender,
+) -> Result<()> {
+    let preferences_token = news::preferences_token(email, secrets).await?;
+    let subject = template::render_news_campaign(
+        &campaign.subject,
+        email,
+        &topic.display_name,
+        &preferences_token,
+    )?;
+    let body = template::render_news_campaign(
+        &campaign.body,
+        email,
+        &topic.display_name,
+        &preferences_token,
+    )?;
+
+    let email_account = email_sender
+        .get_account_by_type(topic.email_type.clone())
+        .await?;
+    let message = news::with_unsubscribe_headers(
+        crate::email::new_message_builder(&email_account)?,
+        &preferences_token,
+    )
+    .header(header::MIME_VERSION_1_0)
+    .header(ContentType::TEXT_PLAIN)
+    .to(email.parse()?)
+    .subject(subject)
+    .singlepart(SinglePart::plain(format!(
+        "{}\n\n{}",
+        body,
+        news::unsubscribe_message(&preferences_token)
+    )))?;
+
+    email_sender.send_message(&email_account, message).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::MockEmailSender;
+    use crate::logic::secrets::SecretKey;
+    use crate::models::{EmailAccount, EmailType, NewsCampaignStatus, NewsSubscription, NewsTopic};
+    use crate::test_utils::{mock_email_sender_capturing, mock_secrets};
+    use pretty_assertions::assert_eq;
+
+    fn input(topic: NewsTopic) -> NewsCampaignInput {
+        NewsCampaignInput {
+            topic,
+            subject: "Neuigkeiten ({{topic}})".into(),
+            body: "Hallo {{email}},\n\nes gibt Neuigkeiten.".into(),
+            scheduled_at: None,
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_preview(pool: PgPool) -> Result<()> {
+        let result = preview(&pool, &input(NewsTopic::FITNESS)).await?;
+        assert_eq!(
+            result,
+            NewsCampaignPreview {
+                subject: "Neuigkeiten (Fitness)".into(),
+                body: "Hallo max.mustermann@example.com,\n\nes gibt Neuigkeiten.".into(),
+            }
+        );
+
+        let mut invalid = input(NewsTopic::FITNESS);
+        invalid.body = "Hallo {{unknown}}".into();
+        let err = preview(&pool, &invalid).await.unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+
+        let err = preview(&pool, &input(NewsTopic::new("Unknown")))
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_update_only_before_sending(pool: PgPool) -> Result<()> {
+        let campaign = create_campaign(&pool, input(NewsTopic::GENERAL)).await?;
+        assert_eq!(campaign.status, NewsCampaignStatus::Draft);
+
+        let mut scheduled = input(NewsTopic::GENERAL);
+        scheduled.scheduled_at = Some(Utc::now() - Duration::minutes(1));
+        let campaign = update_campaign(&pool, campaign.id, scheduled.clone()).await?;
+        assert_eq!(campaign.status, NewsCampaignStatus::Scheduled);
+
+        send_due_campaigns(&pool, &mock_secrets(vec![]), &MockEmailSender::new()).await?;
+
+        let err = update_campaign(&pool, campaign.id, scheduled)
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+        assert!(delete_campaign(&pool, campaign.id).await.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_send_due_campaigns(pool: PgPool) -> Result<()> {
+        db::subscribe(
+            &pool,
+            NewsSubscription::new("fitness@example.com".into(), vec![NewsTopic::FITNESS]),
+            None,
+        )
+        .await?;
+        db::subscribe(
+            &pool,
+            NewsSubscription::new("events@example.com".into(), vec![NewsTopic::EVENTS]),
+            None,
+        )
+        .await?;
+
+        let mut scheduled = input(NewsTopic::FITNESS);
+        scheduled.scheduled_at = Some(Utc::now() - Duration::minutes(1));
+        let campaign = create_campaign(&pool, scheduled).await?;
+        let mut later = input(NewsTopic::EVENTS);
+        later.scheduled_at = Some(Utc::now() + Duration::days(1));
+        let later = create_campaign(&pool, later).await?;
+
+        let (email_sender, captured) =
+            mock_email_sender_capturing(vec![(EmailType::Fitness, "fitness@sv-eutingen.de")]);
+        let secrets = mock_secrets(vec![(SecretKey::NewsTokenSecret, "secret")]);
+        assert_eq!(send_due_campaigns(&pool, &secrets, &email_sender).await?, 1);
+
+        let messages = captured.lock().unwrap().clone();
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert_eq!(
+            message.envelope().to()[0].to_string(),
+            "fitness@example.com"
+        );
+        let formatted = String::from_utf8_lossy(&message.formatted()).to_string();
+        assert!(formatted.contains("Subject: Neuigkeiten (Fitness)"));
+        assert!(formatted.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+
+        let recipients = get_recipients(&pool, campaign.id).await?;
+        assert_eq!(recipients.len(), 1);
+        assert_eq!(recipients[0].status, NewsCampaignRecipientStatus::Sent);
+        assert_eq!(
+            db::get_campaign(&pool, campaign.id).await?.unwrap().status,
+            NewsCampaignStatus::Sent
+        );
+        assert_eq!(
+            db::get_campaign(&pool, later.id).await?.unwrap().status,
+            NewsCampaignStatus::Scheduled
+        );
+
+        let count = record_bounces(
+            &pool,
+            campaign.id,
+            vec![NewsCampaignBounce {
+                email: "fitness@example.com".into(),
+                reason: "mailbox unavailable".into(),
+            }],
+        )
+        .await?;
+        assert_eq!(count, 1);
+        let recipients = get_recipients(&pool, campaign.id).await?;
+        assert_eq!(recipients[0].status, NewsCampaignRecipientStatus::Bounced);
+        assert_eq!(recipients[0].error.as_deref(), Some("mailbox unavailable"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_retry_failed_recipients(pool: PgPool) -> Result<()> {
+        db::subscribe(
+            &pool,
+            NewsSubscription::new("fitness@example.com".into(), vec![NewsTopic::FITNESS]),
+            None,
+        )
+        .await?;
+        let mut scheduled = input(NewsTopic::FITNESS);
+        scheduled.scheduled_at = Some(Utc::now() - Duration::minutes(1));
+        let campaign = create_campaign(&pool, scheduled).await?;
+        let secrets = mock_secrets(vec![(SecretKey::NewsTokenSecret, "secret")]);
+
+        let mut failing_sender = MockEmailSender::new();
+        failing_sender
+            .expect_get_account_by_type()
+            .returning(|email_type| {
+                let account = EmailAccount::new_for_test(email_type, "fitness@sv-eutingen.de");
+                Box::pin(async move { Ok(account) })
+            });
+        failing_sender
+            .expect_send_message()
+            .times(1)
+            .returning(|_, _| Box::pin(async { Err(anyhow!("connection refused")) }));
+        assert_eq!(
+            send_due_campaigns(&pool, &secrets, &failing_sender).await?,
+            0
+        );
+
+        let recipients = get_recipients(&pool, campaign.id).await?;
+        assert_eq!(recipients[0].status, NewsCampaignRecipientStatus::Failed);
+        assert_eq!(recipients[0].attempts, 1);
+        assert_eq!(
+            db::get_campaign(&pool, campaign.id).await?.unwrap().status,
+            NewsCampaignStatus::Sending
+        );
+
+        // recently claimed recipients are not claimed again by an overlapping run
+        assert_eq!(
+            send_due_campaigns(&pool, &secrets, &MockEmailSender::new()).await?,
+            0
+        );
+
+        sqlx::query("UPDATE news_campaign_recipients SET claimed_at = NOW() - INTERVAL '2 hours'")
+            .execute(&pool)
+            .await?;
+        let (email_sender, captured) =
+            mock_email_sender_capturing(vec![(EmailType::Fitness, "fitness@sv-eutingen.de")]);
+        assert_eq!(send_due_campaigns(&pool, &secrets, &email_sender).await?, 1);
+        assert_eq!(captured.lock().unwrap().len(), 1);
+
+        let recipients = get_recipients(&pool, campaign.id).await?;
+        assert_eq!(recipients[0].status, NewsCampaignRecipientStatus::Sent);
+        assert_eq!(recipients[0].attempts, 2);
+        assert_eq!(
+            db::get_campaign(&pool, campaign.id).await?.unwrap().status,
+            NewsCampaignStatus::Sent
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+//! Captcha verification of the public forms.
+//
+// Provides a `CaptchaVerifier` seam with two adapters:
+// - `HCaptchaVerifier` verifies hCaptcha tokens with the hCaptcha API.
+// - `ProofOfWorkVerifier` is a self-hosted, ALTCHA compatible proof of work
+//   which needs no third-party call: the client has to find the number whose
+//   SHA-256 hash together with the salt matches the issued challenge.
+// `CAPTCHA_PROVIDER` selects the adapter, `CAPTCHA_SECRET` is the hCaptcha
+// secret or the key which signs the proof-of-work challenges.
+
+use crate::db;
+use crate::logic::secrets::{SecretKey, SecretProvider};
+use crate::logic::tokens::{self, TokenPurpose};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tracing::debug;
+use uuid::Uuid;
+
+const ALGORITHM: &str = "SHA-256";
+
+/// Upper bound of the secret number of a proof-of-work challenge. Solving a
+/// challenge takes on average half as many hashes on the client.
+const MAX_NUMBER: u64 = 1_000_000;
+
+/// Minutes a proof-of-work challenge can be solved and submitted.
+const CHALLENGE_VALIDITY_MINUTES: i64 = 10;
+
+#[async_trait]
+pub(crate) trait CaptchaVerifier: Send + Sync {
+    /// Create a new challenge for the client, if the verifier is self-hosted.
+    async fn challenge(&self) -> Result<Option<CaptchaChallenge>>;
+
+    /// Check the token of a client. Returns `false` if the token is invalid.
+    async fn verify(&self, token: &str, client_ip: Option<IpAddr>) -> Result<bool>;
+}
+
+/// Proof-of-work challenge in the format of the ALTCHA widget.
+#[derive(Debug, Serialize)]
+pub(crate) struct CaptchaChallenge {
+    algorithm: &'static str,
+    challenge: String,
+    #[serde(rename = "maxnumber")]
+    max_number: u64,
+    salt: String,
+    signature: String,
+}
+
+/// Solution of a challenge, sent by the client as base64 encoded JSON.
+#[derive(Debug, Deserialize, Serialize)]
+struct CaptchaSolution {
+    algorithm: String,
+    challenge: String,
+    number: u64,
+    salt: String,
+    signature: String,
+}
+
+/// Create the configured verifier, hCaptcha is used by default.
+pub(crate) async fn verifier(
+    pool: PgPool,
+    secrets: Arc<dyn SecretProvider>,
+) -> Result<Arc<dyn CaptchaVerifier>> {
+    let provider = secrets.get(SecretKey::CaptchaProvider).await?;
+    match provider.trim() {
+        "" | "hcaptcha" => Ok(Arc::new(HCaptchaVerifier { secrets })),
+        "altcha" => Ok(Arc::new(ProofOfWorkVerifier { pool, secrets })),
+        provider => Err(anyhow!("Unknown captcha provider: {}", provider)),
+    }
+}
+
+pub(crate) struct HCaptchaVerifier {
+    secrets: Arc<dyn SecretProvider>,
+}
+
+#[async_trait]
+impl CaptchaVerifier for HCaptchaVerifier {
+    async fn challenge(&self) -> Result<Option<CaptchaChallenge>> {
+        Ok(None)
+    }
+
+    async fn verify(&self, token: &str, client_ip: Option<IpAddr>) -> Result<bool> {
+        let secret = self.secrets.get(SecretKey::CaptchaSecret).await?;
+
+        let captcha = match hcaptcha::Captcha::new(token) {
+            Ok(captcha) => captcha,
+            Err(e) => {
+                debug!("Invalid captcha token: {:?}", e);
+                return Ok(false);
+            }
+        };
+
+        let mut request = hcaptcha::Request::new(&secret, captcha)
+            .map_err(|e| anyhow!("Failed to build captcha request: {:?}", e))?;
+        if let Some(ip) = client_ip {
+            request = request
+                .set_remoteip(&ip.to_string())
+                .map_err(|e| anyhow!("Failed to build captcha request: {:?}", e))?;
+        }
+
+        // unsuccessful verifications are returned as error codes, anything else
+        // (e.g. network errors) is an error of the verification itself
+        match hcaptcha::Client::new().verify(request).await {
+            Ok(response) => Ok(response.success()),
+            Err(hcaptcha::Error::Codes(codes)) => {
+                debug!("Captcha invalid: {:?}", codes);
+                Ok(false)
+            }
+            Err(e) => Err(anyhow!("Captcha verification failed: {:?}", e)),
+        }
+    }
+}
+
+pub(crate) struct ProofOfWorkVerifier {
+    pool: PgPool,
+    secrets: Arc<dyn SecretProvider>,
+}
+
+#[async_trait]
+impl CaptchaVerifier for ProofOfWorkVerifier {
+    async fn challenge(&self) -> Result<Option<CaptchaChallenge>> {
+        let secret = self.secrets.get(SecretKey::CaptchaSecret).await?;
+        Ok(Some(create_challenge(&secret)?))
+    }
+
+    async fn verify(&self, token: &str, _: Option<IpAddr>) -> Result<bool> {
+        let secret = self.secrets.get(SecretKey::CaptchaSecret).await?;
+        let Some(challenge) = verify_solution(token, &secret) else {
+            return Ok(false);
+        };
+        // every challenge may only be solved once
+        db::insert_captcha_solution(
+            &self.pool,
+            &challenge,
+            Utc::now() - Duration::minutes(CHALLENGE_VALIDITY_MINUTES),
+        )
+        .await
+    }
+}
+
+fn create_challenge(secret: &str) -> Result<CaptchaChallenge> {
+    let salt = Uuid::new_v4().simple().to_string();
+    let number = (Uuid::new_v4().as_u128() % (MAX_NUMBER as u128 + 1)) as u64;
+    let challenge = hash_challenge(&salt, number);
+    let signature = tokens::sign(
+        TokenPurpose::CaptchaChallenge,
+        &challenge,
+        Duration::minutes(CHALLENGE_VALIDITY_MINUTES),
+        secret,
+    )?;
+    Ok(CaptchaChallenge {
+        algorithm: ALGORITHM,
+        challenge,
+        max_number: MAX_NUMBER,
+        salt,
+        signature,
+    })
+}
+
+/// Check the solution and return its challenge, if it is valid.
+fn verify_solution(token: &str, secret: &str) -> Option<String> {
+    let solution: CaptchaSolution = STANDARD
+        .decode(token)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())?;
+    if solution.algorithm != ALGORITHM
+        || hash_challenge(&solution.salt, solution.number) != solution.challenge
+    {
+        return None;
+    }
+    // the signature proves that the challenge has been issued by us and has not expired yet
+    let signed_challenge =
+        tokens::verify(TokenPurpose::CaptchaChallenge, &solution.signature, secret).ok()?;
+    (signed_challenge == solution.challenge).then_some(solution.challenge)
+}
+
+fn hash_challenge(salt: &str, number: u64) -> String {
+    hex::encode(Sha256::digest(format!("{}{}", salt, number).as_bytes()))
+}
+
+/// Verifier for tests which accepts only the given token, without any network or database access.
+#[cfg(test)]
+pub(crate) struct StubCaptchaVerifier(pub(crate) &'static str);
+
+#[cfg(test)]
+#[async_trait]
+impl CaptchaVerifier for StubCaptchaVerifier {
+    async fn challenge(&self) -> Result<Option<CaptchaChallenge>> {
+        Ok(None)
+    }
+
+    async fn verify(&self, token: &str, _: Option<IpAddr>) -> Result<bool> {
+        Ok(token == self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::secrets::MockSecretProvider;
+    use pretty_assertions::assert_eq;
+
+    /// Solve the challenge like the ALTCHA widget does.
+    fn solve(challenge: &CaptchaChallenge) -> String {
+        let number = (0..=challenge.max_number)
+            .find(|number| hash_challenge(&challenge.salt, *number) == challenge.challenge)
+            .unwrap();
+        let solution = CaptchaSolution {
+            algorithm: challenge.algorithm.into(),
+            challenge: challenge.challenge.clone(),
+            number,
+            salt: challenge.salt.clone(),
+            signature: challenge.signature.clone(),
+        };
+        STANDARD.encode(serde_json::to_vec(&solution).unwrap())
+    }
+
+    fn proof_of_work_verifier(pool: PgPool) -> ProofOfWorkVerifier {
+        let mut secrets = MockSecretProvider::new();
+        secrets
+            .expect_get()
+            .returning(|_| Box::pin(async { Ok("captcha-secret".into()) }));
+        ProofOfWorkVerifier {
+            pool,
+            secrets: Arc::new(secrets),
+        }
+    }
+
+    #[test]
+    fn test_verify_solution() {
+        let challenge = create_challenge("secret").unwrap();
+        let token = solve(&challenge);
+        assert_eq!(
+            verify_solution(&token, "secret"),
+            Some(challenge.challenge.clone())
+        );
+        // signed with another key
+        assert_eq!(verify_solution(&token, "other"), None);
+        assert_eq!(verify_solution("not base64!", "secret"), None);
+
+        // a wrong number does not match the challenge
+        let mut solution: CaptchaSolution =
+            serde_json::from_slice(&STANDARD.decode(&token).unwrap()).unwrap();
+        solution.number = (solution.number + 1) % (MAX_NUMBER + 1);
+        let token = STANDARD.encode(serde_json::to_vec(&solution).unwrap());
+        assert_eq!(verify_solution(&token, "secret"), None);
+    }
+
+    #[sqlx::test]
+    async fn test_verifier_propagates_secret_errors(pool: PgPool) -> Result<()> {
+        let mut secrets = MockSecretProvider::new();
+        secrets
+            .expect_get()
+            .returning(|_| Box::pin(async { Err(anyhow!("Secrets are unavailable")) }));
+
+        assert!(verifier(pool, Arc::new(secrets)).await.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_proof_of_work_solution_is_accepted_once(pool: PgPool) -> Result<()> {
+        let verifier = proof_of_work_verifier(pool);
+        let challenge = verifier.challenge().await?.unwrap();
+        let token = solve(&challenge);
+
+        assert!(verifier.verify(&token, None).await?);
+        assert!(!verifier.verify(&token, None).await?);
+
+        Ok(())
+    }
+}
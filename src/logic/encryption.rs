@@ -0,0 +1,201 @@
+//! Field-level encryption of the IBANs of event bookings and members.
+//
+// IBANs are encrypted with AES-256-GCM and the envelope key `IBAN_ENCRYPTION_KEY`
+// (base64 encoded, 32 bytes) before they are stored. Besides the ciphertext the
+// stored value keeps the parts of the masked IBAN (`DE12 **** 1234`), so every
+// response can show the masked IBAN without decrypting it:
+//
+//     enc:v1:DE12:1234:<base64 of nonce and ciphertext>
+//
+// Plain IBANs of rows stored before the encryption has been introduced are
+// encrypted at startup, so a plain IBAN is never accepted for decryption.
+
+use crate::db;
+use crate::logic::secrets::{SecretKey, SecretProvider};
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::Serializer;
+use sqlx::PgPool;
+
+const PREFIX: &str = "enc:v1:";
+
+const NONCE_LENGTH: usize = 12;
+
+pub(crate) struct IbanCipher {
+    cipher: Aes256Gcm,
+}
+
+impl IbanCipher {
+    pub(crate) async fn load(secrets: &dyn SecretProvider) -> Result<Self> {
+        let key = secrets.get(SecretKey::IbanEncryptionKey).await?;
+        Self::new(&key)
+    }
+
+    /// Create a cipher of the base64 encoded key.
+    pub(crate) fn new(key: &str) -> Result<Self> {
+        let key: [u8; 32] = STANDARD
+            .decode(key.trim())
+            .context("IBAN_ENCRYPTION_KEY is not base64 encoded")?
+            .try_into()
+            .map_err(|_| anyhow!("IBAN_ENCRYPTION_KEY has to be 32 bytes long"))?;
+        Ok(Self {
+            cipher: Aes256Gcm::new(&key.into()),
+        })
+    }
+
+    pub(crate) fn encrypt(&self, iban: &str) -> Result<String> {
+        if is_encrypted(iban) {
+            return Ok(iban.to_string());
+        }
+        let data = self
+            .encrypt_bytes(iban.as_bytes())
+            .map_err(|_| anyhow!("Failed to encrypt IBAN"))?;
+        let (start, end) = mask_parts(iban);
+        Ok(format!("{PREFIX}{start}:{end}:{}", STANDARD.encode(data)))
+    }
+
+    pub(crate) fn decrypt(&self, value: &str) -> Result<String> {
+        let Some(encrypted) = value.strip_prefix(PREFIX) else {
+            bail!("IBAN is not encrypted");
+        };
+        let data = encrypted
+            .rsplit(':')
+            .next()
+            .and_then(|data| STANDARD.decode(data).ok())
+            .ok_or_else(|| anyhow!("Invalid encrypted IBAN"))?;
+        let iban = self
+            .decrypt_bytes(&data)
+            .map_err(|_| anyhow!("Failed to decrypt IBAN"))?;
+        Ok(String::from_utf8(iban)?)
+    }
+
+    /// Encrypt other personal data with the same key, e.g. stored responses.
+    /// Returns the nonce followed by the ciphertext.
+    pub(crate) fn encrypt_bytes(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow!("Failed to encrypt data"))?;
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+        Ok(data)
+    }
+
+    pub(crate) fn decrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() <= NONCE_LENGTH {
+            bail!("Invalid encrypted data");
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+        let nonce: [u8; NONCE_LENGTH] = nonce.try_into()?;
+        self.cipher
+            .decrypt(&nonce.into(), ciphertext)
+            .map_err(|_| anyhow!("Failed to decrypt data"))
+    }
+}
+
+fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+fn mask_parts(iban: &str) -> (String, String) {
+    let iban: Vec<char> = iban.chars().filter(|c| !c.is_whitespace()).collect();
+    let start = iban.iter().take(4).collect();
+    let end = iban.iter().skip(iban.len().max(8) - 4).collect();
+    (start, end)
+}
+
+/// Mask a plain or encrypted IBAN, e.g. `DE12 **** 1234`.
+pub(crate) fn mask(value: &str) -> String {
+    let (start, end) = match value.strip_prefix(PREFIX) {
+        Some(encrypted) => {
+            let mut parts = encrypted.split(':');
+            (
+                parts.next().unwrap_or_default().to_string(),
+                parts.next().unwrap_or_default().to_string(),
+            )
+        }
+        None => mask_parts(value),
+    };
+    format!("{start} **** {end}")
+}
+
+pub(crate) fn serialize_masked<S: Serializer>(
+    iban: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match iban {
+        Some(iban) => serializer.serialize_some(&mask(iban)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Encrypt the IBANs which have been stored before the encryption has been
+/// introduced. Runs at startup, so only encrypted IBANs have to be decrypted.
+/// Returns the number of encrypted IBANs.
+pub(crate) async fn encrypt_plain_ibans(
+    pool: &PgPool,
+    secrets: &dyn SecretProvider,
+) -> Result<usize> {
+    let plain_ibans = db::get_plain_ibans(pool, PREFIX).await?;
+    let plain_member_ibans = db::get_plain_member_ibans(pool, PREFIX).await?;
+    if plain_ibans.is_empty() && plain_member_ibans.is_empty() {
+        return Ok(0);
+    }
+    let cipher = IbanCipher::load(secrets).await?;
+    let encrypt = |ibans: Vec<(i32, String)>| {
+        ibans
+            .into_iter()
+            .map(|(id, iban)| Ok((id, cipher.encrypt(&iban)?)))
+            .collect::<Result<Vec<_>>>()
+    };
+    let encrypted = encrypt(plain_ibans)?;
+    db::update_ibans(pool, &encrypted).await?;
+    let encrypted_member_ibans = encrypt(plain_member_ibans)?;
+    db::update_member_ibans(pool, &encrypted_member_ibans).await?;
+    Ok(encrypted.len() + encrypted_member_ibans.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TEST_IBAN_KEY;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_encrypt_and_decrypt() {
+        let cipher = IbanCipher::new(TEST_IBAN_KEY).unwrap();
+        let encrypted = cipher.encrypt("DE89370400440532013000").unwrap();
+        assert!(encrypted.starts_with("enc:v1:DE89:3000:"));
+        assert!(!encrypted.contains("370400440532"));
+        assert_eq!(
+            cipher.decrypt(&encrypted).unwrap(),
+            "DE89370400440532013000"
+        );
+        // every encryption uses another nonce
+        assert_ne!(cipher.encrypt("DE89370400440532013000").unwrap(), encrypted);
+        // encrypted values are not encrypted twice
+        assert_eq!(cipher.encrypt(&encrypted).unwrap(), encrypted);
+        // plain values are refused
+        assert!(cipher.decrypt("DE89370400440532013000").is_err());
+
+        let other = IbanCipher::new(&STANDARD.encode([7u8; 32])).unwrap();
+        assert!(other.decrypt(&encrypted).is_err());
+        assert!(IbanCipher::new(&STANDARD.encode([7u8; 16])).is_err());
+    }
+
+    #[test]
+    fn test_mask() {
+        let cipher = IbanCipher::new(TEST_IBAN_KEY).unwrap();
+        assert_eq!(mask("DE89370400440532013000"), "DE89 **** 3000");
+        assert_eq!(mask("DE89 3704 0044 0532 0130 00"), "DE89 **** 3000");
+        assert_eq!(
+            mask(&cipher.encrypt("DE89370400440532013000").unwrap()),
+            "DE89 **** 3000"
+        );
+        assert_eq!(mask("DE12"), "DE12 **** ");
+    }
+}
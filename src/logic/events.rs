@@ -4,10 +4,11 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 use anyhow::{Context, Result, anyhow, bail};
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD;
-use chrono::{DateTime, Duration, Locale, NaiveDate, Utc};
+use chrono::{DateTime, Days, Duration, Locale, NaiveDate, Utc};
 use encoding::Encoding;
 use encoding::{DecoderTrap, all::ISO_8859_1};
 use lazy_static::lazy_static;
+use lettre::Message;
 use lettre::message::header::ContentType;
 use lettre::message::{Attachment, MultiPart, SinglePart};
 use regex::Regex;
@@ -15,26 +16,35 @@ use sqlx::PgPool;
 use tracing::{error, info, warn};
 
 use super::csv::PaymentRecord;
-use super::{banking, export, template};
-use crate::db::BookingResult;
+use super::encryption::IbanCipher;
+use super::{audit, banking, export, template};
+use crate::db::{BookingResult, OfferAcceptance, WaitingListPromotion};
 use crate::email;
 use crate::error::ValidationError;
 use crate::logic::secrets::{SecretKey, SecretProvider};
+use crate::logic::tokens::{self, TokenPurpose};
 use crate::models::{
-    BookingResponse, Email, Event, EventBooking, EventCounter, EventCustomField, EventEmail,
-    EventId, EventType, LifecycleStatus, MessageType, NewsSubscription, PartialEvent,
-    PaymentMethod, ToEuro, UnpaidEventBooking, VerifyPaymentBookingRecord, VerifyPaymentResult,
+    BookingResponse, DuplicateBookingGroup, Email, EmailAccount, Event, EventBooking,
+    EventCancellation, EventCloneOptions, EventCounter, EventCustomField, EventEmail, EventId,
+    EventRefund, EventTemplate, EventType, EventVersion, EventVersionDiff, LifecycleStatus,
+    MessageType, NewsSubscription, PartialEvent, PaymentMethod, ToEuro, UnpaidEventBooking,
+    VerifyPaymentBookingRecord, VerifyPaymentResult, WaitingListOffer,
 };
 use crate::{db, hashids};
 
 const MESSAGE_FAIL: &str =
     "Leider ist etwas schief gelaufen. Bitte versuche es später noch einmal.";
 
+/// Minutes the confirmation of a duplicate booking can be requested again.
+const RESEND_CONFIRMATION_VALIDITY_MINUTES: i64 = 60;
+
+/// Get the events, canceled events only if `canceled` is `true`.
 pub(crate) async fn get_events(
     pool: &PgPool,
     beta: Option<bool>,
     lifecycle_status: Option<Vec<LifecycleStatus>>,
     subscribers: Option<bool>,
+    canceled: bool,
 ) -> Result<Vec<Event>> {
     let lifecycle_status_list;
     if let Some(beta) = beta {
@@ -47,6 +57,7 @@ pub(crate) async fn get_events(
         true,
         lifecycle_status_list,
         subscribers.unwrap_or(false),
+        canceled,
     )
     .await
 }
@@ -65,9 +76,10 @@ pub(crate) async fn get_event_counters(pool: &PgPool, beta: bool) -> Result<Vec<
 pub(crate) async fn booking(
     pool: &PgPool,
     booking: EventBooking,
+    secrets: &dyn SecretProvider,
     email_sender: &impl email::EmailSender,
 ) -> BookingResponse {
-    match book_event(pool, booking, email_sender).await {
+    match book_event(pool, booking, secrets, email_sender).await {
         Ok(response) => response,
         Err(e) => {
             if let Some(validation_err) = e.downcast_ref::<ValidationError>() {
@@ -84,18 +96,27 @@ pub(crate) async fn prebook_with_iban(
     pool: &PgPool,
     hash: &str,
     iban: String,
+    secrets: &dyn SecretProvider,
     email_sender: &impl email::EmailSender,
 ) -> Result<BookingResponse> {
     let normalized = banking::validate_iban_str(&iban)?;
-    pre_book_event(pool, hash.to_string(), Some(normalized), email_sender).await
+    pre_book_event(
+        pool,
+        hash.to_string(),
+        Some(normalized),
+        secrets,
+        email_sender,
+    )
+    .await
 }
 
 pub(crate) async fn prebooking(
     pool: &PgPool,
     hash: String,
+    secrets: &dyn SecretProvider,
     email_sender: &impl email::EmailSender,
 ) -> BookingResponse {
-    match pre_book_event(pool, hash, None, email_sender).await {
+    match pre_book_event(pool, hash, None, secrets, email_sender).await {
         Ok(response) => response,
         Err(e) => {
             error!("Prebooking failed: {:?}", e);
@@ -104,13 +125,62 @@ pub(crate) async fn prebooking(
     }
 }
 
+/// Send the confirmation of an existing booking again, e.g. after a duplicate booking has been detected.
+pub(crate) async fn resend_confirmation(
+    pool: &PgPool,
+    token: &str,
+    secrets: &dyn SecretProvider,
+    email_sender: &impl email::EmailSender,
+) -> BookingResponse {
+    match send_confirmation_again(pool, token, secrets, email_sender).await {
+        Ok(response) => response,
+        Err(e) if e.downcast_ref::<ValidationError>().is_some() => {
+            BookingResponse::failure(&e.to_string())
+        }
+        Err(e) => {
+            error!("Resending the booking confirmation failed: {:?}", e);
+            BookingResponse::failure(MESSAGE_FAIL)
+        }
+    }
+}
+
+pub(crate) async fn get_duplicate_bookings(
+    pool: &PgPool,
+    event_id: &EventId,
+) -> Result<Vec<DuplicateBookingGroup>> {
+    let mut groups: Vec<DuplicateBookingGroup> = Vec::new();
+    for booking in db::get_duplicate_bookings(pool, event_id).await? {
+        let same_person = |group: &DuplicateBookingGroup| {
+            [&group.email, &group.first_name, &group.last_name]
+                .into_iter()
+                .zip([&booking.email, &booking.first_name, &booking.last_name])
+                .all(|(a, b)| a.trim().to_lowercase() == b.trim().to_lowercase())
+        };
+        match groups.last_mut() {
+            Some(group) if same_person(group) => group.bookings.push(booking),
+            _ => groups.push(DuplicateBookingGroup {
+                email: booking.email.clone(),
+                first_name: booking.first_name.clone(),
+                last_name: booking.last_name.clone(),
+                bookings: vec![booking],
+            }),
+        }
+    }
+    Ok(groups)
+}
+
+/// Create or update the event. Participants are informed about changed dates, location,
+/// duration or price unless `notify_participants` is false.
 pub(crate) async fn update(
     pool: &PgPool,
     partial_event: PartialEvent,
+    notify_participants: bool,
     email_sender: &impl email::EmailSender,
 ) -> Result<Event> {
-    let (event, removed_dates) = db::write_event(pool, partial_event).await?;
-    if let Some(removed_dates) = removed_dates
+    let (event, previous) = db::write_event(pool, partial_event).await?;
+    if let Some(previous) = previous
+        && notify_participants
+        && has_material_changes(&previous, &event)
         && matches!(
             event.lifecycle_status,
             LifecycleStatus::Review | LifecycleStatus::Published | LifecycleStatus::Running
@@ -121,10 +191,10 @@ pub(crate) async fn update(
             return Ok(event);
         }
 
-        let subject = format!("{} Terminänderung {}", event.subject_prefix(), event.name);
+        let subject = format!("{} Änderung {}", event.subject_prefix(), event.name);
         let template = match event.event_type {
-            EventType::Fitness => include_str!("../../templates/schedule_change_fitness.txt"),
-            EventType::Events => include_str!("../../templates/schedule_change_events.txt"),
+            EventType::Fitness => include_str!("../../templates/event_change_fitness.txt"),
+            EventType::Events => include_str!("../../templates/event_change_events.txt"),
         };
 
         let email_account = event.get_associated_email_account(email_sender).await?;
@@ -132,8 +202,10 @@ pub(crate) async fn update(
         let mut messages = Vec::new();
 
         for (booking, _, _) in bookings {
-            let body =
-                template::render_schedule_change(template, &booking, &event, &removed_dates)?;
+            let Some(body) = template::render_event_change(template, &booking, &event, &previous)?
+            else {
+                continue;
+            };
 
             messages.push(
                 Email::new(message_type, booking.email, subject.clone(), body, None)
@@ -141,19 +213,270 @@ pub(crate) async fn update(
             );
         }
 
-        email_sender.send_messages(&email_account, messages).await?;
+        if !messages.is_empty() {
+            email_sender.send_messages(&email_account, messages).await?;
+        }
     }
     Ok(event)
 }
 
+/// Changes of the event participants have to be informed about.
+fn has_material_changes(previous: &Event, event: &Event) -> bool {
+    previous.dates != event.dates
+        || previous.location != event.location
+        || previous.duration_in_minutes != event.duration_in_minutes
+        || previous.price_member != event.price_member
+        || previous.price_non_member != event.price_non_member
+        || previous.custom_fields != event.custom_fields
+}
+
+pub(crate) async fn get_event_versions(
+    pool: &PgPool,
+    event_id: &EventId,
+) -> Result<Vec<EventVersion>> {
+    db::get_event_versions(pool, event_id).await
+}
+
+/// Compare two versions of the event. Returns `None` if one of the versions does not exist.
+pub(crate) async fn diff_event_versions(
+    pool: &PgPool,
+    event_id: &EventId,
+    from: i32,
+    to: i32,
+) -> Result<Option<EventVersionDiff>> {
+    let (Some(from), Some(to)) = (
+        db::get_event_version(pool, event_id, from).await?,
+        db::get_event_version(pool, event_id, to).await?,
+    ) else {
+        return Ok(None);
+    };
+
+    let (before, after) = audit::diff(Some(from.snapshot), Some(to.snapshot));
+    Ok(Some(EventVersionDiff {
+        from: from.version,
+        to: to.version,
+        before: before.unwrap_or_default(),
+        after: after.unwrap_or_default(),
+    }))
+}
+
+/// Restore the content of a previous version of the event as a new version.
+/// The lifecycle status and the schedule are kept, participants are informed
+/// about the changes.
+/// Returns `None` if the version does not exist.
+pub(crate) async fn restore_event_version(
+    pool: &PgPool,
+    event_id: &EventId,
+    version: i32,
+    email_sender: &impl email::EmailSender,
+) -> Result<Option<Event>> {
+    let Some(version) = db::get_event_version(pool, event_id, version).await? else {
+        return Ok(None);
+    };
+    let event: Event = serde_json::from_value(version.snapshot).with_context(|| {
+        format!(
+            "Error reading version {} of event {}",
+            version.version, event_id
+        )
+    })?;
+
+    let Some(current) = db::get_event(pool, event_id, false).await? else {
+        return Ok(None);
+    };
+
+    let mut partial_event = PartialEvent::from(event);
+    partial_event.id = Some(*event_id);
+    partial_event.closed = None;
+    partial_event.lifecycle_status = None;
+    partial_event.publish_at = None;
+    partial_event.unpublish_at = None;
+    partial_event.booking_start = None;
+    partial_event.booking_end = None;
+    // buckets which have been deleted since the version are created again
+    for bucket in partial_event.capacity_buckets.iter_mut().flatten() {
+        if !current
+            .capacity_buckets
+            .iter()
+            .any(|current| current.id == bucket.id)
+        {
+            bucket.id = None;
+        }
+    }
+
+    let event = update(pool, partial_event, true, email_sender).await?;
+    info!(
+        "Version {} of event {} has been restored",
+        version.version, event_id
+    );
+
+    Ok(Some(event))
+}
+
+pub(crate) async fn get_event_type(pool: &PgPool, event_id: &EventId) -> Result<Option<EventType>> {
+    db::get_event_type(pool, event_id).await
+}
+
+pub(crate) async fn get_event_type_of_booking(
+    pool: &PgPool,
+    booking_id: i32,
+) -> Result<Option<EventType>> {
+    db::get_event_type_of_booking(pool, booking_id).await
+}
+
 pub(crate) async fn delete(pool: &PgPool, event_id: EventId) -> Result<()> {
     db::delete_event(pool, event_id).await
 }
 
+/// Copy the event with its custom fields and capacity buckets into a new draft.
+/// The dates can be shifted by a number of days or replaced by a weekly series,
+/// the publishing schedule and booking window are shifted along.
+/// Maximum number of dates of a weekly series created by cloning an event.
+const MAX_WEEKLY_DATES: u16 = 52;
+
+pub(crate) async fn clone_event(
+    pool: &PgPool,
+    event_id: &EventId,
+    options: EventCloneOptions,
+) -> Result<Event> {
+    let event = db::get_event(pool, event_id, false)
+        .await?
+        .ok_or_else(|| anyhow!("Found no event with id '{}'", event_id))?;
+
+    let dates = match options.weekly_dates {
+        Some(count) => {
+            let first = event.dates.first().ok_or_else(|| {
+                ValidationError::new(
+                    "Die Veranstaltung hat keinen Termin für eine wöchentliche Serie.",
+                )
+            })?;
+            if count == 0 {
+                bail!(ValidationError::new(
+                    "Eine wöchentliche Serie braucht mindestens einen Termin."
+                ));
+            }
+            if count > MAX_WEEKLY_DATES {
+                bail!(ValidationError::new(format!(
+                    "Eine wöchentliche Serie kann höchstens {} Termine haben.",
+                    MAX_WEEKLY_DATES
+                )));
+            }
+            (0..i64::from(count))
+                .map(|week| shift_date(first, options.shift_days.saturating_add(7 * week)))
+                .collect::<Result<Vec<_>>>()?
+        }
+        None => event
+            .dates
+            .iter()
+            .map(|date| shift_date(date, options.shift_days))
+            .collect::<Result<Vec<_>>>()?,
+    };
+
+    let mut partial_event = PartialEvent::from(event);
+    partial_event.id = None;
+    partial_event.closed = None;
+    partial_event.lifecycle_status = Some(LifecycleStatus::Draft);
+    partial_event.dates = Some(dates);
+    for bucket in partial_event.capacity_buckets.iter_mut().flatten() {
+        bucket.id = None;
+    }
+    for time in [
+        &mut partial_event.publish_at,
+        &mut partial_event.unpublish_at,
+        &mut partial_event.booking_start,
+        &mut partial_event.booking_end,
+    ]
+    .into_iter()
+    .flatten()
+    .flatten()
+    {
+        *time = shift_date(time, options.shift_days)?;
+    }
+
+    let (new_event, _) = db::write_event(pool, partial_event).await?;
+    info!(
+        "Event {} has been cloned into event {}",
+        event_id, new_event.id
+    );
+
+    Ok(new_event)
+}
+
+/// Shift the date by the given number of days, keeping the local time in Germany
+/// across daylight saving time changes.
+fn shift_date(date: &DateTime<Utc>, days: i64) -> Result<DateTime<Utc>> {
+    let local = date.with_timezone(&chrono_tz::Europe::Berlin);
+    let shift = Days::new(days.unsigned_abs());
+    let shifted = if days < 0 {
+        local.checked_sub_days(shift)
+    } else {
+        local.checked_add_days(shift)
+    };
+    shifted.map(|date| date.with_timezone(&Utc)).ok_or_else(|| {
+        ValidationError::new(format!(
+            "Der Termin {} kann nicht um {} Tage verschoben werden.",
+            date.with_timezone(&chrono_tz::Europe::Berlin)
+                .format("%d.%m.%Y %H:%M"),
+            days
+        ))
+        .into()
+    })
+}
+
+pub(crate) async fn get_event_templates(pool: &PgPool) -> Result<Vec<EventTemplate>> {
+    db::get_event_templates(pool).await
+}
+
+pub(crate) async fn get_event_template(pool: &PgPool, id: i32) -> Result<Option<EventTemplate>> {
+    db::get_event_template(pool, id).await
+}
+
+/// Save the template. Id, status, dates and schedule of the event are not part of a template.
+pub(crate) async fn save_event_template(
+    pool: &PgPool,
+    mut template: EventTemplate,
+) -> Result<EventTemplate> {
+    template.name = template.name.trim().to_string();
+    if template.name.is_empty() {
+        bail!(ValidationError::new(
+            "Bitte gib einen Namen für die Vorlage an."
+        ));
+    }
+    if db::get_event_templates(pool)
+        .await?
+        .iter()
+        .any(|other| other.name == template.name && other.id != template.id)
+    {
+        bail!(ValidationError::new(
+            "Es gibt bereits eine Vorlage mit diesem Namen."
+        ));
+    }
+
+    template.event.id = None;
+    template.event.closed = None;
+    template.event.lifecycle_status = None;
+    template.event.dates = None;
+    template.event.publish_at = None;
+    template.event.unpublish_at = None;
+    template.event.booking_start = None;
+    template.event.booking_end = None;
+    for bucket in template.event.capacity_buckets.iter_mut().flatten() {
+        bucket.id = None;
+    }
+
+    template.id = Some(db::save_event_template(pool, &template).await?);
+
+    Ok(template)
+}
+
+pub(crate) async fn delete_event_template(pool: &PgPool, id: i32) -> Result<()> {
+    db::delete_event_template(pool, id).await
+}
+
 pub(crate) async fn verify_payments(
     pool: &PgPool,
     csv: String,
     csv_start_date: Option<NaiveDate>,
+    secrets: &dyn SecretProvider,
 ) -> Result<Vec<VerifyPaymentResult>> {
     let bytes = STANDARD
         .decode(&csv)
@@ -173,6 +496,12 @@ pub(crate) async fn verify_payments(
     let (verified_payments, result) =
         compare_payment_records_with_bookings(&payment_records, &mut bookings)?;
     if !verified_payments.is_empty() {
+        // the IBANs of the payers are stored encrypted
+        let cipher = IbanCipher::load(secrets).await?;
+        let verified_payments = verified_payments
+            .into_iter()
+            .map(|(booking_id, iban)| Ok((booking_id, cipher.encrypt(&iban)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
         db::mark_as_paid(pool, &verified_payments).await?;
     }
 
@@ -205,6 +534,14 @@ pub(crate) async fn get_unpaid_bookings(
     Ok(bookings)
 }
 
+/// Get the paid bookings of the canceled events which have to be refunded.
+pub(crate) async fn get_event_refunds(
+    pool: &PgPool,
+    event_type: EventType,
+) -> Result<Vec<EventRefund>> {
+    db::get_event_refunds(pool, event_type).await
+}
+
 /// calculate the days until the booking should be paid
 fn calc_due_in_days(
     booking_date: DateTime<Utc>,
@@ -263,22 +600,14 @@ pub(crate) async fn export_sepa_xml(
         return Err(anyhow::Error::from(SepaExportError::NotASepaEvent));
     }
 
-    let creditor_name = secrets.get(SecretKey::SepaCreditorName).await?;
-    let creditor_iban = secrets.get(SecretKey::SepaCreditorIban).await?;
-    let creditor_id = secrets.get(SecretKey::SepaCreditorId).await?;
-
-    if creditor_name.is_empty() || creditor_iban.is_empty() || creditor_id.is_empty() {
-        return Err(anyhow::Error::from(SepaExportError::ConfigIncomplete));
-    }
-    let creditor_bic = banking::lookup_bic(&creditor_iban)
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to lookup creditor BIC: {}", e))?;
+    let creditor = banking::load_sepa_creditor(secrets).await?;
+    let cipher = IbanCipher::load(secrets).await?;
 
     let mut tx = pool.begin().await?;
 
     db::lock_sepa_eligible_bookings(&mut tx, event_id).await?;
 
-    let bookings = db::get_sepa_eligible_bookings(&mut tx, event_id).await?;
+    let bookings = db::get_sepa_eligible_bookings(&mut tx, event_id, &cipher).await?;
 
     if bookings.is_empty() {
         return Err(anyhow::Error::from(SepaExportError::NoBookingsAvailable));
@@ -305,10 +634,10 @@ pub(crate) async fn export_sepa_xml(
     let xml = banking::generate_sepa_xml(
         &event,
         &booking_data,
-        &creditor_name,
-        &creditor_iban,
-        &creditor_bic,
-        &creditor_id,
+        &creditor.name,
+        &creditor.iban,
+        &creditor.bic,
+        &creditor.id,
     )?;
 
     let booking_ids: Vec<i32> = bookings.iter().map(|b| b.id).collect();
@@ -328,10 +657,10 @@ pub(crate) async fn export_sepa_xml(
 pub(crate) async fn cancel_booking(
     pool: &PgPool,
     booking_id: i32,
+    secrets: &dyn SecretProvider,
     email_sender: &impl email::EmailSender,
 ) -> Result<()> {
-    let (event, canceled_booking, waiting_list_booking) =
-        db::cancel_event_booking(pool, booking_id).await?;
+    let (event, canceled_booking, promotion) = db::cancel_event_booking(pool, booking_id).await?;
 
     let email_account = event.get_associated_email_account(email_sender).await?;
     let mut messages = Vec::new();
@@ -351,25 +680,15 @@ pub(crate) async fn cancel_booking(
             .singlepart(SinglePart::plain(body))?,
     );
 
-    // create booking confirmation email for the new booking
-    if let Some((new_booking, payment_id)) = waiting_list_booking {
-        let subject = format!("{} Bestätigung Buchung", event.subject_prefix());
-        let body = template::render_booking(
-            &event.booking_template,
-            &new_booking,
+    // create booking confirmation or offer email for the promoted booking
+    if let Some(promotion) = promotion {
+        let secret = secrets.get(SecretKey::SessionSecret).await?;
+        messages.push(create_promotion_message(
+            &email_account,
             &event,
-            Some(payment_id),
-            None,
-            Some(false),
-        )?;
-
-        messages.push(
-            crate::email::new_message_builder(&email_account)?
-                .to(new_booking.email.parse()?)
-                .bcc(crate::email::mailbox(&email_account)?)
-                .subject(subject)
-                .singlepart(SinglePart::plain(body))?,
-        );
+            promotion,
+            &secret,
+        )?);
     }
 
     email_sender.send_messages(&email_account, messages).await?;
@@ -377,6 +696,233 @@ pub(crate) async fn cancel_booking(
     Ok(())
 }
 
+/// Cancel the event with all of its bookings and inform the participants and the
+/// waiting list about the reason. Returns `None` if the event does not exist.
+pub(crate) async fn cancel_event(
+    pool: &PgPool,
+    event_id: &EventId,
+    reason: &str,
+    email_sender: &impl email::EmailSender,
+) -> Result<Option<EventCancellation>> {
+    if reason.trim().is_empty() {
+        bail!(ValidationError::new(
+            "Bitte gib einen Grund für die Absage an."
+        ));
+    }
+
+    let Some((event, bookings)) = db::cancel_event(pool, event_id).await? else {
+        return Ok(None);
+    };
+
+    // the cancellation has been committed already, a failed mail must not stop the others
+    let mut notified = 0;
+    if !bookings.is_empty() {
+        let subject = format!("{} Absage {}", event.subject_prefix(), event.name);
+        let template = match event.event_type {
+            EventType::Fitness => include_str!("../../templates/event_canceled_fitness.txt"),
+            EventType::Events => include_str!("../../templates/event_canceled_events.txt"),
+        };
+        let message_type: MessageType = event.event_type.into();
+
+        match event.get_associated_email_account(email_sender).await {
+            Ok(email_account) => {
+                for booking in bookings.iter() {
+                    let result = async {
+                        let body =
+                            template::render_event_cancellation(template, booking, &event, reason)?;
+                        let message = Email::new(
+                            message_type,
+                            booking.email.clone(),
+                            subject.clone(),
+                            body,
+                            None,
+                        )
+                        .into_message(&email_account)?;
+                        email_sender.send_message(&email_account, message).await
+                    }
+                    .await;
+                    match result {
+                        Ok(()) => notified += 1,
+                        Err(e) => error!(
+                            "Sending the cancellation of event {} to booking {} failed: {:?}",
+                            event_id, booking.booking_id, e
+                        ),
+                    }
+                }
+            }
+            Err(e) => error!(
+                "Sending the cancellation of event {} failed: {:?}",
+                event_id, e
+            ),
+        }
+    }
+    info!(
+        "Event {} has been canceled, {} bookings have been canceled and {} notified",
+        event_id,
+        bookings.len(),
+        notified
+    );
+
+    Ok(Some(EventCancellation {
+        event,
+        notified,
+        refunds: bookings
+            .into_iter()
+            .filter(|booking| booking.paid)
+            .collect(),
+    }))
+}
+
+/// Create the email to the promoted booking of the waiting list:
+/// the booking confirmation or the offer of the freed spot.
+fn create_promotion_message(
+    email_account: &EmailAccount,
+    event: &Event,
+    promotion: WaitingListPromotion,
+    secret: &str,
+) -> Result<Message> {
+    let (booking, subject, body) = match promotion {
+        WaitingListPromotion::Enrolled(booking, payment_id) => {
+            let subject = format!("{} Bestätigung Buchung", event.subject_prefix());
+            let body = template::render_booking(
+                &event.booking_template,
+                &booking,
+                event,
+                Some(payment_id),
+                None,
+                Some(false),
+            )?;
+            (booking, subject, body)
+        }
+        WaitingListPromotion::Offered(booking, offer) => {
+            let subject = format!("{} Freier Platz", event.subject_prefix());
+            let template = match event.event_type {
+                EventType::Fitness => {
+                    include_str!("../../templates/waiting_list_offer_fitness.txt")
+                }
+                EventType::Events => include_str!("../../templates/waiting_list_offer_events.txt"),
+            };
+            let body = template::render_waiting_list_offer(
+                template,
+                &booking,
+                event,
+                create_waiting_list_offer_link(event.event_type, &offer, secret)?,
+                &offer.expires_at,
+            )?;
+            (booking, subject, body)
+        }
+    };
+
+    Ok(crate::email::new_message_builder(email_account)?
+        .to(booking.email.parse()?)
+        .bcc(crate::email::mailbox(email_account)?)
+        .subject(subject)
+        .singlepart(SinglePart::plain(body))?)
+}
+
+/// Accept the offer of a freed spot of the waiting list and send the booking confirmation.
+pub(crate) async fn accept_waiting_list_offer(
+    pool: &PgPool,
+    token: &str,
+    secrets: &dyn SecretProvider,
+    email_sender: &impl email::EmailSender,
+) -> BookingResponse {
+    match accept_offer(pool, token, secrets, email_sender).await {
+        Ok(response) => response,
+        Err(e) if e.downcast_ref::<ValidationError>().is_some() => {
+            BookingResponse::failure(&e.to_string())
+        }
+        Err(e) => {
+            error!("Accepting the waiting list offer failed: {:?}", e);
+            BookingResponse::failure(MESSAGE_FAIL)
+        }
+    }
+}
+
+async fn accept_offer(
+    pool: &PgPool,
+    token: &str,
+    secrets: &dyn SecretProvider,
+    email_sender: &impl email::EmailSender,
+) -> Result<BookingResponse> {
+    let secret = secrets.get(SecretKey::SessionSecret).await?;
+    let offer_id: i32 = tokens::verify(TokenPurpose::WaitingListOffer, token, &secret)?
+        .parse()
+        .map_err(|_| ValidationError::new("Der Link ist ungültig oder abgelaufen."))?;
+
+    let booking_id = match db::accept_waiting_list_offer(pool, offer_id).await? {
+        OfferAcceptance::Accepted(booking_id) => booking_id,
+        OfferAcceptance::AlreadyAccepted => {
+            return Ok(BookingResponse::failure(
+                "Du hast den Platz bereits angenommen.",
+            ));
+        }
+        OfferAcceptance::Expired => {
+            return Ok(BookingResponse::failure(
+                "Das Angebot ist leider abgelaufen.",
+            ));
+        }
+        OfferAcceptance::NotFound => {
+            return Ok(BookingResponse::failure(
+                "Wir haben kein Angebot für diesen Link gefunden.",
+            ));
+        }
+    };
+
+    let (booking, _, payment_id) = db::get_active_booking(pool, booking_id)
+        .await?
+        .ok_or_else(|| anyhow!("Found no active booking with id '{}'", booking_id))?;
+    let event = db::get_event(pool, &booking.event_id, false)
+        .await?
+        .ok_or_else(|| anyhow!("Event not found"))?;
+
+    send_booking_mail(&booking, &event, true, payment_id, None, email_sender).await?;
+    info!("Waiting list offer {} has been accepted", offer_id);
+
+    let counter = db::get_event_counters(pool, event.lifecycle_status).await?;
+    Ok(BookingResponse::success(
+        "Du hast den Platz angenommen. Du bekommst in den nächsten Minuten eine Bestätigung per E-Mail.",
+        counter,
+    ))
+}
+
+/// Expire the waiting list offers which have not been accepted in time and
+/// offer the spots to the next bookings of the waiting lists.
+/// Returns the number of expired offers.
+pub(crate) async fn expire_waiting_list_offers(
+    pool: &PgPool,
+    secrets: &dyn SecretProvider,
+    email_sender: &impl email::EmailSender,
+) -> Result<usize> {
+    let (count, promotions) = db::expire_waiting_list_offers(pool).await?;
+
+    // the offers have been committed already, a failed mail must not stop the others
+    for (event, promotion) in promotions {
+        let result = async {
+            let email_account = event.get_associated_email_account(email_sender).await?;
+            let secret = secrets.get(SecretKey::SessionSecret).await?;
+            let message = create_promotion_message(&email_account, &event, promotion, &secret)?;
+            email_sender.send_message(&email_account, message).await
+        }
+        .await;
+        if let Err(e) = result {
+            error!(
+                "Sending the waiting list promotion of event {} failed: {:?}",
+                event.id, e
+            );
+        }
+    }
+
+    Ok(count)
+}
+
+pub(crate) async fn get_waiting_list_offers(
+    pool: &PgPool,
+    event_id: &EventId,
+) -> Result<Vec<WaitingListOffer>> {
+    db::get_waiting_list_offers(pool, event_id).await
+}
+
 pub(crate) async fn send_event_email(
     pool: &PgPool,
     data: EventEmail,
@@ -600,6 +1146,7 @@ pub(crate) async fn close_finished_running_events(
                 lifecycle_status: Some(LifecycleStatus::Finished),
                 ..Default::default()
             },
+            true,
             email_sender,
         )
         .await?;
@@ -617,6 +1164,7 @@ pub(crate) async fn close_finished_running_events(
                 lifecycle_status: Some(LifecycleStatus::Closed),
                 ..Default::default()
             },
+            true,
             email_sender,
         )
         .await?;
@@ -627,6 +1175,20 @@ pub(crate) async fn close_finished_running_events(
     Ok(count)
 }
 
+/// Publish and unpublish the events whose scheduled time has come.
+/// Returns the number of changed events.
+pub(crate) async fn publish_scheduled_events(pool: &PgPool) -> Result<usize> {
+    let (published, unpublished) = db::publish_scheduled_events(pool).await?;
+    for event_id in published.iter() {
+        info!("Event {} has been published as scheduled", event_id);
+    }
+    for event_id in unpublished.iter() {
+        info!("Event {} has been unpublished as scheduled", event_id);
+    }
+
+    Ok(published.len() + unpublished.len())
+}
+
 fn into_lifecycle_status(beta: bool) -> LifecycleStatus {
     if beta {
         LifecycleStatus::Review
@@ -638,6 +1200,7 @@ fn into_lifecycle_status(beta: bool) -> LifecycleStatus {
 async fn book_event(
     pool: &PgPool,
     mut booking: EventBooking,
+    secrets: &dyn SecretProvider,
     email_sender: &impl email::EmailSender,
 ) -> Result<BookingResponse> {
     let event = db::get_event(pool, &booking.event_id, false)
@@ -653,11 +1216,14 @@ async fn book_event(
             ValidationError::new("Bitte gib eine gültige IBAN ein.")
         })?;
         let normalized = banking::validate_iban_str(raw_iban)?;
-        booking.iban = Some(normalized);
+        booking.iban = Some(IbanCipher::load(secrets).await?.encrypt(&normalized)?);
     } else {
         booking.iban = None;
     }
 
+    booking.custom_values = validate_custom_values(&event, &booking.custom_values)?;
+    booking.bucket_id = validate_capacity_bucket(&event, booking.bucket_id)?;
+
     let price_multiplier = event.price_relevant_multiplier(&booking.custom_values);
     if event.custom_fields.iter().any(|cf| cf.price_relevant) && price_multiplier.is_none() {
         bail!(ValidationError::new(
@@ -675,6 +1241,7 @@ async fn book_event(
                 counter,
                 true,
                 payment_id,
+                secrets,
                 email_sender,
             )
             .await?
@@ -687,17 +1254,27 @@ async fn book_event(
                 counter,
                 false,
                 payment_id,
+                secrets,
                 email_sender,
             )
             .await?
         }
-        BookingResult::DuplicateBooking => {
+        BookingResult::DuplicateBooking(existing) => {
             info!(
-                "Event ({}) booking failed because a duplicate booking has been detected.",
-                booking.event_id
+                "Event ({}) booking failed because a duplicate of booking {} has been detected.",
+                booking.event_id, existing.id
             );
-            BookingResponse::failure(
-                "Wir haben schon eine Buchung mit diesen Anmeldedaten erkannt. Bitte verwende für weitere Buchungen andere Anmeldedaten.",
+            // the response does not tell whether the person is enrolled or waiting, as
+            // anybody knowing the name and email address gets it
+            let token = tokens::sign(
+                TokenPurpose::BookingConfirmation,
+                &existing.id.to_string(),
+                Duration::minutes(RESEND_CONFIRMATION_VALIDITY_MINUTES),
+                &secrets.get(SecretKey::SessionSecret).await?,
+            )?;
+            BookingResponse::duplicate(
+                "Mit Deinen Daten liegt bereits eine Buchung dieser Veranstaltung vor. Möchtest Du die Bestätigung noch einmal per E-Mail erhalten?",
+                token,
             )
         }
         BookingResult::NotBookable => {
@@ -719,10 +1296,43 @@ async fn book_event(
     Ok(booking_response)
 }
 
+/// Validate the custom values against the custom fields of the event and
+/// return the normalized values, one per custom field.
+fn validate_custom_values(event: &Event, custom_values: &[String]) -> Result<Vec<String>> {
+    event
+        .custom_fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            field
+                .validate(custom_values.get(i).map(String::as_str))
+                .map_err(|message| ValidationError::new(message).into())
+        })
+        .collect()
+}
+
+/// Check that bookings of events with capacity buckets choose one of the buckets
+/// of the event. Bookings of events without capacity buckets have no bucket.
+fn validate_capacity_bucket(event: &Event, bucket_id: Option<i32>) -> Result<Option<i32>> {
+    if event.capacity_buckets.is_empty() {
+        return Ok(None);
+    }
+    bucket_id
+        .filter(|id| {
+            event
+                .capacity_buckets
+                .iter()
+                .any(|bucket| bucket.id == Some(*id))
+        })
+        .map(Some)
+        .ok_or_else(|| ValidationError::new("Bitte wähle eine Kategorie aus.").into())
+}
+
 async fn pre_book_event(
     pool: &PgPool,
     hash: String,
     provided_iban: Option<String>,
+    secrets: &dyn SecretProvider,
     email_sender: &impl email::EmailSender,
 ) -> Result<BookingResponse> {
     let ids = hashids::decode(&hash)
@@ -749,17 +1359,37 @@ async fn pre_book_event(
         ));
     }
 
+    // prebookings keep the capacity bucket of the prior booking
+    let bucket_id = if event.capacity_buckets.is_empty() {
+        None
+    } else {
+        match db::find_prior_capacity_bucket(pool, &event_id, subscriber_id).await? {
+            Some(bucket_id) => Some(bucket_id),
+            None => {
+                return Ok(BookingResponse::failure(
+                    "Bitte buche diese Veranstaltung über das Buchungsformular.",
+                ));
+            }
+        }
+    };
+
     let mut iban = provided_iban;
-    if event.payment_method == PaymentMethod::SepaDirectDebit && iban.is_none() {
-        let prior_iban = db::find_prior_sepa_iban(pool, subscriber_id).await?;
-        if let Some(prior) = prior_iban {
-            iban = Some(prior);
-        } else {
-            return Ok(BookingResponse::requires_iban("Bitte gib deine IBAN ein."));
+    if event.payment_method == PaymentMethod::SepaDirectDebit || iban.is_some() {
+        let cipher = IbanCipher::load(secrets).await?;
+        if iban.is_none() {
+            let prior_iban = db::find_prior_sepa_iban(pool, subscriber_id, &cipher).await?;
+            if let Some(prior) = prior_iban {
+                iban = Some(prior);
+            } else {
+                return Ok(BookingResponse::requires_iban("Bitte gib deine IBAN ein."));
+            }
         }
+        // IBANs are only stored encrypted
+        iban = iban.map(|iban| cipher.encrypt(&iban)).transpose()?;
     }
 
-    let (booking_result, booking) = db::pre_book_event(pool, event_id, subscriber_id, iban).await?;
+    let (booking_result, booking) =
+        db::pre_book_event(pool, event_id, subscriber_id, iban, bucket_id).await?;
     let booking_response = match booking_result {
         BookingResult::Booked(event, counter, payment_id) => {
             process_booking(
@@ -770,6 +1400,7 @@ async fn pre_book_event(
                 counter,
                 true,
                 payment_id,
+                secrets,
                 email_sender,
             )
             .await?
@@ -783,11 +1414,12 @@ async fn pre_book_event(
                 counter,
                 false,
                 payment_id,
+                secrets,
                 email_sender,
             )
             .await?
         }
-        BookingResult::DuplicateBooking => {
+        BookingResult::DuplicateBooking(_) => {
             warn!(
                 "Prebooking link data has been detected and invalidated for booking {:?}",
                 booking
@@ -809,6 +1441,39 @@ async fn pre_book_event(
     Ok(booking_response)
 }
 
+/// Send the confirmation of a duplicate booking again. The token is only handed out
+/// with the response of the duplicate booking, so bookings can't be enumerated.
+async fn send_confirmation_again(
+    pool: &PgPool,
+    token: &str,
+    secrets: &dyn SecretProvider,
+    email_sender: &impl email::EmailSender,
+) -> Result<BookingResponse> {
+    let secret = secrets.get(SecretKey::SessionSecret).await?;
+    let booking_id: i32 = tokens::verify(TokenPurpose::BookingConfirmation, token, &secret)?
+        .parse()
+        .map_err(|_| ValidationError::new("Der Link ist ungültig oder abgelaufen."))?;
+
+    let Some((booking, enrolled, payment_id)) = db::get_active_booking(pool, booking_id).await?
+    else {
+        return Ok(BookingResponse::failure(
+            "Wir haben keine aktive Buchung gefunden.",
+        ));
+    };
+    let event = db::get_event(pool, &booking.event_id, false)
+        .await?
+        .ok_or_else(|| anyhow!("Event not found"))?;
+
+    send_booking_mail(&booking, &event, enrolled, payment_id, None, email_sender).await?;
+    info!("Booking confirmation {} has been sent again", booking_id);
+
+    Ok(BookingResponse::success(
+        "Wir haben Dir die Bestätigung noch einmal per E-Mail geschickt.",
+        Vec::new(),
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn process_booking(
     pool: &PgPool,
     booking: &EventBooking,
@@ -816,10 +1481,24 @@ async fn process_booking(
     counter: Vec<EventCounter>,
     booked: bool,
     payment_id: String,
+    secrets: &dyn SecretProvider,
     email_sender: &impl email::EmailSender,
 ) -> Result<BookingResponse> {
-    subscribe_to_updates(pool, booking, &event, email_sender).await?;
-    send_booking_mail(booking, &event, booked, payment_id, email_sender).await?;
+    subscribe_to_updates(pool, booking, &event).await?;
+    let preferences_token = if booking.updates.unwrap_or(false) {
+        Some(super::news::preferences_token(&booking.email, secrets).await?)
+    } else {
+        None
+    };
+    send_booking_mail(
+        booking,
+        &event,
+        booked,
+        payment_id,
+        preferences_token,
+        email_sender,
+    )
+    .await?;
     info!("Booking of Event {} was successfull", booking.event_id);
     let message = if booked {
         "Die Buchung war erfolgreich. Du bekommst in den nächsten Minuten eine Bestätigung per E-Mail."
@@ -829,18 +1508,13 @@ async fn process_booking(
     Ok(BookingResponse::success(message, counter))
 }
 
-async fn subscribe_to_updates(
-    pool: &PgPool,
-    booking: &EventBooking,
-    event: &Event,
-    email_sender: &impl email::EmailSender,
-) -> Result<()> {
+async fn subscribe_to_updates(pool: &PgPool, booking: &EventBooking, event: &Event) -> Result<()> {
     // only subscribe to updates if updates field is true
     if !booking.updates.unwrap_or(false) {
         return Ok(());
     }
     let subscription = NewsSubscription::new(booking.email.clone(), vec![event.event_type.into()]);
-    super::news::subscribe_to_news(pool, subscription, false, email_sender).await?;
+    super::news::subscribe_to_news(pool, subscription).await?;
 
     Ok(())
 }
@@ -850,6 +1524,7 @@ async fn send_booking_mail(
     event: &Event,
     booked: bool,
     payment_id: String,
+    preferences_token: Option<String>,
     email_sender: &impl email::EmailSender,
 ) -> Result<()> {
     let email_account = event.get_associated_email_account(email_sender).await?;
@@ -872,7 +1547,8 @@ async fn send_booking_mail(
     let mut body =
         template::render_booking(template, booking, event, opt_payment_id, None, Some(true))?;
 
-    if booking.updates.unwrap_or(false) {
+    // the subscriber of the updates gets the personal link to the preference center
+    if let Some(preferences_token) = preferences_token {
         body.push_str(
             format!(
                 "
@@ -883,7 +1559,7 @@ PS: Ab sofort erhältst Du automatisch eine E-Mail, sobald neue {} online sind.
                     EventType::Fitness => "Kursangebote",
                     EventType::Events => "Events",
                 },
-                super::news::UNSUBSCRIBE_MESSAGE
+                super::news::unsubscribe_message(&preferences_token)
             )
             .as_str(),
         )
@@ -900,6 +1576,29 @@ PS: Ab sofort erhältst Du automatisch eine E-Mail, sobald neue {} online sind.
     Ok(())
 }
 
+/// The link of the offer is signed, so offers can't be accepted by guessing their ids.
+/// It stays valid a day longer than the offer to tell late visitors that it has expired.
+fn create_waiting_list_offer_link(
+    event_type: EventType,
+    offer: &WaitingListOffer,
+    secret: &str,
+) -> Result<String> {
+    let token = tokens::sign(
+        TokenPurpose::WaitingListOffer,
+        &offer.id.to_string(),
+        offer.expires_at - Utc::now() + Duration::days(1),
+        secret,
+    )?;
+    Ok(format!(
+        "https://www.sv-eutingen.de/{}?offer={}",
+        match event_type {
+            EventType::Fitness => "fitness",
+            EventType::Events => "events",
+        },
+        token
+    ))
+}
+
 fn create_prebooking_link(
     event_type: EventType,
     event_id: EventId,
@@ -1733,13 +2432,18 @@ Buchungstag;Valuta;Textschlüssel;Primanota;Zahlungsempfänger;Zahlungsempfänge
 mod events_integration_tests {
     use anyhow::Result;
     use bigdecimal::BigDecimal;
-    use chrono::{Duration, Utc};
+    use chrono::{Duration, TimeZone, Utc};
     use pretty_assertions::assert_eq;
     use sqlx::PgPool;
 
     use crate::logic::secrets::MockSecretProvider;
-    use crate::models::{EventBooking, EventType, LifecycleStatus, PartialEvent, PaymentMethod};
-    use crate::test_utils::{mock_email_sender, mock_email_sender_capturing, noop_mock};
+    use crate::models::{
+        EventBooking, EventCapacityBucket, EventType, LifecycleStatus, PartialEvent, PaymentMethod,
+    };
+    use crate::test_utils::{
+        TEST_IBAN_KEY, mock_email_sender, mock_email_sender_capturing, mock_iban_secrets,
+        mock_secrets, noop_mock,
+    };
 
     use super::*;
 
@@ -1797,6 +2501,18 @@ mod events_integration_tests {
         Ok(event.0)
     }
 
+    /// Decrypt a stored IBAN and make sure it has not been stored in plain text.
+    fn decrypt_iban(iban: &Option<String>) -> Option<String> {
+        let iban = iban.as_deref()?;
+        assert!(iban.starts_with("enc:"), "IBAN should be encrypted");
+        Some(
+            IbanCipher::new(crate::test_utils::TEST_IBAN_KEY)
+                .unwrap()
+                .decrypt(iban)
+                .unwrap(),
+        )
+    }
+
     fn make_booking(event_id: EventId) -> EventBooking {
         make_booking_with_values(event_id, vec![])
     }
@@ -1816,6 +2532,7 @@ mod events_integration_tests {
             custom_values,
             token: None,
             iban: None,
+            bucket_id: None,
         }
     }
 
@@ -1836,6 +2553,10 @@ mod events_integration_tests {
             None,
             None,
             true,
+            Vec::new(),
+            false,
+            None,
+            Vec::new(),
         );
 
         let event = db::write_event(
@@ -1875,7 +2596,8 @@ mod events_integration_tests {
         let mock_sender = noop_mock();
         let booking_data = make_booking(event.id);
 
-        let response = super::booking(&pool, booking_data, &mock_sender).await;
+        let response =
+            super::booking(&pool, booking_data, &mock_iban_secrets(), &mock_sender).await;
         assert!(!response.success);
     }
 
@@ -1911,21 +2633,28 @@ mod events_integration_tests {
 
         let mock_sender = noop_mock();
         let booking_data = make_booking(event.0.id);
-        let response = super::booking(&pool, booking_data, &mock_sender).await;
+        let response =
+            super::booking(&pool, booking_data, &mock_iban_secrets(), &mock_sender).await;
         assert!(!response.success);
     }
 
     #[sqlx::test]
     async fn test_prebooking_invalid_hash(pool: PgPool) {
         let mock_sender = noop_mock();
-        let response = prebooking(&pool, "invalid_hash".to_string(), &mock_sender).await;
+        let response = prebooking(
+            &pool,
+            "invalid_hash".to_string(),
+            &mock_iban_secrets(),
+            &mock_sender,
+        )
+        .await;
         assert!(!response.success);
     }
 
     #[sqlx::test]
     async fn test_cancel_nonexistent_booking(pool: PgPool) {
         let mock_sender = noop_mock();
-        let result = cancel_booking(&pool, 99999, &mock_sender).await;
+        let result = cancel_booking(&pool, 99999, &mock_iban_secrets(), &mock_sender).await;
         assert!(result.is_err());
     }
 
@@ -1941,6 +2670,7 @@ mod events_integration_tests {
                 name: Some("Updated Name".to_string()),
                 ..Default::default()
             },
+            true,
             &mock_sender,
         )
         .await?;
@@ -1962,6 +2692,7 @@ mod events_integration_tests {
                 dates: Some(vec![]),
                 ..Default::default()
             },
+            true,
             &mock_sender,
         )
         .await?;
@@ -2069,6 +2800,7 @@ mod events_integration_tests {
             custom_values: vec![],
             token: None,
             iban: None,
+            bucket_id: None,
         };
 
         let mock_sender = mock_email_sender(vec![(
@@ -2076,7 +2808,15 @@ mod events_integration_tests {
             "test@example.com",
         )]);
 
-        send_booking_mail(&booking, &event, true, "PAY123".to_string(), &mock_sender).await?;
+        send_booking_mail(
+            &booking,
+            &event,
+            true,
+            "PAY123".to_string(),
+            None,
+            &mock_sender,
+        )
+        .await?;
 
         Ok(())
     }
@@ -2099,6 +2839,7 @@ mod events_integration_tests {
             custom_values: vec![],
             token: None,
             iban: None,
+            bucket_id: None,
         };
 
         let mock_sender = mock_email_sender(vec![(
@@ -2106,7 +2847,51 @@ mod events_integration_tests {
             "test@example.com",
         )]);
 
-        send_booking_mail(&booking, &event, false, "PAY123".to_string(), &mock_sender).await?;
+        send_booking_mail(
+            &booking,
+            &event,
+            false,
+            "PAY123".to_string(),
+            None,
+            &mock_sender,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_booking_mail_contains_preferences_link(pool: PgPool) -> Result<()> {
+        use crate::logic::secrets::SecretKey;
+
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        let booking = EventBooking {
+            updates: Some(true),
+            ..make_booking(event.id)
+        };
+        let secrets = crate::test_utils::mock_secrets(vec![
+            (
+                SecretKey::IbanEncryptionKey,
+                crate::test_utils::TEST_IBAN_KEY,
+            ),
+            (SecretKey::NewsTokenSecret, "secret"),
+        ]);
+        let (mock_sender, captured) = mock_email_sender_capturing(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+
+        assert!(
+            super::booking(&pool, booking, &secrets, &mock_sender)
+                .await
+                .success
+        );
+
+        let messages = captured.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        let formatted = messages[0].formatted();
+        let body = String::from_utf8_lossy(&formatted);
+        assert!(body.contains("https://www.sv-eutingen.de/newsletter?token="));
 
         Ok(())
     }
@@ -2181,13 +2966,14 @@ mod events_integration_tests {
         let mut booking_data = make_booking(event.id);
         booking_data.iban = Some("DE89 3704 0044 0532 0130 00".to_string());
 
-        let response = super::booking(&pool, booking_data, &mock_sender).await;
+        let response =
+            super::booking(&pool, booking_data, &mock_iban_secrets(), &mock_sender).await;
         assert!(response.success, "Booking should succeed with valid IBAN");
 
         let bookings = db::get_bookings(&pool, &event.id, None).await?;
         assert_eq!(bookings.len(), 1);
         assert_eq!(
-            bookings[0].0.iban.as_deref(),
+            decrypt_iban(&bookings[0].0.iban).as_deref(),
             Some("DE89370400440532013000"),
             "IBAN should be normalized"
         );
@@ -2208,12 +2994,187 @@ mod events_integration_tests {
         let booking_data = make_booking(event.id);
         // iban is None by default
 
-        let response = super::booking(&pool, booking_data, &mock_sender).await;
+        let response =
+            super::booking(&pool, booking_data, &mock_iban_secrets(), &mock_sender).await;
         assert!(!response.success, "Booking should fail without IBAN");
 
         Ok(())
     }
 
+    #[sqlx::test]
+    async fn test_booking_duplicate_detected(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        let secrets = mock_secrets(vec![
+            (SecretKey::IbanEncryptionKey, TEST_IBAN_KEY),
+            (SecretKey::SessionSecret, "secret"),
+        ]);
+        let mock_sender = mock_email_sender(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+        assert!(
+            super::booking(&pool, make_booking(event.id), &secrets, &mock_sender)
+                .await
+                .success
+        );
+
+        // the same person with other contact data
+        let mut duplicate = make_booking(event.id);
+        duplicate.email = "MAX@test.com ".into();
+        duplicate.phone = Some("0123 456789".into());
+        let response = super::booking(&pool, duplicate, &secrets, &noop_mock()).await;
+        assert!(!response.success);
+        let response = serde_json::to_value(&response)?;
+        let message = response["message"].as_str().unwrap();
+        assert!(message.contains("bereits eine Buchung dieser Veranstaltung"));
+        // neither the status nor the date of the booking is disclosed
+        assert!(!message.contains("angemeldet") && !message.contains("seit"));
+        assert_eq!(db::get_bookings(&pool, &event.id, None).await?.len(), 1);
+
+        let (mock_sender, captured) = mock_email_sender_capturing(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+        // the enumerable hash of the booking is not accepted
+        let booking_id = db::get_event(&pool, &event.id, true)
+            .await?
+            .and_then(|event| event.subscribers)
+            .unwrap()[0]
+            .id;
+        let hash = hashids::encode(&[booking_id.try_into()?]);
+        assert!(
+            !resend_confirmation(&pool, &hash, &secrets, &mock_sender)
+                .await
+                .success
+        );
+        let token = response["resend_confirmation"].as_str().unwrap();
+        assert!(
+            resend_confirmation(&pool, token, &secrets, &mock_sender)
+                .await
+                .success
+        );
+        let messages = captured.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].headers().get_raw("To"), Some("max@test.com"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_accept_waiting_list_offer(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        db::write_event(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                max_subscribers: Some(1),
+                waiting_list_offer_hours: Some(24),
+                ..Default::default()
+            },
+        )
+        .await?;
+        for email in ["first@test.com", "second@test.com"] {
+            let mut booking = make_booking(event.id);
+            booking.email = email.into();
+            db::book_event(&pool, &booking).await?;
+        }
+        let booking_id = db::get_event(&pool, &event.id, true)
+            .await?
+            .and_then(|event| event.subscribers)
+            .unwrap()
+            .into_iter()
+            .find(|subscriber| subscriber.enrolled)
+            .unwrap()
+            .id;
+
+        let secrets = mock_secrets(vec![
+            (SecretKey::IbanEncryptionKey, TEST_IBAN_KEY),
+            (SecretKey::SessionSecret, "secret"),
+        ]);
+        let (mock_sender, captured) = mock_email_sender_capturing(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+        cancel_booking(&pool, booking_id, &secrets, &mock_sender).await?;
+        let offer_id = db::get_waiting_list_offers(&pool, &event.id).await?[0].id;
+        assert_eq!(captured.lock().unwrap().len(), 2);
+
+        // neither the guessable hash of the offer nor a token of another purpose is accepted
+        let hash = hashids::encode(&[offer_id.try_into()?]);
+        let forged = tokens::sign(
+            TokenPurpose::BookingConfirmation,
+            &offer_id.to_string(),
+            Duration::hours(1),
+            "secret",
+        )?;
+        for token in [hash.as_str(), forged.as_str(), "1"] {
+            let response = accept_waiting_list_offer(&pool, token, &secrets, &noop_mock()).await;
+            assert!(!response.success);
+            assert_eq!(
+                serde_json::to_value(&response)?["message"],
+                "Der Link ist ungültig oder abgelaufen."
+            );
+        }
+
+        let token = tokens::sign(
+            TokenPurpose::WaitingListOffer,
+            &offer_id.to_string(),
+            Duration::hours(1),
+            "secret",
+        )?;
+        let mock_sender = mock_email_sender(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+        assert!(
+            accept_waiting_list_offer(&pool, &token, &secrets, &mock_sender)
+                .await
+                .success
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_get_duplicate_bookings(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        let mock_sender = mock_email_sender_times(
+            vec![(crate::models::EmailType::Fitness, "test@example.com")],
+            2,
+        );
+        let mut other = make_booking(event.id);
+        other.first_name = "Erika".into();
+        for booking in [make_booking(event.id), other] {
+            assert!(
+                super::booking(&pool, booking, &mock_iban_secrets(), &mock_sender)
+                    .await
+                    .success
+            );
+        }
+        assert!(get_duplicate_bookings(&pool, &event.id).await?.is_empty());
+
+        // a duplicate from before the detection
+        sqlx::query(
+            r#"WITH s AS (
+                INSERT INTO event_subscribers (first_name, last_name, street, city, email, member)
+                VALUES ('max', 'Mustermann', 'Andere Str 2', 'Teststadt', 'Max@Test.com', false)
+                RETURNING id)
+            INSERT INTO event_bookings (event_id, enrolled, pre_booking, subscriber_id, payment_id)
+            SELECT $1, true, false, s.id, '26-999' FROM s"#,
+        )
+        .bind(event.id.get_ref())
+        .execute(&pool)
+        .await?;
+
+        let duplicates = get_duplicate_bookings(&pool, &event.id).await?;
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].email, "max@test.com");
+        assert_eq!(duplicates[0].bookings.len(), 2);
+        assert_eq!(duplicates[0].bookings[1].payment_id, "26-999");
+
+        Ok(())
+    }
+
     #[sqlx::test]
     async fn test_booking_bank_transfer_event_clears_iban(pool: PgPool) -> Result<()> {
         let event = create_test_event_with_payment_method(
@@ -2230,7 +3191,8 @@ mod events_integration_tests {
         let mut booking_data = make_booking(event.id);
         booking_data.iban = Some("DE89370400440532013000".to_string());
 
-        let response = super::booking(&pool, booking_data, &mock_sender).await;
+        let response =
+            super::booking(&pool, booking_data, &mock_iban_secrets(), &mock_sender).await;
         assert!(response.success, "Booking should succeed for BankTransfer");
 
         let bookings = db::get_bookings(&pool, &event.id, None).await?;
@@ -2272,7 +3234,7 @@ mod events_integration_tests {
 
         let hash =
             crate::hashids::encode(&[event.id.into_inner().try_into()?, subscriber_id.try_into()?]);
-        let response = prebooking(&pool, hash, &mock_sender).await;
+        let response = prebooking(&pool, hash, &mock_iban_secrets(), &mock_sender).await;
         assert!(
             !response.success,
             "Prebooking should fail without IBAN and no prior booking"
@@ -2303,7 +3265,8 @@ mod events_integration_tests {
         // First booking with IBAN to establish subscriber
         let mut booking_data = make_booking(event1.id);
         booking_data.iban = Some("DE89370400440532013000".to_string());
-        let response = super::booking(&pool, booking_data, &mock_sender).await;
+        let response =
+            super::booking(&pool, booking_data, &mock_iban_secrets(), &mock_sender).await;
         assert!(response.success, "First booking should succeed");
 
         let bookings1 = db::get_bookings(&pool, &event1.id, None).await?;
@@ -2321,7 +3284,7 @@ mod events_integration_tests {
             event2.id.into_inner().try_into()?,
             subscriber_id.try_into()?,
         ]);
-        let response = prebooking(&pool, hash, &mock_sender).await;
+        let response = prebooking(&pool, hash, &mock_iban_secrets(), &mock_sender).await;
         assert!(
             response.success,
             "Prebooking should succeed using prior IBAN"
@@ -2330,7 +3293,7 @@ mod events_integration_tests {
         let bookings2 = db::get_bookings(&pool, &event2.id, None).await?;
         assert_eq!(bookings2.len(), 1);
         assert_eq!(
-            bookings2[0].0.iban.as_deref(),
+            decrypt_iban(&bookings2[0].0.iban).as_deref(),
             Some("DE89370400440532013000"),
             "Prior IBAN should be reused"
         );
@@ -2354,7 +3317,8 @@ mod events_integration_tests {
         // First booking to establish subscriber
         let mut booking_data = make_booking(event1.id);
         booking_data.iban = Some("DE89370400440532013000".to_string());
-        let response = super::booking(&pool, booking_data, &mock_sender).await;
+        let response =
+            super::booking(&pool, booking_data, &mock_iban_secrets(), &mock_sender).await;
         assert!(response.success);
 
         let bookings1 = db::get_bookings(&pool, &event1.id, None).await?;
@@ -2376,6 +3340,7 @@ mod events_integration_tests {
             &pool,
             &hash,
             "DE89 3704 0044 0532 0130 00".to_string(),
+            &mock_iban_secrets(),
             &mock_sender,
         )
         .await?;
@@ -2387,7 +3352,7 @@ mod events_integration_tests {
         let bookings2 = db::get_bookings(&pool, &event2.id, None).await?;
         assert_eq!(bookings2.len(), 1);
         assert_eq!(
-            bookings2[0].0.iban.as_deref(),
+            decrypt_iban(&bookings2[0].0.iban).as_deref(),
             Some("DE89370400440532013000"),
             "Provided IBAN should be normalized and stored"
         );
@@ -2467,27 +3432,29 @@ mod events_integration_tests {
         let (mock_sender, captured) =
             mock_email_sender_capturing(vec![(EmailType::Events, "test@example.com")]);
 
-        let response = super::booking(&pool, booking, &mock_sender).await;
+        let response = super::booking(&pool, booking, &mock_iban_secrets(), &mock_sender).await;
         assert!(response.success, "Booking should succeed");
 
         // Verify confirmation email shows 75,00 € (25 × 3)
-        let messages = captured.lock().unwrap();
-        assert_eq!(messages.len(), 1, "One confirmation email should be sent");
-        let formatted = messages[0].formatted();
-        let body = String::from_utf8_lossy(&formatted);
-        assert!(
-            body.contains("75,00"),
-            "Email body should show 75,00 € — got: {body}"
-        );
+        {
+            let messages = captured.lock().unwrap();
+            assert_eq!(messages.len(), 1, "One confirmation email should be sent");
+            let formatted = messages[0].formatted();
+            let body = String::from_utf8_lossy(&formatted);
+            assert!(
+                body.contains("75,00"),
+                "Email body should show 75,00 € — got: {body}"
+            );
+        }
 
-        // Verify booking persisted with custom_value_1 = "3"
+        // Verify booking persisted with custom_values = ["3"]
         let persisted = sqlx::query!(
-            r#"SELECT custom_value_1 FROM v_event_bookings WHERE event_id = $1 LIMIT 1"#,
+            r#"SELECT custom_values FROM v_event_bookings WHERE event_id = $1 LIMIT 1"#,
             event.id.get_ref()
         )
         .fetch_one(&pool)
         .await?;
-        assert_eq!(persisted.custom_value_1.as_deref(), Some("3"));
+        assert_eq!(persisted.custom_values, Some(vec!["3".to_string()]));
 
         Ok(())
     }
@@ -2500,7 +3467,7 @@ mod events_integration_tests {
         // sent. noop_mock has no expectations, so any email call would panic —
         // proving the validation branch fires before the confirmation-email path.
         let missing = make_booking_with_values(event.id, vec![]);
-        let response = super::booking(&pool, missing, &noop_mock()).await;
+        let response = super::booking(&pool, missing, &mock_iban_secrets(), &noop_mock()).await;
         assert!(
             !response.success,
             "Booking with a missing price-relevant value should be rejected"
@@ -2508,7 +3475,7 @@ mod events_integration_tests {
 
         // Non-numeric value: also rejected before any email is sent.
         let non_numeric = make_booking_with_values(event.id, vec!["abc".to_string()]);
-        let response = super::booking(&pool, non_numeric, &noop_mock()).await;
+        let response = super::booking(&pool, non_numeric, &mock_iban_secrets(), &noop_mock()).await;
         assert!(
             !response.success,
             "Booking with a non-numeric price-relevant value should be rejected"
@@ -2516,4 +3483,766 @@ mod events_integration_tests {
 
         Ok(())
     }
+
+    #[sqlx::test]
+    async fn test_booking_typed_custom_fields(pool: PgPool) -> Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO event_custom_fields (name, type, options, required)
+               VALUES ('T-Shirt', 'Select', '{S,M,L}', true),
+                      ('Vegetarisch', 'Checkbox', '{}', false),
+                      ('Geburtstag', 'Date', '{}', false),
+                      ('Allergien', 'Text', '{}', false),
+                      ('Geschwister', 'Number', '{}', false)"#
+        )
+        .execute(&pool)
+        .await?;
+        let mut custom_fields = db::get_all_custom_fields(&pool).await?;
+        custom_fields.sort_by_key(|field| field.id);
+
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        let (event, _) = db::write_event(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                custom_fields: Some(custom_fields),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(event.custom_fields.len(), 5);
+        assert_eq!(event.custom_fields[0].options, vec!["S", "M", "L"]);
+
+        // the required select list is missing or has an unknown option
+        for custom_values in [vec![], vec!["XL".to_string()]] {
+            let booking = make_booking_with_values(event.id, custom_values);
+            let response = super::booking(&pool, booking, &mock_iban_secrets(), &noop_mock()).await;
+            assert!(!response.success);
+        }
+
+        // invalid date
+        let booking = make_booking_with_values(
+            event.id,
+            vec![
+                "M".to_string(),
+                "true".to_string(),
+                "30.06.2015".to_string(),
+            ],
+        );
+        let response = super::booking(&pool, booking, &mock_iban_secrets(), &noop_mock()).await;
+        assert!(!response.success);
+
+        let mock_sender = mock_email_sender(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+        let booking = make_booking_with_values(
+            event.id,
+            vec![
+                "M".to_string(),
+                "true".to_string(),
+                "2015-06-30".to_string(),
+            ],
+        );
+        let response = super::booking(&pool, booking, &mock_iban_secrets(), &mock_sender).await;
+        assert!(response.success, "Booking should succeed");
+
+        // one normalized value per custom field
+        let bookings = db::get_bookings(&pool, &event.id, None).await?;
+        assert_eq!(
+            bookings[0].0.custom_values,
+            vec!["M", "true", "2015-06-30", "", ""]
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_clone_event(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        let (event, _) = db::write_event(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                lifecycle_status: Some(LifecycleStatus::Closed),
+                dates: Some(vec![Utc.with_ymd_and_hms(2030, 3, 25, 18, 0, 0).unwrap()]),
+                capacity_buckets: Some(vec![EventCapacityBucket {
+                    id: None,
+                    name: "Erwachsene".into(),
+                    max_subscribers: 10,
+                    max_waiting_list: 0,
+                }]),
+                waiting_list_offer_hours: Some(12),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // the copy is a new draft with its own capacity buckets,
+        // the dates keep their local time across the change to daylight saving time
+        let clone = clone_event(
+            &pool,
+            &event.id,
+            EventCloneOptions {
+                shift_days: 7,
+                weekly_dates: None,
+            },
+        )
+        .await?;
+        assert_ne!(clone.id, event.id);
+        assert_eq!(clone.lifecycle_status, LifecycleStatus::Draft);
+        assert_eq!(clone.name, event.name);
+        assert_eq!(clone.waiting_list_offer_hours, 12);
+        assert_eq!(
+            clone.dates,
+            vec![Utc.with_ymd_and_hms(2030, 4, 1, 17, 0, 0).unwrap()]
+        );
+        assert_eq!(clone.capacity_buckets.len(), 1);
+        assert_ne!(clone.capacity_buckets[0].id, event.capacity_buckets[0].id);
+        assert_eq!(
+            db::get_event(&pool, &event.id, false).await?.unwrap().dates,
+            event.dates
+        );
+
+        // weekly series
+        let clone = clone_event(
+            &pool,
+            &event.id,
+            EventCloneOptions {
+                shift_days: 0,
+                weekly_dates: Some(3),
+            },
+        )
+        .await?;
+        assert_eq!(
+            clone.dates,
+            vec![
+                Utc.with_ymd_and_hms(2030, 3, 25, 18, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2030, 4, 1, 17, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2030, 4, 8, 17, 0, 0).unwrap(),
+            ]
+        );
+
+        for (shift_days, weekly_dates) in [(0, Some(0)), (0, Some(u16::MAX)), (i64::MAX, None)] {
+            let result = clone_event(
+                &pool,
+                &event.id,
+                EventCloneOptions {
+                    shift_days,
+                    weekly_dates,
+                },
+            )
+            .await;
+            assert!(
+                result
+                    .unwrap_err()
+                    .downcast_ref::<ValidationError>()
+                    .is_some()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_lifecycle_transitions(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        let set_status = |status| {
+            db::write_event(
+                &pool,
+                PartialEvent {
+                    id: Some(event.id),
+                    lifecycle_status: Some(status),
+                    ..Default::default()
+                },
+            )
+        };
+
+        let (closed, _) = set_status(LifecycleStatus::Closed).await?;
+        assert_eq!(closed.lifecycle_status, LifecycleStatus::Closed);
+        assert!(closed.closed.is_some());
+
+        // a closed event can't be published again
+        let result = set_status(LifecycleStatus::Published).await;
+        assert!(
+            result
+                .unwrap_err()
+                .downcast_ref::<ValidationError>()
+                .is_some()
+        );
+        let event = db::get_event(&pool, &event.id, false).await?.unwrap();
+        assert_eq!(event.lifecycle_status, LifecycleStatus::Closed);
+
+        // new events can't be created as closed events
+        let mut partial_event = PartialEvent::from(event);
+        partial_event.id = None;
+        let result = db::write_event(&pool, partial_event).await;
+        assert!(
+            result
+                .unwrap_err()
+                .downcast_ref::<ValidationError>()
+                .is_some()
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_publish_scheduled_events(pool: PgPool) -> Result<()> {
+        let draft = create_test_event(&pool, LifecycleStatus::Draft).await?;
+        let published = create_test_event(&pool, LifecycleStatus::Published).await?;
+        let scheduled = create_test_event(&pool, LifecycleStatus::Draft).await?;
+        for (event, publish_at, unpublish_at) in [
+            (
+                &draft,
+                Some(Utc::now() - Duration::try_minutes(1).unwrap()),
+                None,
+            ),
+            (
+                &published,
+                None,
+                Some(Utc::now() - Duration::try_minutes(1).unwrap()),
+            ),
+            (
+                &scheduled,
+                Some(Utc::now() + Duration::try_days(1).unwrap()),
+                None,
+            ),
+        ] {
+            db::write_event(
+                &pool,
+                PartialEvent {
+                    id: Some(event.id),
+                    publish_at: Some(publish_at),
+                    unpublish_at: Some(unpublish_at),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+
+        assert_eq!(publish_scheduled_events(&pool).await?, 2);
+        assert_eq!(publish_scheduled_events(&pool).await?, 0);
+
+        let event = db::get_event(&pool, &draft.id, false).await?.unwrap();
+        assert_eq!(event.lifecycle_status, LifecycleStatus::Published);
+        assert!(event.publish_at.is_none());
+        let event = db::get_event(&pool, &published.id, false).await?.unwrap();
+        assert_eq!(event.lifecycle_status, LifecycleStatus::Finished);
+        assert!(!event.lifecycle_status.is_bookable());
+        assert!(event.unpublish_at.is_none());
+        let event = db::get_event(&pool, &scheduled.id, false).await?.unwrap();
+        assert_eq!(event.lifecycle_status, LifecycleStatus::Draft);
+        assert!(event.publish_at.is_some());
+
+        // an explicit null clears the scheduled time
+        db::write_event(
+            &pool,
+            PartialEvent {
+                id: Some(scheduled.id),
+                publish_at: Some(None),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let event = db::get_event(&pool, &scheduled.id, false).await?.unwrap();
+        assert!(event.publish_at.is_none());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_booking_window(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        let mock_sender = mock_email_sender(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+
+        // the booking window has not been opened yet
+        db::write_event(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                booking_start: Some(Some(Utc::now() + Duration::try_days(1).unwrap())),
+                booking_end: Some(Some(Utc::now() + Duration::try_days(2).unwrap())),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let response = super::booking(
+            &pool,
+            make_booking(event.id),
+            &mock_iban_secrets(),
+            &mock_sender,
+        )
+        .await;
+        assert!(!response.success);
+
+        // the booking window is open
+        db::write_event(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                booking_start: Some(Some(Utc::now() - Duration::try_days(1).unwrap())),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let response = super::booking(
+            &pool,
+            make_booking(event.id),
+            &mock_iban_secrets(),
+            &mock_sender,
+        )
+        .await;
+        assert!(response.success, "Booking should succeed");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_event_templates(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+
+        let template = save_event_template(
+            &pool,
+            EventTemplate {
+                id: None,
+                name: " Yoga ".into(),
+                event: PartialEvent::from(event),
+            },
+        )
+        .await?;
+        assert_eq!(template.name, "Yoga");
+        assert!(template.event.id.is_none());
+        assert!(template.event.lifecycle_status.is_none());
+        assert!(template.event.dates.is_none());
+
+        // the names are unique
+        let result = save_event_template(
+            &pool,
+            EventTemplate {
+                id: None,
+                name: "Yoga".into(),
+                event: PartialEvent::default(),
+            },
+        )
+        .await;
+        assert!(
+            result
+                .unwrap_err()
+                .downcast_ref::<ValidationError>()
+                .is_some()
+        );
+
+        let templates = get_event_templates(&pool).await?;
+        assert_eq!(templates.len(), 1);
+        assert_eq!(templates[0].id, template.id);
+        assert_eq!(templates[0].event.name.as_deref(), Some("Test Event"));
+        assert_eq!(templates[0].event.event_type, Some(EventType::Fitness));
+
+        delete_event_template(&pool, template.id.unwrap()).await?;
+        assert!(get_event_templates(&pool).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_booking_capacity_buckets(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        let (event, _) = db::write_event(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                capacity_buckets: Some(vec![
+                    EventCapacityBucket {
+                        id: None,
+                        name: "Erwachsene".into(),
+                        max_subscribers: 10,
+                        max_waiting_list: 0,
+                    },
+                    EventCapacityBucket {
+                        id: None,
+                        name: "Kinder".into(),
+                        max_subscribers: 10,
+                        max_waiting_list: 0,
+                    },
+                ]),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(event.capacity_buckets.len(), 2);
+        let kids = event.capacity_buckets[1].id;
+
+        // the bucket is missing or belongs to no bucket of the event
+        for bucket_id in [None, Some(-1)] {
+            let mut booking = make_booking(event.id);
+            booking.bucket_id = bucket_id;
+            let response = super::booking(&pool, booking, &mock_iban_secrets(), &noop_mock()).await;
+            assert!(!response.success);
+        }
+
+        let mock_sender = mock_email_sender(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+        let mut booking = make_booking(event.id);
+        booking.bucket_id = kids;
+        let response = super::booking(&pool, booking, &mock_iban_secrets(), &mock_sender).await;
+        assert!(response.success, "Booking should succeed");
+
+        let counters = db::get_event_counters(&pool, LifecycleStatus::Published).await?;
+        let counter = counters
+            .iter()
+            .find(|counter| counter.id == event.id)
+            .unwrap();
+        assert_eq!(counter.subscribers, 1);
+        assert_eq!(counter.buckets[0].subscribers, 0);
+        assert_eq!(counter.buckets[1].subscribers, 1);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_event_versions(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Draft).await?;
+        let mock_sender = noop_mock();
+
+        update(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                name: Some("Updated Name".to_string()),
+                location: Some("Sporthalle Eutingen".to_string()),
+                ..Default::default()
+            },
+            true,
+            &mock_sender,
+        )
+        .await?;
+
+        let versions = get_event_versions(&pool, &event.id).await?;
+        assert_eq!(
+            versions.iter().map(|v| v.version).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+        assert_eq!(versions[0].snapshot["name"], "Updated Name");
+        assert_eq!(versions[1].snapshot["name"], "Test Event");
+
+        // only the changed fields are part of the diff
+        let diff = diff_event_versions(&pool, &event.id, 1, 2).await?.unwrap();
+        assert_eq!(
+            diff.before,
+            serde_json::json!({"name": "Test Event", "location": "Test Location"})
+        );
+        assert_eq!(
+            diff.after,
+            serde_json::json!({"name": "Updated Name", "location": "Sporthalle Eutingen"})
+        );
+        assert_eq!(diff_event_versions(&pool, &event.id, 1, 3).await?, None);
+
+        // restoring creates a new version with the old content
+        let restored = restore_event_version(&pool, &event.id, 1, &mock_sender)
+            .await?
+            .unwrap();
+        assert_eq!(restored.name, "Test Event");
+        assert_eq!(restored.location, "Test Location");
+        assert_eq!(restored.lifecycle_status, LifecycleStatus::Draft);
+        let versions = get_event_versions(&pool, &event.id).await?;
+        assert_eq!(versions.len(), 3);
+        assert_eq!(versions[0].snapshot["name"], "Test Event");
+        assert!(
+            restore_event_version(&pool, &event.id, 4, &mock_sender)
+                .await?
+                .is_none()
+        );
+
+        // deleted capacity buckets are created again, the schedule is kept
+        update(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                capacity_buckets: Some(vec![EventCapacityBucket {
+                    id: None,
+                    name: "Kinder".into(),
+                    max_subscribers: 10,
+                    max_waiting_list: 0,
+                }]),
+                ..Default::default()
+            },
+            true,
+            &mock_sender,
+        )
+        .await?;
+        let publish_at = Utc::now() + Duration::try_days(1).unwrap();
+        update(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                capacity_buckets: Some(Vec::new()),
+                publish_at: Some(Some(publish_at)),
+                ..Default::default()
+            },
+            true,
+            &mock_sender,
+        )
+        .await?;
+        let restored = restore_event_version(&pool, &event.id, 4, &mock_sender)
+            .await?
+            .unwrap();
+        assert_eq!(
+            restored
+                .capacity_buckets
+                .iter()
+                .map(|bucket| bucket.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Kinder"]
+        );
+        assert!(restored.publish_at.is_some());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_update_notifies_participants(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        let mock_sender = mock_email_sender(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+        let response = super::booking(
+            &pool,
+            make_booking(event.id),
+            &mock_iban_secrets(),
+            &mock_sender,
+        )
+        .await;
+        assert!(response.success);
+
+        // the admin has opted out of the notification
+        update(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                location: Some("Sporthalle Eutingen".to_string()),
+                ..Default::default()
+            },
+            false,
+            &noop_mock(),
+        )
+        .await?;
+
+        // changes which are not material are not sent
+        update(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                description: Some("Updated description".to_string()),
+                ..Default::default()
+            },
+            true,
+            &noop_mock(),
+        )
+        .await?;
+
+        let (mock_sender, captured) = mock_email_sender_capturing(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+        update(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                duration_in_minutes: Some(90),
+                ..Default::default()
+            },
+            true,
+            &mock_sender,
+        )
+        .await?;
+
+        let messages = captured.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].headers().get_raw("To"), Some("max@test.com"));
+        let formatted = messages[0].formatted();
+        let body = String::from_utf8_lossy(&formatted);
+        assert!(body.contains("- Dauer: 90 Minuten (bisher: 60 Minuten)"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_cancel_event(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        db::write_event(
+            &pool,
+            PartialEvent {
+                id: Some(event.id),
+                max_subscribers: Some(1),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let mock_sender = mock_email_sender_times(
+            vec![(crate::models::EmailType::Fitness, "test@example.com")],
+            2,
+        );
+        for email in ["max@test.com", "erika@test.com"] {
+            let booking = EventBooking {
+                email: email.to_string(),
+                ..make_booking(event.id)
+            };
+            assert!(
+                super::booking(&pool, booking, &mock_iban_secrets(), &mock_sender)
+                    .await
+                    .success
+            );
+        }
+        sqlx::query(
+            r#"UPDATE event_bookings SET payment_confirmed_at = NOW() WHERE event_id = $1 AND enrolled"#,
+        )
+        .bind(event.id.get_ref())
+        .execute(&pool)
+        .await?;
+
+        // a reason is required
+        assert!(
+            cancel_event(&pool, &event.id, " ", &noop_mock())
+                .await
+                .is_err()
+        );
+
+        let (mock_sender, captured) = mock_email_sender_capturing(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+        let cancellation = cancel_event(
+            &pool,
+            &event.id,
+            "Die Trainerin ist erkrankt.",
+            &mock_sender,
+        )
+        .await?
+        .unwrap();
+        assert_eq!(
+            cancellation.event.lifecycle_status,
+            LifecycleStatus::Canceled
+        );
+        assert_eq!(cancellation.notified, 2);
+        assert_eq!(cancellation.refunds.len(), 1);
+        assert_eq!(cancellation.refunds[0].email, "max@test.com");
+        assert_eq!(cancellation.refunds[0].price, BigDecimal::from(20));
+
+        // the participant and the waiting list are informed, only the paid booking is refunded
+        {
+            let messages = captured.lock().unwrap();
+            assert_eq!(messages.len(), 2);
+            assert_eq!(messages[0].headers().get_raw("To"), Some("max@test.com"));
+            let formatted = messages[0].formatted();
+            let body = String::from_utf8_lossy(&formatted);
+            assert!(body.contains("Die Trainerin ist erkrankt."));
+            assert!(body.contains("20,00"));
+            let formatted = messages[1].formatted();
+            assert!(!String::from_utf8_lossy(&formatted).contains("20,00"));
+        }
+        let refunds = get_event_refunds(&pool, EventType::Fitness).await?;
+        assert_eq!(refunds.len(), 1);
+        assert_eq!(refunds[0].event_id, event.id);
+        assert_eq!(refunds[0].email, "max@test.com");
+        assert_eq!(refunds[0].price, BigDecimal::from(20));
+        assert!(
+            get_event_refunds(&pool, EventType::Events)
+                .await?
+                .is_empty()
+        );
+
+        // canceled events are not listed publicly
+        assert!(
+            get_events(&pool, None, None, None, false)
+                .await?
+                .iter()
+                .all(|e| e.id != event.id)
+        );
+        assert!(
+            get_events(&pool, None, None, None, true)
+                .await?
+                .iter()
+                .any(|e| e.id == event.id)
+        );
+
+        // the bookings are canceled and the event is no longer counted
+        assert!(db::get_bookings(&pool, &event.id, None).await?.is_empty());
+        assert!(
+            get_event_counters(&pool, false)
+                .await?
+                .iter()
+                .all(|counter| counter.id != event.id)
+        );
+
+        // a canceled event can't be canceled again
+        assert!(
+            cancel_event(&pool, &event.id, "Erneut", &noop_mock())
+                .await
+                .is_err()
+        );
+        assert!(
+            cancel_event(&pool, &EventId::from(0), "Unbekannt", &noop_mock())
+                .await?
+                .is_none()
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_cancel_event_with_failing_mails(pool: PgPool) -> Result<()> {
+        let event = create_test_event(&pool, LifecycleStatus::Published).await?;
+        let mock_sender = mock_email_sender(vec![(
+            crate::models::EmailType::Fitness,
+            "test@example.com",
+        )]);
+        assert!(
+            super::booking(
+                &pool,
+                make_booking(event.id),
+                &mock_iban_secrets(),
+                &mock_sender
+            )
+            .await
+            .success
+        );
+        sqlx::query(
+            r#"UPDATE event_bookings SET payment_confirmed_at = NOW() WHERE event_id = $1"#,
+        )
+        .bind(event.id.get_ref())
+        .execute(&pool)
+        .await?;
+
+        let mut mock_sender = crate::email::MockEmailSender::new();
+        mock_sender.expect_get_account_by_type().returning(|_| {
+            Box::pin(async {
+                Ok(crate::models::EmailAccount::new_for_test(
+                    crate::models::EmailType::Fitness,
+                    "test@example.com",
+                ))
+            })
+        });
+        mock_sender
+            .expect_send_message()
+            .returning(|_, _| Box::pin(async { Err(anyhow!("SMTP server unavailable")) }));
+
+        // the cancellation and the refunds are returned although no mail has been sent
+        let cancellation = cancel_event(&pool, &event.id, "Die Halle ist gesperrt.", &mock_sender)
+            .await?
+            .unwrap();
+        assert_eq!(
+            cancellation.event.lifecycle_status,
+            LifecycleStatus::Canceled
+        );
+        assert_eq!(cancellation.notified, 0);
+        assert_eq!(cancellation.refunds.len(), 1);
+
+        Ok(())
+    }
 }
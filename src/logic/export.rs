@@ -1,9 +1,12 @@
 use crate::{
     db,
-    models::{Event, EventId, EventSubscription, PaymentMethod, ToEuro},
+    models::{
+        Event, EventCustomField, EventCustomFieldType, EventId, EventSubscription, PaymentMethod,
+        ToEuro,
+    },
 };
 use anyhow::{Result, anyhow};
-use chrono::Locale;
+use chrono::{Locale, NaiveDate};
 use printpdf::{
     Color, Line, LinePoint, Mm, Op, PdfDocument, PdfFontHandle, PdfPage, PdfParseErrorSeverity,
     PdfSaveOptions, PdfWarnMsg, Point, Polygon, PolygonRing, Pt, Rgb, Svg, TextItem, TextMatrix,
@@ -82,10 +85,8 @@ fn create_sheet(
     sheet.add_column(Column { width: 10.0 });
     sheet.add_column(Column { width: 6.5 });
 
-    let mut custom_fields = Vec::new();
-    for custom_field in event.custom_fields.iter() {
+    for _ in event.custom_fields.iter() {
         sheet.add_column(Column { width: 20.0 });
-        custom_fields.push(custom_field.name.clone());
     }
 
     let payment_column = match event.payment_method {
@@ -109,8 +110,8 @@ fn create_sheet(
         row.add_cell("Betrag");
         row.add_cell("Buchungsnr");
         row.add_cell(payment_column);
-        for custom_field in custom_fields.iter() {
-            row.add_cell(custom_field.to_owned());
+        for custom_field in event.custom_fields.iter() {
+            row.add_cell(custom_field.name.clone());
         }
         row.add_cell("Kommentar");
 
@@ -138,8 +139,11 @@ fn create_sheet(
                     row.add_cell(bool(value.sepa_exported_at.is_some()));
                 }
             }
-            for (index, _) in custom_fields.iter().enumerate() {
-                row.add_cell(opt(value.custom_values.get(index).cloned()));
+            for (index, custom_field) in event.custom_fields.iter().enumerate() {
+                row.add_cell(custom_value(
+                    custom_field,
+                    value.custom_values.get(index).cloned(),
+                ));
             }
             row.add_cell(opt(value.comment));
             sheet_writer.append_row(row)?;
@@ -168,6 +172,26 @@ fn bool(value: bool) -> CellValue {
     }
 }
 
+/// Typed cell of a custom value, values which do not match the type of
+/// the custom field are exported as they are.
+fn custom_value(custom_field: &EventCustomField, value: Option<String>) -> CellValue {
+    let Some(value) = value.filter(|value| !value.is_empty()) else {
+        return CellValue::Blank(1);
+    };
+    match custom_field.cf_type {
+        EventCustomFieldType::Number => match value.parse::<f64>() {
+            Ok(number) => number.to_cell_value(),
+            Err(_) => value.to_cell_value(),
+        },
+        EventCustomFieldType::Checkbox => bool(value == "true"),
+        EventCustomFieldType::Date => match NaiveDate::parse_from_str(&value, "%Y-%m-%d") {
+            Ok(date) => date.to_cell_value(),
+            Err(_) => value.to_cell_value(),
+        },
+        EventCustomFieldType::Text | EventCustomFieldType::Select => value.to_cell_value(),
+    }
+}
+
 /// create a participants list for the event bookings of the given event id
 pub(crate) async fn event_participants_list(
     pool: &PgPool,
@@ -868,9 +892,68 @@ mod tests {
             None,
             None,
             true,
+            Vec::new(),
+            false,
+            None,
+            Vec::new(),
         );
 
-        let event = Event::new(
+        let event = new_event(vec![custom_field]);
+        let subscription = new_subscription(vec![String::from("3")]);
+
+        let content = export_content(event, subscription);
+
+        assert!(content.contains("Betrag"));
+        assert!(content.contains("60,00"));
+    }
+
+    #[test]
+    fn test_export_typed_custom_fields() {
+        let custom_field = |id, name: &str, cf_type, options: Vec<String>| {
+            EventCustomField::new(
+                id,
+                String::from(name),
+                cf_type,
+                None,
+                None,
+                false,
+                options,
+                false,
+                None,
+                Vec::new(),
+            )
+        };
+        let event = new_event(vec![
+            custom_field(1, "Alter", EventCustomFieldType::Number, Vec::new()),
+            custom_field(
+                2,
+                "T-Shirt",
+                EventCustomFieldType::Select,
+                vec![String::from("M"), String::from("L")],
+            ),
+            custom_field(3, "Vegetarisch", EventCustomFieldType::Checkbox, Vec::new()),
+            custom_field(4, "Geburtstag", EventCustomFieldType::Date, Vec::new()),
+            custom_field(5, "Allergien", EventCustomFieldType::Text, Vec::new()),
+        ]);
+        let subscription = new_subscription(vec![
+            String::from("8"),
+            String::from("L"),
+            String::from("true"),
+            String::from("2015-06-30"),
+            String::new(),
+        ]);
+
+        let content = export_content(event, subscription);
+
+        assert!(content.contains(r#"<c r="M2"><v>8</v></c>"#));
+        assert!(content.contains(r#"<c r="N2" t="str"><v>L</v></c>"#));
+        assert!(content.contains(r#"<c r="O2" t="str"><v>Y</v></c>"#));
+        assert!(content.contains(r#"<c r="P2" s="1"><v>42185</v></c>"#));
+        assert!(!content.contains(r#"<c r="Q2""#));
+    }
+
+    fn new_event(custom_fields: Vec<EventCustomField>) -> Event {
+        Event::new(
             1,
             Utc::now(),
             None,
@@ -896,11 +979,18 @@ mod tests {
             None,
             None,
             false,
-            vec![custom_field],
+            custom_fields,
             PaymentMethod::BankTransfer,
-        );
+            0,
+            None,
+            None,
+            None,
+            None,
+        )
+    }
 
-        let subscription = EventSubscription::new(
+    fn new_subscription(custom_values: Vec<String>) -> EventSubscription {
+        EventSubscription::new(
             1,
             Utc::now(),
             String::from("Max"),
@@ -916,9 +1006,11 @@ mod tests {
             None,
             None,
             None,
-            vec![String::from("3")],
-        );
+            custom_values,
+        )
+    }
 
+    fn export_content(event: Event, subscription: EventSubscription) -> String {
         let (_filename, bytes) = super::export(event, vec![subscription], vec![]).unwrap();
 
         let cursor = Cursor::new(&bytes);
@@ -930,8 +1022,6 @@ mod tests {
                 file.read_to_string(&mut content).unwrap();
             }
         }
-
-        assert!(content.contains("Betrag"));
-        assert!(content.contains("60,00"));
+        content
     }
 }
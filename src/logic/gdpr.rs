@@ -0,0 +1,436 @@
+//! Data subject requests (export and erasure) and the retention of participant data.
+//
+// Bookings are financial records and have to be kept, so they are never deleted:
+// the subscribers are anonymized instead and only the IBANs which have been used
+// for a SEPA direct debit are kept. The append-only audit log references bookings
+// only by their ids, just the email address of the subject of its entries is removed.
+
+use crate::db;
+use crate::error::ValidationError;
+use crate::logic::encryption;
+use crate::logic::secrets::{SecretKey, SecretProvider};
+use crate::models::{ErasureResult, PersonalDataExport};
+use anyhow::{Context, Result, bail};
+use chrono::{Duration, Utc};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::io::{Cursor, Write};
+use uuid::Uuid;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+/// Days after closing an event until its participants are anonymized, if
+/// `PARTICIPANT_RETENTION_DAYS` is not configured.
+const DEFAULT_RETENTION_DAYS: i64 = 730;
+
+pub(crate) async fn export(pool: &PgPool, email: &str) -> Result<PersonalDataExport> {
+    let email = validate_email(email)?;
+    Ok(PersonalDataExport {
+        event_subscribers: db::get_event_subscriber_data(pool, &email).await?,
+        event_bookings: mask_ibans(db::get_event_booking_data(pool, &email).await?),
+        news_subscribers: db::get_news_subscriber_data(pool, &email).await?,
+        news_subscription_requests: db::get_news_subscription_request_data(pool, &email).await?,
+        news_campaign_recipients: db::get_news_campaign_recipient_data(pool, &email).await?,
+        members: mask_ibans(db::get_member_data(pool, &email).await?),
+        audit_log: db::get_audit_log_data(pool, &email).await?,
+        email,
+        exported_at: Utc::now(),
+    })
+}
+
+/// The IBANs of bookings and members are encrypted at rest and only exported masked.
+fn mask_ibans(mut rows: Vec<Value>) -> Vec<Value> {
+    for row in &mut rows {
+        if let Some(Value::String(iban)) = row.get_mut("iban") {
+            *iban = encryption::mask(iban);
+        }
+    }
+    rows
+}
+
+/// Bundle the export as ZIP archive with one JSON file per kind of data.
+pub(crate) fn export_as_zip(export: &PersonalDataExport) -> Result<(String, Vec<u8>)> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let files = [
+        ("event_subscribers.json", &export.event_subscribers),
+        ("event_bookings.json", &export.event_bookings),
+        ("news_subscribers.json", &export.news_subscribers),
+        (
+            "news_subscription_requests.json",
+            &export.news_subscription_requests,
+        ),
+        (
+            "news_campaign_recipients.json",
+            &export.news_campaign_recipients,
+        ),
+        ("members.json", &export.members),
+        ("audit_log.json", &export.audit_log),
+    ];
+    for (name, rows) in files {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(&serde_json::to_vec_pretty(rows)?)?;
+    }
+    let bytes = zip.finish()?.into_inner();
+
+    let filename = format!(
+        "personal-data-{}-{}.zip",
+        export.email.replace(['@', '.'], "_"),
+        export.exported_at.format("%Y-%m-%d")
+    );
+    Ok((filename, bytes))
+}
+
+/// Erase the personal data of the email address. Not possible as long as the
+/// person has bookings of events which are still running. The erasure gets a
+/// random id, so it can be recorded without the erased email address.
+pub(crate) async fn erase(pool: &PgPool, email: &str) -> Result<ErasureResult> {
+    let email = validate_email(email)?;
+    if db::count_open_bookings(pool, &email).await? > 0 {
+        bail!(ValidationError::new(
+            "Es gibt noch aktive Buchungen von Veranstaltungen, die nicht abgeschlossen sind. Bitte storniere diese zuerst."
+        ));
+    }
+    let erasure_id = Uuid::new_v4().simple().to_string();
+    db::erase_personal_data(pool, &email, &erasure_id).await
+}
+
+/// Anonymize the participants of archived and canceled events whose retention
+/// period is over.
+pub(crate) async fn anonymize_archived_participants(
+    pool: &PgPool,
+    secrets: &dyn SecretProvider,
+) -> Result<u64> {
+    let retention_days = match secrets.get(SecretKey::ParticipantRetentionDays).await {
+        Ok(days) if !days.trim().is_empty() => days
+            .trim()
+            .parse::<i64>()
+            .context("Invalid PARTICIPANT_RETENTION_DAYS")?,
+        _ => DEFAULT_RETENTION_DAYS,
+    };
+    db::anonymize_archived_participants(pool, Utc::now() - Duration::days(retention_days)).await
+}
+
+fn validate_email(email: &str) -> Result<String> {
+    let email = email.trim().to_lowercase();
+    if email.is_empty() || !email.contains('@') {
+        bail!(ValidationError::new(
+            "Bitte gib eine gültige E-Mail-Adresse ein."
+        ));
+    }
+    Ok(email)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::BookingResult;
+    use crate::logic::audit;
+    use crate::logic::secrets::MockSecretProvider;
+    use crate::models::{
+        EventBooking, EventId, EventType, LifecycleStatus, MemberImport, MembershipType,
+        NewsSubscription, NewsTopic, PartialEvent,
+    };
+    use crate::test_utils::mock_iban_secrets;
+    use bigdecimal::BigDecimal;
+    use pretty_assertions::assert_eq;
+    use std::io::Read;
+
+    async fn create_event(pool: &PgPool) -> Result<EventId> {
+        let event = db::write_event(
+            pool,
+            PartialEvent {
+                event_type: Some(EventType::Fitness),
+                lifecycle_status: Some(LifecycleStatus::Published),
+                name: Some("Test Event".to_string()),
+                sort_index: Some(0),
+                short_description: Some("Short desc".to_string()),
+                description: Some("Full desc".to_string()),
+                image: Some("test.png".to_string()),
+                light: Some(true),
+                dates: Some(vec![Utc::now() + Duration::days(30)]),
+                duration_in_minutes: Some(60),
+                max_subscribers: Some(10),
+                max_waiting_list: Some(5),
+                price_member: Some(BigDecimal::from(20)),
+                price_non_member: Some(BigDecimal::from(25)),
+                location: Some("Test Location".to_string()),
+                booking_template: Some("Booking template".to_string()),
+                payment_account: Some("DE1234".to_string()),
+                external_operator: Some(false),
+                ..Default::default()
+            },
+        )
+        .await?;
+        Ok(event.0.id)
+    }
+
+    async fn book(pool: &PgPool, event_id: EventId, email: &str) -> Result<()> {
+        let booking = EventBooking {
+            event_id,
+            first_name: "Max".to_string(),
+            last_name: "Mustermann".to_string(),
+            street: "Teststr 1".to_string(),
+            city: "Teststadt".to_string(),
+            email: email.to_string(),
+            phone: None,
+            member: Some(true),
+            updates: Some(false),
+            comments: Some("Vegetarisch".to_string()),
+            custom_values: vec![],
+            token: None,
+            iban: Some("DE02120300000000202051".to_string()),
+            bucket_id: None,
+        };
+        assert!(matches!(
+            db::book_event(pool, &booking).await?,
+            BookingResult::Booked(..)
+        ));
+        Ok(())
+    }
+
+    async fn archive(pool: &PgPool, event_id: EventId, closed_days_ago: i64) -> Result<()> {
+        sqlx::query!(
+            r#"UPDATE events SET lifecycle_status = 'Archived', closed = $2 WHERE id = $1"#,
+            event_id.get_ref(),
+            Utc::now() - Duration::days(closed_days_ago)
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_export(pool: PgPool) -> Result<()> {
+        let event_id = create_event(&pool).await?;
+        book(&pool, event_id, "max@test.com").await?;
+        db::subscribe(
+            &pool,
+            NewsSubscription::new("max@test.com".into(), vec![NewsTopic::GENERAL]),
+            None,
+        )
+        .await?;
+
+        let booking_id = sqlx::query_scalar!(r#"SELECT id FROM event_bookings"#)
+            .fetch_one(&pool)
+            .await?;
+        audit::record(
+            &pool,
+            "admin@sv-eutingen.de",
+            "DELETE /api/admin/events/booking/{id}",
+            "/api/admin/events/booking/1",
+            &audit::AuditRecord::booking(booking_id),
+        )
+        .await?;
+        audit::record(
+            &pool,
+            "admin@sv-eutingen.de",
+            "GET /api/admin/gdpr/export",
+            "/api/admin/gdpr/export",
+            &audit::AuditRecord::subject("max@test.com"),
+        )
+        .await?;
+
+        // a member stored before the IBANs of members have been encrypted
+        db::import_members(
+            &pool,
+            &[MemberImport {
+                salutation: "Herr".into(),
+                first_name: "Max".into(),
+                last_name: "Mustermann".into(),
+                street: "Teststr 1".into(),
+                zipcode: "12345".into(),
+                city: "Teststadt".into(),
+                email: "max@test.com".into(),
+                phone: "0123".into(),
+                birthday: "1980-01-01".into(),
+                membership_type: MembershipType::AdultActive,
+                start_date: "2020-01-01".parse()?,
+                end_date: None,
+                account_owner: "Max Mustermann".into(),
+                iban: "DE89370400440532013000".into(),
+                family_members: vec![],
+            }],
+        )
+        .await?;
+        assert_eq!(
+            encryption::encrypt_plain_ibans(&pool, &mock_iban_secrets()).await?,
+            2
+        );
+
+        let export = export(&pool, " Max@Test.com ").await?;
+        assert_eq!(export.email, "max@test.com");
+        assert_eq!(export.event_subscribers.len(), 1);
+        assert_eq!(export.event_bookings.len(), 1);
+        assert_eq!(export.event_bookings[0]["event_name"], "Test Event");
+        assert_eq!(export.event_bookings[0]["iban"], "DE02 **** 2051");
+        assert_eq!(export.news_subscribers.len(), 1);
+        assert_eq!(export.members.len(), 1);
+        assert_eq!(export.members[0]["iban"], "DE89 **** 3000");
+        assert_eq!(export.audit_log.len(), 2);
+        assert_eq!(export.audit_log[0]["booking_id"], booking_id);
+
+        let (filename, bytes) = export_as_zip(&export)?;
+        assert!(filename.starts_with("personal-data-max_test_com-"));
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+        assert_eq!(archive.len(), 7);
+        let mut json = String::new();
+        archive
+            .by_name("event_bookings.json")?
+            .read_to_string(&mut json)?;
+        assert_eq!(
+            serde_json::from_str::<Value>(&json)?,
+            Value::Array(export.event_bookings)
+        );
+
+        assert!(export_is_invalid(&pool, "").await);
+        assert!(export_is_invalid(&pool, "no email").await);
+
+        Ok(())
+    }
+
+    async fn export_is_invalid(pool: &PgPool, email: &str) -> bool {
+        export(pool, email)
+            .await
+            .unwrap_err()
+            .downcast_ref::<ValidationError>()
+            .is_some()
+    }
+
+    #[sqlx::test]
+    async fn test_erase(pool: PgPool) -> Result<()> {
+        let event_id = create_event(&pool).await?;
+        book(&pool, event_id, "max@test.com").await?;
+
+        // the event is still running
+        let err = erase(&pool, "max@test.com").await.unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+
+        // only the IBAN of the second event has been used for a SEPA direct debit
+        let other_event_id = create_event(&pool).await?;
+        book(&pool, other_event_id, "max@test.com").await?;
+        sqlx::query!(
+            r#"UPDATE event_bookings SET sepa_exported_at = NOW() WHERE event_id = $1"#,
+            other_event_id.get_ref()
+        )
+        .execute(&pool)
+        .await?;
+        archive(&pool, event_id, 0).await?;
+        archive(&pool, other_event_id, 0).await?;
+
+        db::subscribe(
+            &pool,
+            NewsSubscription::new("max@test.com".into(), vec![NewsTopic::GENERAL]),
+            None,
+        )
+        .await?;
+
+        audit::record(
+            &pool,
+            "admin@sv-eutingen.de",
+            "GET /api/admin/gdpr/export",
+            "/api/admin/gdpr/export",
+            &audit::AuditRecord::subject("max@test.com"),
+        )
+        .await?;
+
+        // a free text and a checkbox with a surcharge
+        sqlx::query!(
+            r#"WITH f AS (
+                INSERT INTO event_custom_fields (name, type, price_relevant, price)
+                VALUES ('Allergien', 'Text', false, NULL), ('Bus', 'Checkbox', false, 5)
+                RETURNING id
+            )
+            INSERT INTO event_custom_field_assignments (event_id, custom_field_id, position)
+            SELECT e.id, f.id, ROW_NUMBER() OVER (PARTITION BY e.id ORDER BY f.id)
+            FROM f, events e"#
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query!(r#"UPDATE event_bookings SET custom_values = '{Nüsse,true}'"#)
+            .execute(&pool)
+            .await?;
+
+        let result = erase(&pool, "MAX@test.com").await?;
+        assert_eq!(
+            result,
+            ErasureResult {
+                erasure_id: result.erasure_id.clone(),
+                anonymized_subscribers: 1,
+                anonymized_bookings: 2,
+                deleted_news_subscribers: 1,
+                redacted_audit_log_entries: 1,
+                ..Default::default()
+            }
+        );
+
+        let bookings = sqlx::query!(
+            r#"SELECT b.event_id, b.iban, b.comment, b.custom_values, s.first_name, s.email
+            FROM event_bookings b
+            JOIN event_subscribers s ON s.id = b.subscriber_id ORDER BY b.event_id"#
+        )
+        .fetch_all(&pool)
+        .await?;
+        assert_eq!(bookings.len(), 2);
+        assert!(bookings.iter().all(|b| b.first_name == db::ANONYMIZED_NAME
+            && b.email.is_empty()
+            && b.comment.is_none()
+            && b.custom_values == ["", "true"]));
+        assert_eq!(bookings[0].iban, None);
+        assert_eq!(bookings[1].iban.as_deref(), Some("DE02120300000000202051"));
+
+        let export = export(&pool, "max@test.com").await?;
+        assert!(export.event_subscribers.is_empty() && export.news_subscribers.is_empty());
+        assert!(export.audit_log.is_empty());
+
+        // everything else of the audit log stays append-only
+        assert!(
+            sqlx::query("UPDATE audit_log SET actor = 'someone@sv-eutingen.de'")
+                .execute(&pool)
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_anonymize_archived_participants(pool: PgPool) -> Result<()> {
+        let old_event_id = create_event(&pool).await?;
+        let recent_event_id = create_event(&pool).await?;
+        book(&pool, old_event_id, "old@test.com").await?;
+        book(&pool, old_event_id, "both@test.com").await?;
+        book(&pool, recent_event_id, "both@test.com").await?;
+        archive(&pool, old_event_id, 800).await?;
+        archive(&pool, recent_event_id, 10).await?;
+        let canceled_event_id = create_event(&pool).await?;
+        book(&pool, canceled_event_id, "canceled@test.com").await?;
+        sqlx::query!(
+            r#"UPDATE events SET lifecycle_status = 'Canceled', closed = $2 WHERE id = $1"#,
+            canceled_event_id.get_ref(),
+            Utc::now() - Duration::days(800)
+        )
+        .execute(&pool)
+        .await?;
+
+        let mut secrets = MockSecretProvider::new();
+        secrets
+            .expect_get()
+            .returning(|_| Box::pin(async { Ok("".into()) }));
+
+        // the subscriber of both events is kept until the recent event is due
+        assert_eq!(anonymize_archived_participants(&pool, &secrets).await?, 2);
+        for email in ["old@test.com", "canceled@test.com"] {
+            assert!(export(&pool, email).await?.event_subscribers.is_empty());
+        }
+        assert_eq!(
+            export(&pool, "both@test.com")
+                .await?
+                .event_subscribers
+                .len(),
+            1
+        );
+        assert_eq!(anonymize_archived_participants(&pool, &secrets).await?, 0);
+
+        Ok(())
+    }
+}
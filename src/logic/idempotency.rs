@@ -0,0 +1,200 @@
+//! Replay of responses for requests with an `Idempotency-Key` header.
+//
+// The first request with a key claims it and stores its response when it is
+// done. A retry with the same key gets the stored response instead of running
+// the request again, e.g. after a double click or a retry of the Lambda
+// runtime. Keys are scoped to the caller and the endpoint and are kept for 24
+// hours. The stored response bodies are encrypted with the key of the IBANs, as
+// they may contain personal data.
+
+use crate::db;
+use crate::logic::encryption::IbanCipher;
+use crate::logic::secrets::SecretProvider;
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+
+/// Hours a response is kept for a retry.
+const KEY_VALIDITY_HOURS: i64 = 24;
+
+/// Minutes after which a request which has not finished is treated as aborted,
+/// so a retry with the same key can run it again.
+const IN_PROGRESS_TIMEOUT_MINUTES: i64 = 5;
+
+/// Response which has been stored for a key.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StoredResponse {
+    pub(crate) status_code: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum IdempotencyState {
+    /// The key has been claimed, the request has to be run.
+    New,
+    /// The first request with the key has not finished yet.
+    InProgress,
+    /// The key has been used for another request payload.
+    Mismatch,
+    Completed(StoredResponse),
+}
+
+/// Claim the key of the caller (`actor`) for the endpoint (`scope`).
+pub(crate) async fn begin(
+    pool: &PgPool,
+    secrets: &dyn SecretProvider,
+    actor: &str,
+    scope: &str,
+    key: &str,
+    request_hash: &str,
+) -> Result<IdempotencyState> {
+    let now = Utc::now();
+    if db::claim_idempotency_key(
+        pool,
+        actor,
+        scope,
+        key,
+        request_hash,
+        now - Duration::hours(KEY_VALIDITY_HOURS),
+        now - Duration::minutes(IN_PROGRESS_TIMEOUT_MINUTES),
+    )
+    .await?
+    {
+        return Ok(IdempotencyState::New);
+    }
+
+    // the key has expired in the meantime, the client has to retry
+    let Some(row) = db::get_idempotency_key(pool, actor, scope, key).await? else {
+        return Ok(IdempotencyState::InProgress);
+    };
+    if row.request_hash != request_hash {
+        return Ok(IdempotencyState::Mismatch);
+    }
+    match (row.status_code, row.headers, row.body) {
+        (Some(status_code), headers, body) => Ok(IdempotencyState::Completed(StoredResponse {
+            status_code: status_code as u16,
+            headers: headers
+                .map(serde_json::from_value)
+                .transpose()?
+                .unwrap_or_default(),
+            body: match body {
+                Some(body) => IbanCipher::load(secrets).await?.decrypt_bytes(&body)?,
+                None => Vec::new(),
+            },
+        })),
+        _ => Ok(IdempotencyState::InProgress),
+    }
+}
+
+/// Store the response of a claimed key, the body is stored encrypted.
+pub(crate) async fn complete(
+    pool: &PgPool,
+    secrets: &dyn SecretProvider,
+    actor: &str,
+    scope: &str,
+    key: &str,
+    response: &StoredResponse,
+) -> Result<()> {
+    let body = IbanCipher::load(secrets)
+        .await?
+        .encrypt_bytes(&response.body)?;
+    db::complete_idempotency_key(
+        pool,
+        actor,
+        scope,
+        key,
+        response.status_code as i32,
+        &serde_json::to_value(&response.headers)?,
+        &body,
+    )
+    .await
+}
+
+/// Release a claimed key without a response, e.g. after a server error, so the
+/// request can be retried.
+pub(crate) async fn release(pool: &PgPool, actor: &str, scope: &str, key: &str) -> Result<()> {
+    db::delete_idempotency_key(pool, actor, scope, key).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::mock_iban_secrets;
+    use pretty_assertions::assert_eq;
+
+    const ACTOR: &str = "192.0.2.1";
+
+    #[sqlx::test]
+    async fn test_replay(pool: PgPool) -> Result<()> {
+        let secrets = mock_iban_secrets();
+        let scope = "POST /api/events/booking";
+        assert_eq!(
+            begin(&pool, &secrets, ACTOR, scope, "key-1", "hash").await?,
+            IdempotencyState::New
+        );
+        assert_eq!(
+            begin(&pool, &secrets, ACTOR, scope, "key-1", "hash").await?,
+            IdempotencyState::InProgress
+        );
+        assert_eq!(
+            begin(&pool, &secrets, ACTOR, scope, "key-1", "other").await?,
+            IdempotencyState::Mismatch
+        );
+
+        let response = StoredResponse {
+            status_code: 200,
+            headers: vec![("content-type".into(), "application/json".into())],
+            body: br#"{"success":true}"#.to_vec(),
+        };
+        complete(&pool, &secrets, ACTOR, scope, "key-1", &response).await?;
+        assert_eq!(
+            begin(&pool, &secrets, ACTOR, scope, "key-1", "hash").await?,
+            IdempotencyState::Completed(response)
+        );
+        // the body is not stored in plain text
+        let stored = db::get_idempotency_key(&pool, ACTOR, scope, "key-1")
+            .await?
+            .unwrap();
+        assert!(
+            !stored
+                .body
+                .unwrap()
+                .windows(7)
+                .any(|part| part == b"success")
+        );
+
+        // keys are separated per endpoint
+        assert_eq!(
+            begin(
+                &pool,
+                &secrets,
+                ACTOR,
+                "POST /api/events/prebooking/{hash}/iban",
+                "key-1",
+                "hash"
+            )
+            .await?,
+            IdempotencyState::New
+        );
+
+        // and per caller
+        assert_eq!(
+            begin(&pool, &secrets, "192.0.2.2", scope, "key-1", "hash").await?,
+            IdempotencyState::New
+        );
+
+        // a released key can be used again
+        assert_eq!(
+            begin(&pool, &secrets, ACTOR, scope, "key-2", "hash").await?,
+            IdempotencyState::New
+        );
+        release(&pool, ACTOR, scope, "key-2").await?;
+        assert_eq!(
+            begin(&pool, &secrets, ACTOR, scope, "key-2", "hash").await?,
+            IdempotencyState::New
+        );
+
+        Ok(())
+    }
+}
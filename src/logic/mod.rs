@@ -1,11 +1,23 @@
+pub(crate) mod api_keys;
+pub(crate) mod audit;
 pub(crate) mod banking;
 pub(crate) mod calendar;
+pub(crate) mod campaigns;
+pub(crate) mod captcha;
 pub(crate) mod contact;
 pub(crate) mod csv;
+pub(crate) mod encryption;
 pub(crate) mod events;
 pub(crate) mod export;
+pub(crate) mod gdpr;
+pub(crate) mod idempotency;
 pub(crate) mod membership;
 pub(crate) mod news;
+pub(crate) mod rate_limits;
+pub(crate) mod roles;
 pub(crate) mod secrets;
+pub(crate) mod sessions;
 pub(crate) mod tasks;
 pub(crate) mod template;
+pub(crate) mod tokens;
+pub(crate) mod trainers;
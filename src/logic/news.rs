@@ -1,25 +1,83 @@
+use super::secrets::{SecretKey, SecretProvider};
+use super::tokens::{self, TokenPurpose};
 use crate::db;
-use crate::email::EmailSender;
-use crate::models::{NewsSubscription, NewsTopic};
-use anyhow::Result;
-use lettre::message::SinglePart;
+use crate::email::{EmailSender, ListUnsubscribe, ListUnsubscribePost};
+use crate::error::ValidationError;
+use crate::models::{
+    EmailType, NewsPreferences, NewsSubscription, NewsTopic, NewsTopicDefinition, NewsTopicUpdate,
+};
+use anyhow::{Result, bail};
+use chrono::{Duration, Utc};
 use lettre::message::header::{self, ContentType};
+use lettre::message::{MessageBuilder, SinglePart};
 use sqlx::PgPool;
 use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 
-pub(crate)const UNSUBSCRIBE_MESSAGE: &str = "Solltest Du an unserem E-Mail-Service kein Interesse mehr haben, kannst Du dich hier wieder abmelden:
-https://www.sv-eutingen.de/newsletter";
+/// Number of days a subscriber has to confirm the subscription.
+const CONFIRMATION_VALIDITY_DAYS: i64 = 7;
 
+/// Number of days the personal unsubscribe and preference links of a newsletter mail are valid.
+const PREFERENCES_VALIDITY_DAYS: i64 = 365;
+
+/// Store a pending subscription and ask the subscriber for confirmation (double opt-in).
 pub(crate) async fn subscribe(
     pool: &PgPool,
     subscription: NewsSubscription,
+    request_ip: Option<IpAddr>,
+    secrets: &dyn SecretProvider,
+    email_sender: &impl EmailSender,
+) -> Result<()> {
+    let topics = get_topic_definitions(pool, &subscription.topics).await?;
+    let request_ip = request_ip.map(|ip| ip.to_string());
+    let id = db::insert_subscription_request(pool, &subscription, request_ip.as_deref()).await?;
+
+    let secret = secrets.get(SecretKey::NewsTokenSecret).await?;
+    let token = tokens::sign(
+        TokenPurpose::NewsConfirmation,
+        &id.to_string(),
+        Duration::days(CONFIRMATION_VALIDITY_DAYS),
+        &secret,
+    )?;
+
+    send_confirmation_request_mail(&subscription, &topics, &token, email_sender).await?;
+
+    Ok(())
+}
+
+/// Confirm a pending subscription and store the consent of the subscriber.
+pub(crate) async fn confirm(
+    pool: &PgPool,
+    token: &str,
+    consent_ip: Option<IpAddr>,
+    secrets: &dyn SecretProvider,
     email_sender: &impl EmailSender,
 ) -> Result<()> {
-    subscribe_to_news(pool, subscription, true, email_sender).await?;
+    let secret = secrets.get(SecretKey::NewsTokenSecret).await?;
+    let id = tokens::verify(TokenPurpose::NewsConfirmation, token, &secret)?
+        .parse::<i32>()
+        .map_err(|_| ValidationError::new("Der Link ist ungültig oder abgelaufen."))?;
+
+    let consent_ip = consent_ip.map(|ip| ip.to_string());
+    let valid_since = Utc::now() - Duration::days(CONFIRMATION_VALIDITY_DAYS);
+    let subscription =
+        db::confirm_subscription_request(pool, id, valid_since, consent_ip.as_deref())
+            .await?
+            .ok_or_else(|| ValidationError::new("Der Link ist ungültig oder abgelaufen."))?;
+
+    let topics = get_topic_definitions(pool, &subscription.topics).await?;
+    let preferences_token = preferences_token(&subscription.email, secrets).await?;
+    send_mail(subscription, &topics, &preferences_token, email_sender).await?;
 
     Ok(())
 }
 
+/// Delete all pending subscriptions which have not been confirmed in time.
+pub(crate) async fn expire_subscription_requests(pool: &PgPool) -> Result<u64> {
+    let created_before = Utc::now() - Duration::days(CONFIRMATION_VALIDITY_DAYS);
+    db::delete_expired_subscription_requests(pool, created_before).await
+}
+
 pub(crate) async fn unsubscribe(pool: &PgPool, subscription: NewsSubscription) -> Result<()> {
     db::unsubscribe(pool, &subscription).await?;
 
@@ -44,88 +102,334 @@ pub(crate) async fn get_subscriptions(
     Ok(result)
 }
 
+/// Unsubscribe from all topics via the personal link of a newsletter mail (RFC 8058 one-click).
+pub(crate) async fn unsubscribe_by_token(
+    pool: &PgPool,
+    token: &str,
+    secrets: &dyn SecretProvider,
+) -> Result<()> {
+    let email = verify_preferences_token(token, secrets).await?;
+    db::replace_subscription(pool, &NewsSubscription::new(email, vec![]), None).await?;
+
+    Ok(())
+}
+
+/// Get the current topics of the subscriber identified by the given token.
+pub(crate) async fn get_preferences(
+    pool: &PgPool,
+    token: &str,
+    secrets: &dyn SecretProvider,
+) -> Result<NewsSubscription> {
+    let email = verify_preferences_token(token, secrets).await?;
+    let subscription = db::get_subscription(pool, &email)
+        .await?
+        .unwrap_or_else(|| NewsSubscription::new(email, vec![]));
+
+    Ok(subscription)
+}
+
+/// Set the topics of the subscriber identified by the given token.
+pub(crate) async fn update_preferences(
+    pool: &PgPool,
+    token: &str,
+    preferences: NewsPreferences,
+    consent_ip: Option<IpAddr>,
+    secrets: &dyn SecretProvider,
+) -> Result<()> {
+    let email = verify_preferences_token(token, secrets).await?;
+    get_topic_definitions(pool, &preferences.topics).await?;
+    let consent_ip = consent_ip.map(|ip| ip.to_string());
+    db::replace_subscription(
+        pool,
+        &NewsSubscription::new(email, preferences.topics),
+        consent_ip.as_deref(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn get_topics(pool: &PgPool) -> Result<Vec<NewsTopicDefinition>> {
+    db::get_topics(pool).await
+}
+
+/// Resolve the given topics. Fails with a [`ValidationError`] if a topic does not exist.
+pub(crate) async fn get_topic_definitions(
+    pool: &PgPool,
+    topics: &[NewsTopic],
+) -> Result<Vec<NewsTopicDefinition>> {
+    let definitions = db::get_topics(pool).await?;
+    topics
+        .iter()
+        .map(|topic| {
+            definitions
+                .iter()
+                .find(|definition| &definition.name == topic)
+                .cloned()
+                .ok_or_else(|| ValidationError::new(format!("Unbekanntes Thema: {}", topic)).into())
+        })
+        .collect()
+}
+
+pub(crate) async fn create_topic(pool: &PgPool, topic: NewsTopicDefinition) -> Result<()> {
+    if topic.name.as_str().parse::<NewsTopic>().is_err() {
+        bail!(ValidationError::new(
+            "Der Name eines Themas darf nur aus Buchstaben, Ziffern und Unterstrichen bestehen."
+        ));
+    }
+    if topic.display_name.trim().is_empty() {
+        bail!(ValidationError::new("Bitte gib einen Anzeigenamen ein."));
+    }
+    if !db::insert_topic(pool, &topic).await? {
+        bail!(ValidationError::new(format!(
+            "Das Thema {} existiert bereits.",
+            topic.name
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) async fn update_topic(
+    pool: &PgPool,
+    name: NewsTopic,
+    update: NewsTopicUpdate,
+) -> Result<()> {
+    if update.display_name.trim().is_empty() {
+        bail!(ValidationError::new("Bitte gib einen Anzeigenamen ein."));
+    }
+    let topic = NewsTopicDefinition {
+        name,
+        display_name: update.display_name,
+        email_type: update.email_type,
+    };
+    if !db::update_topic(pool, &topic).await? {
+        bail!(ValidationError::new(format!(
+            "Unbekanntes Thema: {}",
+            topic.name
+        )));
+    }
+    Ok(())
+}
+
+/// Delete a topic including all of its subscriptions.
+pub(crate) async fn delete_topic(pool: &PgPool, name: NewsTopic) -> Result<()> {
+    match db::delete_topic(pool, &name).await {
+        Ok(true) => Ok(()),
+        Ok(false) => bail!(ValidationError::new(format!("Unbekanntes Thema: {}", name))),
+        Err(e)
+            if e.downcast_ref::<sqlx::Error>()
+                .and_then(|e| e.as_database_error())
+                .is_some_and(|e| e.is_foreign_key_violation()) =>
+        {
+            bail!(ValidationError::new(format!(
+                "Das Thema {} wird noch von Kampagnen verwendet.",
+                name
+            )))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Sign the token used in the personal unsubscribe and preference links of a subscriber.
+pub(crate) async fn preferences_token(email: &str, secrets: &dyn SecretProvider) -> Result<String> {
+    let secret = secrets.get(SecretKey::NewsTokenSecret).await?;
+    tokens::sign(
+        TokenPurpose::NewsPreferences,
+        email,
+        Duration::days(PREFERENCES_VALIDITY_DAYS),
+        &secret,
+    )
+}
+
+async fn verify_preferences_token(token: &str, secrets: &dyn SecretProvider) -> Result<String> {
+    let secret = secrets.get(SecretKey::NewsTokenSecret).await?;
+    Ok(tokens::verify(
+        TokenPurpose::NewsPreferences,
+        token,
+        &secret,
+    )?)
+}
+
+/// Add the `List-Unsubscribe` and `List-Unsubscribe-Post` (RFC 8058) headers
+/// for the subscriber of the given token.
+pub(crate) fn with_unsubscribe_headers(builder: MessageBuilder, token: &str) -> MessageBuilder {
+    builder
+        .header(ListUnsubscribe(format!(
+            "https://backend.sv-eutingen.de/api/news/unsubscribe/{}",
+            token
+        )))
+        .header(ListUnsubscribePost)
+}
+
+/// Footer of newsletter mails with the personal link to the preference center.
+pub(crate) fn unsubscribe_message(token: &str) -> String {
+    format!(
+        "Solltest Du an unserem E-Mail-Service kein Interesse mehr haben, kannst Du Dich hier wieder abmelden oder Deine Themen ändern:
+https://www.sv-eutingen.de/newsletter?token={}",
+        token
+    )
+}
+
+/// Subscribe without double opt-in, e.g. for bookings and membership applications.
 pub(in crate::logic) async fn subscribe_to_news(
     pool: &PgPool,
     subscription: NewsSubscription,
-    send_email: bool,
-    email_sender: &impl EmailSender,
 ) -> Result<()> {
-    let subscription = db::subscribe(pool, subscription).await?;
-    if send_email {
-        send_mail(subscription, email_sender).await?
-    }
+    db::subscribe(pool, subscription, None).await?;
 
     Ok(())
 }
 
-async fn send_mail(subscription: NewsSubscription, email_sender: &impl EmailSender) -> Result<()> {
-    let primary_news_topic;
-    let multiple_topics;
-    if subscription.topics.len() == 1 {
-        primary_news_topic = *subscription.topics.first().unwrap();
-        multiple_topics = None
-    } else {
-        primary_news_topic = NewsTopic::General;
-        multiple_topics = Some(
-            subscription
-                .topics
-                .iter()
-                .map(|topic| topic.display_name())
-                .collect::<Vec<&str>>()
-                .join(", "),
-        );
+/// Returns the topic whose email account is used to send mails regarding the given topics
+/// and a comma separated list of all topics if there are multiple.
+fn primary_topic(topics: &[NewsTopicDefinition]) -> (NewsTopicDefinition, Option<String>) {
+    match topics {
+        [topic] => (topic.clone(), None),
+        _ => (
+            NewsTopicDefinition {
+                name: NewsTopic::GENERAL,
+                display_name: "Allgemein".into(),
+                email_type: EmailType::Info,
+            },
+            Some(
+                topics
+                    .iter()
+                    .map(|topic| topic.display_name.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", "),
+            ),
+        ),
     }
+}
+
+async fn send_confirmation_request_mail(
+    subscription: &NewsSubscription,
+    topics: &[NewsTopicDefinition],
+    token: &str,
+    email_sender: &impl EmailSender,
+) -> Result<()> {
+    let (primary_news_topic, multiple_topics) = primary_topic(topics);
     let subject;
     let topic;
-    let kind;
     let regards;
-    match primary_news_topic {
-        NewsTopic::General => {
-            subject = "[Infos@SVE] Bestätigung Newsletter Anmeldung";
-            topic = "News rund um den SVE";
-            if let Some(multiple_topics) = multiple_topics {
-                kind = format!(" zu folgenden Themen: {}", multiple_topics);
-            } else {
-                kind = ", sobald es etwas neues gibt".into();
-            }
-            regards = "SV Eutingen";
-        }
-        NewsTopic::Events => {
-            subject = "[Events@SVE] Bestätigung Event-News Anmeldung";
-            topic = "unseren Events";
-            kind = ", sobald neue Events online sind".into();
+    match primary_news_topic.name.as_str() {
+        "Events" => {
+            subject = "[Events@SVE] Bitte bestätige Deine Event-News Anmeldung";
+            topic = "unseren Events".into();
             regards = "Team Events@SVE";
         }
-        NewsTopic::Fitness => {
-            subject = "[Fitness@SVE] Bestätigung Fitness-News Anmeldung";
-            topic = "unseren Fitnesskursen";
-            kind = ", sobald neue Kurse online sind".into();
+        "Fitness" => {
+            subject = "[Fitness@SVE] Bitte bestätige Deine Fitness-News Anmeldung";
+            topic = "unseren Fitnesskursen".into();
             regards = "Team Fitness@SVE";
         }
+        name => {
+            subject = "[Infos@SVE] Bitte bestätige Deine Newsletter Anmeldung";
+            topic = match multiple_topics {
+                Some(multiple_topics) => format!("News zu folgenden Themen: {}", multiple_topics),
+                None if name == NewsTopic::GENERAL.as_str() => "News rund um den SVE".into(),
+                None => format!("News zum Thema {}", primary_news_topic.display_name),
+            };
+            regards = "SV Eutingen";
+        }
     };
 
     let email_account = email_sender
-        .get_account_by_type(primary_news_topic.into())
+        .get_account_by_type(primary_news_topic.email_type)
         .await?;
     let message = crate::email::new_message_builder(&email_account)?
         .header(header::MIME_VERSION_1_0)
         .header(ContentType::TEXT_PLAIN)
         .to(subscription.email.parse()?)
-        .bcc(crate::email::mailbox(&email_account)?)
         .subject(subject)
         .singlepart(SinglePart::plain(format!(
             "Lieber Interessent/In,
 
 vielen Dank für Dein Interesse an {}.
 
+Bitte bestätige Deine Anmeldung über folgenden Link:
+https://www.sv-eutingen.de/newsletter?confirm={}
+
+Der Link ist {} Tage gültig. Falls Du Dich nicht selbst angemeldet hast, kannst Du diese E-Mail einfach ignorieren.
+
+Herzliche Grüße
+{}",
+            topic, token, CONFIRMATION_VALIDITY_DAYS, regards
+        )))?;
+
+    email_sender.send_message(&email_account, message).await?;
+
+    Ok(())
+}
+
+async fn send_mail(
+    subscription: NewsSubscription,
+    topics: &[NewsTopicDefinition],
+    preferences_token: &str,
+    email_sender: &impl EmailSender,
+) -> Result<()> {
+    let (primary_news_topic, multiple_topics) = primary_topic(topics);
+    let subject;
+    let topic: String;
+    let kind;
+    let regards;
+    match primary_news_topic.name.as_str() {
+        "Events" => {
+            subject = "[Events@SVE] Bestätigung Event-News Anmeldung";
+            topic = "unseren Events".into();
+            kind = ", sobald neue Events online sind".into();
+            regards = "Team Events@SVE";
+        }
+        "Fitness" => {
+            subject = "[Fitness@SVE] Bestätigung Fitness-News Anmeldung";
+            topic = "unseren Fitnesskursen".into();
+            kind = ", sobald neue Kurse online sind".into();
+            regards = "Team Fitness@SVE";
+        }
+        name => {
+            subject = "[Infos@SVE] Bestätigung Newsletter Anmeldung";
+            if name == NewsTopic::GENERAL.as_str() || multiple_topics.is_some() {
+                topic = "News rund um den SVE".into();
+            } else {
+                topic = format!("News zum Thema {}", primary_news_topic.display_name);
+            }
+            if let Some(multiple_topics) = multiple_topics {
+                kind = format!(" zu folgenden Themen: {}", multiple_topics);
+            } else {
+                kind = ", sobald es etwas neues gibt".into();
+            }
+            regards = "SV Eutingen";
+        }
+    };
+
+    let email_account = email_sender
+        .get_account_by_type(primary_news_topic.email_type)
+        .await?;
+    let message = with_unsubscribe_headers(
+        crate::email::new_message_builder(&email_account)?,
+        preferences_token,
+    )
+    .header(header::MIME_VERSION_1_0)
+    .header(ContentType::TEXT_PLAIN)
+    .to(subscription.email.parse()?)
+    .bcc(crate::email::mailbox(&email_account)?)
+    .subject(subject)
+    .singlepart(SinglePart::plain(format!(
+        "Lieber Interessent/In,
+
+vielen Dank für Dein Interesse an {}.
+
 Ab sofort erhältst Du automatisch eine E-Mail{}.
 
 {}
 
 Herzliche Grüße
 {}",
-            topic, kind, UNSUBSCRIBE_MESSAGE, regards
-        )))?;
+        topic,
+        kind,
+        unsubscribe_message(preferences_token),
+        regards
+    )))?;
 
     email_sender.send_message(&email_account, message).await?;
 
@@ -135,48 +439,318 @@ Herzliche Grüße
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::email::MockEmailSender;
-    use crate::models::{EmailType, NewsSubscription, NewsTopic};
-    use crate::test_utils::mock_email_sender_capturing;
+    use crate::models::{EmailType, NewsSubscription, NewsTopic, NewsTopicDefinition};
+    use crate::test_utils::{mock_email_sender_capturing, mock_secrets, noop_mock};
     use anyhow::Result;
     use pretty_assertions::assert_eq;
     use sqlx::PgPool;
 
+    fn extract_confirmation_token(message: &lettre::Message) -> String {
+        let body = String::from_utf8_lossy(&message.formatted())
+            .replace("=\r\n", "")
+            .replace("=3D", "=");
+        let start = body
+            .find("?confirm=")
+            .expect("Confirmation link should be present")
+            + 9;
+        body[start..]
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '-' || *c == '_' || *c == '.')
+            .collect()
+    }
+
     #[sqlx::test]
     async fn test_subscribe_sends_confirmation_email(pool: PgPool) -> Result<()> {
         let (mock_sender, captured) =
             mock_email_sender_capturing(vec![(EmailType::Fitness, "fitness@sv-eutingen.de")]);
+        let secrets = mock_secrets(vec![(SecretKey::NewsTokenSecret, "secret")]);
 
         let subscription =
-            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::Fitness]);
-        subscribe(&pool, subscription, &mock_sender).await?;
+            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::FITNESS]);
+        subscribe(&pool, subscription, None, &secrets, &mock_sender).await?;
 
-        let messages = captured.lock().unwrap();
         assert!(
-            !messages.is_empty(),
+            !captured.lock().unwrap().is_empty(),
             "Confirmation email should have been sent"
         );
 
+        // the subscription is pending until confirmed
+        assert!(db::get_subscriptions(&pool).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_confirm_activates_subscription(pool: PgPool) -> Result<()> {
+        let (mock_sender, captured) =
+            mock_email_sender_capturing(vec![(EmailType::Events, "events@sv-eutingen.de")]);
+        let secrets = mock_secrets(vec![(SecretKey::NewsTokenSecret, "secret")]);
+
+        let subscription =
+            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::EVENTS]);
+        subscribe(
+            &pool,
+            subscription,
+            Some("10.0.0.1".parse()?),
+            &secrets,
+            &mock_sender,
+        )
+        .await?;
+        let token = extract_confirmation_token(captured.lock().unwrap().first().unwrap());
+
+        let (mock_sender, captured) =
+            mock_email_sender_capturing(vec![(EmailType::Events, "events@sv-eutingen.de")]);
+        confirm(
+            &pool,
+            &token,
+            Some("10.0.0.2".parse()?),
+            &secrets,
+            &mock_sender,
+        )
+        .await?;
+        assert_eq!(captured.lock().unwrap().len(), 1);
+
+        let subscriptions = db::get_subscriptions(&pool).await?;
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].topics, vec![NewsTopic::EVENTS]);
+
+        let consent_ip = sqlx::query_scalar!(
+            r#"SELECT consent_ip FROM news_subscribers WHERE email = $1 AND consent_at IS NOT NULL"#,
+            "test@example.com"
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(consent_ip.as_deref(), Some("10.0.0.2"));
+
+        // the token can only be used once
+        let result = confirm(&pool, &token, None, &secrets, &noop_mock()).await;
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_confirm_rejects_invalid_token(pool: PgPool) -> Result<()> {
+        let secrets = mock_secrets(vec![(SecretKey::NewsTokenSecret, "secret")]);
+        let result = confirm(&pool, "invalid", None, &secrets, &noop_mock()).await;
+        assert!(
+            result
+                .unwrap_err()
+                .downcast_ref::<ValidationError>()
+                .is_some()
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_welcome_mail_contains_unsubscribe_headers(pool: PgPool) -> Result<()> {
+        let (mock_sender, captured) =
+            mock_email_sender_capturing(vec![(EmailType::Fitness, "fitness@sv-eutingen.de")]);
+
+        let subscription =
+            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::FITNESS]);
+        let result = db::subscribe(&pool, subscription, None).await?;
+        let topics = get_topic_definitions(&pool, &result.topics).await?;
+        send_mail(result, &topics, "personal-token", &mock_sender).await?;
+
+        let messages = captured.lock().unwrap();
+        let message = messages.first().expect("Email should have been sent");
+        let formatted = String::from_utf8_lossy(&message.formatted())
+            .replace("=\r\n", "")
+            .replace("=3D", "=");
+        assert!(formatted.contains(
+            "List-Unsubscribe: <https://backend.sv-eutingen.de/api/news/unsubscribe/personal-token>"
+        ));
+        assert!(formatted.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        assert!(formatted.contains("https://www.sv-eutingen.de/newsletter?token=personal-token"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_unsubscribe_by_token(pool: PgPool) -> Result<()> {
+        let secrets = mock_secrets(vec![(SecretKey::NewsTokenSecret, "secret")]);
+        db::subscribe(
+            &pool,
+            NewsSubscription::new(
+                "test@example.com".to_string(),
+                vec![NewsTopic::GENERAL, NewsTopic::FITNESS],
+            ),
+            None,
+        )
+        .await?;
+
+        let token = preferences_token("test@example.com", &secrets).await?;
+        unsubscribe_by_token(&pool, &token, &secrets).await?;
+
+        assert!(db::get_subscriptions(&pool).await?.is_empty());
+
+        // a confirmation token can not be used to unsubscribe
+        let confirmation_token = tokens::sign(
+            TokenPurpose::NewsConfirmation,
+            "test@example.com",
+            Duration::days(CONFIRMATION_VALIDITY_DAYS),
+            "secret",
+        )?;
+        let result = unsubscribe_by_token(&pool, &confirmation_token, &secrets).await;
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_update_preferences(pool: PgPool) -> Result<()> {
+        let secrets = mock_secrets(vec![(SecretKey::NewsTokenSecret, "secret")]);
+        db::subscribe(
+            &pool,
+            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::GENERAL]),
+            None,
+        )
+        .await?;
+        let token = preferences_token("test@example.com", &secrets).await?;
+
+        let preferences = get_preferences(&pool, &token, &secrets).await?;
+        assert_eq!(preferences.email, "test@example.com");
+        assert_eq!(preferences.topics, vec![NewsTopic::GENERAL]);
+
+        update_preferences(
+            &pool,
+            &token,
+            NewsPreferences {
+                topics: vec![NewsTopic::EVENTS, NewsTopic::FITNESS],
+            },
+            None,
+            &secrets,
+        )
+        .await?;
+        let preferences = get_preferences(&pool, &token, &secrets).await?;
+        assert_eq!(
+            preferences.topics,
+            vec![NewsTopic::EVENTS, NewsTopic::FITNESS]
+        );
+
+        update_preferences(
+            &pool,
+            &token,
+            NewsPreferences { topics: vec![] },
+            None,
+            &secrets,
+        )
+        .await?;
+        let preferences = get_preferences(&pool, &token, &secrets).await?;
+        assert!(preferences.topics.is_empty());
+        assert!(db::get_subscriptions(&pool).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_custom_topic(pool: PgPool) -> Result<()> {
+        let jugend = NewsTopic::new("Jugend");
+        create_topic(
+            &pool,
+            NewsTopicDefinition {
+                name: jugend.clone(),
+                display_name: "Jugendfußball".into(),
+                email_type: EmailType::Jugendturnier,
+            },
+        )
+        .await?;
+        assert_eq!(
+            get_topics(&pool)
+                .await?
+                .into_iter()
+                .map(|topic| topic.name)
+                .collect::<Vec<_>>(),
+            vec![
+                NewsTopic::GENERAL,
+                NewsTopic::EVENTS,
+                NewsTopic::FITNESS,
+                jugend.clone()
+            ]
+        );
+
+        let (mock_sender, captured) = mock_email_sender_capturing(vec![(
+            EmailType::Jugendturnier,
+            "jugendturnier@sv-eutingen.de",
+        )]);
+        let secrets = mock_secrets(vec![(SecretKey::NewsTokenSecret, "secret")]);
+        subscribe(
+            &pool,
+            NewsSubscription::new("test@example.com".to_string(), vec![jugend.clone()]),
+            None,
+            &secrets,
+            &mock_sender,
+        )
+        .await?;
+        let body = String::from_utf8_lossy(&captured.lock().unwrap()[0].formatted())
+            .replace("=\r\n", "")
+            .replace("=C3=9F", "ß");
+        assert!(body.contains("News zum Thema Jugendfußball"));
+
+        let result = subscribe(
+            &pool,
+            NewsSubscription::new(
+                "test@example.com".to_string(),
+                vec![NewsTopic::new("Unknown")],
+            ),
+            None,
+            &secrets,
+            &noop_mock(),
+        )
+        .await;
+        assert!(
+            result
+                .unwrap_err()
+                .downcast_ref::<ValidationError>()
+                .is_some()
+        );
+
+        db::subscribe(
+            &pool,
+            NewsSubscription::new(
+                "test@example.com".to_string(),
+                vec![NewsTopic::GENERAL, jugend.clone()],
+            ),
+            None,
+        )
+        .await?;
+        delete_topic(&pool, jugend).await?;
+        let subscriptions = db::get_subscriptions(&pool).await?;
+        assert_eq!(subscriptions[0].topics, vec![NewsTopic::GENERAL]);
+
         Ok(())
     }
 
     #[sqlx::test]
-    async fn test_subscribe_to_news_no_email(pool: PgPool) -> Result<()> {
-        let mut mock_sender = MockEmailSender::new();
+    async fn test_expire_subscription_requests(pool: PgPool) -> Result<()> {
+        let subscription =
+            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::GENERAL]);
+        let id = db::insert_subscription_request(&pool, &subscription, None).await?;
+        db::insert_subscription_request(&pool, &subscription, None).await?;
+        sqlx::query!(
+            r#"UPDATE news_subscription_requests SET created = NOW() - INTERVAL '8 days' WHERE id = $1"#,
+            id
+        )
+        .execute(&pool)
+        .await?;
 
-        mock_sender
-            .expect_get_account_by_type()
-            .times(0)
-            .returning(|_| Box::pin(async { unreachable!() }));
+        assert_eq!(expire_subscription_requests(&pool).await?, 1);
+        assert_eq!(expire_subscription_requests(&pool).await?, 0);
 
-        mock_sender
-            .expect_send_message()
-            .times(0)
-            .returning(|_, _| Box::pin(async { unreachable!() }));
+        Ok(())
+    }
 
+    #[sqlx::test]
+    async fn test_subscribe_to_news_without_confirmation(pool: PgPool) -> Result<()> {
         let subscription =
-            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::General]);
-        subscribe_to_news(&pool, subscription, false, &mock_sender).await?;
+            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::GENERAL]);
+        subscribe_to_news(&pool, subscription).await?;
+
+        let subscriptions = db::get_subscriptions(&pool).await?;
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].topics, vec![NewsTopic::GENERAL]);
 
         Ok(())
     }
@@ -187,9 +761,10 @@ mod tests {
             mock_email_sender_capturing(vec![(EmailType::Events, "events@sv-eutingen.de")]);
 
         let subscription =
-            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::Events]);
-        let result = db::subscribe(&pool, subscription).await?;
-        send_mail(result, &mock_sender).await?;
+            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::EVENTS]);
+        let result = db::subscribe(&pool, subscription, None).await?;
+        let topics = get_topic_definitions(&pool, &result.topics).await?;
+        send_mail(result, &topics, "token", &mock_sender).await?;
 
         let messages = captured.lock().unwrap();
         let message = messages.first().expect("Email should have been sent");
@@ -220,10 +795,11 @@ mod tests {
 
         let subscription = NewsSubscription::new(
             "test@example.com".to_string(),
-            vec![NewsTopic::Events, NewsTopic::Fitness],
+            vec![NewsTopic::EVENTS, NewsTopic::FITNESS],
         );
-        let result = db::subscribe(&pool, subscription).await?;
-        send_mail(result, &mock_sender).await?;
+        let result = db::subscribe(&pool, subscription, None).await?;
+        let topics = get_topic_definitions(&pool, &result.topics).await?;
+        send_mail(result, &topics, "token", &mock_sender).await?;
 
         let messages = captured.lock().unwrap();
         let message = messages.first().expect("Email should have been sent");
@@ -254,10 +830,10 @@ mod tests {
     #[sqlx::test]
     async fn test_unsubscribe(pool: PgPool) -> Result<()> {
         let subscription =
-            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::Fitness]);
-        db::subscribe(&pool, subscription).await?;
+            NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::FITNESS]);
+        db::subscribe(&pool, subscription, None).await?;
 
-        let unsub = NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::Fitness]);
+        let unsub = NewsSubscription::new("test@example.com".to_string(), vec![NewsTopic::FITNESS]);
         unsubscribe(&pool, unsub).await?;
 
         let subs = db::get_subscriptions(&pool).await?;
@@ -273,33 +849,36 @@ mod tests {
     async fn test_get_subscriptions(pool: PgPool) -> Result<()> {
         db::subscribe(
             &pool,
-            NewsSubscription::new("user1@example.com".to_string(), vec![NewsTopic::Fitness]),
+            NewsSubscription::new("user1@example.com".to_string(), vec![NewsTopic::FITNESS]),
+            None,
         )
         .await?;
         db::subscribe(
             &pool,
-            NewsSubscription::new("user2@example.com".to_string(), vec![NewsTopic::Events]),
+            NewsSubscription::new("user2@example.com".to_string(), vec![NewsTopic::EVENTS]),
+            None,
         )
         .await?;
         db::subscribe(
             &pool,
             NewsSubscription::new(
                 "user3@example.com".to_string(),
-                vec![NewsTopic::Fitness, NewsTopic::Events],
+                vec![NewsTopic::FITNESS, NewsTopic::EVENTS],
             ),
+            None,
         )
         .await?;
 
         let result = get_subscriptions(&pool).await?;
 
-        let fitness_emails = result.get(&NewsTopic::Fitness);
+        let fitness_emails = result.get(&NewsTopic::FITNESS);
         assert!(fitness_emails.is_some());
         let fitness_emails = fitness_emails.unwrap();
         assert!(fitness_emails.contains("user1@example.com"));
         assert!(fitness_emails.contains("user3@example.com"));
         assert_eq!(fitness_emails.len(), 2);
 
-        let events_emails = result.get(&NewsTopic::Events);
+        let events_emails = result.get(&NewsTopic::EVENTS);
         assert!(events_emails.is_some());
         let events_emails = events_emails.unwrap();
         assert!(events_emails.contains("user2@example.com"));
@@ -0,0 +1,186 @@
+//! Sliding-window rate limits of the public endpoints.
+//
+// Every request is recorded per client key (IP address or email address) in
+// Postgres, so the limits hold across all Lambda instances. A request is
+// rejected if the key already has the maximum number of hits in the window.
+
+use crate::db;
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Limits of a group of endpoints. A missing maximum disables the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub(crate) struct RateLimit {
+    pub(crate) window_seconds: i64,
+    pub(crate) per_ip: Option<i64>,
+    pub(crate) per_email: Option<i64>,
+}
+
+impl RateLimit {
+    const fn new(window_seconds: i64, per_ip: Option<i64>, per_email: Option<i64>) -> Self {
+        Self {
+            window_seconds,
+            per_ip,
+            per_email,
+        }
+    }
+}
+
+/// Names of the limited endpoint groups and their default limits per hour.
+const DEFAULT_LIMITS: &[(&str, RateLimit)] = &[
+    ("booking", RateLimit::new(3600, Some(30), Some(10))),
+    ("prebooking", RateLimit::new(3600, Some(30), None)),
+    ("confirmation", RateLimit::new(3600, Some(10), None)),
+    ("contact", RateLimit::new(3600, Some(10), Some(5))),
+    ("news", RateLimit::new(3600, Some(10), Some(3))),
+    ("membership", RateLimit::new(3600, Some(10), Some(3))),
+    ("trainer_login", RateLimit::new(3600, Some(10), Some(3))),
+];
+
+/// Configured limits of all endpoint groups.
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimits(HashMap<String, RateLimit>);
+
+impl RateLimits {
+    /// Build the limits from the JSON object of the `RATE_LIMITS` secret, e.g.
+    /// `{"booking": {"window_seconds": 600, "per_ip": 10, "per_email": 3}}`.
+    /// Groups which are not configured keep their default limits.
+    pub(crate) fn from_config(config: &str) -> Result<Self> {
+        let mut limits: HashMap<String, RateLimit> = DEFAULT_LIMITS
+            .iter()
+            .map(|(bucket, limit)| (bucket.to_string(), *limit))
+            .collect();
+        if !config.trim().is_empty() {
+            let configured: HashMap<String, RateLimit> =
+                serde_json::from_str(config).context("Invalid rate limit configuration")?;
+            limits.extend(configured);
+        }
+        Ok(Self(limits))
+    }
+
+    pub(crate) fn get(&self, bucket: &str) -> Option<RateLimit> {
+        self.0.get(bucket).copied()
+    }
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self::from_config("").expect("default rate limits are valid")
+    }
+}
+
+/// Result of a rate limit check.
+#[derive(Debug, PartialEq)]
+pub(crate) enum RateLimitDecision {
+    Allowed,
+    /// The limit has been reached, the client should retry after the given number of seconds.
+    Limited {
+        retry_after: i64,
+    },
+}
+
+/// Check and record a request of the given client to the endpoint group. The
+/// email address is only checked if the group has a limit per email address.
+pub(crate) async fn check(
+    pool: &PgPool,
+    bucket: &str,
+    limit: &RateLimit,
+    ip: Option<IpAddr>,
+    email: Option<&str>,
+) -> Result<RateLimitDecision> {
+    let mut keys = Vec::new();
+    if let (Some(max_hits), Some(ip)) = (limit.per_ip, ip) {
+        keys.push((format!("ip:{}", ip), max_hits));
+    }
+    if let (Some(max_hits), Some(email)) = (limit.per_email, email) {
+        let email = email.trim().to_lowercase();
+        if !email.is_empty() {
+            keys.push((format!("email:{}", email), max_hits));
+        }
+    }
+
+    let now = Utc::now();
+    let window = Duration::seconds(limit.window_seconds);
+    for (key, max_hits) in keys {
+        if let Some(oldest) =
+            db::register_rate_limit_hit(pool, bucket, &key, max_hits, now - window).await?
+        {
+            // the next request is allowed as soon as the oldest hit leaves the window
+            return Ok(RateLimitDecision::Limited {
+                retry_after: (oldest + window - now).num_seconds().max(1),
+            });
+        }
+    }
+
+    Ok(RateLimitDecision::Allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_from_config() {
+        let limits = RateLimits::from_config(
+            r#"{"booking": {"window_seconds": 600, "per_ip": 5, "per_email": null}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            limits.get("booking"),
+            Some(RateLimit::new(600, Some(5), None))
+        );
+        assert_eq!(
+            limits.get("contact"),
+            Some(RateLimit::new(3600, Some(10), Some(5)))
+        );
+        assert_eq!(limits.get("unknown"), None);
+        assert!(RateLimits::from_config("{").is_err());
+    }
+
+    #[sqlx::test]
+    async fn test_check(pool: PgPool) -> Result<()> {
+        let limit = RateLimit::new(3600, Some(3), Some(2));
+        let ip: Option<IpAddr> = Some("192.0.2.1".parse()?);
+        let limited = |decision: RateLimitDecision| matches!(decision, RateLimitDecision::Limited { retry_after } if (3590..=3600).contains(&retry_after));
+
+        assert_eq!(
+            check(&pool, "contact", &limit, ip, Some("max@example.com")).await?,
+            RateLimitDecision::Allowed
+        );
+        assert_eq!(
+            check(&pool, "contact", &limit, ip, Some(" Max@Example.com")).await?,
+            RateLimitDecision::Allowed
+        );
+        // the email address is limited
+        assert!(limited(
+            check(&pool, "contact", &limit, ip, Some("max@example.com")).await?
+        ));
+        // the rejected request above counts for the IP address
+        assert!(limited(
+            check(&pool, "contact", &limit, ip, Some("erika@example.com")).await?
+        ));
+        // other endpoint groups and clients are not affected
+        assert_eq!(
+            check(&pool, "news", &limit, ip, Some("max@example.com")).await?,
+            RateLimitDecision::Allowed
+        );
+        assert_eq!(
+            check(&pool, "contact", &limit, Some("192.0.2.2".parse()?), None).await?,
+            RateLimitDecision::Allowed
+        );
+
+        // hits outside of the window are not counted anymore
+        let expired = RateLimit::new(0, Some(3), Some(2));
+        assert_eq!(
+            check(&pool, "contact", &expired, ip, Some("max@example.com")).await?,
+            RateLimitDecision::Allowed
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use crate::db;
+use crate::error::ValidationError;
+use crate::models::{Role, RoleAssignment};
+use anyhow::{Result, bail};
+use sqlx::PgPool;
+
+pub(crate) async fn get_roles(pool: &PgPool, email: &str) -> Result<Vec<Role>> {
+    db::get_roles(pool, &email.to_lowercase()).await
+}
+
+pub(crate) async fn get_role_assignments(pool: &PgPool) -> Result<Vec<RoleAssignment>> {
+    db::get_role_assignments(pool).await
+}
+
+/// Replace the roles of a user. An empty list revokes all access of the user.
+pub(crate) async fn set_roles(pool: &PgPool, assignment: RoleAssignment) -> Result<()> {
+    let email = assignment.email.trim().to_lowercase();
+    if email.parse::<lettre::Address>().is_err() {
+        bail!(ValidationError::new(
+            "Bitte gib eine gültige E-Mail-Adresse ein."
+        ));
+    }
+    if !db::set_roles(pool, &email, &assignment.roles).await? {
+        bail!(ValidationError::new(
+            "Es muss mindestens ein Benutzer die Rolle admin behalten."
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[sqlx::test]
+    async fn test_set_roles(pool: PgPool) -> Result<()> {
+        set_roles(
+            &pool,
+            RoleAssignment {
+                email: "Kassier@sv-eutingen.de".into(),
+                roles: vec![Role::Treasurer, Role::TrainerReadonly],
+            },
+        )
+        .await?;
+        assert_eq!(
+            get_roles(&pool, "kassier@sv-eutingen.de").await?,
+            vec![Role::Treasurer, Role::TrainerReadonly]
+        );
+        assert!(
+            get_role_assignments(&pool)
+                .await?
+                .contains(&RoleAssignment {
+                    email: "kassier@sv-eutingen.de".into(),
+                    roles: vec![Role::Treasurer, Role::TrainerReadonly],
+                })
+        );
+
+        set_roles(
+            &pool,
+            RoleAssignment {
+                email: "kassier@sv-eutingen.de".into(),
+                roles: vec![],
+            },
+        )
+        .await?;
+        assert!(get_roles(&pool, "kassier@sv-eutingen.de").await?.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_set_roles_keeps_last_admin(pool: PgPool) -> Result<()> {
+        let result = set_roles(
+            &pool,
+            RoleAssignment {
+                email: "info@sv-eutingen.de".into(),
+                roles: vec![Role::Treasurer],
+            },
+        )
+        .await;
+        assert!(
+            result
+                .unwrap_err()
+                .downcast_ref::<ValidationError>()
+                .is_some()
+        );
+        assert_eq!(
+            get_roles(&pool, "info@sv-eutingen.de").await?,
+            vec![Role::Admin]
+        );
+
+        Ok(())
+    }
+}
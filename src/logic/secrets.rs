@@ -25,6 +25,7 @@ const CONSOLIDATED_SECRET_NAME: &str = "sve-backend";
 pub(crate) enum SecretKey {
     DatabaseUrl,
     TaskApiKey,
+    CaptchaProvider,
     CaptchaSecret,
     GoogleCreds,
     EmailAccounts,
@@ -32,6 +33,11 @@ pub(crate) enum SecretKey {
     SepaCreditorIban,
     SepaCreditorId,
     SessionSecret,
+    PreviousSessionSecrets,
+    NewsTokenSecret,
+    RateLimits,
+    ParticipantRetentionDays,
+    IbanEncryptionKey,
 }
 
 impl SecretKey {
@@ -39,6 +45,7 @@ impl SecretKey {
         match self {
             Self::DatabaseUrl => "DATABASE_URL",
             Self::TaskApiKey => "TASK_API_KEY",
+            Self::CaptchaProvider => "CAPTCHA_PROVIDER",
             Self::CaptchaSecret => "CAPTCHA_SECRET",
             Self::GoogleCreds => "GOOGLE_CREDS",
             Self::EmailAccounts => "EMAIL_ACCOUNTS",
@@ -46,6 +53,11 @@ impl SecretKey {
             Self::SepaCreditorIban => "SEPA_CREDITOR_IBAN",
             Self::SepaCreditorId => "SEPA_CREDITOR_ID",
             Self::SessionSecret => "SESSION_SECRET",
+            Self::PreviousSessionSecrets => "PREVIOUS_SESSION_SECRETS",
+            Self::NewsTokenSecret => "NEWS_TOKEN_SECRET",
+            Self::RateLimits => "RATE_LIMITS",
+            Self::ParticipantRetentionDays => "PARTICIPANT_RETENTION_DAYS",
+            Self::IbanEncryptionKey => "IBAN_ENCRYPTION_KEY",
         }
     }
 }
@@ -70,8 +82,12 @@ impl SecretProvider for EnvSecretProvider {
 struct Secrets {
     #[serde(rename = "DATABASE_URL")]
     database_url: String,
-    #[serde(rename = "TASK_API_KEY")]
+    /// Former single key of the task endpoints, imported as API key with all scopes.
+    #[serde(default, rename = "TASK_API_KEY")]
     task_api_key: String,
+    /// Either `hcaptcha` (default) or `altcha` for the self-hosted proof of work.
+    #[serde(default, rename = "CAPTCHA_PROVIDER")]
+    captcha_provider: String,
     #[serde(rename = "CAPTCHA_SECRET")]
     captcha_secret: String,
     #[serde(rename = "GOOGLE_CREDS")]
@@ -86,6 +102,21 @@ struct Secrets {
     sepa_creditor_id: String,
     #[serde(rename = "SESSION_SECRET")]
     session_secret: String,
+    /// Comma separated list of former session secrets which are still accepted
+    /// for verification after the `SESSION_SECRET` has been rotated.
+    #[serde(default, rename = "PREVIOUS_SESSION_SECRETS")]
+    previous_session_secrets: String,
+    #[serde(rename = "NEWS_TOKEN_SECRET")]
+    news_token_secret: String,
+    /// JSON object which overrides the default limits of the public endpoints.
+    #[serde(default, rename = "RATE_LIMITS")]
+    rate_limits: String,
+    /// Days after closing an event until the participants of the archived event are anonymized.
+    #[serde(default, rename = "PARTICIPANT_RETENTION_DAYS")]
+    participant_retention_days: String,
+    /// Base64 encoded AES-256 key which encrypts the IBANs of the event bookings.
+    #[serde(rename = "IBAN_ENCRYPTION_KEY")]
+    iban_encryption_key: String,
 }
 
 /// Reads the consolidated AWS secret, falling back to environment variables.
@@ -159,6 +190,7 @@ impl SecretProvider for ConsolidatedAwsSecretProvider {
         match key {
             SecretKey::DatabaseUrl => Ok(secrets.database_url),
             SecretKey::TaskApiKey => Ok(secrets.task_api_key),
+            SecretKey::CaptchaProvider => Ok(secrets.captcha_provider),
             SecretKey::CaptchaSecret => Ok(secrets.captcha_secret),
             SecretKey::GoogleCreds => Ok(secrets.google_creds),
             SecretKey::EmailAccounts => Ok(secrets.email_accounts),
@@ -166,6 +198,11 @@ impl SecretProvider for ConsolidatedAwsSecretProvider {
             SecretKey::SepaCreditorIban => Ok(secrets.sepa_creditor_iban),
             SecretKey::SepaCreditorId => Ok(secrets.sepa_creditor_id),
             SecretKey::SessionSecret => Ok(secrets.session_secret),
+            SecretKey::PreviousSessionSecrets => Ok(secrets.previous_session_secrets),
+            SecretKey::NewsTokenSecret => Ok(secrets.news_token_secret),
+            SecretKey::RateLimits => Ok(secrets.rate_limits),
+            SecretKey::ParticipantRetentionDays => Ok(secrets.participant_retention_days),
+            SecretKey::IbanEncryptionKey => Ok(secrets.iban_encryption_key),
         }
     }
 }
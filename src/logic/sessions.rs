@@ -0,0 +1,161 @@
+//! Server-side refresh tokens of the admin sessions.
+//
+// A login creates a new token family. Every refresh rotates the token: the
+// presented token is marked as rotated and a new one of the same family is
+// issued. Presenting a rotated token again means it has been stolen, so the
+// whole family is revoked.
+
+use super::tokens::hash;
+use crate::db;
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Number of days a refresh token is valid if it is not used.
+const REFRESH_TOKEN_VALIDITY_DAYS: i64 = 30;
+
+/// User of a session whose refresh token has been rotated successfully.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SessionUser {
+    pub(crate) email: String,
+    pub(crate) hd: Option<String>,
+}
+
+/// Start a new session and return its refresh token.
+pub(crate) async fn create_session(pool: &PgPool, email: &str, hd: Option<&str>) -> Result<String> {
+    let mut conn = pool.acquire().await?;
+    let family = Uuid::new_v4().to_string();
+    issue_refresh_token(&mut conn, &family, email, hd).await
+}
+
+/// Rotate the given refresh token. Returns the user of the session and the new
+/// refresh token, or `None` if the token is unknown, expired or revoked.
+pub(crate) async fn refresh(
+    pool: &PgPool,
+    refresh_token: &str,
+) -> Result<Option<(SessionUser, String)>> {
+    let mut tx = pool.begin().await?;
+
+    let Some(session) = db::lock_session(&mut tx, &hash(refresh_token)).await? else {
+        return Ok(None);
+    };
+    if session.revoked_at.is_some() {
+        return Ok(None);
+    }
+    if session.rotated_at.is_some() {
+        warn!(
+            "Reuse of a rotated refresh token of {} detected, revoking the session",
+            session.email
+        );
+        db::revoke_session_family(&mut tx, &session.family).await?;
+        tx.commit().await?;
+        return Ok(None);
+    }
+    if session.expires_at < Utc::now() {
+        return Ok(None);
+    }
+
+    db::mark_session_rotated(&mut tx, session.id).await?;
+    let refresh_token = issue_refresh_token(
+        &mut tx,
+        &session.family,
+        &session.email,
+        session.hd.as_deref(),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Some((
+        SessionUser {
+            email: session.email,
+            hd: session.hd,
+        },
+        refresh_token,
+    )))
+}
+
+/// End the session of the given refresh token.
+pub(crate) async fn logout(pool: &PgPool, refresh_token: &str) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    if let Some(session) = db::lock_session(&mut tx, &hash(refresh_token)).await? {
+        db::revoke_session_family(&mut tx, &session.family).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// End all sessions of the given user.
+pub(crate) async fn revoke_all(pool: &PgPool, email: &str) -> Result<u64> {
+    db::revoke_sessions_of_user(pool, &email.trim().to_lowercase()).await
+}
+
+pub(crate) async fn delete_expired_sessions(pool: &PgPool) -> Result<u64> {
+    db::delete_expired_sessions(pool).await
+}
+
+async fn issue_refresh_token(
+    conn: &mut sqlx::PgConnection,
+    family: &str,
+    email: &str,
+    hd: Option<&str>,
+) -> Result<String> {
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    db::insert_session(
+        conn,
+        family,
+        &email.to_lowercase(),
+        hd,
+        &hash(&token),
+        Utc::now() + Duration::days(REFRESH_TOKEN_VALIDITY_DAYS),
+    )
+    .await?;
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[sqlx::test]
+    async fn test_refresh_rotates_token(pool: PgPool) -> Result<()> {
+        let token = create_session(&pool, "Info@sv-eutingen.de", Some("sv-eutingen.de")).await?;
+
+        let (user, rotated) = refresh(&pool, &token).await?.unwrap();
+        assert_eq!(
+            user,
+            SessionUser {
+                email: "info@sv-eutingen.de".into(),
+                hd: Some("sv-eutingen.de".into()),
+            }
+        );
+        assert_ne!(token, rotated);
+
+        // reusing the old token revokes the whole session
+        assert!(refresh(&pool, &token).await?.is_none());
+        assert!(refresh(&pool, &rotated).await?.is_none());
+        assert!(refresh(&pool, "unknown").await?.is_none());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_logout_and_revoke_all(pool: PgPool) -> Result<()> {
+        let laptop = create_session(&pool, "info@sv-eutingen.de", None).await?;
+        let phone = create_session(&pool, "info@sv-eutingen.de", None).await?;
+        let other = create_session(&pool, "fitness@sv-eutingen.de", None).await?;
+
+        logout(&pool, &laptop).await?;
+        assert!(refresh(&pool, &laptop).await?.is_none());
+
+        let (_, phone) = refresh(&pool, &phone).await?.unwrap();
+        assert_eq!(revoke_all(&pool, "info@sv-eutingen.de").await?, 1);
+        assert!(refresh(&pool, &phone).await?.is_none());
+        assert!(refresh(&pool, &other).await?.is_some());
+
+        Ok(())
+    }
+}
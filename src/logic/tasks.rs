@@ -2,7 +2,8 @@ use anyhow::Result;
 use sqlx::PgPool;
 use tracing::{error, info};
 
-use super::{calendar, events};
+use super::secrets::SecretProvider;
+use super::{calendar, campaigns, encryption, events, gdpr, news, sessions};
 use crate::calendar::CalendarClient;
 use crate::email::EmailSender;
 use crate::models::{EventId, EventType};
@@ -39,6 +40,84 @@ pub(crate) async fn close_finished_events(pool: &PgPool, email_sender: &impl Ema
     }
 }
 
+/// Publish and unpublish the events whose scheduled time has come.
+pub(crate) async fn publish_scheduled_events(pool: &PgPool) {
+    match events::publish_scheduled_events(pool).await {
+        Ok(count) if count > 0 => {
+            info!("{count} scheduled events have been published or unpublished.")
+        }
+        Ok(_) => (),
+        Err(e) => error!("Error while publishing scheduled events: {}", e),
+    }
+}
+
+/// Expire the waiting list offers which have not been accepted in time and offer the spots to the next bookings.
+pub(crate) async fn expire_waiting_list_offers(
+    pool: &PgPool,
+    secrets: &dyn SecretProvider,
+    email_sender: &impl EmailSender,
+) {
+    match events::expire_waiting_list_offers(pool, secrets, email_sender).await {
+        Ok(count) if count > 0 => info!("{count} waiting list offers have been expired."),
+        Ok(_) => (),
+        Err(e) => error!("Error while expiring waiting list offers: {}", e),
+    }
+}
+
+/// Delete all newsletter subscriptions which have not been confirmed in time.
+pub(crate) async fn expire_news_subscriptions(pool: &PgPool) {
+    match news::expire_subscription_requests(pool).await {
+        Ok(count) if count > 0 => info!("{count} unconfirmed news subscriptions has been expired."),
+        Ok(_) => (),
+        Err(e) => error!("Error while expiring news subscriptions: {}", e),
+    }
+}
+
+/// Delete all admin sessions whose refresh token has expired.
+pub(crate) async fn delete_expired_sessions(pool: &PgPool) {
+    match sessions::delete_expired_sessions(pool).await {
+        Ok(count) if count > 0 => info!("{count} expired sessions have been deleted."),
+        Ok(_) => (),
+        Err(e) => error!("Error while deleting expired sessions: {}", e),
+    }
+}
+
+/// Anonymize the participants of archived events whose retention period is over.
+pub(crate) async fn anonymize_archived_participants(pool: &PgPool, secrets: &dyn SecretProvider) {
+    match gdpr::anonymize_archived_participants(pool, secrets).await {
+        Ok(count) if count > 0 => {
+            info!("{count} participants of archived events have been anonymized.")
+        }
+        Ok(_) => (),
+        Err(e) => error!(
+            "Error while anonymizing participants of archived events: {}",
+            e
+        ),
+    }
+}
+
+/// Encrypt the IBANs of the bookings which have been stored in plain text.
+pub(crate) async fn encrypt_ibans(pool: &PgPool, secrets: &dyn SecretProvider) {
+    match encryption::encrypt_plain_ibans(pool, secrets).await {
+        Ok(count) if count > 0 => info!("{count} IBANs have been encrypted."),
+        Ok(_) => (),
+        Err(e) => error!("Error while encrypting the IBANs: {}", e),
+    }
+}
+
+/// Start all due newsletter campaigns and send the next batch of mails.
+pub(crate) async fn send_news_campaigns(
+    pool: &PgPool,
+    secrets: &dyn SecretProvider,
+    email_sender: &impl EmailSender,
+) {
+    match campaigns::send_due_campaigns(pool, secrets, email_sender).await {
+        Ok(count) if count > 0 => info!("{count} newsletter mails has been send successfully."),
+        Ok(_) => (),
+        Err(e) => error!("Error while sending newsletter campaigns: {}", e),
+    }
+}
+
 /// send a reminder email for all bookings which are due with payment
 pub(crate) async fn send_payment_reminders(
     pool: &PgPool,
@@ -1,7 +1,7 @@
 use super::events;
 use crate::models::{
-    Event, EventBooking, EventSubscription, MembershipApplication, PaymentMethod, ToEuro,
-    UnpaidEventBooking,
+    CanceledEventBooking, Event, EventBooking, EventSubscription, MembershipApplication,
+    PaymentMethod, ToEuro, UnpaidEventBooking,
 };
 use anyhow::{Result, anyhow};
 use chrono::{DateTime, Locale, Utc};
@@ -76,25 +76,104 @@ impl<'a> BookingTemplateData<'a> {
 }
 
 #[derive(Serialize)]
-struct ScheduleChangeTemplateData<'a> {
+struct EventChangeTemplateData<'a> {
     firstname: &'a str,
     name: &'a str,
     removed_dates: String,
+    added_dates: String,
+    /// Changed location, duration and price, one line per change.
+    changes: String,
     new_dates: String,
 }
 
-impl<'a> ScheduleChangeTemplateData<'a> {
-    fn new(booking: &'a EventBooking, event: &'a Event, removed_dates: &[DateTime<Utc>]) -> Self {
+impl<'a> EventChangeTemplateData<'a> {
+    fn new(
+        booking: &'a EventBooking,
+        event: &'a Event,
+        previous: &'a Event,
+        changes: Vec<String>,
+    ) -> Self {
         let now = Utc::now();
         Self {
             firstname: booking.first_name.trim(),
             name: event.name.trim(),
-            removed_dates: format_dates(removed_dates),
+            removed_dates: format_and_filter_dates(&previous.dates, |d| !event.dates.contains(d)),
+            added_dates: format_and_filter_dates(&event.dates, |d| {
+                d > &&now && !previous.dates.contains(d)
+            }),
+            changes: changes.join("\n"),
             new_dates: format_and_filter_dates(&event.dates, |d| d > &&now),
         }
     }
 }
 
+#[derive(Serialize)]
+struct WaitingListOfferTemplateData<'a> {
+    firstname: &'a str,
+    name: &'a str,
+    link: String,
+    deadline: String,
+}
+
+impl<'a> WaitingListOfferTemplateData<'a> {
+    fn new(
+        booking: &'a EventBooking,
+        event: &'a Event,
+        link: String,
+        expires_at: &DateTime<Utc>,
+    ) -> Self {
+        Self {
+            firstname: booking.first_name.trim(),
+            name: event.name.trim(),
+            link,
+            deadline: expires_at
+                .with_timezone(&chrono_tz::Europe::Berlin)
+                .format_localized("%A, %-d. %B %Y, %H:%M Uhr", Locale::de_DE)
+                .to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EventCancellationTemplateData<'a> {
+    firstname: &'a str,
+    name: &'a str,
+    reason: &'a str,
+    /// The price to refund, only set for paid bookings.
+    refund: Option<String>,
+}
+
+impl<'a> EventCancellationTemplateData<'a> {
+    fn new(booking: &'a CanceledEventBooking, event: &'a Event, reason: &'a str) -> Self {
+        Self {
+            firstname: booking.first_name.trim(),
+            name: event.name.trim(),
+            reason: reason.trim(),
+            refund: booking.paid.then(|| booking.price.to_euro()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct NewsCampaignTemplateData<'a> {
+    email: &'a str,
+    topic: &'a str,
+    preferences_link: String,
+}
+
+impl<'a> NewsCampaignTemplateData<'a> {
+    fn new(email: &'a str, topic: &'a str, preferences_token: &str) -> Self {
+        Self {
+            email,
+            topic,
+            preferences_link: format!(
+                "https://www.sv-eutingen.de/newsletter?token={}",
+                preferences_token
+            ),
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct ReminderTemplateData<'a> {
     firstname: &'a str,
@@ -315,15 +394,89 @@ pub(crate) fn render_payment_reminder<'a>(
     )
 }
 
-pub(crate) fn render_schedule_change<'a>(
+/// Returns `None` if nothing relevant to the participant has changed.
+pub(crate) fn render_event_change<'a>(
+    template: &str,
+    booking: &'a EventBooking,
+    event: &'a Event,
+    previous: &'a Event,
+) -> Result<Option<String>> {
+    let changes = event_changes(booking, event, previous);
+    if previous.dates == event.dates && changes.is_empty() {
+        return Ok(None);
+    }
+    render(
+        template,
+        EventChangeTemplateData::new(booking, event, previous, changes),
+        None,
+    )
+    .map(Some)
+}
+
+/// Describe the changes of location, duration and price relevant to the participant.
+fn event_changes(booking: &EventBooking, event: &Event, previous: &Event) -> Vec<String> {
+    let mut changes = Vec::new();
+    if event.location != previous.location {
+        changes.push(format!(
+            "- Ort: {} (bisher: {})",
+            event.location.trim(),
+            previous.location.trim()
+        ));
+    }
+    if event.duration_in_minutes != previous.duration_in_minutes {
+        changes.push(format!(
+            "- Dauer: {} Minuten (bisher: {} Minuten)",
+            event.duration_in_minutes, previous.duration_in_minutes
+        ));
+    }
+    let price = booking.total_price(event);
+    let previous_price = booking.total_price(previous);
+    if price != previous_price {
+        changes.push(format!(
+            "- Preis: {} (bisher: {})",
+            price.to_euro(),
+            previous_price.to_euro()
+        ));
+    }
+    changes
+}
+
+pub(crate) fn render_waiting_list_offer<'a>(
     template: &str,
     booking: &'a EventBooking,
     event: &'a Event,
-    removed_dates: &[DateTime<Utc>],
+    link: String,
+    expires_at: &DateTime<Utc>,
+) -> Result<String> {
+    render(
+        template,
+        WaitingListOfferTemplateData::new(booking, event, link, expires_at),
+        None,
+    )
+}
+
+pub(crate) fn render_event_cancellation<'a>(
+    template: &str,
+    booking: &'a CanceledEventBooking,
+    event: &'a Event,
+    reason: &'a str,
+) -> Result<String> {
+    render(
+        template,
+        EventCancellationTemplateData::new(booking, event, reason),
+        None,
+    )
+}
+
+pub(crate) fn render_news_campaign(
+    template: &str,
+    email: &str,
+    topic: &str,
+    preferences_token: &str,
 ) -> Result<String> {
     render(
         template,
-        ScheduleChangeTemplateData::new(booking, event, removed_dates),
+        NewsCampaignTemplateData::new(email, topic, preferences_token),
         None,
     )
 }
@@ -358,7 +511,9 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::{EventType, LifecycleStatus, PaymentMethod};
+    use crate::models::{
+        EventCustomField, EventCustomFieldType, EventType, LifecycleStatus, PaymentMethod,
+    };
     use bigdecimal::{BigDecimal, FromPrimitive};
     use chrono::{DateTime, Duration, Locale, TimeZone, Utc};
     use pretty_assertions::assert_eq;
@@ -432,6 +587,11 @@ IBAN: DE16 6429 1010 0034 4696 05",
             false,
             Vec::new(),
             PaymentMethod::BankTransfer,
+            0,
+            None,
+            None,
+            None,
+            None,
         );
 
         assert_eq!(
@@ -623,6 +783,11 @@ Platz als Wartelistennachrücker gebucht.{{/if}}";
             false,
             Vec::new(),
             PaymentMethod::BankTransfer,
+            0,
+            None,
+            None,
+            None,
+            None,
         );
         let event_subscription = EventSubscription::new(
             0,
@@ -702,6 +867,11 @@ Platz als Wartelistennachrücker gebucht.{{/if}}";
             false,
             Vec::new(),
             PaymentMethod::BankTransfer,
+            0,
+            None,
+            None,
+            None,
+            None,
         );
         let event_subscription = EventSubscription::new(
             0,
@@ -734,7 +904,7 @@ Platz als Wartelistennachrücker gebucht.{{/if}}";
     }
 
     #[test]
-    fn test_render_schedule_change() {
+    fn test_render_event_change() {
         let date_1 = Utc.with_ymd_and_hms(2022, 3, 7, 19, 00, 00).unwrap();
         let date_2 = Utc.with_ymd_and_hms(2022, 3, 8, 19, 00, 00).unwrap();
         let date_3 = Utc.with_ymd_and_hms(2100, 3, 9, 19, 00, 00).unwrap();
@@ -770,6 +940,11 @@ Platz als Wartelistennachrücker gebucht.{{/if}}";
             false,
             Vec::new(),
             PaymentMethod::BankTransfer,
+            0,
+            None,
+            None,
+            None,
+            None,
         );
         let booking = EventBooking::new(
             0,
@@ -785,27 +960,107 @@ Platz als Wartelistennachrücker gebucht.{{/if}}";
             Vec::new(),
             None,
         );
+        let mut previous = event.clone();
+        previous.dates = vec![date_1, date_2, date_3, date_4, date_5];
+        previous.location = String::from("Sporthalle Eutingen");
+        previous.price_member = BigDecimal::from_i8(4).unwrap();
 
-        assert_eq!(
-            render_schedule_change(
-                r#"{{firstname}} / {{name}}
+        let template = r#"{{firstname}} / {{name}}
 <-->
 {{removed_dates}}
 <-->
-{{new_dates}}"#,
-                &booking,
-                &event,
-                &[date_4],
-            )
-            .unwrap(),
+{{added_dates}}
+<-->
+{{changes}}
+<-->
+{{new_dates}}"#;
+
+        assert_eq!(
+            render_event_change(template, &booking, &event, &previous)
+                .unwrap()
+                .unwrap(),
             r#"Max / FitForFun
 <-->
 - Mi., 10. März 2100, 19:00 Uhr
 <-->
+- Fr., 12. März 2100, 19:00 Uhr
+<-->
+- Ort: Turn- & Festhalle Eutingen (bisher: Sporthalle Eutingen)
+- Preis: 5,00 € (bisher: 4,00 €)
+<-->
 - Di., 09. März 2100, 19:00 Uhr
 - Do., 11. März 2100, 19:00 Uhr
 - Fr., 12. März 2100, 19:00 Uhr"#,
         );
+
+        // changes not relevant to the participant are not sent
+        let mut previous = event.clone();
+        previous.price_non_member = BigDecimal::from_i8(8).unwrap();
+        assert_eq!(
+            render_event_change(template, &booking, &event, &previous).unwrap(),
+            None
+        );
+
+        // the price includes the surcharges of the booking
+        let mut event = event;
+        event.custom_fields = vec![EventCustomField::new(
+            0,
+            String::from("Leihschläger"),
+            EventCustomFieldType::Checkbox,
+            None,
+            None,
+            false,
+            Vec::new(),
+            false,
+            Some(BigDecimal::from_i8(3).unwrap()),
+            Vec::new(),
+        )];
+        let mut previous = event.clone();
+        previous.custom_fields[0].price = Some(BigDecimal::from_i8(2).unwrap());
+        let mut booking = booking;
+        booking.custom_values = vec![String::from("true")];
+        assert!(
+            render_event_change("{{changes}}", &booking, &event, &previous)
+                .unwrap()
+                .unwrap()
+                .contains("- Preis: 8,00 € (bisher: 7,00 €)")
+        );
+        booking.custom_values = vec![String::from("false")];
+        assert_eq!(
+            render_event_change("{{changes}}", &booking, &event, &previous).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_render_waiting_list_offer() {
+        let event = new_event(Vec::new());
+        let booking = EventBooking::new(
+            0,
+            String::from("Max"),
+            String::from("Mustermann"),
+            String::from("Haupstraße 1"),
+            String::from("72184 Eutingen"),
+            String::from("max@mustermann.de"),
+            None,
+            Some(true),
+            None,
+            None,
+            Vec::new(),
+            None,
+        );
+
+        assert_eq!(
+            render_waiting_list_offer(
+                "{{firstname}} / {{name}} / {{deadline}} / {{link}}",
+                &booking,
+                &event,
+                String::from("https://www.sv-eutingen.de/fitness?offer=abc"),
+                &Utc.with_ymd_and_hms(2100, 3, 10, 18, 00, 00).unwrap(),
+            )
+            .unwrap(),
+            "Max / name / Mittwoch, 10. März 2100, 19:00 Uhr / https://www.sv-eutingen.de/fitness?offer=abc"
+        );
     }
 
     fn format_payday(date_time: DateTime<Utc>) -> String {
@@ -843,6 +1098,11 @@ Platz als Wartelistennachrücker gebucht.{{/if}}";
             false,
             Vec::new(),
             PaymentMethod::BankTransfer,
+            0,
+            None,
+            None,
+            None,
+            None,
         )
     }
 }
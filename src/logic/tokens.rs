@@ -0,0 +1,122 @@
+//! Signed, self-contained tokens used in links sent by email.
+//
+// Tokens are HS256 JWTs. The audience claim binds a token to its purpose so a
+// token issued for one kind of link can never be replayed against another.
+
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::ValidationError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenPurpose {
+    NewsConfirmation,
+    NewsPreferences,
+    TrainerLogin,
+    CaptchaChallenge,
+    BookingConfirmation,
+    WaitingListOffer,
+}
+
+impl TokenPurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::NewsConfirmation => "news_confirmation",
+            Self::NewsPreferences => "news_preferences",
+            Self::TrainerLogin => "trainer_login",
+            Self::CaptchaChallenge => "captcha_challenge",
+            Self::BookingConfirmation => "booking_confirmation",
+            Self::WaitingListOffer => "waiting_list_offer",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenClaims {
+    sub: String,
+    aud: String,
+    exp: usize,
+    iat: usize,
+}
+
+pub(crate) fn sign(
+    purpose: TokenPurpose,
+    subject: &str,
+    valid_for: Duration,
+    secret: &str,
+) -> Result<String> {
+    let now = Utc::now();
+    let claims = TokenClaims {
+        sub: subject.to_string(),
+        aud: purpose.as_str().to_string(),
+        exp: (now + valid_for).timestamp() as usize,
+        iat: now.timestamp() as usize,
+    };
+    let token = jsonwebtoken::encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )?;
+    Ok(token)
+}
+
+/// Verify the token for the given purpose and return its subject.
+pub(crate) fn verify(
+    purpose: TokenPurpose,
+    token: &str,
+    secret: &str,
+) -> Result<String, ValidationError> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_audience(&[purpose.as_str()]);
+    let token_data = decode::<TokenClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )
+    .map_err(|_| ValidationError::new("Der Link ist ungültig oder abgelaufen."))?;
+    Ok(token_data.claims.sub)
+}
+
+/// Hash of a random secret token (e.g. a refresh token or an API key) which is
+/// stored instead of the token itself.
+pub(crate) fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let token = sign(
+            TokenPurpose::NewsConfirmation,
+            "42",
+            Duration::days(1),
+            "secret",
+        )
+        .unwrap();
+        assert_eq!(
+            verify(TokenPurpose::NewsConfirmation, &token, "secret").unwrap(),
+            "42"
+        );
+        assert!(verify(TokenPurpose::NewsConfirmation, &token, "other").is_err());
+        assert!(verify(TokenPurpose::NewsPreferences, &token, "secret").is_err());
+    }
+
+    #[test]
+    fn test_verify_rejects_expired_token() {
+        let token = sign(
+            TokenPurpose::NewsConfirmation,
+            "42",
+            Duration::days(-1),
+            "secret",
+        )
+        .unwrap();
+        assert!(verify(TokenPurpose::NewsConfirmation, &token, "secret").is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use super::secrets::{SecretKey, SecretProvider};
+use super::tokens::{self, TokenPurpose};
+use crate::db;
+use crate::email::EmailSender;
+use crate::error::ValidationError;
+use crate::models::{
+    EmailType, EventId, Trainer, TrainerAttendance, TrainerEvent, TrainerInput, TrainerParticipant,
+};
+use anyhow::{Result, anyhow, bail};
+use chrono::{Duration, Utc};
+use lettre::message::SinglePart;
+use lettre::message::header::{self, ContentType};
+use sqlx::PgPool;
+use tracing::info;
+
+/// Number of minutes a login link sent to a trainer is valid.
+const LOGIN_LINK_VALIDITY_MINUTES: i64 = 30;
+
+pub(crate) async fn get_trainers(pool: &PgPool) -> Result<Vec<Trainer>> {
+    db::get_trainers(pool).await
+}
+
+/// Create or update a trainer and replace the events assigned to the trainer.
+pub(crate) async fn save_trainer(pool: &PgPool, mut input: TrainerInput) -> Result<Trainer> {
+    input.email = input.email.trim().to_lowercase();
+    input.name = input.name.trim().to_string();
+    if input.email.parse::<lettre::Address>().is_err() {
+        bail!(ValidationError::new(
+            "Bitte gib eine gültige E-Mail-Adresse ein."
+        ));
+    }
+    if input.name.is_empty() {
+        bail!(ValidationError::new("Bitte gib einen Namen ein."));
+    }
+
+    let id = db::save_trainer(pool, &input).await?;
+    db::get_trainer(pool, id)
+        .await?
+        .ok_or_else(|| anyhow!("Trainer with id '{}' is missing", id))
+}
+
+pub(crate) async fn delete_trainer(pool: &PgPool, id: i32) -> Result<bool> {
+    db::delete_trainer(pool, id).await
+}
+
+/// Send a login link to the trainer with the given email address. Unknown
+/// addresses are ignored silently to not disclose which trainers exist.
+pub(crate) async fn request_login(
+    pool: &PgPool,
+    email: &str,
+    secrets: &dyn SecretProvider,
+    email_sender: &impl EmailSender,
+) -> Result<()> {
+    let email = email.trim().to_lowercase();
+    let Some(trainer_id) = db::get_trainer_id_by_email(pool, &email).await? else {
+        info!("Login requested for unknown trainer {}", email);
+        return Ok(());
+    };
+
+    // The token is bound to a random id which is consumed on login, so each link can
+    // only be used once.
+    let token_id = uuid::Uuid::new_v4().simple().to_string();
+    let validity = Duration::minutes(LOGIN_LINK_VALIDITY_MINUTES);
+    db::insert_trainer_login_token(pool, &token_id, trainer_id, Utc::now() - validity).await?;
+
+    let secret = secrets.get(SecretKey::SessionSecret).await?;
+    let token = tokens::sign(TokenPurpose::TrainerLogin, &token_id, validity, &secret)?;
+
+    let email_account = email_sender.get_account_by_type(EmailType::Info).await?;
+    let message = crate::email::new_message_builder(&email_account)?
+        .header(header::MIME_VERSION_1_0)
+        .header(ContentType::TEXT_PLAIN)
+        .to(email.parse()?)
+        .subject("[Infos@SVE] Dein Anmeldelink für den Trainerbereich")
+        .singlepart(SinglePart::plain(format!(
+            "Hallo,
+
+über folgenden Link kannst Du Dich im Trainerbereich des SV Eutingen anmelden:
+https://www.sv-eutingen.de/trainer?token={}
+
+Der Link ist {} Minuten gültig. Falls Du die Anmeldung nicht selbst angefordert hast, kannst Du diese E-Mail einfach ignorieren.
+
+Herzliche Grüße
+SV Eutingen",
+            token, LOGIN_LINK_VALIDITY_MINUTES
+        )))?;
+
+    email_sender.send_message(&email_account, message).await?;
+
+    Ok(())
+}
+
+/// Verify the token of a login link, mark it as used and return the trainer it has
+/// been issued for.
+pub(crate) async fn login(
+    pool: &PgPool,
+    token: &str,
+    secrets: &dyn SecretProvider,
+) -> Result<Trainer> {
+    let secret = secrets.get(SecretKey::SessionSecret).await?;
+    let token_id = tokens::verify(TokenPurpose::TrainerLogin, token, &secret)?;
+
+    let trainer = match db::consume_trainer_login_token(pool, &token_id).await? {
+        Some(trainer_id) => db::get_trainer(pool, trainer_id).await?,
+        None => None,
+    };
+    match trainer {
+        Some(trainer) => Ok(trainer),
+        None => bail!(ValidationError::new(
+            "Der Link ist ungültig oder abgelaufen."
+        )),
+    }
+}
+
+pub(crate) async fn is_trainer_of_event(
+    pool: &PgPool,
+    trainer_id: i32,
+    event_id: &EventId,
+) -> Result<bool> {
+    db::is_trainer_of_event(pool, trainer_id, event_id).await
+}
+
+/// Get all events assigned to the given trainer.
+pub(crate) async fn get_events(pool: &PgPool, trainer_id: i32) -> Result<Vec<TrainerEvent>> {
+    let Some(trainer) = db::get_trainer(pool, trainer_id).await? else {
+        return Ok(vec![]);
+    };
+
+    let mut events = Vec::new();
+    for event_id in trainer.event_ids {
+        if let Some(event) = db::get_event(pool, &event_id, false).await? {
+            events.push(TrainerEvent::from(event));
+        }
+    }
+
+    Ok(events)
+}
+
+/// Get the enrolled participants of the given event. The caller has to make sure the
+/// event is assigned to the trainer.
+pub(crate) async fn get_participants(
+    pool: &PgPool,
+    event_id: &EventId,
+) -> Result<Vec<TrainerParticipant>> {
+    let event = db::get_event(pool, event_id, true)
+        .await?
+        .ok_or_else(|| anyhow!("Error fetching event with id '{}'", event_id.get_ref()))?;
+
+    let mut attendances = db::get_attendances(pool, event_id).await?;
+
+    Ok(event
+        .subscribers
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|subscriber| subscriber.enrolled)
+        .map(|subscriber| {
+            let mut participant = TrainerParticipant::from(subscriber);
+            participant.attended = attendances
+                .remove(&participant.booking_id)
+                .unwrap_or_default();
+            participant
+        })
+        .collect())
+}
+
+/// Mark or unmark the attendance of a participant at one date of the event. The caller
+/// has to make sure the event is assigned to the trainer.
+pub(crate) async fn set_attendance(
+    pool: &PgPool,
+    event_id: &EventId,
+    trainer_id: i32,
+    attendance: &TrainerAttendance,
+) -> Result<()> {
+    if !db::set_attendance(pool, event_id, trainer_id, attendance).await? {
+        bail!(ValidationError::new(
+            "Die Teilnahme kann für diesen Termin nicht erfasst werden."
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{EventBooking, EventType, LifecycleStatus, PartialEvent};
+    use crate::test_utils::{mock_email_sender_capturing, mock_secrets};
+    use bigdecimal::BigDecimal;
+    use pretty_assertions::assert_eq;
+
+    async fn create_event(pool: &PgPool, name: &str) -> Result<EventId> {
+        let (event, _) = db::write_event(
+            pool,
+            PartialEvent {
+                event_type: Some(EventType::Fitness),
+                lifecycle_status: Some(LifecycleStatus::Published),
+                name: Some(name.to_string()),
+                sort_index: Some(0),
+                short_description: Some("Short desc".to_string()),
+                description: Some("Full desc".to_string()),
+                image: Some("test.png".to_string()),
+                light: Some(true),
+                dates: Some(vec![Utc::now() + Duration::days(30)]),
+                duration_in_minutes: Some(60),
+                max_subscribers: Some(10),
+                max_waiting_list: Some(5),
+                price_member: Some(BigDecimal::from(20)),
+                price_non_member: Some(BigDecimal::from(25)),
+                location: Some("Sporthalle".to_string()),
+                booking_template: Some("Booking template".to_string()),
+                payment_account: Some("DE1234".to_string()),
+                external_operator: Some(true),
+                ..Default::default()
+            },
+        )
+        .await?;
+        Ok(event.id)
+    }
+
+    fn trainer_input(event_ids: Vec<EventId>) -> TrainerInput {
+        TrainerInput {
+            email: " Trainer@Example.com ".into(),
+            name: "Erika Trainer".into(),
+            event_ids,
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_save_trainer(pool: PgPool) -> Result<()> {
+        let yoga = create_event(&pool, "Yoga").await?;
+        let pilates = create_event(&pool, "Pilates").await?;
+
+        let trainer = save_trainer(&pool, trainer_input(vec![yoga])).await?;
+        assert_eq!(trainer.email, "trainer@example.com");
+        assert_eq!(trainer.event_ids, vec![yoga]);
+
+        let updated = save_trainer(&pool, trainer_input(vec![pilates])).await?;
+        assert_eq!(updated.id, trainer.id);
+        assert_eq!(updated.event_ids, vec![pilates]);
+        assert!(is_trainer_of_event(&pool, trainer.id, &pilates).await?);
+        assert!(!is_trainer_of_event(&pool, trainer.id, &yoga).await?);
+        assert_eq!(
+            get_events(&pool, trainer.id)
+                .await?
+                .into_iter()
+                .map(|event| event.name)
+                .collect::<Vec<_>>(),
+            vec!["Pilates".to_string()]
+        );
+
+        let mut invalid = trainer_input(vec![]);
+        invalid.email = "no-email".into();
+        let err = save_trainer(&pool, invalid).await.unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+
+        assert!(delete_trainer(&pool, trainer.id).await?);
+        assert!(get_trainers(&pool).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_login_link(pool: PgPool) -> Result<()> {
+        let trainer = save_trainer(&pool, trainer_input(vec![])).await?;
+        let secrets = mock_secrets(vec![(SecretKey::SessionSecret, "secret")]);
+
+        let (email_sender, captured) =
+            mock_email_sender_capturing(vec![(EmailType::Info, "info@sv-eutingen.de")]);
+        request_login(&pool, "trainer@example.com", &secrets, &email_sender).await?;
+        request_login(&pool, "unknown@example.com", &secrets, &email_sender).await?;
+
+        let messages = captured.lock().unwrap().clone();
+        assert_eq!(messages.len(), 1);
+        let body = String::from_utf8_lossy(&messages[0].formatted())
+            .replace("=\r\n", "")
+            .replace("=3D", "=");
+        let token = body
+            .split("https://www.sv-eutingen.de/trainer?token=")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap()
+            .to_string();
+
+        assert_eq!(login(&pool, &token, &secrets).await?, trainer);
+        let err = login(&pool, &token, &secrets).await.unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+        let err = login(&pool, "invalid", &secrets).await.unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+
+        // A validly signed token without a stored id is rejected as well
+        let forged = tokens::sign(
+            TokenPurpose::TrainerLogin,
+            &trainer.id.to_string(),
+            Duration::minutes(LOGIN_LINK_VALIDITY_MINUTES),
+            "secret",
+        )?;
+        let err = login(&pool, &forged, &secrets).await.unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_get_participants(pool: PgPool) -> Result<()> {
+        let event_id = create_event(&pool, "Yoga").await?;
+        db::book_event(
+            &pool,
+            &EventBooking {
+                event_id,
+                first_name: "Max".to_string(),
+                last_name: "Mustermann".to_string(),
+                street: "Teststr 1".to_string(),
+                city: "Teststadt".to_string(),
+                email: "max@test.com".to_string(),
+                phone: Some("0123".to_string()),
+                member: Some(true),
+                updates: Some(false),
+                comments: None,
+                custom_values: vec![],
+                token: None,
+                iban: Some("DE02120300000000202051".to_string()),
+                bucket_id: None,
+            },
+        )
+        .await?;
+
+        let participants = get_participants(&pool, &event_id).await?;
+        let booking_id = participants[0].booking_id;
+        assert_eq!(
+            participants,
+            vec![TrainerParticipant {
+                booking_id,
+                first_name: "Max".into(),
+                last_name: "Mustermann".into(),
+                email: "max@test.com".into(),
+                phone: Some("0123".into()),
+                comment: None,
+                attended: vec![],
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_set_attendance(pool: PgPool) -> Result<()> {
+        let event_id = create_event(&pool, "Yoga").await?;
+        let other_event_id = create_event(&pool, "Pilates").await?;
+        let trainer = save_trainer(&pool, trainer_input(vec![event_id])).await?;
+        db::book_event(
+            &pool,
+            &EventBooking {
+                event_id,
+                first_name: "Max".to_string(),
+                last_name: "Mustermann".to_string(),
+                street: "Teststr 1".to_string(),
+                city: "Teststadt".to_string(),
+                email: "max@test.com".to_string(),
+                phone: None,
+                member: Some(true),
+                updates: Some(false),
+                comments: None,
+                custom_values: vec![],
+                token: None,
+                iban: Some("DE02120300000000202051".to_string()),
+                bucket_id: None,
+            },
+        )
+        .await?;
+        let booking_id = get_participants(&pool, &event_id).await?[0].booking_id;
+        let date = db::get_event(&pool, &event_id, false).await?.unwrap().dates[0];
+
+        let attendance = |date, attended| TrainerAttendance {
+            booking_id,
+            date,
+            attended,
+        };
+        set_attendance(&pool, &event_id, trainer.id, &attendance(date, true)).await?;
+        // Marking twice is a no-op
+        set_attendance(&pool, &event_id, trainer.id, &attendance(date, true)).await?;
+        assert_eq!(
+            get_participants(&pool, &event_id).await?[0].attended,
+            vec![date]
+        );
+
+        // Neither other dates nor bookings of other events can be marked
+        let err = set_attendance(
+            &pool,
+            &event_id,
+            trainer.id,
+            &attendance(date + Duration::days(1), true),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+        let err = set_attendance(&pool, &other_event_id, trainer.id, &attendance(date, true))
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+
+        set_attendance(&pool, &event_id, trainer.id, &attendance(date, false)).await?;
+        assert!(
+            get_participants(&pool, &event_id).await?[0]
+                .attended
+                .is_empty()
+        );
+
+        Ok(())
+    }
+}
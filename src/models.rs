@@ -7,6 +7,7 @@ use lettre::Message;
 use lettre::message::header::ContentType;
 use lettre::message::{Attachment, MultiPart, SinglePart};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
 use std::fmt::{Debug, Display};
 use std::ops::Deref;
 use std::str::FromStr;
@@ -103,7 +104,19 @@ pub(crate) struct Event {
     pub(crate) alt_email_address: Option<String>,
     pub(crate) external_operator: bool,
     pub(crate) custom_fields: Vec<EventCustomField>,
+    #[serde(default)]
+    pub(crate) capacity_buckets: Vec<EventCapacityBucket>,
     pub(crate) payment_method: PaymentMethod,
+    /// Hours to accept a spot offered to the waiting list,
+    /// 0 enrolls the next waiting person immediately.
+    pub(crate) waiting_list_offer_hours: i16,
+    /// Time to publish the event automatically.
+    pub(crate) publish_at: Option<DateTime<Utc>>,
+    /// Time to hide the event automatically by moving it into status running.
+    pub(crate) unpublish_at: Option<DateTime<Utc>>,
+    /// Bookings are only possible within the booking window (if set).
+    pub(crate) booking_start: Option<DateTime<Utc>>,
+    pub(crate) booking_end: Option<DateTime<Utc>>,
     pub(crate) subscribers: Option<Vec<EventSubscription>>,
 }
 
@@ -137,6 +150,11 @@ impl Event {
         external_operator: bool,
         custom_fields: Vec<EventCustomField>,
         payment_method: PaymentMethod,
+        waiting_list_offer_hours: i16,
+        publish_at: Option<DateTime<Utc>>,
+        unpublish_at: Option<DateTime<Utc>>,
+        booking_start: Option<DateTime<Utc>>,
+        booking_end: Option<DateTime<Utc>>,
     ) -> Self {
         Self {
             id: id.into(),
@@ -165,17 +183,23 @@ impl Event {
             alt_email_address,
             external_operator,
             custom_fields,
+            capacity_buckets: Vec::new(),
             payment_method,
+            waiting_list_offer_hours,
+            publish_at,
+            unpublish_at,
+            booking_start,
+            booking_end,
             subscribers: None,
         }
     }
 
-    /// Total booking price: base price multiplied by the value of the
-    /// single price-relevant custom field (if any).  Missing or
-    /// unparseable values fall back to the base price — the booking-time
-    /// validation path raises the user-facing error.
+    /// Total booking price: base price plus the surcharges of the custom
+    /// fields, multiplied by the value of the single price-relevant custom
+    /// field (if any).  Missing or unparseable values fall back to the base
+    /// price — the booking-time validation path raises the user-facing error.
     pub(crate) fn total_price(&self, is_member: bool, custom_values: &[String]) -> BigDecimal {
-        BookingCustomFieldValues::from_event_fields(&self.custom_fields, custom_values).total_price(
+        BookingCustomFieldValues::new(&self.custom_fields, custom_values).total_price(
             &self.price_member,
             &self.price_non_member,
             is_member,
@@ -186,7 +210,7 @@ impl Event {
     /// or `None` if there is no price-relevant field or the value is
     /// missing/unparseable.
     pub(crate) fn price_relevant_multiplier(&self, custom_values: &[String]) -> Option<i32> {
-        BookingCustomFieldValues::from_event_fields(&self.custom_fields, custom_values)
+        BookingCustomFieldValues::new(&self.custom_fields, custom_values)
             .price_relevant_multiplier()
     }
 
@@ -238,7 +262,147 @@ pub(crate) struct PartialEvent {
     pub(crate) alt_email_address: Option<String>,
     pub(crate) external_operator: Option<bool>,
     pub(crate) custom_fields: Option<Vec<EventCustomField>>,
+    pub(crate) capacity_buckets: Option<Vec<EventCapacityBucket>>,
     pub(crate) payment_method: Option<PaymentMethod>,
+    pub(crate) waiting_list_offer_hours: Option<i16>,
+    /// The schedule fields distinguish a missing value (unchanged) from an
+    /// explicit `null`, which clears the scheduled time.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub(crate) publish_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub(crate) unpublish_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub(crate) booking_start: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub(crate) booking_end: Option<Option<DateTime<Utc>>>,
+}
+
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+impl From<Event> for PartialEvent {
+    fn from(event: Event) -> Self {
+        Self {
+            id: Some(event.id),
+            closed: event.closed,
+            event_type: Some(event.event_type),
+            lifecycle_status: Some(event.lifecycle_status),
+            name: Some(event.name),
+            sort_index: Some(event.sort_index),
+            short_description: Some(event.short_description),
+            description: Some(event.description),
+            image: Some(event.image),
+            light: Some(event.light),
+            dates: Some(event.dates),
+            custom_date: event.custom_date,
+            duration_in_minutes: Some(event.duration_in_minutes),
+            max_subscribers: Some(event.max_subscribers),
+            max_waiting_list: Some(event.max_waiting_list),
+            price_member: Some(event.price_member),
+            price_non_member: Some(event.price_non_member),
+            cost_per_date: event.cost_per_date,
+            location: Some(event.location),
+            booking_template: Some(event.booking_template),
+            payment_account: event.payment_account,
+            alt_booking_button_text: event.alt_booking_button_text,
+            alt_email_address: event.alt_email_address,
+            external_operator: Some(event.external_operator),
+            custom_fields: Some(event.custom_fields),
+            capacity_buckets: Some(event.capacity_buckets),
+            payment_method: Some(event.payment_method),
+            waiting_list_offer_hours: Some(event.waiting_list_offer_hours),
+            publish_at: Some(event.publish_at),
+            unpublish_at: Some(event.unpublish_at),
+            booking_start: Some(event.booking_start),
+            booking_end: Some(event.booking_end),
+        }
+    }
+}
+
+/// Options to copy an event into a new draft.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub(crate) struct EventCloneOptions {
+    /// Days to shift all dates of the copy by (may be negative).
+    pub(crate) shift_days: i64,
+    /// Number of weekly dates of the copy (at most 52), starting with the (shifted) first date.
+    pub(crate) weekly_dates: Option<u16>,
+}
+
+/// Reason of the organiser to cancel an event, sent to all participants.
+#[derive(Deserialize, Debug)]
+pub(crate) struct EventCancellationInput {
+    pub(crate) reason: String,
+}
+
+/// Result of the cancellation of an event.
+#[derive(Serialize, Debug)]
+pub(crate) struct EventCancellation {
+    pub(crate) event: Event,
+    /// Number of participants and waiting-list entries whose notification
+    /// has been sent.
+    pub(crate) notified: usize,
+    /// Paid bookings whose price has to be refunded.
+    pub(crate) refunds: Vec<CanceledEventBooking>,
+}
+
+/// Booking canceled together with its event.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct CanceledEventBooking {
+    pub(crate) booking_id: i32,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) email: String,
+    pub(crate) enrolled: bool,
+    pub(crate) price: BigDecimal,
+    pub(crate) payment_id: String,
+    /// The payment has been confirmed or collected via SEPA direct debit.
+    pub(crate) paid: bool,
+}
+
+/// Paid booking of a canceled event whose price has to be refunded.
+#[derive(Serialize, Debug)]
+pub(crate) struct EventRefund {
+    pub(crate) event_id: EventId,
+    pub(crate) event_name: String,
+    pub(crate) booking_id: i32,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) email: String,
+    pub(crate) price: BigDecimal,
+    pub(crate) payment_id: String,
+    pub(crate) canceled: DateTime<Utc>,
+}
+
+/// Snapshot of an event, stored on every write of the event.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct EventVersion {
+    pub(crate) version: i32,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) snapshot: serde_json::Value,
+}
+
+/// Fields of an event which differ between two versions.
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct EventVersionDiff {
+    pub(crate) from: i32,
+    pub(crate) to: i32,
+    pub(crate) before: serde_json::Value,
+    pub(crate) after: serde_json::Value,
+}
+
+/// Named event template to prefill new events.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct EventTemplate {
+    #[serde(default)]
+    pub(crate) id: Option<i32>,
+    pub(crate) name: String,
+    pub(crate) event: PartialEvent,
 }
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, sqlx::Type)]
@@ -269,11 +433,209 @@ impl From<EventType> for &str {
     }
 }
 
+/// Role of a user of the admin area.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, sqlx::Type)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(type_name = "user_role")]
+pub(crate) enum Role {
+    /// Full access including the management of roles.
+    Admin,
+    /// Payments, SEPA exports and membership fees.
+    Treasurer,
+    /// Events and bookings of type Fitness, trainers and news.
+    FitnessCoordinator,
+    /// Events and bookings of type Events, trainers and news.
+    EventsCoordinator,
+    /// Read-only access to events and participant lists.
+    TrainerReadonly,
+}
+
+impl Role {
+    /// Whether the role may change events and bookings of the given type.
+    pub(crate) fn can_manage(&self, event_type: EventType) -> bool {
+        matches!(
+            (self, event_type),
+            (Role::Admin, _)
+                | (Role::FitnessCoordinator, EventType::Fitness)
+                | (Role::EventsCoordinator, EventType::Events)
+        )
+    }
+
+    /// Whether the role may view events and participants of the given type.
+    pub(crate) fn can_view(&self, event_type: EventType) -> bool {
+        self.can_manage(event_type) || matches!(self, Role::Treasurer | Role::TrainerReadonly)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct RoleAssignment {
+    pub(crate) email: String,
+    pub(crate) roles: Vec<Role>,
+}
+
+/// Named API key for the task endpoints. The key itself is only shown once on creation.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct ApiKey {
+    pub(crate) id: i32,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) name: String,
+    pub(crate) scopes: Vec<String>,
+    pub(crate) expires_at: Option<DateTime<Utc>>,
+    pub(crate) last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ApiKeyInput {
+    pub(crate) name: String,
+    pub(crate) scopes: Vec<String>,
+    pub(crate) expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct CreatedApiKey {
+    pub(crate) api_key: ApiKey,
+    pub(crate) key: String,
+}
+
+/// Entry of the append-only audit log of the admin area. `before` and `after`
+/// only contain the fields which have been changed by the call.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct AuditLogEntry {
+    pub(crate) id: i64,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) actor: String,
+    pub(crate) action: String,
+    pub(crate) path: String,
+    pub(crate) event_id: Option<EventId>,
+    pub(crate) booking_id: Option<i32>,
+    /// Email address of the person whose data is concerned, e.g. by a data subject
+    /// request, or the id of an erasure.
+    pub(crate) subject: Option<String>,
+    pub(crate) before: Option<serde_json::Value>,
+    pub(crate) after: Option<serde_json::Value>,
+}
+
+/// Filter of the audit log, all criteria are optional.
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct AuditLogFilter {
+    pub(crate) actor: Option<String>,
+    pub(crate) action: Option<String>,
+    pub(crate) event_id: Option<EventId>,
+    pub(crate) booking_id: Option<i32>,
+    pub(crate) subject: Option<String>,
+    pub(crate) from: Option<DateTime<Utc>>,
+    pub(crate) to: Option<DateTime<Utc>>,
+    pub(crate) limit: Option<i64>,
+}
+
+/// Everything stored about an email address, as snapshots of the database rows.
+#[derive(Serialize, Debug)]
+pub(crate) struct PersonalDataExport {
+    pub(crate) email: String,
+    pub(crate) exported_at: DateTime<Utc>,
+    pub(crate) event_subscribers: Vec<serde_json::Value>,
+    pub(crate) event_bookings: Vec<serde_json::Value>,
+    pub(crate) news_subscribers: Vec<serde_json::Value>,
+    pub(crate) news_subscription_requests: Vec<serde_json::Value>,
+    pub(crate) news_campaign_recipients: Vec<serde_json::Value>,
+    pub(crate) members: Vec<serde_json::Value>,
+    pub(crate) audit_log: Vec<serde_json::Value>,
+}
+
+/// Number of rows which have been anonymized or deleted by an erasure.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub(crate) struct ErasureResult {
+    /// Random id of the erasure, recorded in the audit log instead of the email address.
+    pub(crate) erasure_id: String,
+    pub(crate) anonymized_subscribers: u64,
+    pub(crate) anonymized_bookings: u64,
+    pub(crate) deleted_news_subscribers: u64,
+    pub(crate) deleted_news_subscription_requests: u64,
+    pub(crate) anonymized_campaign_recipients: u64,
+    pub(crate) redacted_audit_log_entries: u64,
+    /// Memberships are contracts and are only removed by ending the membership.
+    pub(crate) retained_members: u64,
+}
+
+/// External trainer with read access to the participants of the assigned events.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Trainer {
+    pub(crate) id: i32,
+    pub(crate) email: String,
+    pub(crate) name: String,
+    pub(crate) event_ids: Vec<EventId>,
+}
+
+/// Create or update a trainer, identified by the email address.
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct TrainerInput {
+    pub(crate) email: String,
+    pub(crate) name: String,
+    pub(crate) event_ids: Vec<EventId>,
+}
+
+/// Event as shown to a trainer.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct TrainerEvent {
+    pub(crate) id: EventId,
+    pub(crate) name: String,
+    pub(crate) dates: Vec<DateTime<Utc>>,
+    pub(crate) custom_date: Option<String>,
+    pub(crate) location: String,
+}
+
+impl From<Event> for TrainerEvent {
+    fn from(event: Event) -> Self {
+        Self {
+            id: event.id,
+            name: event.name,
+            dates: event.dates,
+            custom_date: event.custom_date,
+            location: event.location,
+        }
+    }
+}
+
+/// Participant of an event as shown to a trainer, without any payment details.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct TrainerParticipant {
+    pub(crate) booking_id: i32,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) email: String,
+    pub(crate) phone: Option<String>,
+    pub(crate) comment: Option<String>,
+    /// Dates of the event the participant has attended.
+    pub(crate) attended: Vec<DateTime<Utc>>,
+}
+
+impl From<EventSubscription> for TrainerParticipant {
+    fn from(subscription: EventSubscription) -> Self {
+        Self {
+            booking_id: subscription.id,
+            first_name: subscription.first_name,
+            last_name: subscription.last_name,
+            email: subscription.email,
+            phone: subscription.phone,
+            comment: subscription.comment,
+            attended: Vec::new(),
+        }
+    }
+}
+
+/// Attendance of a participant at one date of the event, marked by a trainer.
+#[derive(Deserialize, Debug)]
+pub(crate) struct TrainerAttendance {
+    pub(crate) booking_id: i32,
+    pub(crate) date: DateTime<Utc>,
+    pub(crate) attended: bool,
+}
+
 impl From<EventType> for NewsTopic {
     fn from(event_type: EventType) -> Self {
         match event_type {
-            EventType::Fitness => Self::Fitness,
-            EventType::Events => Self::Events,
+            EventType::Fitness => Self::FITNESS,
+            EventType::Events => Self::EVENTS,
         }
     }
 }
@@ -338,6 +700,10 @@ pub(crate) enum LifecycleStatus {
 
     /// Archived and only there for a complete history.
     Archived,
+
+    /// Canceled by the organiser, all bookings have been canceled.
+    /// No longer visible and no longer bookable - can only be archived.
+    Canceled,
 }
 
 impl LifecycleStatus {
@@ -347,6 +713,35 @@ impl LifecycleStatus {
             LifecycleStatus::Review | LifecycleStatus::Published | LifecycleStatus::Running
         )
     }
+
+    /// Whether a new event may be created in this status.
+    pub(crate) fn is_initial(self) -> bool {
+        matches!(
+            self,
+            LifecycleStatus::Draft
+                | LifecycleStatus::Review
+                | LifecycleStatus::Published
+                | LifecycleStatus::Running
+        )
+    }
+
+    /// Whether an event may be moved from this status into the given status.
+    /// Once bookable, an event can't become a draft again; once closed, it can
+    /// only be archived. Events are canceled together with their bookings, not by a transition.
+    pub(crate) fn can_transition_to(self, next: LifecycleStatus) -> bool {
+        use LifecycleStatus::*;
+
+        self == next
+            || matches!(
+                (self, next),
+                (Draft, Review | Published)
+                    | (Review, Published | Running | Finished | Closed)
+                    | (Published, Review | Running | Finished | Closed)
+                    | (Running, Review | Published | Finished | Closed)
+                    | (Finished, Closed)
+                    | (Closed | Canceled, Archived)
+            )
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, sqlx::Type)]
@@ -368,30 +763,74 @@ impl FromStr for LifecycleStatus {
             "finished" => Ok(Self::Finished),
             "closed" => Ok(Self::Closed),
             "archived" => Ok(Self::Archived),
+            "canceled" => Ok(Self::Canceled),
             other => bail!("Invalid lifecycle status {}", other),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, sqlx::Type)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, sqlx::Type)]
 #[sqlx(type_name = "event_cf_type")]
 pub(crate) enum EventCustomFieldType {
     Text,
     Number,
+    /// One of the options of the field.
+    Select,
+    /// `true` or `false`.
+    Checkbox,
+    /// ISO 8601 date, e.g. `2015-06-30`.
+    Date,
+}
+
+/// Separate quota of an event (e.g. adults and kids) with its own waiting list.
+/// Bookings of events with capacity buckets have to choose one of them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct EventCapacityBucket {
+    /// `None` for buckets which have not been saved yet.
+    #[serde(default)]
+    pub(crate) id: Option<i32>,
+    pub(crate) name: String,
+    pub(crate) max_subscribers: i16,
+    pub(crate) max_waiting_list: i16,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl EventCapacityBucket {
+    pub(crate) fn new(id: i32, name: String, max_subscribers: i16, max_waiting_list: i16) -> Self {
+        Self {
+            id: Some(id),
+            name,
+            max_subscribers,
+            max_waiting_list,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub(crate) struct EventCustomField {
     pub(crate) id: i32,
     pub(crate) name: String,
     #[serde(rename = "type")]
     pub(crate) cf_type: EventCustomFieldType,
+    /// Minimum of numbers, minimum length of texts.
     pub(crate) min_value: Option<i32>,
+    /// Maximum of numbers, maximum length of texts.
     pub(crate) max_value: Option<i32>,
     pub(crate) price_relevant: bool,
+    #[serde(default)]
+    pub(crate) options: Vec<String>,
+    #[serde(default)]
+    pub(crate) required: bool,
+    /// Surcharge of a checked checkbox, surcharge per unit of a number
+    /// which is not price-relevant.
+    #[serde(default)]
+    pub(crate) price: Option<BigDecimal>,
+    /// Surcharges of the options of a select list, one per option.
+    #[serde(default)]
+    pub(crate) option_prices: Vec<BigDecimal>,
 }
 
 impl EventCustomField {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         id: i32,
         name: String,
@@ -399,6 +838,10 @@ impl EventCustomField {
         min_value: Option<i32>,
         max_value: Option<i32>,
         price_relevant: bool,
+        options: Vec<String>,
+        required: bool,
+        price: Option<BigDecimal>,
+        option_prices: Vec<BigDecimal>,
     ) -> Self {
         Self {
             id,
@@ -407,39 +850,106 @@ impl EventCustomField {
             min_value,
             max_value,
             price_relevant,
+            options,
+            required,
+            price,
+            option_prices,
         }
     }
+
+    /// Surcharge of the booked value of the field, if the field has a price.
+    fn surcharge(&self, value: &str) -> Option<BigDecimal> {
+        match self.cf_type {
+            EventCustomFieldType::Checkbox if value == "true" => self.price.clone(),
+            EventCustomFieldType::Select => self
+                .options
+                .iter()
+                .position(|option| option == value)
+                .and_then(|i| self.option_prices.get(i))
+                .cloned(),
+            EventCustomFieldType::Number if !self.price_relevant => {
+                let units = value.parse::<i32>().ok()?;
+                Some(self.price.as_ref()? * BigDecimal::from(units))
+            }
+            _ => None,
+        }
+    }
+
+    /// Validate and normalize the booked value of the field. Empty values
+    /// are only allowed for optional fields and are normalized to an empty
+    /// string, checkboxes to `true` or `false`.
+    pub(crate) fn validate(&self, value: Option<&str>) -> Result<String, String> {
+        let value = match value.map(str::trim).unwrap_or_default() {
+            "" if self.cf_type == EventCustomFieldType::Checkbox => "false",
+            "" if self.required => {
+                return Err(format!("Bitte fülle das Feld '{}' aus.", self.name));
+            }
+            "" => return Ok(String::new()),
+            value => value,
+        };
+        let invalid = || format!("Bitte gib einen gültigen Wert für '{}' ein.", self.name);
+        match self.cf_type {
+            EventCustomFieldType::Text => {
+                let length = i32::try_from(value.chars().count()).unwrap_or(i32::MAX);
+                if self.min_value.is_some_and(|min| length < min)
+                    || self.max_value.is_some_and(|max| length > max)
+                {
+                    return Err(invalid());
+                }
+            }
+            EventCustomFieldType::Number => {
+                let number = value.parse::<i32>().map_err(|_| invalid())?;
+                // priced units and multipliers must not reduce the price
+                let priced = self.price_relevant || self.price.is_some();
+                if (priced && number < 0)
+                    || self.min_value.is_some_and(|min| number < min)
+                    || self.max_value.is_some_and(|max| number > max)
+                {
+                    return Err(invalid());
+                }
+            }
+            EventCustomFieldType::Select => {
+                if !self.options.iter().any(|option| option == value) {
+                    return Err(invalid());
+                }
+            }
+            EventCustomFieldType::Checkbox => {
+                let checked = value.parse::<bool>().map_err(|_| invalid())?;
+                if self.required && !checked {
+                    return Err(format!("Bitte bestätige '{}'.", self.name));
+                }
+                return Ok(checked.to_string());
+            }
+            EventCustomFieldType::Date => {
+                NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| invalid())?;
+            }
+        }
+        Ok(value.to_string())
+    }
 }
 
-/// The custom values supplied with a booking together with the
-/// price-relevance metadata of their fields.  Bundling the two avoids
-/// passing parallel `[Option<String>; 4]` / `[bool; 4]` arrays around.
-#[derive(Debug, Clone, Default)]
-pub(crate) struct BookingCustomFieldValues {
-    pub(crate) values: [Option<String>; 4],
-    pub(crate) price_relevant: [bool; 4],
+/// The custom values supplied with a booking together with the custom
+/// fields of the event, which define the price rules of the booking.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BookingCustomFieldValues<'a> {
+    pub(crate) custom_fields: &'a [EventCustomField],
+    pub(crate) values: &'a [String],
 }
 
-impl BookingCustomFieldValues {
-    pub(crate) fn new(values: [Option<String>; 4], price_relevant: [bool; 4]) -> Self {
+impl<'a> BookingCustomFieldValues<'a> {
+    pub(crate) fn new(custom_fields: &'a [EventCustomField], values: &'a [String]) -> Self {
         Self {
+            custom_fields,
             values,
-            price_relevant,
         }
     }
 
-    pub(crate) fn from_event_fields(custom_fields: &[EventCustomField], values: &[String]) -> Self {
-        let mut result = Self::default();
-        for (i, field) in custom_fields.iter().enumerate().take(4) {
-            result.price_relevant[i] = field.price_relevant;
-            result.values[i] = values.get(i).cloned();
-        }
-        result
-    }
-
-    /// Total price based on the member/non-member base price and the
-    /// value of the single price-relevant custom field (if any).  Missing
-    /// or unparseable values fall back to the base price.
+    /// Total price based on the member/non-member base price: the
+    /// surcharges of all custom fields (checked checkboxes, selected
+    /// options, priced units) are added to the base price, the result is
+    /// multiplied by the value of the single price-relevant custom field
+    /// (if any).  Missing or unparseable values add no surcharge and fall
+    /// back to a multiplier of one.
     pub(crate) fn total_price(
         &self,
         price_member: &BigDecimal,
@@ -451,9 +961,17 @@ impl BookingCustomFieldValues {
             false => price_non_member,
         };
 
+        let price = base
+            + self
+                .custom_fields
+                .iter()
+                .zip(self.values)
+                .filter_map(|(field, value)| field.surcharge(value))
+                .sum::<BigDecimal>();
+
         match self.price_relevant_multiplier() {
-            Some(n) => base * BigDecimal::from(n),
-            None => base.clone(),
+            Some(n) => price * BigDecimal::from(n),
+            None => price,
         }
     }
 
@@ -461,10 +979,10 @@ impl BookingCustomFieldValues {
     /// or `None` if there is no price-relevant field or the value is
     /// missing/unparseable.
     pub(crate) fn price_relevant_multiplier(&self) -> Option<i32> {
-        self.price_relevant
+        self.custom_fields
             .iter()
-            .position(|&price_relevant| price_relevant)
-            .and_then(|i| self.values[i].as_ref())
+            .position(|field| field.price_relevant)
+            .and_then(|i| self.values.get(i))
             .and_then(|v| v.parse::<i32>().ok())
     }
 }
@@ -484,6 +1002,8 @@ pub(crate) struct EventBooking {
     pub(crate) custom_values: Vec<String>,
     pub(crate) token: Option<String>,
     pub(crate) iban: Option<String>,
+    /// Chosen capacity bucket, required if the event has capacity buckets.
+    pub(crate) bucket_id: Option<i32>,
 }
 
 impl EventBooking {
@@ -516,6 +1036,7 @@ impl EventBooking {
             custom_values,
             token: None,
             iban,
+            bucket_id: None,
         }
     }
 
@@ -535,6 +1056,8 @@ pub(crate) struct EventCounter {
     pub(crate) max_waiting_list: i16,
     pub(crate) subscribers: i16,
     pub(crate) waiting_list: i16,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) buckets: Vec<EventBucketCounter>,
 }
 
 impl EventCounter {
@@ -551,14 +1074,86 @@ impl EventCounter {
             max_waiting_list,
             subscribers,
             waiting_list,
+            buckets: Vec::new(),
         }
     }
 
+    /// An event with capacity buckets is booked up if all of its buckets are booked up.
     pub(crate) fn is_booked_up(&self) -> bool {
-        if self.max_subscribers == -1 {
-            return false;
+        if !self.buckets.is_empty() {
+            return self.buckets.iter().all(EventBucketCounter::is_booked_up);
         }
-        self.subscribers >= self.max_subscribers && self.waiting_list >= self.max_waiting_list
+        self.enroll_status().is_none()
+    }
+
+    pub(crate) fn enroll_status(&self) -> Option<bool> {
+        enroll_status(
+            self.max_subscribers,
+            self.max_waiting_list,
+            self.subscribers,
+            self.waiting_list,
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct EventBucketCounter {
+    pub(crate) id: i32,
+    pub(crate) name: String,
+    pub(crate) max_subscribers: i16,
+    pub(crate) max_waiting_list: i16,
+    pub(crate) subscribers: i16,
+    pub(crate) waiting_list: i16,
+}
+
+impl EventBucketCounter {
+    pub(crate) fn new(
+        id: i32,
+        name: String,
+        max_subscribers: i16,
+        max_waiting_list: i16,
+        subscribers: i16,
+        waiting_list: i16,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            max_subscribers,
+            max_waiting_list,
+            subscribers,
+            waiting_list,
+        }
+    }
+
+    pub(crate) fn is_booked_up(&self) -> bool {
+        self.enroll_status().is_none()
+    }
+
+    pub(crate) fn enroll_status(&self) -> Option<bool> {
+        enroll_status(
+            self.max_subscribers,
+            self.max_waiting_list,
+            self.subscribers,
+            self.waiting_list,
+        )
+    }
+}
+
+/// `Some(true)` if a new booking is enrolled, `Some(false)` if it is put on
+/// the waiting list and `None` if there is no space left at all.
+/// A `max_subscribers` of -1 means unlimited.
+fn enroll_status(
+    max_subscribers: i16,
+    max_waiting_list: i16,
+    subscribers: i16,
+    waiting_list: i16,
+) -> Option<bool> {
+    if max_subscribers == -1 || subscribers < max_subscribers {
+        Some(true)
+    } else if waiting_list < max_waiting_list {
+        Some(false)
+    } else {
+        None
     }
 }
 
@@ -569,6 +1164,9 @@ pub(crate) struct BookingResponse {
     counter: Vec<EventCounter>,
     #[serde(skip_serializing_if = "is_false")]
     requires_iban: bool,
+    /// Signed token to send the confirmation of the existing booking again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resend_confirmation: Option<String>,
 }
 
 impl BookingResponse {
@@ -578,6 +1176,7 @@ impl BookingResponse {
             message: message.into(),
             counter,
             requires_iban: false,
+            resend_confirmation: None,
         }
     }
 
@@ -587,6 +1186,7 @@ impl BookingResponse {
             message: message.into(),
             counter: Vec::new(),
             requires_iban: false,
+            resend_confirmation: None,
         }
     }
 
@@ -596,8 +1196,54 @@ impl BookingResponse {
             message: message.into(),
             counter: Vec::new(),
             requires_iban: true,
+            resend_confirmation: None,
         }
     }
+
+    pub(crate) fn duplicate(message: &str, resend_confirmation: String) -> Self {
+        Self {
+            success: false,
+            message: message.into(),
+            counter: Vec::new(),
+            requires_iban: false,
+            resend_confirmation: Some(resend_confirmation),
+        }
+    }
+}
+
+/// Time-limited offer of a freed spot to a booking of the waiting list.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct WaitingListOffer {
+    pub(crate) id: i32,
+    pub(crate) booking_id: i32,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) email: String,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) expires_at: DateTime<Utc>,
+    pub(crate) accepted_at: Option<DateTime<Utc>>,
+    pub(crate) expired_at: Option<DateTime<Utc>>,
+}
+
+/// Active booking of an event with the same email address and name as another active booking.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct DuplicateBooking {
+    pub(crate) id: i32,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) email: String,
+    pub(crate) enrolled: bool,
+    pub(crate) payment_id: String,
+    pub(crate) payment_confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct DuplicateBookingGroup {
+    pub(crate) email: String,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) bookings: Vec<DuplicateBooking>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -615,6 +1261,8 @@ pub(crate) struct EventSubscription {
     pub(crate) payment_id: String,
     pub(crate) payment_confirmed_at: Option<DateTime<Utc>>,
     pub(crate) sepa_exported_at: Option<DateTime<Utc>>,
+    /// Encrypted at rest, always serialized masked.
+    #[serde(serialize_with = "crate::logic::encryption::serialize_masked")]
     pub(crate) iban: Option<String>,
     pub(crate) comment: Option<String>,
     pub(crate) custom_values: Vec<String>,
@@ -694,30 +1342,35 @@ impl NewsSubscription {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Hash)]
-pub(crate) enum NewsTopic {
-    General,
-    Events,
-    Fitness,
+/// Topics selected by a subscriber in the preference center.
+#[derive(Deserialize, Debug)]
+pub(crate) struct NewsPreferences {
+    #[serde(rename = "types")]
+    pub(crate) topics: Vec<NewsTopic>,
 }
 
+/// Name of a newsletter topic, e.g. `General`. The available topics are stored in the database.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Hash)]
+#[serde(transparent)]
+pub(crate) struct NewsTopic(Cow<'static, str>);
+
 impl NewsTopic {
-    pub(crate) fn display_name(&self) -> &str {
-        match self {
-            NewsTopic::General => "Allgemein",
-            NewsTopic::Events => "Events",
-            NewsTopic::Fitness => "Fitness",
-        }
+    pub(crate) const GENERAL: NewsTopic = NewsTopic(Cow::Borrowed("General"));
+    pub(crate) const EVENTS: NewsTopic = NewsTopic(Cow::Borrowed("Events"));
+    pub(crate) const FITNESS: NewsTopic = NewsTopic(Cow::Borrowed("Fitness"));
+
+    pub(crate) fn new(name: impl Into<String>) -> Self {
+        Self(Cow::Owned(name.into()))
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        &self.0
     }
 }
 
-impl From<NewsTopic> for &str {
-    fn from(topic: NewsTopic) -> Self {
-        match topic {
-            NewsTopic::General => "General",
-            NewsTopic::Events => "Events",
-            NewsTopic::Fitness => "Fitness",
-        }
+impl Display for NewsTopic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
     }
 }
 
@@ -725,23 +1378,91 @@ impl FromStr for NewsTopic {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "General" => Ok(Self::General),
-            "Events" => Ok(Self::Events),
-            "Fitness" => Ok(Self::Fitness),
-            other => bail!("Invalid topic {}", other),
+        let s = s.trim();
+        if s.is_empty() || !s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("Invalid topic {}", s);
         }
+        Ok(Self::new(s))
     }
 }
 
-impl From<NewsTopic> for EmailType {
-    fn from(topic: NewsTopic) -> Self {
-        match topic {
-            NewsTopic::General => Self::Info,
-            NewsTopic::Events => Self::Events,
-            NewsTopic::Fitness => Self::Fitness,
-        }
-    }
+/// A newsletter topic with the email account used to send mails regarding the topic.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct NewsTopicDefinition {
+    pub(crate) name: NewsTopic,
+    pub(crate) display_name: String,
+    pub(crate) email_type: EmailType,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct NewsTopicUpdate {
+    pub(crate) display_name: String,
+    pub(crate) email_type: EmailType,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "news_campaign_status")]
+pub(crate) enum NewsCampaignStatus {
+    /// Not scheduled yet and can be changed.
+    Draft,
+    /// Will be sent at `scheduled_at` and can still be changed.
+    Scheduled,
+    /// Recipients have been determined and are being delivered in batches.
+    Sending,
+    /// All recipients have been processed.
+    Sent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "news_campaign_recipient_status")]
+pub(crate) enum NewsCampaignRecipientStatus {
+    Pending,
+    Sent,
+    Failed,
+    Bounced,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct NewsCampaign {
+    pub(crate) id: i32,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) topic: NewsTopic,
+    pub(crate) subject: String,
+    pub(crate) body: String,
+    pub(crate) status: NewsCampaignStatus,
+    pub(crate) scheduled_at: Option<DateTime<Utc>>,
+    pub(crate) sent_at: Option<DateTime<Utc>>,
+}
+
+/// Content of a campaign as composed by an admin. Subject and body are Handlebars templates.
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct NewsCampaignInput {
+    pub(crate) topic: NewsTopic,
+    pub(crate) subject: String,
+    pub(crate) body: String,
+    pub(crate) scheduled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct NewsCampaignPreview {
+    pub(crate) subject: String,
+    pub(crate) body: String,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct NewsCampaignRecipient {
+    pub(crate) email: String,
+    pub(crate) status: NewsCampaignRecipientStatus,
+    pub(crate) sent_at: Option<DateTime<Utc>>,
+    pub(crate) error: Option<String>,
+    /// Number of attempts to send the mail.
+    pub(crate) attempts: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct NewsCampaignBounce {
+    pub(crate) email: String,
+    pub(crate) reason: String,
 }
 
 #[derive(Serialize, Debug)]
@@ -804,7 +1525,8 @@ impl EmailAccount {
     }
 }
 
-#[derive(Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, sqlx::Type)]
+#[sqlx(type_name = "email_type")]
 pub(crate) enum EmailType {
     Fitness,
     Events,
@@ -923,6 +1645,7 @@ pub(crate) enum SepaExportError {
     NoBookingsAvailable,
     BicLookupFailed(String),
     ConfigIncomplete,
+    NoMembershipFeesDue,
 }
 
 impl std::fmt::Display for SepaExportError {
@@ -934,6 +1657,9 @@ impl std::fmt::Display for SepaExportError {
             }
             SepaExportError::BicLookupFailed(msg) => write!(f, "{}", msg),
             SepaExportError::ConfigIncomplete => write!(f, "SEPA config incomplete."),
+            SepaExportError::NoMembershipFeesDue => {
+                write!(f, "No membership fees due for SEPA export")
+            }
         }
     }
 }
@@ -1099,7 +1825,8 @@ pub(crate) struct MembershipFamilyMember {
     pub(crate) birthday: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "membership_type")]
 pub(crate) enum MembershipType {
     Fitness,
     Family,
@@ -1131,6 +1858,94 @@ impl MembershipType {
     }
 }
 
+/// Configured fee of a membership type for a full calendar year.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct MembershipFeeSchedule {
+    pub(crate) membership_type: MembershipType,
+    pub(crate) annual_fee: BigDecimal,
+    /// Fee charged additionally for each registered family member.
+    #[serde(default)]
+    pub(crate) family_member_fee: BigDecimal,
+}
+
+/// Existing member imported for the annual membership fee collection.
+#[derive(Deserialize)]
+pub(crate) struct MemberImport {
+    pub(crate) salutation: String,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) street: String,
+    pub(crate) zipcode: String,
+    pub(crate) city: String,
+    pub(crate) email: String,
+    pub(crate) phone: String,
+    pub(crate) birthday: String,
+    pub(crate) membership_type: MembershipType,
+    pub(crate) start_date: NaiveDate,
+    pub(crate) end_date: Option<NaiveDate>,
+    pub(crate) account_owner: String,
+    pub(crate) iban: String,
+    #[serde(default)]
+    pub(crate) family_members: Vec<MembershipFamilyMember>,
+}
+
+/// End of a membership, `None` to continue the membership.
+#[derive(Deserialize, Debug)]
+pub(crate) struct MemberEndDate {
+    pub(crate) end_date: Option<NaiveDate>,
+}
+
+/// A member that is due for the annual membership fee collection.
+#[derive(Debug, Clone)]
+pub(crate) struct Member {
+    pub(crate) id: i32,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) membership_type: MembershipType,
+    pub(crate) start_date: NaiveDate,
+    pub(crate) account_owner: String,
+    pub(crate) iban: String,
+    pub(crate) family_members: i64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct MembershipFee {
+    pub(crate) member_id: i32,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) account_owner: String,
+    pub(crate) membership_type: MembershipType,
+    pub(crate) start_date: NaiveDate,
+    pub(crate) family_members: i64,
+    pub(crate) pro_rata: bool,
+    pub(crate) amount: BigDecimal,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct MembershipFeeReport {
+    pub(crate) year: i32,
+    pub(crate) count: usize,
+    pub(crate) total: BigDecimal,
+    pub(crate) fees: Vec<MembershipFee>,
+}
+
+impl MembershipFeeReport {
+    pub(crate) fn new(year: i32, fees: Vec<MembershipFee>) -> Self {
+        let total = fees
+            .iter()
+            .map(|fee| &fee.amount)
+            .sum::<BigDecimal>()
+            .round(2);
+        Self {
+            year,
+            count: fees.len(),
+            total,
+            fees,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1194,6 +2009,10 @@ mod tests {
             None,
             None,
             true,
+            Vec::new(),
+            false,
+            None,
+            Vec::new(),
         );
 
         let event =
@@ -1302,6 +2121,10 @@ mod tests {
             None,
             None,
             true,
+            Vec::new(),
+            false,
+            None,
+            Vec::new(),
         );
 
         let event =
@@ -1375,10 +2198,136 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_total_price_with_surcharges() {
+        let event = new_event_with_custom_fields(
+            "20.0",
+            "25.0",
+            vec![
+                // Bus +5 €
+                priced_field(EventCustomFieldType::Checkbox, false, &[], Some(5), &[]),
+                // Essen +12 € or +10 €
+                priced_field(
+                    EventCustomFieldType::Select,
+                    false,
+                    &["Fleisch", "Vegetarisch", "Ohne"],
+                    None,
+                    &[12, 10, 0],
+                ),
+                // Flasche Wein +8 € each
+                priced_field(EventCustomFieldType::Number, false, &[], Some(8), &[]),
+                // Personen
+                priced_field(EventCustomFieldType::Number, true, &[], None, &[]),
+            ],
+        );
+        let values = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+
+        // (25 + 5 + 12 + 2 × 8) × 2
+        assert_eq!(
+            event.total_price(false, &values(&["true", "Fleisch", "2", "2"])),
+            BigDecimal::from(116)
+        );
+        // (20 + 10) × 3
+        assert_eq!(
+            event.total_price(true, &values(&["false", "Vegetarisch", "", "3"])),
+            BigDecimal::from(90)
+        );
+        // no surcharges and no multiplier
+        assert_eq!(
+            event.total_price(true, &values(&["false", "Ohne", "", ""])),
+            BigDecimal::from(20)
+        );
+        assert_eq!(event.total_price(true, &[]), BigDecimal::from(20));
+    }
+
+    #[test]
+    fn test_custom_field_validation() {
+        let field = |cf_type, options: &[&str], required| {
+            EventCustomField::new(
+                0,
+                String::from("Feld"),
+                cf_type,
+                Some(1),
+                Some(10),
+                false,
+                options.iter().map(|option| option.to_string()).collect(),
+                required,
+                None,
+                Vec::new(),
+            )
+        };
+
+        let text = field(EventCustomFieldType::Text, &[], true);
+        assert_eq!(text.validate(Some(" Max ")), Ok(String::from("Max")));
+        assert!(text.validate(Some("")).is_err());
+        assert!(text.validate(None).is_err());
+        assert!(text.validate(Some("Maximilian Mustermann")).is_err());
+
+        let number = field(EventCustomFieldType::Number, &[], false);
+        assert_eq!(number.validate(Some("3")), Ok(String::from("3")));
+        assert_eq!(number.validate(None), Ok(String::new()));
+        assert!(number.validate(Some("0")).is_err());
+        assert!(number.validate(Some("11")).is_err());
+        assert!(number.validate(Some("drei")).is_err());
+        let mut units = field(EventCustomFieldType::Number, &[], false);
+        units.min_value = None;
+        assert_eq!(units.validate(Some("-2")), Ok(String::from("-2")));
+        units.price = Some(BigDecimal::from(5));
+        assert!(units.validate(Some("-2")).is_err());
+        assert_eq!(units.validate(Some("0")), Ok(String::from("0")));
+        units.price = None;
+        units.price_relevant = true;
+        assert!(units.validate(Some("-2")).is_err());
+
+        let select = field(EventCustomFieldType::Select, &["S", "M", "L"], true);
+        assert_eq!(select.validate(Some("M")), Ok(String::from("M")));
+        assert!(select.validate(Some("XL")).is_err());
+        assert!(select.validate(None).is_err());
+
+        let checkbox = field(EventCustomFieldType::Checkbox, &[], false);
+        assert_eq!(checkbox.validate(Some("true")), Ok(String::from("true")));
+        assert_eq!(checkbox.validate(None), Ok(String::from("false")));
+        assert!(checkbox.validate(Some("ja")).is_err());
+        let consent = field(EventCustomFieldType::Checkbox, &[], true);
+        assert!(consent.validate(Some("false")).is_err());
+        assert!(consent.validate(None).is_err());
+
+        let date = field(EventCustomFieldType::Date, &[], false);
+        assert_eq!(
+            date.validate(Some("2015-06-30")),
+            Ok(String::from("2015-06-30"))
+        );
+        assert!(date.validate(Some("30.06.2015")).is_err());
+    }
+
     fn new_event(price_member: &str, price_non_member: &str) -> Event {
         new_event_with_custom_fields(price_member, price_non_member, Vec::new())
     }
 
+    fn priced_field(
+        cf_type: EventCustomFieldType,
+        price_relevant: bool,
+        options: &[&str],
+        price: Option<i32>,
+        option_prices: &[i32],
+    ) -> EventCustomField {
+        EventCustomField::new(
+            0,
+            String::from("Feld"),
+            cf_type,
+            None,
+            None,
+            price_relevant,
+            options.iter().map(|option| option.to_string()).collect(),
+            false,
+            price.map(BigDecimal::from),
+            option_prices
+                .iter()
+                .map(|&price| BigDecimal::from(price))
+                .collect(),
+        )
+    }
+
     fn new_event_with_custom_fields(
         price_member: &str,
         price_non_member: &str,
@@ -1412,6 +2361,11 @@ mod tests {
             false,
             custom_fields,
             PaymentMethod::BankTransfer,
+            0,
+            None,
+            None,
+            None,
+            None,
         )
     }
 }
@@ -1476,6 +2430,17 @@ mod additional_model_tests {
         // Empty: no subscribers, empty waiting
         let counter = EventCounter::new(0, 10, 5, 0, 0);
         assert!(!counter.is_booked_up());
+        // Capacity buckets: booked up if all buckets are booked up
+        let mut counter = EventCounter::new(0, -1, 0, 3, 1);
+        counter.buckets = vec![
+            EventBucketCounter::new(1, "Erwachsene".into(), 2, 1, 2, 1),
+            EventBucketCounter::new(2, "Kinder".into(), 2, 0, 1, 0),
+        ];
+        assert!(!counter.is_booked_up());
+        assert_eq!(counter.buckets[0].enroll_status(), None);
+        assert_eq!(counter.buckets[1].enroll_status(), Some(true));
+        counter.buckets[1].subscribers = 2;
+        assert!(counter.is_booked_up());
     }
 
     #[test]
@@ -1487,6 +2452,32 @@ mod additional_model_tests {
         assert!(!LifecycleStatus::Finished.is_bookable());
         assert!(!LifecycleStatus::Closed.is_bookable());
         assert!(!LifecycleStatus::Archived.is_bookable());
+        assert!(!LifecycleStatus::Canceled.is_bookable());
+    }
+
+    #[test]
+    fn test_lifecycle_status_transitions() {
+        use LifecycleStatus::*;
+
+        assert!(Draft.can_transition_to(Draft));
+        assert!(Draft.can_transition_to(Published));
+        assert!(!Draft.can_transition_to(Closed));
+        assert!(Published.can_transition_to(Running));
+        assert!(Running.can_transition_to(Published));
+        assert!(!Published.can_transition_to(Draft));
+        assert!(Running.can_transition_to(Finished));
+        assert!(Finished.can_transition_to(Closed));
+        assert!(!Finished.can_transition_to(Running));
+        assert!(!Closed.can_transition_to(Published));
+        assert!(Closed.can_transition_to(Archived));
+        assert!(!Archived.can_transition_to(Closed));
+        assert!(!Published.can_transition_to(Canceled));
+        assert!(Canceled.can_transition_to(Archived));
+        assert!(!Canceled.can_transition_to(Published));
+
+        assert!(Draft.is_initial());
+        assert!(Running.is_initial());
+        assert!(!Closed.is_initial());
     }
 
     #[test]
@@ -1543,18 +2534,62 @@ mod additional_model_tests {
             LifecycleStatus::from_str("archived").unwrap(),
             LifecycleStatus::Archived
         );
+        assert_eq!(
+            LifecycleStatus::from_str("canceled").unwrap(),
+            LifecycleStatus::Canceled
+        );
         assert!(LifecycleStatus::from_str("invalid").is_err());
     }
 
     #[test]
-    fn test_news_topic_display_name_and_from_str() {
-        assert_eq!(NewsTopic::General.display_name(), "Allgemein");
-        assert_eq!(NewsTopic::Events.display_name(), "Events");
-        assert_eq!(NewsTopic::Fitness.display_name(), "Fitness");
-        assert_eq!(NewsTopic::from_str("General").unwrap(), NewsTopic::General);
-        assert_eq!(NewsTopic::from_str("Events").unwrap(), NewsTopic::Events);
-        assert_eq!(NewsTopic::from_str("Fitness").unwrap(), NewsTopic::Fitness);
-        assert!(NewsTopic::from_str("Other").is_err());
+    fn test_role_permissions() {
+        assert!(Role::Admin.can_manage(EventType::Fitness));
+        assert!(Role::Admin.can_manage(EventType::Events));
+        assert!(Role::FitnessCoordinator.can_manage(EventType::Fitness));
+        assert!(!Role::FitnessCoordinator.can_manage(EventType::Events));
+        assert!(!Role::FitnessCoordinator.can_view(EventType::Events));
+        assert!(Role::EventsCoordinator.can_manage(EventType::Events));
+        assert!(!Role::Treasurer.can_manage(EventType::Fitness));
+        assert!(Role::Treasurer.can_view(EventType::Fitness));
+        assert!(!Role::TrainerReadonly.can_manage(EventType::Events));
+        assert!(Role::TrainerReadonly.can_view(EventType::Events));
+        assert_eq!(
+            serde_json::to_string(&Role::FitnessCoordinator).unwrap(),
+            r#""fitness-coordinator""#
+        );
+    }
+
+    #[test]
+    fn test_news_topic_from_str() {
+        assert_eq!(NewsTopic::from_str("General").unwrap(), NewsTopic::GENERAL);
+        assert_eq!(NewsTopic::from_str("Events").unwrap(), NewsTopic::EVENTS);
+        assert_eq!(NewsTopic::from_str("Fitness").unwrap(), NewsTopic::FITNESS);
+        assert_eq!(
+            NewsTopic::from_str("Jugend").unwrap(),
+            NewsTopic::new("Jugend")
+        );
+        assert!(NewsTopic::from_str("").is_err());
+        assert!(NewsTopic::from_str("Kunst rasen").is_err());
+        assert_eq!(
+            serde_json::to_string(&vec![NewsTopic::GENERAL, NewsTopic::new("Jugend")]).unwrap(),
+            r#"["General","Jugend"]"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Vec<NewsTopic>>(r#"["Events"]"#).unwrap(),
+            vec![NewsTopic::EVENTS]
+        );
+    }
+
+    #[test]
+    fn test_partial_event_schedule_deserialization() {
+        let event = serde_json::from_str::<PartialEvent>(r#"{"publish_at":null}"#).unwrap();
+        assert_eq!(event.publish_at, Some(None));
+        assert_eq!(event.unpublish_at, None);
+        let event =
+            serde_json::from_str::<PartialEvent>(r#"{"booking_end":"2030-04-01T17:00:00Z"}"#)
+                .unwrap();
+        assert!(matches!(event.booking_end, Some(Some(_))));
+        assert_eq!(event.booking_start, None);
     }
 
     #[test]
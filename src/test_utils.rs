@@ -3,6 +3,7 @@ use std::sync::{Arc, Mutex};
 use lettre::Message;
 
 use crate::email::MockEmailSender;
+use crate::logic::secrets::{MockSecretProvider, SecretKey};
 use crate::models::{EmailAccount, EmailType};
 
 /// No-op mock — no email methods are called.
@@ -69,14 +70,14 @@ pub(crate) fn mock_email_sender_capturing(
     (mock, for_return)
 }
 
+/// Messages captured per send call together with the sending account.
+pub(crate) type CapturedBatches = Arc<Mutex<Vec<(EmailAccount, Vec<Message>)>>>;
+
 /// Mock that resolves accounts and captures (account, messages) per send_messages call.
 /// Returns (mock, captured_batches).
 pub(crate) fn mock_email_sender_capturing_batch(
     accounts: Vec<(EmailType, &str)>,
-) -> (
-    MockEmailSender,
-    Arc<Mutex<Vec<(EmailAccount, Vec<Message>)>>>,
-) {
+) -> (MockEmailSender, CapturedBatches) {
     let mut mock = MockEmailSender::new();
 
     for (email_type, address) in accounts {
@@ -113,3 +114,29 @@ pub(crate) fn mock_email_sender_capturing_batch(
 
     (mock, for_return)
 }
+
+/// Mock that returns the given values for the given secret keys.
+/// Panics if any unconfigured secret is requested.
+pub(crate) fn mock_secrets(secrets: Vec<(SecretKey, &str)>) -> MockSecretProvider {
+    let mut mock = MockSecretProvider::new();
+
+    for (key, value) in secrets {
+        let value = value.to_string();
+        mock.expect_get()
+            .withf(move |k| k == &key)
+            .returning(move |_| {
+                let value = value.clone();
+                Box::pin(async move { Ok(value) })
+            });
+    }
+
+    mock
+}
+
+/// Base64 encoded key of the IBAN encryption in tests.
+pub(crate) const TEST_IBAN_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+/// Mock that returns the test key of the IBAN encryption.
+pub(crate) fn mock_iban_secrets() -> MockSecretProvider {
+    mock_secrets(vec![(SecretKey::IbanEncryptionKey, TEST_IBAN_KEY)])
+}